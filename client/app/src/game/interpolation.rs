@@ -0,0 +1,164 @@
+use raylib::Vector2;
+use std::collections::{HashMap, VecDeque};
+
+//////////////////////////////////////////////
+///
+///
+/// Interpolate
+///
+///
+//////////////////////////////////////////////
+
+/// A value that can be blended between two server states
+pub trait Interpolate: Clone {
+    /// `t` is between 0 and 1 between the two states, greater than 1 when extrapolating
+    fn lerp(&self, other: &Self, t: f32) -> Self;
+}
+
+impl Interpolate for f32 {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Interpolate for Vector2 {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        Vector2 {
+            x: self.x.lerp(&other.x, t),
+            y: self.y.lerp(&other.y, t),
+        }
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Interpolator
+///
+///
+//////////////////////////////////////////////
+
+/// Timestamped states of a remote value, rendered `delay` seconds in the past so that there is
+/// almost always a state on each side of the rendered time
+///
+/// When packets go missing the value is extrapolated from the last two states, for at most
+/// `max_extrapolation` seconds, then it stays still
+pub struct Interpolator<T> {
+    delay: f32,
+    max_extrapolation: f32,
+    samples: VecDeque<(f32, T)>,
+}
+
+impl<T: Interpolate> Interpolator<T> {
+    pub fn new(delay: f32, max_extrapolation: f32) -> Self {
+        Interpolator {
+            delay: delay,
+            max_extrapolation: max_extrapolation,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Record the state received at `time`, states have to be pushed in order
+    pub fn push(&mut self, time: f32, value: T) {
+        if let Some(&(last, _)) = self.samples.back() {
+            if time <= last {
+                return;
+            }
+        }
+        self.samples.push_back((time, value));
+    }
+
+    /// Value to render at `now`, None until the first state is old enough to be rendered
+    pub fn sample(&mut self, now: f32) -> Option<T> {
+        let render_time = now - self.delay;
+
+        // keep a single state before the rendered time
+        while self.samples.len() > 2 && self.samples[1].0 <= render_time {
+            self.samples.pop_front();
+        }
+
+        match self.samples.len() {
+            0 => None,
+            1 => {
+                let (time, ref value) = self.samples[0];
+                if time <= render_time {
+                    Some(value.clone())
+                } else {
+                    None
+                }
+            }
+            _ => {
+                let (t0, ref v0) = self.samples[0];
+                let (t1, ref v1) = self.samples[1];
+                if render_time < t0 {
+                    return None;
+                }
+                let render_time = render_time.min(t1 + self.max_extrapolation);
+                Some(v0.lerp(v1, (render_time - t0) / (t1 - t0)))
+            }
+        }
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Entities
+///
+///
+//////////////////////////////////////////////
+
+/// Interpolators of a set of entities identified by ids, like the sections of a snapshot
+pub struct EntityInterpolator<T> {
+    delay: f32,
+    max_extrapolation: f32,
+    // interpolator and time at which the entity disappeared from the states
+    entities: HashMap<u16, (Interpolator<T>, Option<f32>)>,
+}
+
+impl<T: Interpolate> EntityInterpolator<T> {
+    pub fn new(delay: f32, max_extrapolation: f32) -> Self {
+        EntityInterpolator {
+            delay: delay,
+            max_extrapolation: max_extrapolation,
+            entities: HashMap::new(),
+        }
+    }
+
+    /// Record the state of every entity at `time`, those missing are removed once rendered up to
+    /// that time
+    pub fn push<I: Iterator<Item = (u16, T)>>(&mut self, time: f32, entities: I) {
+        for &mut (_, ref mut removed) in self.entities.values_mut() {
+            if removed.is_none() {
+                *removed = Some(time);
+            }
+        }
+
+        for (id, value) in entities {
+            let (delay, max_extrapolation) = (self.delay, self.max_extrapolation);
+            let entry = self
+                .entities
+                .entry(id)
+                .or_insert_with(|| (Interpolator::new(delay, max_extrapolation), None));
+            entry.0.push(time, value);
+            entry.1 = None;
+        }
+    }
+
+    /// Entities to render at `now`
+    pub fn sample(&mut self, now: f32) -> Vec<(u16, T)> {
+        let render_time = now - self.delay;
+        self.entities.retain(|_, &mut (_, removed)| match removed {
+            Some(time) => time > render_time,
+            None => true,
+        });
+
+        let mut res = Vec::with_capacity(self.entities.len());
+        for (id, &mut (ref mut interpolator, _)) in self.entities.iter_mut() {
+            if let Some(value) = interpolator.sample(now) {
+                res.push((*id, value));
+            }
+        }
+        res
+    }
+}
@@ -1,15 +1,17 @@
 use crate::{
-    network::{self, packet},
+    game::{
+        interpolation::{EntityInterpolator, Interpolate},
+        snapshot, ClientGame, Input,
+    },
     ui::colors,
 };
 use c_char;
 use raylib::{self, Color, Rectangle, Vector2};
 use std::{
-    convert::TryInto,
+    collections::VecDeque,
     ffi::{c_float, c_int},
     vec,
 };
-use time;
 
 mod sprite;
 mod wall;
@@ -22,6 +24,7 @@ mod wall;
 ///
 //////////////////////////////////////////////
 
+#[derive(Clone)]
 struct Entity {
     pub pos: raylib::Vector2,
     pub dir: raylib::Vector2,
@@ -29,105 +32,265 @@ struct Entity {
     pub color: Color,
 }
 
+impl Interpolate for Entity {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        Entity {
+            pos: self.pos.lerp(&other.pos, t),
+            ..other.clone()
+        }
+    }
+}
+
+/// Remote entities are rendered this late, in seconds, to interpolate between two server states
+const RENDER_DELAY: f32 = 0.1;
+/// Longest extrapolation when server states go missing, in seconds
+const MAX_EXTRAPOLATION: f32 = 0.25;
+
+/// Inputs sent but not yet applied by the server, replayed over each server state
+struct PendingInput {
+    seq: u16,
+    dt: c_float,
+    speed: Vector2,
+}
+
+/// Kinds of the updates of the map, sent when players join or leave the game
+/// Update : 0 (u16) | kind (u8) | maze or sprites, as sent at the start of the game
+const UPDATE_MAZE: u8 = 0;
+const UPDATE_SPRITES: u8 = 1;
+
+/// Inputs kept for prediction at most, older ones are dropped if the server stops answering
+const MAX_PENDING_INPUTS: usize = 128;
+
 //////////////////////////////////////////////
 ///
 ///
-/// Entry point
+/// Game
 ///
 ///
 //////////////////////////////////////////////
 
-pub unsafe fn main_game(network: &mut network::Network) {
-    let tex_tile = raylib::LoadTexture(raylib::raylib_str!("marble_tile.png"));
-    let tex_wall = raylib::LoadTexture(raylib::raylib_str!("wall.png"));
-    let tex_sprite = raylib::LoadTexture(raylib::raylib_str!("sprite.png"));
+struct Textures {
+    tile: raylib::Texture,
+    wall: raylib::Texture,
+    sprite: raylib::Texture,
+}
 
-    let mut buffer = [0_u8; network::packet::MAX_DATA_SIZE];
-    while network.recv(&mut buffer) == 0 {
-        std::thread::sleep(std::time::Duration::from_millis(10));
-    }
+pub struct MazeFight {
+    textures: Option<Textures>,
 
-    let maze = wall::Wall::unpack_maze(&buffer);
+    maze: Vec<wall::Wall>,
+    sprites: Vec<sprite::Sprite>,
+    bullets: Vec<Entity>,
+    powerups: Vec<Vector2>,
+    life: u8,
+    tile_size: f32,
 
-    while network.recv(&mut buffer) == 0 {
-        std::thread::sleep(std::time::Duration::from_millis(10));
-    }
+    snapshots: snapshot::SnapshotBuffer,
 
-    let mut _sprites = sprite::Sprite::unpack_sprites(&buffer);
+    // sprite of the phone and scale from server units to screen units
+    rank: usize,
+    scale: Vector2,
+    input_seq: u16,
+    pending_inputs: VecDeque<PendingInput>,
 
-    let tile_size = maze[0].end.x - maze[0].start.x + maze[0].end.y - maze[0].start.y;
+    // time since the start of the game, in seconds, and remote entities to interpolate
+    clock: f32,
+    remote_sprites: EntityInterpolator<Vector2>,
+    remote_bullets: EntityInterpolator<Entity>,
 
-    let mut bullets: Vec<Entity> = vec::Vec::new();
-    let mut powerups: Vec<Vector2> = vec::Vec::new();
-    let mut life = 10;
+    record_central_pos: raylib::Vector2,
+    update_pos: raylib::Vector2,
+}
 
-    let mut record_central_pos = raylib::Vector2 { x: 0., y: 0. };
-    let mut update_pos = raylib::Vector2 { x: 0., y: 0. };
+impl MazeFight {
+    pub fn new() -> Self {
+        MazeFight {
+            textures: None,
+            maze: vec::Vec::new(),
+            sprites: vec::Vec::new(),
+            bullets: vec::Vec::new(),
+            powerups: vec::Vec::new(),
+            life: 10,
+            tile_size: 0.,
+            snapshots: snapshot::SnapshotBuffer::new(),
+            rank: 0,
+            scale: Vector2 { x: 1., y: 1. },
+            input_seq: 0,
+            pending_inputs: VecDeque::with_capacity(MAX_PENDING_INPUTS),
+            clock: 0.,
+            remote_sprites: EntityInterpolator::new(RENDER_DELAY, MAX_EXTRAPOLATION),
+            remote_bullets: EntityInterpolator::new(RENDER_DELAY, MAX_EXTRAPOLATION),
+            record_central_pos: raylib::Vector2 { x: 0., y: 0. },
+            update_pos: raylib::Vector2 { x: 0., y: 0. },
+        }
+    }
 
-    let mut internal_timer = time::Instant::now();
+    /// The server first sends the maze, then the sprites, the game can start only after that
+    fn is_initialized(&self) -> bool {
+        !self.maze.is_empty() && !self.sprites.is_empty()
+    }
 
-    loop {
-        let mut buffer = [0_u8; packet::MAX_DATA_SIZE];
-        let mut anex = [0_u8; packet::MAX_DATA_SIZE];
-        let n1 = network.recv(&mut anex);
-        let mut n = 0;
-        loop {
-            buffer.copy_from_slice(&anex);
-            n = network.recv(&mut anex);
-            if n == 0 {
-                break;
+    /// Players joined or left : the maze is sent again if the screen moved, the sprites if some
+    /// were added, the ones already known keep their state
+    fn apply_update(&mut self, kind: u8, data: &[u8]) {
+        match kind {
+            UPDATE_MAZE => {
+                self.maze = wall::Wall::unpack_maze(data);
+                if let Some(w) = self.maze.first() {
+                    self.tile_size = w.end.x - w.start.x + w.end.y - w.start.y;
+                }
             }
-        }
-        if n1 > 0 {
-            (bullets, powerups, life) = unpack_game_data(&buffer, &mut _sprites);
-        } else {
-            for s in _sprites.iter_mut() {
-                s.update_sprite_pos(internal_timer, &maze);
+            UPDATE_SPRITES => {
+                let known = self.sprites.len();
+                self.sprites
+                    .extend(sprite::Sprite::unpack_sprites(data).into_iter().skip(known));
             }
+            _ => {}
+        }
+    }
 
-            // update bullet status
-            let mut i = 0;
-            while i < bullets.len() {
-                let b = &mut bullets[i];
-                b.pos.x += b.dir.x * internal_timer.elapsed().as_secs_f32() * 50.;
-                b.pos.y += b.dir.y * internal_timer.elapsed().as_secs_f32() * 50.;
-                if b.pos.x < 0. || b.pos.y < 0. || b.pos.x > 5000. || b.pos.y > 5000. {
-                    bullets.swap_remove(i);
-                } else {
-                    i += 1;
-                    for w in maze.iter() {
-                        if w.intersect(&mut b.pos, b.size, b.size) {
-                            i -= 1;
-                            bullets.swap_remove(i);
-                            break;
-                        }
-                    }
+    /// The server state includes the inputs up to `last_input`, replay the following ones on it
+    fn reconcile(&mut self, last_input: u16) {
+        while let Some(true) = self
+            .pending_inputs
+            .front()
+            .map(|i| i.seq.wrapping_sub(last_input) as i16 <= 0)
+        {
+            self.pending_inputs.pop_front();
+        }
+
+        let scale = self.scale;
+        let maze = &self.maze;
+        if let Some(s) = self.sprites.get_mut(self.rank) {
+            if s.is_alive() {
+                for input in self.pending_inputs.iter() {
+                    s.predict(input.speed, input.dt, scale, maze);
                 }
             }
         }
-        internal_timer = time::Instant::now();
+    }
+}
 
-        if raylib::IsMouseButtonPressed(raylib::MouseButton_MOUSE_BUTTON_LEFT.try_into().unwrap()) {
-            record_central_pos = raylib::GetMousePosition();
+impl ClientGame for MazeFight {
+    fn load_assets(&mut self) {
+        unsafe {
+            self.textures = Some(Textures {
+                tile: raylib::LoadTexture(raylib::raylib_str!("marble_tile.png")),
+                wall: raylib::LoadTexture(raylib::raylib_str!("wall.png")),
+                sprite: raylib::LoadTexture(raylib::raylib_str!("sprite.png")),
+            });
         }
+    }
 
-        if raylib::IsMouseButtonDown(raylib::MouseButton_MOUSE_BUTTON_LEFT.try_into().unwrap()) {
-            update_pos = raylib::GetMousePosition();
+    fn on_message(&mut self, data: &[u8]) {
+        if self.maze.is_empty() {
+            self.maze = wall::Wall::unpack_maze(data);
+            self.tile_size = self.maze[0].end.x - self.maze[0].start.x + self.maze[0].end.y
+                - self.maze[0].start.y;
+        } else if self.sprites.is_empty() {
+            self.sprites = sprite::Sprite::unpack_sprites(data);
+            let (rank, scale) = unpack_player(&data[1 + 2 * self.sprites.len()..]);
+            self.rank = rank;
+            self.scale = scale;
+        } else if let [0, 0, kind, update @ ..] = data {
+            // the snapshots never start with the seq 0
+            self.apply_update(*kind, update);
+        } else if let Some(snapshot) = self.snapshots.receive(data) {
+            let (bullets, powerups, life, last_input) =
+                unpack_game_data(snapshot, &mut self.sprites);
+            self.powerups = powerups;
+            self.life = life;
+
+            let rank = self.rank;
+            self.remote_sprites.push(
+                self.clock,
+                self.sprites
+                    .iter()
+                    .enumerate()
+                    .filter(|&(i, s)| i != rank && s.is_alive())
+                    .map(|(i, s)| (i as u16, s.get_pos())),
+            );
+            self.remote_bullets.push(self.clock, bullets.into_iter());
+
+            self.reconcile(last_input);
         }
+    }
 
-        if raylib::IsMouseButtonReleased(raylib::MouseButton_MOUSE_BUTTON_LEFT.try_into().unwrap())
-        {
-            record_central_pos = raylib::Vector2 { x: 0., y: 0. };
-            update_pos = raylib::Vector2 { x: 0., y: 0. };
+    fn update(&mut self, dt: f32, input: &Input) -> Option<Vec<u8>> {
+        if !self.is_initialized() {
+            return None;
+        }
+
+        // the other sprites and the bullets are rendered in the past, between two server states
+        self.clock += dt;
+        let remote_sprites = self.remote_sprites.sample(self.clock);
+        for (i, s) in self.sprites.iter_mut().enumerate() {
+            if i != self.rank {
+                s.show_at(
+                    remote_sprites
+                        .iter()
+                        .find(|&&(id, _)| id as usize == i)
+                        .map(|&(_, pos)| pos),
+                );
+            }
+        }
+        self.bullets = self
+            .remote_bullets
+            .sample(self.clock)
+            .into_iter()
+            .map(|(_, b)| b)
+            .collect();
+
+        if input.pressed {
+            self.record_central_pos = input.position;
+        }
+
+        if input.down {
+            self.update_pos = input.position;
+        }
+
+        if input.released {
+            self.record_central_pos = raylib::Vector2 { x: 0., y: 0. };
+            self.update_pos = raylib::Vector2 { x: 0., y: 0. };
         }
 
         let speed = raylib::Vector2 {
-            x: (update_pos.x - record_central_pos.x) / 10.,
-            y: (update_pos.y - record_central_pos.y) / 10.,
+            x: (self.update_pos.x - self.record_central_pos.x) / 10.,
+            y: (self.update_pos.y - self.record_central_pos.y) / 10.,
+        };
+
+        // the sprite of the phone moves right away, the server will confirm
+        self.input_seq = self.input_seq.wrapping_add(1);
+        if let Some(s) = self.sprites.get_mut(self.rank) {
+            if s.is_alive() {
+                s.predict(speed, dt, self.scale, &self.maze);
+            }
+        }
+        if self.pending_inputs.len() == MAX_PENDING_INPUTS {
+            self.pending_inputs.pop_front();
+        }
+        self.pending_inputs.push_back(PendingInput {
+            seq: self.input_seq,
+            dt: dt,
+            speed: speed,
+        });
+
+        Some(pack_input(
+            self.input_seq,
+            dt,
+            speed,
+            self.snapshots.last_seq(),
+        ))
+    }
+
+    fn draw(&self) {
+        let textures = match &self.textures {
+            Some(t) if self.is_initialized() => t,
+            _ => return,
         };
-        send_speed(network, speed);
+        let tile_size = self.tile_size;
 
-        raylib::draw!({
+        unsafe {
             raylib::ClearBackground(raylib::Color {
                 r: 65,
                 g: 65,
@@ -138,7 +301,7 @@ pub unsafe fn main_game(network: &mut network::Network) {
             for i in 0..(raylib::GetScreenWidth() as f32 / tile_size) as usize + 1 {
                 for j in 0..(raylib::GetScreenHeight() as f32 / tile_size) as usize + 1 {
                     raylib::DrawTextureEx(
-                        tex_tile,
+                        textures.tile,
                         raylib::Vector2 {
                             x: i as f32 * tile_size,
                             y: j as f32 * tile_size,
@@ -155,13 +318,13 @@ pub unsafe fn main_game(network: &mut network::Network) {
                 }
             }
 
-            for w in maze.iter() {
-                w.draw(tex_wall);
+            for w in self.maze.iter() {
+                w.draw(textures.wall);
             }
-            for b in bullets.iter() {
+            for b in self.bullets.iter() {
                 raylib::DrawRectangle(b.pos.x as i32, b.pos.y as i32, b.size, b.size, b.color);
             }
-            for p in powerups.iter() {
+            for p in self.powerups.iter() {
                 raylib::DrawCircle(
                     p.x as c_int,
                     p.y as c_int,
@@ -175,10 +338,10 @@ pub unsafe fn main_game(network: &mut network::Network) {
                 );
             }
 
-            for s in _sprites.iter() {
+            for s in self.sprites.iter() {
                 if s.is_alive() {
                     raylib::DrawTexturePro(
-                        tex_sprite,
+                        textures.sprite,
                         raylib::Rectangle {
                             x: ((s.get_skin() % 4) * 103 + s.get_state() * 32) as f32,
                             y: ((s.get_skin() / 4) * 155 + (s.get_direction() * 36)) as f32,
@@ -204,7 +367,7 @@ pub unsafe fn main_game(network: &mut network::Network) {
             }
 
             raylib::DrawText(
-                raylib::raylib_str!(format!("{}", life)),
+                raylib::raylib_str!(format!("{}", self.life)),
                 50,
                 50,
                 100,
@@ -215,7 +378,17 @@ pub unsafe fn main_game(network: &mut network::Network) {
                     a: 255,
                 },
             );
-        });
+        }
+    }
+
+    fn unload(&mut self) {
+        if let Some(textures) = self.textures.take() {
+            unsafe {
+                raylib::UnloadTexture(textures.tile);
+                raylib::UnloadTexture(textures.wall);
+                raylib::UnloadTexture(textures.sprite);
+            }
+        }
     }
 }
 
@@ -227,82 +400,84 @@ pub unsafe fn main_game(network: &mut network::Network) {
 ///
 //////////////////////////////////////////////
 
+/// Rank of the phone and scale from server units to screen units, sent after the sprites
+fn unpack_player(data: &[u8]) -> (usize, Vector2) {
+    if data.len() < 9 {
+        return (0, Vector2 { x: 1., y: 1. });
+    }
+    (
+        u8::from_be(data[0]) as usize,
+        Vector2 {
+            x: snapshot::read_f32(&data[1..], 0),
+            y: snapshot::read_f32(&data[1..], 1),
+        },
+    )
+}
+
+/// Header of the snapshot : life (u8) | last input applied (u16)
+/// Sections : sprites, bullets, power-ups
 fn unpack_game_data(
-    _data: &[u8],
+    snapshot: &snapshot::Snapshot,
     sprites: &mut [sprite::Sprite],
-) -> (Vec<Entity>, Vec<Vector2>, u8) {
-    let life = u8::from_be(_data[0]);
-    let mut data = &sprite::Sprite::unpack_game_sprites(sprites, &_data[1..])[..];
-    let mut n = u8::from_be(data[0]) as usize;
-    data = &data[1..];
-    let mut bullets = vec::Vec::new();
-
-    for i in 0..n {
-        let mut buffer = [0_u8; 4];
-
-        buffer.copy_from_slice(&data[i * 20..i * 20 + 4]);
-        let pos_x = f32::from_be_bytes(buffer);
-        buffer.copy_from_slice(&data[i * 20 + 4..i * 20 + 8]);
-        let pos_y = f32::from_be_bytes(buffer);
-
-        buffer.copy_from_slice(&data[i * 20 + 8..i * 20 + 12]);
-        let speed_x = f32::from_be_bytes(buffer);
-        buffer.copy_from_slice(&data[i * 20 + 12..i * 20 + 16]);
-        let speed_y = f32::from_be_bytes(buffer);
-
-        buffer.copy_from_slice(&data[i * 20 + 16..i * 20 + 20]);
-        let size = f32::from_be_bytes(buffer);
+) -> (Vec<(u16, Entity)>, Vec<Vector2>, u8, u16) {
+    let (life, last_input) = match &snapshot.header[..] {
+        &[life, a, b, ..] => (u8::from_be(life), u16::from_be_bytes([a, b])),
+        _ => (0, 0),
+    };
+    sprite::Sprite::unpack_game_sprites(sprites, snapshot.section(0));
 
-        bullets.push(Entity {
-            pos: Vector2 { x: pos_x, y: pos_y },
-            dir: Vector2 {
-                x: speed_x,
-                y: speed_y,
-            },
-            size: size as c_int,
-            color: raylib::Color {
-                r: 255,
-                g: 0,
-                b: 0,
-                a: 255,
+    let mut bullets = vec::Vec::new();
+    for &(uid, ref entry) in snapshot.section(1).iter() {
+        bullets.push((
+            uid,
+            Entity {
+                pos: Vector2 {
+                    x: snapshot::read_f32(entry, 0),
+                    y: snapshot::read_f32(entry, 1),
+                },
+                dir: Vector2 {
+                    x: snapshot::read_f32(entry, 2),
+                    y: snapshot::read_f32(entry, 3),
+                },
+                size: snapshot::read_f32(entry, 4) as c_int,
+                color: raylib::Color {
+                    r: 255,
+                    g: 0,
+                    b: 0,
+                    a: 255,
+                },
             },
-        });
+        ));
     }
 
-    data = &data[n * 20..];
-    n = u8::from_be(data[0]) as usize;
-    data = &data[1..];
     let mut powerups = vec::Vec::new();
-
-    for i in 0..n {
-        let mut buffer = [0_u8; 4];
-
-        buffer.copy_from_slice(&data[i * 8..i * 8 + 4]);
-        let pos_x = f32::from_be_bytes(buffer);
-        buffer.copy_from_slice(&data[i * 8 + 4..i * 8 + 8]);
-        let pos_y = f32::from_be_bytes(buffer);
-
-        powerups.push(Vector2 { x: pos_x, y: pos_y });
+    for &(_, ref entry) in snapshot.section(2).iter() {
+        powerups.push(Vector2 {
+            x: snapshot::read_f32(entry, 0),
+            y: snapshot::read_f32(entry, 1),
+        });
     }
 
-    (bullets, powerups, life)
+    (bullets, powerups, life, last_input)
 }
 
 //////////////////////////////////////////////
 ///
 ///
-/// Sender
+/// Pack input
 ///
 ///
 //////////////////////////////////////////////
 
-fn send_speed(network: &mut network::Network, speed: Vector2) {
-    let mut data = [0_u8; 8];
+/// seq (u16) | frame duration (f32) | speed x (f32) | speed y (f32) | last snapshot received (u16)
+fn pack_input(seq: u16, dt: c_float, speed: Vector2, ack: u16) -> Vec<u8> {
+    let mut data = vec::Vec::with_capacity(16);
 
-    let mut buffer = speed.x.to_be_bytes();
-    data[..4].copy_from_slice(&buffer);
-    buffer = speed.y.to_be_bytes();
-    data[4..].copy_from_slice(&buffer);
+    data.extend_from_slice(&seq.to_be_bytes());
+    data.extend_from_slice(&dt.to_be_bytes());
+    data.extend_from_slice(&speed.x.to_be_bytes());
+    data.extend_from_slice(&speed.y.to_be_bytes());
+    data.extend_from_slice(&ack.to_be_bytes());
 
-    network.send(&data).unwrap();
+    data
 }
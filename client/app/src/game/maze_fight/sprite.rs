@@ -5,6 +5,11 @@ use std::time;
 use std::vec;
 
 use crate::game::maze_fight::sprite;
+use crate::game::snapshot;
+
+/// Same values as the server, the movement of the player's sprite is predicted with them
+const SPEED_LIMIT: c_float = 6.;
+const MAX_INPUT_DT: c_float = 0.1;
 
 pub struct Sprite {
     pos: raylib::Vector2,
@@ -23,53 +28,26 @@ pub struct Sprite {
 }
 
 impl Sprite {
-    pub fn unpack_game_sprites(sprites: &mut [Self], _data: &[u8]) -> Vec<u8> {
-        const SIZE: usize = 25;
-
-        let mut update_recorder = vec::Vec::with_capacity(sprites.len());
-        for s in sprites.iter() {
-            update_recorder.push(false);
-        }
-
-        let len = u8::from_be(_data[0]) as usize;
-        let data = &_data[1..];
-
-        for i in 0..len {
-            let id = u8::from_be(data[i * SIZE]) as usize;
-
-            let mut buffer = [0_u8; 4];
-
-            buffer.copy_from_slice(&data[i * SIZE + 1..i * SIZE + 5]);
-            let pos_x = f32::from_be_bytes(buffer);
-            buffer.copy_from_slice(&data[i * SIZE + 5..i * SIZE + 9]);
-            let pos_y = f32::from_be_bytes(buffer);
-
-            sprites[id].pos.x = pos_x;
-            sprites[id].pos.y = pos_y;
-
-            buffer.copy_from_slice(&data[i * SIZE + 9..i * SIZE + 13]);
-            let speed_x = f32::from_be_bytes(buffer);
-            buffer.copy_from_slice(&data[i * SIZE + 13..i * SIZE + 17]);
-            let speed_y = f32::from_be_bytes(buffer);
-
-            sprites[id].speed.x = speed_x;
-            sprites[id].speed.y = speed_y;
-
-            buffer.copy_from_slice(&data[i * SIZE + 17..i * SIZE + 21]);
-            let width = f32::from_be_bytes(buffer);
-            buffer.copy_from_slice(&data[i * SIZE + 21..i * SIZE + 25]);
-            let height = f32::from_be_bytes(buffer);
-
-            sprites[id].width = width;
-            sprites[id].height = height;
-            update_recorder[id] = true;
+    /// Update the sprites from their section of the snapshot, those missing are dead or too far
+    pub fn unpack_game_sprites(sprites: &mut [Self], entries: &[(u16, Vec<u8>)]) {
+        for s in sprites.iter_mut() {
+            s.is_alive = false;
         }
 
-        for (i, s) in sprites.iter_mut().enumerate() {
-            s.is_alive = update_recorder[i];
+        for &(id, ref entry) in entries.iter() {
+            let s = match sprites.get_mut(id as usize) {
+                Some(s) => s,
+                None => continue,
+            };
+
+            s.pos.x = snapshot::read_f32(entry, 0);
+            s.pos.y = snapshot::read_f32(entry, 1);
+            s.speed.x = snapshot::read_f32(entry, 2);
+            s.speed.y = snapshot::read_f32(entry, 3);
+            s.width = snapshot::read_f32(entry, 4);
+            s.height = snapshot::read_f32(entry, 5);
+            s.is_alive = true;
         }
-
-        data[len * SIZE..].to_vec()
     }
 
     pub fn unpack_sprites(_data: &[u8]) -> Vec<Self> {
@@ -103,14 +81,51 @@ impl Sprite {
         sprites
     }
 
-    pub fn update_sprite_pos(&mut self, timer: time::Instant, maze: &Vec<super::wall::Wall>) {
-        self.pos.x += self.speed.x * timer.elapsed().as_secs_f32() * 50.;
-        self.pos.y += self.speed.y * timer.elapsed().as_secs_f32() * 50.;
+    /// Predict the movement the server will apply for an input, `speed` is in server units and
+    /// `scale` converts them to screen units
+    ///
+    /// Speed power-ups are not known here, the server state corrects the prediction
+    pub fn predict(
+        &mut self,
+        speed: Vector2,
+        dt: c_float,
+        scale: Vector2,
+        maze: &Vec<super::wall::Wall>,
+    ) {
+        let norm = (speed.x * speed.x + speed.y * speed.y).sqrt();
+        let ratio = if norm > SPEED_LIMIT {
+            SPEED_LIMIT / norm
+        } else {
+            1.
+        };
+        self.speed = Vector2 {
+            x: speed.x * ratio * scale.x,
+            y: speed.y * ratio * scale.y,
+        };
+        self.update_sprite_pos(dt.max(0.).min(MAX_INPUT_DT), maze);
+    }
+
+    pub fn update_sprite_pos(&mut self, dt: c_float, maze: &Vec<super::wall::Wall>) {
+        self.pos.x += self.speed.x * dt * 50.;
+        self.pos.y += self.speed.y * dt * 50.;
 
         for w in maze.iter() {
             w.realign_sprite(&mut self.pos, self.width as c_int, self.height as c_int);
         }
 
+        self.animate();
+    }
+
+    /// Place a remote sprite where the interpolation puts it, None if it is not rendered
+    pub fn show_at(&mut self, pos: Option<Vector2>) {
+        self.is_alive = pos.is_some();
+        if let Some(pos) = pos {
+            self.pos = pos;
+            self.animate();
+        }
+    }
+
+    fn animate(&mut self) {
         if self.speed.x.abs() + self.speed.y.abs() > 0. {
             if self.internal_state_timer.elapsed().as_millis() > 400 {
                 self.internal_state_timer = time::Instant::now();
@@ -1,6 +1,14 @@
-use crate::network::Network;
+use raylib::{self, Vector2};
+use std::convert::TryInto;
 use std::fmt::Display;
 
+use crate::ui::text::waiting_text;
+
+/// Helpers shared by the games
+pub mod interpolation;
+pub mod settings;
+pub mod snapshot;
+
 
 //////////////////////////////////////////////
 ///
@@ -52,17 +60,6 @@ impl From<u16> for Game {
     }
 }
 
-/// Add your game title
-pub fn title(game_id: u16) -> String {
-    String::from(
-    match game_id {
-        1 => "Racer",
-        2 => "Snake",
-        3 => "Maze",
-        _ => "Unknown ???",
-    })
-}
-
 impl Display for Game {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -77,12 +74,13 @@ impl Display for Game {
 
 
 impl Game {
-    /// Add your game structure : title, main_game function, and max number of players
-    pub fn create_game(&self) -> GameStruct {
+    /// Add your game structure : title and game instance
+    /// None for the games still awaiting for your awesome code, the phones can't play them
+    pub fn create_game(&self) -> Option<GameStruct> {
         match self {
-            Game::Racer => GameStruct::new(String::from("Racer"), Box::new(racer::main_game), None),
-            Game::MazeFight => GameStruct::new(String::from("Maze Fight"), Box::new(|network| unsafe{maze_fight::main_game(network)}), Some(9)),
-            _ => panic!("this game is still awaiting for your awesome code ..."),
+            Game::Racer => Some(GameStruct::new(String::from("Racer"), Box::new(racer::Racer::new()))),
+            Game::MazeFight => Some(GameStruct::new(String::from("Maze Fight"), Box::new(maze_fight::MazeFight::new()))),
+            _ => None,
         }
     }
 }
@@ -98,17 +96,91 @@ impl Game {
 pub struct GameStruct{
     title: String,
     //description: String,
-    main_game: Box<dyn Fn(&mut Network)>,
-    max_player: Option<usize>
+    game: Box<dyn ClientGame>,
 }
 
 impl GameStruct {
     pub fn new(
         title: String,
         //description: String,
-        main_game: Box<dyn Fn(&mut Network)>,
-        max_player: Option<usize>)
+        game: Box<dyn ClientGame>)
         -> GameStruct {
-        GameStruct {title, main_game, max_player}
+        GameStruct {title, game}
+    }
+
+    /// The game instance, driven frame by frame by the app shell
+    pub fn game(&mut self) -> &mut dyn ClientGame {
+        self.game.as_mut()
+    }
+}
+
+/// Stand-in for a game the phone can't play, launched by a host with another client
+/// It shows the phone is waiting until the server ends the game
+pub struct Unsupported;
+
+impl ClientGame for Unsupported {
+    fn on_message(&mut self, _data: &[u8]) {}
+
+    fn update(&mut self, _dt: f32, _input: &Input) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn draw(&self) {
+        unsafe {
+            waiting_text(raylib::GetScreenHeight(), raylib::GetScreenWidth());
+        }
+    }
+}
+
+/////////////////////////////////////
+/// 
+/// Client game interface
+/// 
+/////////////////////////////////////
+
+/// Touch state of the current frame, sampled once by the app shell before `update`
+#[derive(Clone, Copy)]
+pub struct Input {
+    pub pressed: bool,
+    pub down: bool,
+    pub released: bool,
+    pub position: Vector2,
+}
+
+impl Input {
+    pub fn poll() -> Input {
+        unsafe {
+            let button = raylib::MouseButton_MOUSE_BUTTON_LEFT.try_into().unwrap();
+            Input {
+                pressed: raylib::IsMouseButtonPressed(button),
+                down: raylib::IsMouseButtonDown(button),
+                released: raylib::IsMouseButtonReleased(button),
+                position: raylib::GetMousePosition(),
+            }
+        }
     }
+}
+
+/// Hooks a game has to implement, the frame loop itself is owned by the app shell :
+/// `load_assets` once, then every frame `on_message` for each packet received, `update` and `draw`,
+/// and finally `unload` when the window is closed
+pub trait ClientGame {
+    /// Load textures and other resources, the window is already opened at this point
+    fn load_assets(&mut self) {}
+
+    /// Handle a message sent by the server game
+    fn on_message(&mut self, data: &[u8]);
+
+    /// Advance the game of `dt` seconds, the returned data is sent to the server
+    fn update(&mut self, dt: f32, input: &Input) -> Option<Vec<u8>>;
+
+    /// Apply the settings chosen by the host, in the order of the schema of the game
+    /// It is called once before `load_assets`
+    fn configure(&mut self, _settings: &[u16]) {}
+
+    /// Draw the frame, this is called between `BeginDrawing` and `EndDrawing`
+    fn draw(&self);
+
+    /// Release what was loaded in `load_assets`
+    fn unload(&mut self) {}
 }
\ No newline at end of file
@@ -1,21 +1,45 @@
-use crate::network;
-use std::convert::TryInto;
+use crate::game::interpolation::{Interpolate, Interpolator};
+use crate::game::{ClientGame, Input};
 use std::ffi::{c_float, c_int};
 
 use c_char;
 
 use raylib::{
-    ClearBackground, Color, DrawCircle, DrawFPS, DrawRectanglePro, DrawSplineSegmentBezierCubic,
+    ClearBackground, Color, DrawCircle, DrawRectanglePro, DrawSplineSegmentBezierCubic,
     DrawSplineSegmentLinear, DrawText, GetScreenHeight, GetScreenWidth, GetSplinePointBezierCubic,
-    GetSplinePointLinear, IsMouseButtonDown, MouseButton_MOUSE_BUTTON_LEFT, Rectangle,
-    SetTargetFPS, Vector2, WindowShouldClose,
+    GetSplinePointLinear, Rectangle, Vector2,
 };
 
-use raylib::{draw, raylib_str};
+use raylib::raylib_str;
 
-struct Car<'a> {
+/// Number of segments of the track, the car goes back to the first one after the last
+const TRACK_SEGMENTS: u8 = 4;
+
+/// The other car is rendered this late, in seconds, to interpolate between two of its positions
+const RENDER_DELAY: f32 = 0.1;
+/// Longest extrapolation when positions go missing, in seconds
+const MAX_EXTRAPOLATION: f32 = 0.25;
+
+/// Position along the track : segment + t
+#[derive(Clone, Copy)]
+struct Progress(c_float);
+
+impl Interpolate for Progress {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        // the track is a loop, go the short way around
+        let segments = TRACK_SEGMENTS as c_float;
+        let mut delta = other.0 - self.0;
+        if delta > segments / 2. {
+            delta -= segments;
+        } else if delta < -segments / 2. {
+            delta += segments;
+        }
+        Progress((self.0 + delta * t).rem_euclid(segments))
+    }
+}
+
+struct Car {
     pos: Vector2,
-    track: &'a Track,
     track_segment: u8,
     track_t: c_float,
     speed: c_float,
@@ -27,11 +51,10 @@ struct Car<'a> {
     color: Color,
 }
 
-impl<'a> Car<'a> {
-    fn new(track: &'a Track) -> Car {
+impl Car {
+    fn new(track: &Track) -> Car {
         Car {
             pos: track.get_pos(0, 0.5),
-            track,
             track_segment: 0,
             track_t: 0.5,
             speed: 0.0,
@@ -62,14 +85,20 @@ impl<'a> Car<'a> {
         self.speed = 0.0_f32.max(self.speed - self.decceleration);
     }
 
-    fn new_pos(&mut self) {
+    fn set_progress(&mut self, progress: Progress, track: &Track) {
+        self.track_segment = (progress.0 as u8).min(TRACK_SEGMENTS - 1);
+        self.track_t = progress.0 - self.track_segment as c_float;
+        self.new_pos(track);
+    }
+
+    fn new_pos(&mut self, track: &Track) {
         if self.speed >= 0.0 {
             self.track_t += self.speed;
             if self.track_t >= 1.0 {
                 self.track_t = self.track_t - 1.0;
-                self.track_segment = (self.track_segment + 1) % 4;
+                self.track_segment = (self.track_segment + 1) % TRACK_SEGMENTS;
             }
-            let new_pos = self.track.get_pos(self.track_segment, self.track_t);
+            let new_pos = track.get_pos(self.track_segment, self.track_t);
             let old_pos = self.pos;
             let (w_x, w_y) = (new_pos.x - old_pos.x, new_pos.y - old_pos.y);
             let (v_x, v_y) = (0.0, -1.0);
@@ -241,75 +270,110 @@ impl Track {
     }
 }
 
-pub fn main_game(network: &mut network::Network) {
-    unsafe {
-        let (width, height) = (GetScreenWidth(), GetScreenHeight());
+pub struct Racer {
+    track: Track,
+    car: Car,
+    car2: Car,
+    width: c_int,
+    height: c_int,
+    is_click: bool,
+
+    // time since the start of the game, in seconds, and positions received for the other car
+    clock: f32,
+    car2_progress: Interpolator<Progress>,
+}
+
+impl Racer {
+    pub fn new() -> Self {
+        let (width, height) = unsafe { (GetScreenWidth(), GetScreenHeight()) };
         let track = Track::new(width, height);
+        let car = Car::new(&track);
+        let car2 = Car::new(&track);
+
+        Racer {
+            track,
+            car,
+            car2,
+            width,
+            height,
+            is_click: false,
+            clock: 0.,
+            car2_progress: Interpolator::new(RENDER_DELAY, MAX_EXTRAPOLATION),
+        }
+    }
+}
+
+impl ClientGame for Racer {
+    fn configure(&mut self, settings: &[u16]) {
+        // acceleration and top speed, in percent of the defaults, as listed by the server
+        if let [acceleration, top_speed] = settings {
+            self.car.accelertion *= *acceleration as c_float / 100.;
+            self.car.speed_max *= *top_speed as c_float / 100.;
+        }
+    }
+
+    fn on_message(&mut self, data: &[u8]) {
+        if data.len() < 5 {
+            return;
+        }
+        let mut track_seg = [0_u8; 1];
+        track_seg.copy_from_slice(&data[..1]);
+        let track_seg = u8::from_le_bytes(track_seg) % TRACK_SEGMENTS;
+        let mut track_t = [0_u8; 4];
+        track_t.copy_from_slice(&data[1..5]);
+        let track_t = c_float::from_le_bytes(track_t);
+        self.car2_progress
+            .push(self.clock, Progress(track_seg as c_float + track_t));
+    }
+
+    fn update(&mut self, dt: f32, input: &Input) -> Option<Vec<u8>> {
+        // the other car moves every frame, between the positions received
+        self.clock += dt;
+        if let Some(progress) = self.car2_progress.sample(self.clock) {
+            self.car2.set_progress(progress, &self.track);
+        }
 
-        let mut car = Car::new(&track);
-        let mut car2 = Car::new(&track);
-        let is_click = false;
+        if input.down {
+            self.car.accelerate();
+        } else {
+            self.car.decelerate();
+        }
+        self.car.new_pos(&self.track);
 
-        SetTargetFPS(60);
+        let mut data = vec![0_u8; 5];
+        data[..1].copy_from_slice(&self.car.track_segment.to_le_bytes());
+        data[1..].copy_from_slice(&self.car.track_t.to_le_bytes());
+        Some(data)
+    }
 
-        while !WindowShouldClose() {
-            draw!({
-                ClearBackground(Color {
+    fn draw(&self) {
+        unsafe {
+            ClearBackground(Color {
+                r: 0,
+                g: 0,
+                b: 0,
+                a: 255,
+            });
+
+            DrawText(
+                raylib_str!(format!(
+                    "App size : {}:{}:{}",
+                    self.width, self.height, self.is_click
+                )),
+                100,
+                100,
+                50,
+                Color {
                     r: 0,
-                    g: 0,
+                    g: 255,
                     b: 0,
                     a: 255,
-                });
-
-                DrawText(
-                    raylib_str!(format!("App size : {width}:{height}:{is_click}")),
-                    100,
-                    100,
-                    50,
-                    Color {
-                        r: 0,
-                        g: 255,
-                        b: 0,
-                        a: 255,
-                    },
-                );
+                },
+            );
 
-                track.draw();
-                car.draw();
-                car2.draw();
-                DrawFPS(10, 10);
-            });
-
-            if IsMouseButtonDown(MouseButton_MOUSE_BUTTON_LEFT.try_into().unwrap()) {
-                car.accelerate();
-            } else {
-                car.decelerate();
-            }
-            car.new_pos();
-            let mut data = [0_u8; network::packet::MAX_DATA_SIZE];
-            let track_seg = car.track_segment.to_le_bytes();
-            let track_t = car.track_t.to_le_bytes();
-            for i in 0..1 {
-                data[i] = track_seg[i];
-            }
-            for i in 0..4 {
-                data[i + 1] = track_t[i];
-            }
-            let _ = network.send(&data);
-
-            if network.recv(&mut data) > 0 {
-                let mut track_seg = [0_u8; 1];
-                for i in 0..1 {
-                    track_seg[i] = data[i];
-                }
-                car2.track_segment = u8::from_le_bytes(track_seg);
-                let mut track_t = [0_u8; 4];
-                for i in 0..4 {
-                    track_t[i] = data[i + 1];
-                }
-                car2.track_t = c_float::from_le_bytes(track_t);
-                car2.new_pos();
-            }
+            self.track.draw();
+            self.car.draw();
+            self.car2.draw();
         }
     }
 }
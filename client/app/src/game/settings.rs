@@ -0,0 +1,48 @@
+/// Settings the host can change before locking the room, as listed by the server in its
+/// catalogue : the server refuses the values out of their bounds and sends the ones chosen back
+/// to every phone with the launch
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Kind {
+    /// 0 or 1
+    Toggle,
+    /// Between `min` and `max` included, changed by `step`
+    Number { min: u16, max: u16, step: u16 },
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Setting {
+    pub name: String,
+    pub kind: Kind,
+    pub default: u16,
+}
+
+impl Setting {
+    /// Value after `value` in the direction of `up`, kept in the bounds
+    pub fn next(&self, value: u16, up: bool) -> u16 {
+        match self.kind {
+            Kind::Toggle => up as u16,
+            Kind::Number { min, max, step } => {
+                if up {
+                    value.saturating_add(step).min(max)
+                } else {
+                    value.saturating_sub(step).max(min)
+                }
+            }
+        }
+    }
+
+    /// Value as shown to the host
+    pub fn text(&self, value: u16) -> String {
+        match self.kind {
+            Kind::Toggle if value != 0 => String::from("on"),
+            Kind::Toggle => String::from("off"),
+            Kind::Number { .. } => format!("{}", value),
+        }
+    }
+}
+
+/// Defaults of the settings of `schema`, in its order
+pub fn defaults(schema: &[Setting]) -> Vec<u16> {
+    schema.iter().map(|s| s.default).collect()
+}
@@ -0,0 +1,186 @@
+use std::collections::VecDeque;
+use std::vec;
+
+//////////////////////////////////////////////
+///
+///
+/// Constants
+///
+///
+//////////////////////////////////////////////
+
+/// Number of snapshots kept to be used as baseline, as many as the server keeps
+const HISTORY_SIZE: usize = 32;
+
+/// Sequence number meaning "no baseline", the snapshot is then complete
+pub const NO_BASELINE: u16 = 0;
+
+//////////////////////////////////////////////
+///
+///
+/// Snapshot
+///
+///
+//////////////////////////////////////////////
+
+/// Entities of one kind, every entity is identified by an id
+#[derive(Clone)]
+pub struct Section {
+    pub entry_size: u8,
+    pub entries: Vec<(u16, Vec<u8>)>,
+}
+
+/// State of the game sent by the server : a small header and sections of entities
+#[derive(Clone)]
+pub struct Snapshot {
+    pub header: Vec<u8>,
+    pub sections: Vec<Section>,
+}
+
+impl Snapshot {
+    /// Entities of the section `index`, empty if the server did not send it
+    pub fn section(&self, index: usize) -> &[(u16, Vec<u8>)] {
+        match self.sections.get(index) {
+            Some(s) => &s.entries,
+            None => &[],
+        }
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Buffer
+///
+///
+//////////////////////////////////////////////
+
+/// Snapshots received, the server encodes each one against the last one we acknowledged
+pub struct SnapshotBuffer {
+    received: VecDeque<(u16, Snapshot)>,
+}
+
+impl SnapshotBuffer {
+    pub fn new() -> Self {
+        SnapshotBuffer {
+            received: VecDeque::with_capacity(HISTORY_SIZE),
+        }
+    }
+
+    /// Sequence number of the last snapshot received, to acknowledge to the server
+    pub fn last_seq(&self) -> u16 {
+        match self.received.back() {
+            Some((seq, _)) => *seq,
+            None => NO_BASELINE,
+        }
+    }
+
+    /// Decode a snapshot and return it complete, None if it is malformed or its baseline is lost
+    pub fn receive(&mut self, data: &[u8]) -> Option<&Snapshot> {
+        let snapshot = self.decode(data)?;
+        if self.received.len() == HISTORY_SIZE {
+            self.received.pop_front();
+        }
+        self.received.push_back(snapshot);
+        self.received.back().map(|(_, s)| s)
+    }
+
+    fn decode(&self, data: &[u8]) -> Option<(u16, Snapshot)> {
+        let mut reader = Reader {
+            data: data,
+            index: 0,
+        };
+        let seq = reader.u16()?;
+        let baseline_seq = reader.u16()?;
+        let baseline = if baseline_seq == NO_BASELINE {
+            None
+        } else {
+            Some(
+                self.received
+                    .iter()
+                    .find(|(s, _)| *s == baseline_seq)
+                    .map(|(_, b)| b)?,
+            )
+        };
+
+        let header_len = reader.u8()? as usize;
+        let header = reader.bytes(header_len)?.to_vec();
+
+        let section_count = reader.u8()? as usize;
+        let mut sections = vec::Vec::with_capacity(section_count);
+        for i in 0..section_count {
+            let entry_size = reader.u8()?;
+            let mut section = match baseline.and_then(|b| b.sections.get(i)) {
+                Some(b) if b.entry_size == entry_size => b.clone(),
+                _ => Section {
+                    entry_size: entry_size,
+                    entries: vec::Vec::new(),
+                },
+            };
+
+            let upserts = reader.u16()?;
+            for _ in 0..upserts {
+                let id = reader.u16()?;
+                let entry = reader.bytes(entry_size as usize)?.to_vec();
+                match section.entries.iter_mut().position(|&mut (i, _)| i == id) {
+                    Some(index) => section.entries[index].1 = entry,
+                    None => section.entries.push((id, entry)),
+                }
+            }
+            let removed = reader.u16()?;
+            for _ in 0..removed {
+                let id = reader.u16()?;
+                section.entries.retain(|&(i, _)| i != id);
+            }
+            sections.push(section);
+        }
+
+        Some((
+            seq,
+            Snapshot {
+                header: header,
+                sections: sections,
+            },
+        ))
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Helpers
+///
+///
+//////////////////////////////////////////////
+
+struct Reader<'a> {
+    data: &'a [u8],
+    index: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.index + n > self.data.len() {
+            return None;
+        }
+        let res = &self.data[self.index..self.index + n];
+        self.index += n;
+        Some(res)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let b = self.bytes(2)?;
+        Some(u16::from_be_bytes([b[0], b[1]]))
+    }
+}
+
+/// Read the big endian f32 number `index` of an entry
+pub fn read_f32(entry: &[u8], index: usize) -> f32 {
+    let mut buffer = [0_u8; 4];
+    buffer.copy_from_slice(&entry[index * 4..index * 4 + 4]);
+    f32::from_be_bytes(buffer)
+}
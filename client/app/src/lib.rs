@@ -31,10 +31,12 @@ mod network;
 mod ui;
 use ui::button;
 use ui::button::Draw;
+use ui::chat::{ChatOverlay, Pick};
 use ui::colors;
 use ui::keyboard::Keyboard;
-use ui::text::waiting_text;
-use game::Game;
+use ui::settings::SettingsEditor;
+use ui::text::{leaderboard_text, paused_text, place_text, ready_text, roster_text, waiting_text};
+use game::{ClientGame, Game};
 
 // Main function
 #[no_mangle]
@@ -125,6 +127,14 @@ fn main_rust() {
 
         let mut network = network.unwrap();
 
+        // games of the server this phone can play, in the order of its catalogue
+        let catalogue: Vec<network::packet::Listing> = network
+            .catalogue()
+            .iter()
+            .filter(|l| Game::from(l.game).create_game().is_some())
+            .cloned()
+            .collect();
+
         keyboard.reset_value();
 
         let mut room = 0;
@@ -135,13 +145,47 @@ fn main_rust() {
         let mut game_chosen = Game::Unknown;
         let mut page_selection = 0;
 
+        // settings of the game chosen by the host, and the last message of the server
+        let mut editor = SettingsEditor::new(Vec::new());
+        let mut notice = String::new();
+
+        let mut chat = ChatOverlay::new();
+
+        // game of the catalogue whose leaderboard is shown on the home screen, if any
+        let mut scores: Option<usize> = None;
+        let mut standings = Vec::new();
+
         while !WindowShouldClose() {
+            if let Some(message) = network.messages().pop() {
+                notice = message;
+            }
+            chat.push(network.chat(), network.roster());
+            chat.update(GetFrameTime());
             draw!({
                 ClearBackground(colors::BLACK);
 
                 match network.get_status() {
                     network::Status::Connected => {
-                        if want_join {
+                        if let Some(board) = network.leaderboard() {
+                            standings = board;
+                        }
+                        if let Some(shown) = scores {
+                            let title = &catalogue[shown].name;
+                            leaderboard_text(screen_height, screen_width, title, &standings);
+                            let next_page = button::next_page(screen_height, screen_width);
+                            let back = button::back(screen_height, screen_width);
+                            next_page.draw();
+                            back.draw();
+                            if next_page.click() {
+                                let next = (shown + 1) % catalogue.len();
+                                scores = Some(next);
+                                standings = Vec::new();
+                                let _ = network.query_leaderboard(Game::from(catalogue[next].game));
+                            }
+                            if back.click() {
+                                scores = None;
+                            }
+                        } else if want_join {
                             let mut val = keyboard.get_value();
                             DrawText(
                                 raylib_str!(format!("Room ID :")),
@@ -162,9 +206,10 @@ fn main_rust() {
 
                             if val.matches(".").count() > 0 {
                                 val.pop();
-                                let room = val.parse().unwrap();
+                                let code = val.parse().unwrap_or(0);
                                 keyboard.reset_value();
-                                network.join_room(room).unwrap();
+                                // a wrong code leaves the phone on the keyboard to try again
+                                let _ = network.join_room(code);
                             }
                         } else {
                             DrawText(
@@ -184,9 +229,11 @@ fn main_rust() {
 
                             let create_room = button::create_room(screen_height, screen_width);
                             let join_room = button::join_room(screen_height, screen_width);
+                            let show_scores = button::scores(screen_height, screen_width);
 
                             create_room.draw();
                             join_room.draw();
+                            show_scores.draw();
 
                             if create_room.click() {
                                 is_host = true;
@@ -195,6 +242,11 @@ fn main_rust() {
                             if join_room.click() {
                                 want_join = true;
                             }
+                            if show_scores.click() && !catalogue.is_empty() {
+                                scores = Some(0);
+                                standings = Vec::new();
+                                let _ = network.query_leaderboard(Game::from(catalogue[0].game));
+                            }
                         }
                     }
                     network::Status::Disconnected => {
@@ -207,7 +259,54 @@ fn main_rust() {
                         );
                     }
                     network::Status::InRoom => {
-                        if is_host {
+                        if let (true, Some(listing)) = (
+                            is_host,
+                            catalogue.iter().find(|l| Game::from(l.game) == game_chosen),
+                        ) {
+                            DrawText(
+                                raylib_str!(format!("{} :", listing.name)),
+                                ((screen_width as f32) * (1. / 9.)) as c_int,
+                                ((screen_height as f32) * (1. / 13.)) as c_int,
+                                ((screen_height as f32) * (1. / 13.)) as c_int,
+                                colors::YELLOW,
+                            );
+                            DrawText(
+                                raylib_str!(listing.description.clone()),
+                                ((screen_width as f32) * (1. / 20.)) as c_int,
+                                ((screen_height as f32) * (2.05 / 13.)) as c_int,
+                                ((screen_height as f32) * (0.35 / 13.)) as c_int,
+                                colors::WHITE,
+                            );
+                            DrawText(
+                                raylib_str!(format!(
+                                    "{} to {} players",
+                                    listing.min_players, listing.max_players
+                                )),
+                                ((screen_width as f32) * (1. / 20.)) as c_int,
+                                ((screen_height as f32) * (2.5 / 13.)) as c_int,
+                                ((screen_height as f32) * (0.35 / 13.)) as c_int,
+                                colors::WHITE,
+                            );
+                            DrawText(
+                                raylib_str!(notice.clone()),
+                                ((screen_width as f32) * (1. / 20.)) as c_int,
+                                ((screen_height as f32) * (3. / 13.)) as c_int,
+                                ((screen_height as f32) * (0.4 / 13.)) as c_int,
+                                colors::RED,
+                            );
+                            editor.update(screen_height, screen_width);
+                            let lock = button::lock(screen_height, screen_width);
+                            let back = button::back(screen_height, screen_width);
+                            lock.draw();
+                            back.draw();
+                            if lock.click() {
+                                notice.clear();
+                                network.lock_room_with(game_chosen, editor.values()).unwrap();
+                            }
+                            if back.click() {
+                                game_chosen = Game::Unknown;
+                            }
+                        } else if is_host {
                             DrawText(
                                 raylib_str!(format!("Room ID :")),
                                 ((screen_width as f32) * (1.5 / 9.)) as c_int,
@@ -223,63 +322,44 @@ fn main_rust() {
                                 colors::YELLOW,
                             );
 
-                            let game_id = 3*page_selection + 1;
-
-                            if Game::from(game_id+3) == Game::Unknown {
-                                let top = button::top(screen_height, screen_width, game::title(game_id));
-                                top.draw();
-                                if top.click() {
-                                    game_chosen = Game::from(game_id);
-                                }
-
-                                let mid_game = Game::from(game_id+1);
-                                if mid_game != Game::Unknown {
-                                    let middle = button::mid(screen_height, screen_width, game::title(game_id+1));
-                                    middle.draw();
-                                    if middle.click() {
-                                        game_chosen = Game::from(game_id+1);
-                                    }
-                                }
-
-                                let bottom_game = Game::from(game_id+2);
-                                if bottom_game != Game::Unknown {
-                                    let bottom = button::bottom(screen_height, screen_width, game::title(game_id+2));
-                                    bottom.draw();
-                                    if bottom.click() {
-                                        game_chosen = Game::from(game_id+2);
-                                    }
+                            let page = catalogue.iter().skip(3 * page_selection).take(3);
+                            for (slot, listing) in page.enumerate() {
+                                let text = format!(
+                                    "{} ({}-{})",
+                                    listing.name, listing.min_players, listing.max_players
+                                );
+                                let choice = match slot {
+                                    0 => button::top(screen_height, screen_width, text),
+                                    1 => button::mid(screen_height, screen_width, text),
+                                    _ => button::bottom(screen_height, screen_width, text),
+                                };
+                                choice.draw();
+                                if choice.click() {
+                                    game_chosen = Game::from(listing.game);
+                                    editor = SettingsEditor::new(listing.settings.clone());
                                 }
-                            } else {
-                                let top = button::top(screen_height, screen_width, game::title(game_id));
-                                let middle = button::mid(screen_height, screen_width, game::title(game_id+1));
-                                let bottom = button::bottom(screen_height, screen_width, game::title(game_id+2));
+                            }
+                            if catalogue.len() > 3 * (page_selection + 1) {
                                 let next_page = button::next_page(screen_height, screen_width);
-
-                                top.draw();
-                                middle.draw();
-                                bottom.draw();
                                 next_page.draw();
-
-                                if top.click() {
-                                    game_chosen = Game::from(game_id);
-                                }
-                                if middle.click() {
-                                    game_chosen = Game::from(game_id+1);
-                                }
-                                if bottom.click() {
-                                    game_chosen = Game::from(game_id+2);
-                                }
-
                                 if next_page.click() {
                                     page_selection = page_selection + 1;
                                 }
                             }
-                            if game_chosen != Game::Unknown{
-                                network.lock_room(game_chosen).unwrap();
-                            }
-                        } else {
+                        } else if network.roster().is_empty() {
                             waiting_text(screen_height, screen_width)
+                        } else {
+                            let nicknames: Vec<String> = network
+                                .roster()
+                                .iter()
+                                .map(|m| m.nickname.clone())
+                                .collect();
+                            roster_text(screen_height, screen_width, &nicknames)
+                        }
+                        if let Some(place) = network.last_place() {
+                            place_text(screen_height, screen_width, place);
                         }
+                        chat_overlay(&chat, &mut network, screen_height, screen_width);
                     }
                     network::Status::InLockRoom(n) => {
                         DrawText(
@@ -303,35 +383,140 @@ fn main_rust() {
                             ((screen_height as f32) * (2. / 13.)) as c_int,
                             colors::PURPLE,
                         );
-                        if is_host {
+                        let roster = network.roster();
+                        let ready = roster.iter().skip(1).filter(|m| m.ready).count();
+                        // the server sets everyone unready when the players are ranked again
+                        let is_ready = roster.iter().any(|m| m.rank == Some(n) && m.ready);
+                        let players = roster.len().saturating_sub(1);
+                        let countdown = network.countdown();
+                        ready_text(screen_height, screen_width, ready, players, countdown);
+                        if countdown.is_some() {
+                            // the game is about to start, nothing left to choose
+                        } else if is_host {
                             let start_game = button::start_game(screen_height, screen_width);
+                            let force_start = button::force_start(screen_height, screen_width);
                             start_game.draw();
+                            force_start.draw();
                             /*if button::start_game().colision() {
                                 button::start_game().change_foreground_color(colors::BLUE);
                             };*/
                             if start_game.click() {
                                 network.launch_game().unwrap();
                             }
+                            if force_start.click() {
+                                network.force_launch_game().unwrap();
+                            }
+                        } else {
+                            let ready_button = button::ready(screen_height, screen_width, is_ready);
+                            ready_button.draw();
+                            if ready_button.click() {
+                                network.set_ready(!is_ready).unwrap();
+                            }
                         }
+                        chat_overlay(&chat, &mut network, screen_height, screen_width);
                     }
-                    network::Status::InGame(game_id) => {
-                        game_chosen = Game::from(game_id);
-                        break;
-                    }
+                    // the game is played once the frame is drawn
+                    network::Status::InGame(_) => {}
                 }
             });
             DrawFPS(10, 10);
-        }
-        game::maze_fight::main_game(&mut network);
 
-        /* 
-        match game_chosen {
-            Game::MazeFight => game::maze_fight::main_game(&mut network),
-            _ => {},
-        }*/
+            // the phones come back to the room after each game
+            if let network::Status::InGame(game_id) = network.get_status() {
+                match Game::from(game_id).create_game() {
+                    Some(mut game_struct) => {
+                        play(&mut network, game_struct.game(), &mut chat, is_host)
+                    }
+                    None => play(&mut network, &mut game::Unsupported, &mut chat, is_host),
+                }
+                game_chosen = Game::Unknown;
+            }
+        }
 
         CloseWindow();
     }
 
     // UnloadTexture(tex_bunny);
 }
+
+/// Last lines of the chat, and the buttons to send one, drawn over the room screens
+unsafe fn chat_overlay(
+    chat: &ChatOverlay,
+    network: &mut network::Network,
+    screen_height: i32,
+    screen_width: i32,
+) {
+    chat.draw(screen_height, screen_width);
+    match ChatOverlay::buttons(screen_height, screen_width) {
+        Some(Pick::Line(line)) => {
+            let _ = network.send_chat(line);
+        }
+        Some(Pick::Emote(emote)) => {
+            let _ = network.send_emote(emote);
+        }
+        None => {}
+    }
+}
+
+/// Frame loop of a running game : the game only implements the hooks of `ClientGame`
+/// The chat is only shown, the touches belong to the game, but for the pause button of the host
+/// The phone holds the game paused while it is in the background
+/// It returns once the server ends the game, or when the window is closed
+unsafe fn play(
+    network: &mut network::Network,
+    game: &mut dyn ClientGame,
+    chat: &mut ChatOverlay,
+    is_host: bool,
+) {
+    game.configure(network.settings());
+    game.load_assets();
+
+    let mut buffer = [0_u8; network::packet::MAX_DATA_SIZE];
+    let mut focused = true;
+    while !WindowShouldClose() {
+        loop {
+            let n = network.recv(&mut buffer);
+            if n == 0 {
+                break;
+            }
+            game.on_message(&buffer[..n]);
+        }
+        if let network::Status::InRoom = network.get_status() {
+            break;
+        }
+
+        if IsWindowFocused() != focused {
+            focused = !focused;
+            let _ = network.pause_game(!focused);
+        }
+        let paused = network.is_paused();
+        let pause_button = button::pause(GetScreenHeight(), GetScreenWidth(), paused);
+        if is_host && pause_button.click() {
+            let _ = network.pause_game(!paused);
+        }
+
+        // the game stands still while paused, the touches are not sent
+        if !paused {
+            let input = game::Input::poll();
+            if let Some(data) = game.update(GetFrameTime(), &input) {
+                let _ = network.send(&data);
+            }
+        }
+        chat.push(network.chat(), network.roster());
+        chat.update(GetFrameTime());
+
+        draw!({
+            game.draw();
+            if paused {
+                paused_text(GetScreenHeight(), GetScreenWidth(), network.countdown());
+            }
+            if is_host {
+                pause_button.draw();
+            }
+            chat.draw(GetScreenHeight(), GetScreenWidth());
+            DrawFPS(10, 10);
+        });
+    }
+
+    game.unload();
+}
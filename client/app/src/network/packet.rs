@@ -6,6 +6,8 @@ use std::net::TcpStream;
 use std::thread;
 use std::time::{self, SystemTime};
 
+use crate::game::settings::{Kind, Setting};
+
 //////////////////////////////////////////////
 ///
 ///
@@ -14,8 +16,8 @@ use std::time::{self, SystemTime};
 ///
 //////////////////////////////////////////////
 
-pub const HEADER_SIZE: usize = 12;
-pub const MAX_DATA_SIZE: usize = 2036;
+pub const HEADER_SIZE: usize = 20;
+pub const MAX_DATA_SIZE: usize = 2028;
 pub const BUFFER_SIZE: usize = HEADER_SIZE + MAX_DATA_SIZE;
 
 //////////////////////////////////////////////
@@ -33,6 +35,9 @@ pub enum ProtocolError {
     RoomClosed,
     InvalidPacket,
     InvalidRequest,
+    Timeout,
+    Flooding,
+    Unauthorized,
     Unknown,
 }
 
@@ -45,6 +50,9 @@ impl From<u8> for ProtocolError {
             3 => Self::RoomClosed,
             4 => Self::InvalidPacket,
             5 => Self::InvalidRequest,
+            6 => Self::Timeout,
+            7 => Self::Flooding,
+            8 => Self::Unauthorized,
             _ => Self::Unknown,
         }
     }
@@ -58,6 +66,9 @@ impl From<ProtocolError> for u8 {
             ProtocolError::RoomClosed => 3 | 0x80_u8,
             ProtocolError::InvalidPacket => 4 | 0x80_u8,
             ProtocolError::InvalidRequest => 5 | 0x80_u8,
+            ProtocolError::Timeout => 6 | 0x80_u8,
+            ProtocolError::Flooding => 7 | 0x80_u8,
+            ProtocolError::Unauthorized => 8 | 0x80_u8,
             ProtocolError::Unknown => 0xff_u8,
         }
     }
@@ -71,6 +82,9 @@ impl Display for ProtocolError {
             ProtocolError::RoomClosed => write!(f, "RoomClosed"),
             ProtocolError::InvalidPacket => write!(f, "InvalidPacket"),
             ProtocolError::InvalidRequest => write!(f, "InvalidRequest"),
+            ProtocolError::Timeout => write!(f, "Timeout"),
+            ProtocolError::Flooding => write!(f, "Flooding"),
+            ProtocolError::Unauthorized => write!(f, "Unauthorized"),
             ProtocolError::Unknown => write!(f, "Unknown"),
         }
     }
@@ -93,6 +107,14 @@ pub enum Flag {
     Lock,
     Launch,
     Transmit,
+    Message,
+    Roster,
+    Ready,
+    Countdown,
+    Chat,
+    End,
+    Leaderboard,
+    Pause,
     Unknown,
 }
 
@@ -108,6 +130,14 @@ impl From<u8> for Flag {
                 4 => Flag::Lock,
                 5 => Flag::Launch,
                 6 => Flag::Transmit,
+                7 => Flag::Message,
+                8 => Flag::Roster,
+                9 => Flag::Ready,
+                10 => Flag::Countdown,
+                11 => Flag::Chat,
+                12 => Flag::End,
+                13 => Flag::Leaderboard,
+                14 => Flag::Pause,
                 _ => Flag::Unknown,
             }
         }
@@ -124,6 +154,14 @@ impl From<Flag> for u8 {
             Flag::Lock => 4,
             Flag::Launch => 5,
             Flag::Transmit => 6,
+            Flag::Message => 7,
+            Flag::Roster => 8,
+            Flag::Ready => 9,
+            Flag::Countdown => 10,
+            Flag::Chat => 11,
+            Flag::End => 12,
+            Flag::Leaderboard => 13,
+            Flag::Pause => 14,
             Flag::Unknown => 0xff_u8,
         }
     }
@@ -138,6 +176,14 @@ impl Display for Flag {
             Flag::Lock => write!(f, "Lock"),
             Flag::Launch => write!(f, "Launch"),
             Flag::Transmit => write!(f, "Transmit"),
+            Flag::Message => write!(f, "Message"),
+            Flag::Roster => write!(f, "Roster"),
+            Flag::Ready => write!(f, "Ready"),
+            Flag::Countdown => write!(f, "Countdown"),
+            Flag::Chat => write!(f, "Chat"),
+            Flag::End => write!(f, "End"),
+            Flag::Leaderboard => write!(f, "Leaderboard"),
+            Flag::Pause => write!(f, "Pause"),
             Flag::Error(e) => write!(f, "Error : {}", e),
             Flag::Unknown => write!(f, "Unknown"),
         }
@@ -156,6 +202,7 @@ impl Display for Flag {
 #[derive(Clone, Copy, Debug)]
 pub enum Version {
     V0 = 0x0,
+    V1 = 0x1,
     Unknown = 0xf,
 }
 
@@ -163,6 +210,7 @@ impl From<u8> for Version {
     fn from(orig: u8) -> Self {
         match orig {
             0x00 => Version::V0,
+            0x01 => Version::V1,
             _ => Version::Unknown,
         }
     }
@@ -172,11 +220,237 @@ impl From<Version> for u8 {
     fn from(val: Version) -> Self {
         match val {
             Version::V0 => 0x00,
+            Version::V1 => 0x01,
             Version::Unknown => 0xff,
         }
     }
 }
 
+//////////////////////////////////////////////
+///
+///
+/// Roster
+///
+///
+//////////////////////////////////////////////
+
+/// Longest nickname, in bytes
+pub const NICKNAME_SIZE: usize = 16;
+
+/// Player of the room, as sent in the data of the Roster packet :
+/// count (u8) then for each player
+/// session (u16) | rank (u8, 0xff before the room is locked) | avatar (u8) | ready (u8) | nickname size (u8) | nickname
+#[derive(Debug, Clone, PartialEq)]
+pub struct Member {
+    pub session: u16,
+    pub rank: Option<u8>,
+    pub avatar: u8,
+    pub ready: bool,
+    pub nickname: String,
+}
+
+impl Member {
+    pub fn unpack_roster(data: &[u8]) -> Result<Vec<Member>, Error> {
+        let too_short = || Error::new(ErrorKind::InvalidData, "roster too short");
+        let (&count, mut data) = data.split_first().ok_or_else(too_short)?;
+        let mut members = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            if data.len() < 6 || data.len() < 6 + data[5] as usize {
+                return Err(too_short());
+            }
+            let size = data[5] as usize;
+            members.push(Member {
+                session: ((data[0] as u16) << 8) + data[1] as u16,
+                rank: if data[2] == 0xff { None } else { Some(data[2]) },
+                avatar: data[3],
+                ready: data[4] != 0,
+                nickname: String::from_utf8_lossy(&data[6..6 + size]).into_owned(),
+            });
+            data = &data[6 + size..];
+        }
+        Ok(members)
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Chat
+///
+///
+//////////////////////////////////////////////
+
+/// Longest chat line, in bytes
+pub const CHAT_SIZE: usize = 140;
+
+/// Chat line or emote of a player, as sent in the data of the Chat packet :
+/// session (u16, set by the server) | emote (u8, 0xff for none) | text (UTF-8, up to CHAT_SIZE bytes)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chat {
+    pub session: u16,
+    pub emote: Option<u8>,
+    pub text: String,
+}
+
+impl Chat {
+    pub fn pack(&self) -> Vec<u8> {
+        let mut data = vec![(self.session >> 8) as u8, self.session as u8];
+        data.push(self.emote.unwrap_or(0xff));
+        data.extend_from_slice(self.text.as_bytes());
+        data
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Chat, Error> {
+        if data.len() < 3 {
+            return Err(Error::new(ErrorKind::InvalidData, "chat too short"));
+        }
+        Ok(Chat {
+            session: ((data[0] as u16) << 8) + data[1] as u16,
+            emote: if data[2] == 0xff { None } else { Some(data[2]) },
+            text: String::from_utf8_lossy(&data[3..]).into_owned(),
+        })
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Leaderboard
+///
+///
+//////////////////////////////////////////////
+
+/// All-time statistics of a player at a game, as sent in the data of the Leaderboard packet :
+/// count (u8) then for each player, best first
+/// played (u32) | wins (u32) | kills (u32) | best lap (u32, in ms, 0xffffffff for none) | nickname size (u8) | nickname
+#[derive(Debug, Clone, PartialEq)]
+pub struct Standing {
+    pub nickname: String,
+    pub played: u32,
+    pub wins: u32,
+    pub kills: u32,
+    pub best_lap: Option<time::Duration>,
+}
+
+impl Standing {
+    pub fn unpack_leaderboard(data: &[u8]) -> Result<Vec<Standing>, Error> {
+        let too_short = || Error::new(ErrorKind::InvalidData, "leaderboard too short");
+        let (&count, mut data) = data.split_first().ok_or_else(too_short)?;
+        let mut standings = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            if data.len() < 17 || data.len() < 17 + data[16] as usize {
+                return Err(too_short());
+            }
+            let field = |i: usize| {
+                ((data[i] as u32) << 24)
+                    + ((data[i + 1] as u32) << 16)
+                    + ((data[i + 2] as u32) << 8)
+                    + data[i + 3] as u32
+            };
+            let size = data[16] as usize;
+            standings.push(Standing {
+                played: field(0),
+                wins: field(4),
+                kills: field(8),
+                best_lap: if field(12) == 0xffff_ffff {
+                    None
+                } else {
+                    Some(time::Duration::from_millis(field(12) as u64))
+                },
+                nickname: String::from_utf8_lossy(&data[17..17 + size]).into_owned(),
+            });
+            data = &data[17 + size..];
+        }
+        Ok(standings)
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Catalogue
+///
+///
+//////////////////////////////////////////////
+
+/// A game run by the server, as listed in the data of the Init packet answering the handshake :
+/// count (u8) then for each game
+/// id (u16) | min players (u8) | max players (u8) | name | description | settings count (u8) | settings
+/// setting : kind (u8, 0 for a toggle) | default (u16) | min (u16) | max (u16) | step (u16) | name
+/// The texts are sent after their size (u8)
+#[derive(Clone)]
+pub struct Listing {
+    pub game: u16,
+    pub name: String,
+    pub description: String,
+    pub min_players: u8,
+    pub max_players: u8,
+    pub settings: Vec<Setting>,
+}
+
+impl Listing {
+    pub fn unpack_catalogue(data: &[u8]) -> Result<Vec<Listing>, Error> {
+        let too_short = || Error::new(ErrorKind::InvalidData, "catalogue too short");
+        let (&count, mut data) = data.split_first().ok_or_else(too_short)?;
+        let mut listings = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            if data.len() < 4 {
+                return Err(too_short());
+            }
+            let (head, rest) = data.split_at(4);
+            data = rest;
+            let name = unpack_label(&mut data)?;
+            let description = unpack_label(&mut data)?;
+            let (&setting_count, rest) = data.split_first().ok_or_else(too_short)?;
+            data = rest;
+            let mut settings = Vec::with_capacity(setting_count as usize);
+            for _ in 0..setting_count {
+                if data.len() < 9 {
+                    return Err(too_short());
+                }
+                let (fields, rest) = data.split_at(9);
+                data = rest;
+                let field = |i: usize| ((fields[i] as u16) << 8) + fields[i + 1] as u16;
+                let kind = match fields[0] {
+                    0 => Kind::Toggle,
+                    _ => Kind::Number {
+                        min: field(3),
+                        max: field(5),
+                        step: field(7),
+                    },
+                };
+                settings.push(Setting {
+                    name: unpack_label(&mut data)?,
+                    kind,
+                    default: field(1),
+                });
+            }
+            listings.push(Listing {
+                game: ((head[0] as u16) << 8) + head[1] as u16,
+                name,
+                description,
+                min_players: head[2],
+                max_players: head[3],
+                settings,
+            });
+        }
+        Ok(listings)
+    }
+}
+
+/// Read a text sent after its size and move `data` past it
+fn unpack_label(data: &mut &[u8]) -> Result<String, Error> {
+    let too_short = || Error::new(ErrorKind::InvalidData, "text too short");
+    let (&size, rest) = data.split_first().ok_or_else(too_short)?;
+    let size = size as usize;
+    if size > rest.len() {
+        return Err(too_short());
+    }
+    let text = String::from_utf8_lossy(&rest[..size]).into_owned();
+    *data = &rest[size..];
+    Ok(text)
+}
+
 //////////////////////////////////////////////
 ///
 ///
@@ -194,6 +468,8 @@ pub struct Packet {
     pub option: u16,
     pub session: u16,
     pub room: u16,
+    // secret of the session, given by the server in the handshake and checked on every packet
+    pub secret: u64,
     pub data: [u8; MAX_DATA_SIZE],
 
     processed_time: SystemTime,
@@ -225,18 +501,25 @@ impl Packet {
             data[..size].copy_from_slice(raw_data);
         }
         Packet {
-            version: Version::V0,
+            version: Version::V1,
             flag,
             sync,
             size,
             option,
             session,
             room,
+            secret: 0,
             data,
             processed_time: SystemTime::now(),
         }
     }
 
+    /// The same packet, signed with the secret of the session
+    pub fn with_secret(mut self, secret: u64) -> Packet {
+        self.secret = secret;
+        self
+    }
+
     /// Receive and unpack a packet, it will be blocking until it receives a packet or the pipe is procken
     pub fn recv_packet(stream: &mut TcpStream) -> Result<Self, Error> {
         let mut buffer = [0_u8; BUFFER_SIZE];
@@ -339,7 +622,11 @@ impl Packet {
     /// Create a packet from raw data
     fn unpack(packet: &[u8; BUFFER_SIZE]) -> Result<Self, Error> {
         match packet[0].into() {
-            Version::V0 => Self::process_v0_packet(packet),
+            Version::V1 => Self::process_v1_packet(packet),
+            Version::V0 => Err(Error::new(
+                ErrorKind::InvalidData,
+                "outdated packet : version 0 is not supported anymore",
+            )),
             _ => Err(Error::new(
                 ErrorKind::InvalidData,
                 "non-standard packet : not recognized version",
@@ -356,6 +643,7 @@ impl Packet {
         Self::pack_u16(self.option, &mut packet[6..8]);
         Self::pack_u16(self.session, &mut packet[8..10]);
         Self::pack_u16(self.room, &mut packet[10..12]);
+        packet[12..20].copy_from_slice(&self.secret.to_be_bytes());
         packet[HEADER_SIZE..].clone_from_slice(self.data.as_slice());
     }
 
@@ -367,7 +655,7 @@ impl Packet {
     ///
     //////////////////////////////////////////////
 
-    fn process_v0_packet(packet: &[u8; BUFFER_SIZE]) -> Result<Self, Error> {
+    fn process_v1_packet(packet: &[u8; BUFFER_SIZE]) -> Result<Self, Error> {
         let mut data = [0_u8; MAX_DATA_SIZE];
         let size = Packet::unpack_u16(&packet[4..6]) as usize;
 
@@ -380,13 +668,14 @@ impl Packet {
         }
 
         Ok(Packet {
-            version: Version::V0,
+            version: Version::V1,
             flag: packet[1].into(),
             sync: packet[2],
             size,
             option: Packet::unpack_u16(&packet[6..8]),
             session: Packet::unpack_u16(&packet[8..10]),
             room: Packet::unpack_u16(&packet[10..12]),
+            secret: u64::from_be_bytes(<[u8; 8]>::try_from(&packet[12..20]).unwrap()),
             data,
 
             processed_time: SystemTime::now(),
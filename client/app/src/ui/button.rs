@@ -182,6 +182,51 @@ pub fn join_room(screen_height: i32, screen_width: i32) -> Button {
     )
 }
 
+/// button of the home screen showing the leaderboards
+pub fn scores(screen_height: i32, screen_width: i32) -> Button {
+    Button::new(
+        raylib::Rectangle {
+            x: ((screen_width as f32) * (1. / 5.)) as c_float,
+            y: ((screen_height as f32) * (10. / 11.)) as c_float,
+            width: ((screen_width as f32) * (3. / 5.)) as c_float,
+            height: ((screen_height as f32) * (0.8 / 11.)) as c_float,
+        },
+        Style::new(colors::WHITE, colors::BLUE),
+        Some(format!("Scores")),
+        1. / 5. * (screen_width as f32) as c_float,
+    )
+}
+
+/// button leaving the leaderboards for the home screen
+pub fn back(screen_height: i32, screen_width: i32) -> Button {
+    Button::new(
+        raylib::Rectangle {
+            x: ((screen_width as f32) * (1. / 5.)) as c_float,
+            y: ((screen_height as f32) * (11. / 13.)) as c_float,
+            width: ((screen_width as f32) * (2. / 5.)) as c_float,
+            height: ((screen_height as f32) * (1. / 13.)) as c_float,
+        },
+        Style::new(colors::WHITE, colors::RED),
+        Some(format!("Back")),
+        1. / 10. * (screen_width as f32) as c_float,
+    )
+}
+
+/// button of the host locking the room with the settings chosen
+pub fn lock(screen_height: i32, screen_width: i32) -> Button {
+    Button::new(
+        raylib::Rectangle {
+            x: ((screen_width as f32) * (1. / 5.)) as c_float,
+            y: ((screen_height as f32) * (9.5 / 13.)) as c_float,
+            width: ((screen_width as f32) * (3. / 5.)) as c_float,
+            height: ((screen_height as f32) * (1.2 / 13.)) as c_float,
+        },
+        Style::new(colors::WHITE, colors::GREEN),
+        Some(format!("Lock")),
+        1. / 5. * (screen_width as f32) as c_float,
+    )
+}
+
 /*
 pub fn game_select() -> Button {
     Button::new(
@@ -210,6 +255,61 @@ pub fn start_game(screen_height: i32, screen_width: i32) -> Button {
     )
 }
 
+/// button of the host launching the game even if some players aren't ready
+pub fn force_start(screen_height: i32, screen_width: i32) -> Button {
+    Button::new(
+        raylib::Rectangle {
+            x: ((screen_width as f32) * (1. / 5.)) as c_float,
+            y: (screen_height as f32) * (9. / 13.) as c_float,
+            width: ((screen_width as f32) * (3. / 5.)) as c_float,
+            height: ((screen_height as f32) * (1.5 / 13.)) as c_float,
+        },
+        Style::new(colors::WHITE, colors::RED),
+        Some(format!("Force")),
+        1. / 7. * (screen_width as f32) as c_float,
+    )
+}
+
+/// button of the other players, toggling whether they are ready
+pub fn ready(screen_height: i32, screen_width: i32, ready: bool) -> Button {
+    let (color, text) = if ready {
+        (colors::GREEN, "Ready !")
+    } else {
+        (colors::ORANGE, "Ready ?")
+    };
+    Button::new(
+        raylib::Rectangle {
+            x: ((screen_width as f32) * (1. / 5.)) as c_float,
+            y: (screen_height as f32) * (7. / 13.) as c_float,
+            width: ((screen_width as f32) * (3. / 5.)) as c_float,
+            height: ((screen_height as f32) * (1.5 / 13.)) as c_float,
+        },
+        Style::new(colors::WHITE, color),
+        Some(format!("{}", text)),
+        1. / 9. * (screen_width as f32) as c_float,
+    )
+}
+
+/// button of the host pausing the game running, or letting it go on
+pub fn pause(screen_height: i32, screen_width: i32, paused: bool) -> Button {
+    let (color, text) = if paused {
+        (colors::GREEN, "Resume")
+    } else {
+        (colors::ORANGE, "Pause")
+    };
+    Button::new(
+        raylib::Rectangle {
+            x: ((screen_width as f32) * (3. / 5.)) as c_float,
+            y: ((screen_height as f32) * (0.3 / 13.)) as c_float,
+            width: ((screen_width as f32) * (1.5 / 5.)) as c_float,
+            height: ((screen_height as f32) * (0.8 / 13.)) as c_float,
+        },
+        Style::new(colors::WHITE, color),
+        Some(format!("{}", text)),
+        1. / 12. * (screen_width as f32) as c_float,
+    )
+}
+
 /// top button on game select
 pub fn top(screen_height: i32, screen_width: i32, text: String) -> Button {
     Button::new(
@@ -0,0 +1,103 @@
+use crate::network::packet;
+use crate::ui::button::{Button, Draw, Style};
+use crate::ui::colors;
+use raylib::{raylib_str, DrawText};
+use std::ffi::{c_char, c_float, c_int};
+
+/// Lines sent in one touch, the keyboard of the app only has digits
+const QUICK_LINES: [&str; 4] = ["Wait !", "Go !", "GG", "Oops"];
+
+/// Labels of the emotes, indexed like the emotes of the chat packets
+const EMOTES: [&str; 4] = [":)", ":(", "<3", "!!"];
+
+/// How long a chat line stays on the screen, in seconds
+const LINE_LIFETIME: f32 = 6.;
+
+/// Lines shown at once, the older ones are dropped
+const SHOWN_LINES: usize = 4;
+
+/// What the player picked in the chat buttons
+pub enum Pick {
+    Line(&'static str),
+    Emote(u8),
+}
+
+/// Last lines of the chat of the room, drawn on top of the screens
+pub struct ChatOverlay {
+    // text of the line and how long it has been shown
+    lines: Vec<(String, f32)>,
+}
+
+impl ChatOverlay {
+    pub fn new() -> Self {
+        ChatOverlay { lines: Vec::new() }
+    }
+
+    /// Add the lines received since the last frame, the players are named after the roster
+    pub fn push(&mut self, chat: Vec<packet::Chat>, roster: &[packet::Member]) {
+        for line in chat {
+            let name = match roster.iter().find(|m| m.session == line.session) {
+                Some(member) => member.nickname.clone(),
+                None => format!("Player {}", line.session),
+            };
+            let text = match line.emote {
+                Some(emote) => EMOTES.get(emote as usize).unwrap_or(&"?").to_string(),
+                None => line.text,
+            };
+            self.lines.push((format!("{} : {}", name, text), 0.));
+        }
+        let extra = self.lines.len().saturating_sub(SHOWN_LINES);
+        self.lines.drain(..extra);
+    }
+
+    /// Age the lines by the time of the frame and forget the old ones
+    pub fn update(&mut self, frame_time: f32) {
+        for line in self.lines.iter_mut() {
+            line.1 += frame_time;
+        }
+        self.lines.retain(|line| line.1 < LINE_LIFETIME);
+    }
+
+    pub fn draw(&self, screen_height: i32, screen_width: i32) {
+        for (i, line) in self.lines.iter().enumerate() {
+            unsafe {
+                DrawText(
+                    raylib_str!(line.0.clone()),
+                    ((screen_width as f32) * (1. / 20.)) as c_int,
+                    ((screen_height as f32) * ((0.3 + 0.5 * i as f32) / 13.)) as c_int,
+                    ((screen_height as f32) * (0.4 / 13.)) as c_int,
+                    colors::WHITE,
+                );
+            }
+        }
+    }
+
+    /// Draw the quick lines and emotes at the bottom of the screen, return the one touched
+    pub fn buttons(screen_height: i32, screen_width: i32) -> Option<Pick> {
+        let count = (QUICK_LINES.len() + EMOTES.len()) as f32;
+        let width = (screen_width as f32) / count;
+        let mut picked = None;
+        let labels = QUICK_LINES.iter().chain(EMOTES.iter());
+        for (i, label) in labels.enumerate() {
+            let button = Button::new(
+                raylib::Rectangle {
+                    x: (width * i as f32) as c_float,
+                    y: ((screen_height as f32) * (12.2 / 13.)) as c_float,
+                    width: (width - 5.) as c_float,
+                    height: ((screen_height as f32) * (0.7 / 13.)) as c_float,
+                },
+                Style::new(colors::WHITE, colors::BLUE),
+                Some(label.to_string()),
+                width / 10.,
+            );
+            button.draw();
+            if button.click() {
+                picked = Some(match QUICK_LINES.get(i) {
+                    Some(line) => Pick::Line(line),
+                    None => Pick::Emote((i - QUICK_LINES.len()) as u8),
+                });
+            }
+        }
+        picked
+    }
+}
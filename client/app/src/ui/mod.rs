@@ -1,4 +1,6 @@
 pub mod button;
+pub mod chat;
 pub mod colors;
 pub mod keyboard;
+pub mod settings;
 pub mod text;
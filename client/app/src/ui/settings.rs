@@ -0,0 +1,61 @@
+use crate::game::settings::{self, Setting};
+use crate::ui::button::{Button, Draw, Style};
+use crate::ui::colors;
+use raylib::{raylib_str, DrawText};
+use std::ffi::{c_char, c_float, c_int};
+
+/// Settings of the game chosen by the host, one row per setting with buttons lowering and
+/// raising it
+pub struct SettingsEditor {
+    schema: Vec<Setting>,
+    values: Vec<u16>,
+}
+
+impl SettingsEditor {
+    /// Editor starting from the defaults of `schema`
+    pub fn new(schema: Vec<Setting>) -> Self {
+        SettingsEditor {
+            values: settings::defaults(&schema),
+            schema,
+        }
+    }
+
+    /// Values chosen, in the order of the schema
+    pub fn values(&self) -> &[u16] {
+        &self.values
+    }
+
+    /// Draw the rows under the code of the room and apply the buttons touched
+    pub fn update(&mut self, screen_height: i32, screen_width: i32) {
+        for (i, setting) in self.schema.iter().enumerate() {
+            let y = (screen_height as f32) * ((4.5 + 0.9 * i as f32) / 13.);
+            let text = format!("{} : {}", setting.name, setting.text(self.values[i]));
+            unsafe {
+                DrawText(
+                    raylib_str!(text),
+                    ((screen_width as f32) * (1. / 20.)) as c_int,
+                    (y + (screen_height as f32) * (0.2 / 13.)) as c_int,
+                    ((screen_height as f32) * (0.4 / 13.)) as c_int,
+                    colors::WHITE,
+                );
+            }
+            for (label, x, up) in [("-", 13. / 20., false), ("+", 17. / 20., true)].iter() {
+                let button = Button::new(
+                    raylib::Rectangle {
+                        x: ((screen_width as f32) * x) as c_float,
+                        y: y as c_float,
+                        width: ((screen_width as f32) * (2.5 / 20.)) as c_float,
+                        height: ((screen_height as f32) * (0.7 / 13.)) as c_float,
+                    },
+                    Style::new(colors::WHITE, colors::BLUE),
+                    Some(label.to_string()),
+                    (screen_width as f32) / 20.,
+                );
+                button.draw();
+                if button.click() {
+                    self.values[i] = setting.next(self.values[i], *up);
+                }
+            }
+        }
+    }
+}
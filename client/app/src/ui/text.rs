@@ -1,3 +1,4 @@
+use crate::network::packet::Standing;
 use crate::ui::colors;
 use raylib::{raylib_str, DrawText};
 use std::ffi::{c_char, c_int};
@@ -48,3 +49,128 @@ pub fn waiting_text(screen_height: i32, screen_width: i32) {
         );
     }
 }
+
+/// Nicknames of the players in the room
+pub fn roster_text(screen_height: i32, screen_width: i32, nicknames: &[String]) {
+    unsafe {
+        DrawText(
+            raylib_str!("In the room :"),
+            ((screen_width as f32) * (1. / 9.)) as c_int,
+            ((screen_height as f32) * (1. / 13.)) as c_int,
+            ((screen_height as f32) * (1. / 13.)) as c_int,
+            colors::YELLOW,
+        );
+        for (i, nickname) in nicknames.iter().enumerate() {
+            DrawText(
+                raylib_str!(nickname.clone()),
+                ((screen_width as f32) * (1. / 9.)) as c_int,
+                ((screen_height as f32) * ((2.5 + i as f32) / 13.)) as c_int,
+                ((screen_height as f32) * (0.8 / 13.)) as c_int,
+                colors::WHITE,
+            );
+        }
+    }
+}
+
+/// Players ready to launch, or the seconds left once the launch is counted down
+pub fn ready_text(
+    screen_height: i32,
+    screen_width: i32,
+    ready: usize,
+    players: usize,
+    countdown: Option<u16>,
+) {
+    let (text, color) = match countdown {
+        Some(seconds) => (format!("{}", seconds), colors::YELLOW),
+        None => (format!("{}/{} ready", ready, players), colors::WHITE),
+    };
+    unsafe {
+        DrawText(
+            raylib_str!(text),
+            ((screen_width as f32) * (1. / 9.)) as c_int,
+            ((screen_height as f32) * (11. / 13.)) as c_int,
+            ((screen_height as f32) * (1. / 13.)) as c_int,
+            color,
+        );
+    }
+}
+
+/// Game paused, with the seconds left once it is about to go on
+pub fn paused_text(screen_height: i32, screen_width: i32, countdown: Option<u16>) {
+    let (text, color) = match countdown {
+        Some(seconds) => (format!("{}", seconds), colors::YELLOW),
+        None => (format!("Paused"), colors::WHITE),
+    };
+    unsafe {
+        DrawText(
+            raylib_str!(text),
+            ((screen_width as f32) * (1. / 9.)) as c_int,
+            ((screen_height as f32) * (6. / 13.)) as c_int,
+            ((screen_height as f32) * (1.5 / 13.)) as c_int,
+            color,
+        );
+    }
+}
+
+/// Place of the phone in the game it just played
+pub fn place_text(screen_height: i32, screen_width: i32, place: u8) {
+    let text = match place {
+        1 => format!("You won !"),
+        place => format!("You placed {}", place),
+    };
+    unsafe {
+        DrawText(
+            raylib_str!(text),
+            ((screen_width as f32) * (1. / 9.)) as c_int,
+            ((screen_height as f32) * (12. / 13.)) as c_int,
+            ((screen_height as f32) * (0.8 / 13.)) as c_int,
+            colors::GREEN,
+        );
+    }
+}
+
+/// All-time best players of a game, with their wins, kills and best lap
+pub fn leaderboard_text(
+    screen_height: i32,
+    screen_width: i32,
+    title: &str,
+    standings: &[Standing],
+) {
+    unsafe {
+        DrawText(
+            raylib_str!(title),
+            ((screen_width as f32) * (1. / 9.)) as c_int,
+            ((screen_height as f32) * (1. / 13.)) as c_int,
+            ((screen_height as f32) * (1. / 13.)) as c_int,
+            colors::YELLOW,
+        );
+        if standings.is_empty() {
+            DrawText(
+                raylib_str!("No game played yet"),
+                ((screen_width as f32) * (1. / 9.)) as c_int,
+                ((screen_height as f32) * (2.5 / 13.)) as c_int,
+                ((screen_height as f32) * (0.6 / 13.)) as c_int,
+                colors::WHITE,
+            );
+        }
+        for (i, s) in standings.iter().enumerate() {
+            let mut line = format!(
+                "{}. {} : {} wins, {} kills",
+                i + 1,
+                s.nickname,
+                s.wins,
+                s.kills
+            );
+            if let Some(lap) = s.best_lap {
+                line.push_str(&format!(", {:.1} s", lap.as_secs_f32()));
+            }
+            DrawText(
+                raylib_str!(line),
+                ((screen_width as f32) * (1. / 9.)) as c_int,
+                ((screen_height as f32) * ((2.5 + 0.8 * i as f32) / 13.)) as c_int,
+                ((screen_height as f32) * (0.5 / 13.)) as c_int,
+                colors::WHITE,
+            );
+        }
+    }
+}
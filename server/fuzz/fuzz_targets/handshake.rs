@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use server::network::packet::Handshake;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(screen) = Handshake::unpack(data) {
+        assert!(screen.physical_height > 0. && screen.physical_width > 0.);
+    }
+});
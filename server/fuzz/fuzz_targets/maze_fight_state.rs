@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use server::game::maze_fight::Input;
+use server::game::snapshot::Snapshot;
+
+// The inputs of the phones and the snapshots, decoded alone then against themselves as baseline
+fuzz_target!(|data: &[u8]| {
+    let _ = Input::unpack(data);
+    if let Ok((_, baseline)) = Snapshot::decode(data, None) {
+        let _ = Snapshot::decode(data, Some(&baseline));
+    }
+});
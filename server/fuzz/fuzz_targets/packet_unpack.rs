@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use server::network::packet::{Packet, BUFFER_SIZE};
+
+// Packets are read as whole buffers, the input is cut or padded with zeros to the size of one
+fuzz_target!(|data: &[u8]| {
+    let mut buffer = [0_u8; BUFFER_SIZE];
+    let size = data.len().min(BUFFER_SIZE);
+    buffer[..size].copy_from_slice(&data[..size]);
+    let _ = Packet::unpack(&buffer);
+});
@@ -0,0 +1,539 @@
+// The sections of the modules are delimited by doc banners followed by an empty line
+#![allow(clippy::empty_line_after_doc_comments)]
+
+use rand::Rng;
+use server::network::{client, packet};
+use std::collections::VecDeque;
+use std::env;
+use std::io::Error;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Barrier, Mutex};
+use std::thread;
+use std::time;
+
+/// Headless phones to load and soak test a server, on the real protocol
+///
+///     bot [--server <addr>] [--rooms <n>] [--phones <n>] [--game maze_fight|racer]
+///         [--duration <seconds>] [--screen <width>x<height>[:<physical width>x<physical height>]]...
+///
+/// Each room is created by its first phone and joined by the others, the screens are given to the
+/// phones in turn. Every phone sends an input each frame for the duration of the game.
+
+const USAGE: &str = "usage : bot [--server <addr>] [--rooms <n>] [--phones <n>] [--game maze_fight|racer]
+           [--duration <seconds>] [--screen <width>x<height>[:<physical width>x<physical height>]]...";
+
+//////////////////////////////////////////////
+///
+///
+/// Constants
+///
+///
+//////////////////////////////////////////////
+
+/// Rate at which the bots send their inputs, like the phones
+const FRAME_RATE: u32 = 60;
+
+/// Time given to the server to go through each step of the lobby
+const LOBBY_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+/// Time between two reports while the bots play
+const REPORT_PERIOD: time::Duration = time::Duration::from_secs(5);
+
+/// Maze fight messages sent before the first snapshot : the maze and the sprites
+const MAZE_FIGHT_INIT_MESSAGES: u32 = 2;
+
+/// Speed limit of the sprites of maze fight
+const MAZE_FIGHT_MAX_SPEED: f32 = 6.;
+
+/// Input sent the longest ago whose acknowledgement is still awaited
+const MAX_PENDING_INPUTS: usize = 128;
+
+//////////////////////////////////////////////
+///
+///
+/// Options
+///
+///
+//////////////////////////////////////////////
+
+#[derive(Clone, Copy)]
+struct Screen {
+    window_width: u32,
+    window_height: u32,
+    physical_width: f32,
+    physical_height: f32,
+}
+
+impl Screen {
+    /// `<width>x<height>[:<physical width>x<physical height>]`, a phone of 10 pixels per unit
+    /// when the physical size is not given
+    fn parse(spec: &str) -> Option<Self> {
+        let pair = |s: &str| {
+            let (w, h) = s.split_once('x')?;
+            Some((w.parse::<u32>().ok()?, h.parse::<u32>().ok()?))
+        };
+        let (window, physical) = match spec.split_once(':') {
+            Some((window, physical)) => (pair(window)?, Some(pair(physical)?)),
+            None => (pair(spec)?, None),
+        };
+        let (physical_width, physical_height) = physical.unwrap_or((window.0 / 10, window.1 / 10));
+        Some(Screen {
+            window_width: window.0,
+            window_height: window.1,
+            physical_width: physical_width as f32,
+            physical_height: physical_height as f32,
+        })
+    }
+}
+
+#[derive(Clone)]
+struct Options {
+    server: SocketAddr,
+    rooms: usize,
+    phones: usize,
+    game: client::Game,
+    duration: time::Duration,
+    screens: Vec<Screen>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Option<Self> {
+        let mut options = Options {
+            server: SocketAddr::from(([127, 0, 0, 1], 8888)),
+            rooms: 1,
+            phones: 2,
+            game: client::Game::MazeFight,
+            duration: time::Duration::from_secs(30),
+            screens: Vec::new(),
+        };
+        for option in args.chunks(2) {
+            match option {
+                [name, value] => match name.as_str() {
+                    "--server" => options.server = value.parse().ok()?,
+                    "--rooms" => options.rooms = value.parse().ok()?,
+                    "--phones" => options.phones = value.parse().ok()?,
+                    "--game" => {
+                        options.game = match value.as_str() {
+                            "maze_fight" => client::Game::MazeFight,
+                            "racer" => client::Game::Racer,
+                            _ => return None,
+                        }
+                    }
+                    "--duration" => {
+                        options.duration = time::Duration::from_secs_f32(value.parse().ok()?)
+                    }
+                    "--screen" => options.screens.push(Screen::parse(value)?),
+                    _ => return None,
+                },
+                _ => return None,
+            }
+        }
+        if options.screens.is_empty() {
+            options.screens.push(Screen::parse("1080x2400:757x1547")?);
+        }
+        if options.rooms == 0 || options.phones < 2 {
+            return None;
+        }
+        Some(options)
+    }
+
+    fn screen(&self, phone: usize) -> Screen {
+        self.screens[phone % self.screens.len()]
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Statistics
+///
+///
+//////////////////////////////////////////////
+
+/// Counters shared by all the bots
+#[derive(Default)]
+struct Stats {
+    connection_failures: AtomicU64,
+    lobby_failures: AtomicU64,
+    disconnections: AtomicU64,
+    in_game: AtomicU64,
+    sent: AtomicU64,
+    sent_bytes: AtomicU64,
+    received: AtomicU64,
+    received_bytes: AtomicU64,
+    // time between an input and the first snapshot acknowledging it, in milliseconds
+    latencies: Mutex<Vec<f32>>,
+}
+
+impl Stats {
+    fn count(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+
+    fn get(counter: &AtomicU64) -> u64 {
+        counter.load(Ordering::Relaxed)
+    }
+
+    fn report(&self, elapsed: time::Duration, total_phones: usize) {
+        let seconds = elapsed.as_secs_f32().max(f32::EPSILON);
+        let sent = Stats::get(&self.sent);
+        let received = Stats::get(&self.received);
+        println!(
+            "[{:>6.1}s] {}/{} phones in game | sent {} ({:.0}/s, {:.1} kB/s) | received {} ({:.0}/s, {:.1} kB/s)",
+            elapsed.as_secs_f32(),
+            Stats::get(&self.in_game),
+            total_phones,
+            sent,
+            sent as f32 / seconds,
+            Stats::get(&self.sent_bytes) as f32 / seconds / 1000.,
+            received,
+            received as f32 / seconds,
+            Stats::get(&self.received_bytes) as f32 / seconds / 1000.,
+        );
+    }
+
+    fn summary(&self) {
+        println!(
+            "failures : {} connections, {} lobbies, {} disconnections in game",
+            Stats::get(&self.connection_failures),
+            Stats::get(&self.lobby_failures),
+            Stats::get(&self.disconnections),
+        );
+
+        let mut latencies = self.latencies.lock().unwrap().clone();
+        if latencies.is_empty() {
+            println!("latency : no input acknowledged");
+            return;
+        }
+        latencies.sort_by(|a, b| a.total_cmp(b));
+        let percentile = |p: f32| latencies[((latencies.len() - 1) as f32 * p) as usize];
+        println!(
+            "latency : {} inputs acknowledged | p50 {:.1} ms | p95 {:.1} ms | p99 {:.1} ms | max {:.1} ms",
+            latencies.len(),
+            percentile(0.5),
+            percentile(0.95),
+            percentile(0.99),
+            latencies[latencies.len() - 1],
+        );
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Main
+///
+///
+//////////////////////////////////////////////
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match Options::parse(&args) {
+        Some(options) => options,
+        None => {
+            eprintln!("{USAGE}");
+            return;
+        }
+    };
+    let total_phones = options.rooms * options.phones;
+    println!(
+        "{} rooms of {} phones playing {} on {} for {:?}",
+        options.rooms, options.phones, options.game, options.server, options.duration
+    );
+
+    let stats = Arc::new(Stats::default());
+    let start = time::Instant::now();
+    let rooms: Vec<thread::JoinHandle<()>> = (0..options.rooms)
+        .map(|i| {
+            let options = options.clone();
+            let stats = stats.clone();
+            thread::spawn(move || room(i, &options, &stats))
+        })
+        .collect();
+
+    let mut next_report = start + REPORT_PERIOD;
+    while rooms.iter().any(|r| !r.is_finished()) {
+        thread::sleep(time::Duration::from_millis(100));
+        if time::Instant::now() >= next_report {
+            stats.report(start.elapsed(), total_phones);
+            next_report += REPORT_PERIOD;
+        }
+    }
+    for r in rooms {
+        let _ = r.join();
+    }
+
+    stats.report(start.elapsed(), total_phones);
+    stats.summary();
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Lobby
+///
+///
+//////////////////////////////////////////////
+
+/// Create a room with the first phone, join it with the others and play
+fn room(index: usize, options: &Options, stats: &Arc<Stats>) {
+    let first_phone = index * options.phones;
+    let mut master = match connect(options, first_phone, stats) {
+        Some(network) => network,
+        None => return,
+    };
+    let room = match master.create_room() {
+        Ok(room) => room,
+        Err(e) => {
+            eprintln!("room {index} : couldn't create the room : {e}");
+            Stats::count(&stats.lobby_failures, 1);
+            return;
+        }
+    };
+
+    // the room is locked once every phone went through joining, successfully or not
+    let joined = Arc::new(Barrier::new(options.phones));
+    let others: Vec<thread::JoinHandle<()>> = (1..options.phones)
+        .map(|i| {
+            let (options, stats, joined) = (options.clone(), stats.clone(), joined.clone());
+            thread::spawn(move || {
+                let network = connect(&options, first_phone + i, &stats).and_then(|mut n| match n
+                    .join_room(room)
+                {
+                    Ok(_) => Some(n),
+                    Err(e) => {
+                        eprintln!(
+                            "phone {} : couldn't join room {room} : {e}",
+                            first_phone + i
+                        );
+                        Stats::count(&stats.lobby_failures, 1);
+                        None
+                    }
+                });
+                joined.wait();
+                if let Some(mut network) = network {
+                    match wait_for_game(&mut network) {
+                        Ok(_) => play(&mut network, &options, &stats),
+                        Err(e) => {
+                            eprintln!("phone {} : {e}", first_phone + i);
+                            Stats::count(&stats.lobby_failures, 1);
+                        }
+                    }
+                }
+            })
+        })
+        .collect();
+
+    joined.wait();
+    match launch(&mut master, options.game) {
+        Ok(_) => play(&mut master, options, stats),
+        Err(e) => {
+            eprintln!("room {index} : {e}");
+            Stats::count(&stats.lobby_failures, 1);
+        }
+    }
+    for other in others {
+        let _ = other.join();
+    }
+}
+
+fn connect(options: &Options, phone: usize, stats: &Stats) -> Option<client::Network> {
+    let screen = options.screen(phone);
+    match client::Network::connect(
+        &options.server,
+        screen.physical_height,
+        screen.physical_width,
+        screen.window_height,
+        screen.window_width,
+    ) {
+        Ok(network) => Some(network),
+        Err(e) => {
+            eprintln!("phone {phone} : {e}");
+            Stats::count(&stats.connection_failures, 1);
+            None
+        }
+    }
+}
+
+/// Lock the room then launch the game, like the master phone
+fn launch(network: &mut client::Network, game: client::Game) -> Result<(), Error> {
+    network.lock_room(game)?;
+    network.wait_for_status(LOBBY_TIMEOUT, |s| {
+        matches!(s, client::Status::InLockRoom(_))
+    })?;
+    network.launch_game()
+}
+
+fn wait_for_game(network: &mut client::Network) -> Result<(), Error> {
+    network.wait_for_status(LOBBY_TIMEOUT, |s| matches!(s, client::Status::InGame(_)))?;
+    Ok(())
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Game
+///
+///
+//////////////////////////////////////////////
+
+/// Inputs of a phone, wandering around
+trait Inputs {
+    /// Data to send this frame
+    fn next(&mut self, dt: f32, rng: &mut impl Rng) -> Vec<u8>;
+
+    /// Sequence number of the input just sent, if the game acknowledges the inputs
+    fn seq(&self) -> Option<u16>;
+
+    /// Handle a message from the server, return the sequence number of the last input it
+    /// acknowledges, if any
+    fn received(&mut self, data: &[u8]) -> Option<u16>;
+}
+
+struct MazeFightInputs {
+    seq: u16,
+    messages: u32,
+    last_snapshot: u16,
+    angle: f32,
+}
+
+impl Inputs for MazeFightInputs {
+    fn next(&mut self, dt: f32, rng: &mut impl Rng) -> Vec<u8> {
+        self.seq = self.seq.wrapping_add(1).max(1);
+        self.angle += rng.gen_range(-0.3..0.3);
+
+        let mut data = Vec::with_capacity(16);
+        data.extend_from_slice(&self.seq.to_be_bytes());
+        data.extend_from_slice(&dt.to_be_bytes());
+        data.extend_from_slice(&(self.angle.cos() * MAZE_FIGHT_MAX_SPEED).to_be_bytes());
+        data.extend_from_slice(&(self.angle.sin() * MAZE_FIGHT_MAX_SPEED).to_be_bytes());
+        data.extend_from_slice(&self.last_snapshot.to_be_bytes());
+        data
+    }
+
+    fn seq(&self) -> Option<u16> {
+        Some(self.seq)
+    }
+
+    fn received(&mut self, data: &[u8]) -> Option<u16> {
+        self.messages += 1;
+        // seq | baseline | header size | life | last input
+        if self.messages <= MAZE_FIGHT_INIT_MESSAGES || data.len() < 8 {
+            return None;
+        }
+        self.last_snapshot = u16::from_be_bytes([data[0], data[1]]);
+        Some(u16::from_be_bytes([data[6], data[7]]))
+    }
+}
+
+struct RacerInputs {
+    segment: u8,
+    t: f32,
+}
+
+impl Inputs for RacerInputs {
+    fn next(&mut self, dt: f32, rng: &mut impl Rng) -> Vec<u8> {
+        // the state of the car, as the phones send it
+        self.t += dt * rng.gen_range(0.1..0.5);
+        if self.t >= 1. {
+            self.t -= 1.;
+            self.segment = self.segment.wrapping_add(1);
+        }
+        let mut data = Vec::with_capacity(5);
+        data.extend_from_slice(&self.segment.to_le_bytes());
+        data.extend_from_slice(&self.t.to_le_bytes());
+        data
+    }
+
+    fn seq(&self) -> Option<u16> {
+        None
+    }
+
+    fn received(&mut self, _data: &[u8]) -> Option<u16> {
+        None
+    }
+}
+
+fn play(network: &mut client::Network, options: &Options, stats: &Stats) {
+    match options.game {
+        client::Game::Racer => {
+            play_with(network, options, stats, RacerInputs { segment: 0, t: 0. })
+        }
+        _ => play_with(
+            network,
+            options,
+            stats,
+            MazeFightInputs {
+                seq: 0,
+                messages: 0,
+                last_snapshot: 0,
+                angle: 0.,
+            },
+        ),
+    }
+}
+
+fn play_with(
+    network: &mut client::Network,
+    options: &Options,
+    stats: &Stats,
+    mut inputs: impl Inputs,
+) {
+    Stats::count(&stats.in_game, 1);
+    let mut rng = rand::thread_rng();
+    let mut buffer = [0_u8; packet::MAX_DATA_SIZE];
+    let mut pending: VecDeque<(u16, time::Instant)> = VecDeque::new();
+    let frame = time::Duration::from_secs(1) / FRAME_RATE;
+
+    let start = time::Instant::now();
+    let mut last_frame = start;
+    while start.elapsed() < options.duration {
+        loop {
+            let n = network.recv(&mut buffer);
+            if n == 0 {
+                break;
+            }
+            Stats::count(&stats.received, 1);
+            Stats::count(&stats.received_bytes, n as u64);
+            if let Some(acked) = inputs.received(&buffer[..n]) {
+                acknowledge(&mut pending, acked, stats);
+            }
+        }
+
+        let dt = last_frame.elapsed().as_secs_f32();
+        last_frame = time::Instant::now();
+        let data = inputs.next(dt, &mut rng);
+        if let Err(e) = network.send(&data) {
+            eprintln!("disconnected from the server : {e}");
+            Stats::count(&stats.disconnections, 1);
+            break;
+        }
+        Stats::count(&stats.sent, 1);
+        Stats::count(&stats.sent_bytes, data.len() as u64);
+        if let Some(seq) = inputs.seq() {
+            if pending.len() == MAX_PENDING_INPUTS {
+                pending.pop_front();
+            }
+            pending.push_back((seq, last_frame));
+        }
+
+        thread::sleep(frame.saturating_sub(last_frame.elapsed()));
+    }
+    stats.in_game.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// Record the latency of the input `acked` and forget the inputs sent before
+fn acknowledge(pending: &mut VecDeque<(u16, time::Instant)>, acked: u16, stats: &Stats) {
+    while let Some(&(seq, sent)) = pending.front() {
+        // sequence numbers wrap around
+        if (seq.wrapping_sub(acked) as i16) > 0 {
+            break;
+        }
+        pending.pop_front();
+        if seq == acked {
+            let latency = sent.elapsed().as_secs_f32() * 1000.;
+            stats.latencies.lock().unwrap().push(latency);
+        }
+    }
+}
@@ -0,0 +1,191 @@
+// The sections of the modules are delimited by doc banners followed by an empty line
+#![allow(clippy::empty_line_after_doc_comments)]
+
+use server::game::{self, context::GameContext};
+use server::network::{client, packet};
+use server::replay::{self, Event, Replay, ReplayClock};
+use std::env;
+use std::io::Error;
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time;
+
+/// Replay tool, for the files written by the server launched with `--record <dir>`
+///
+///     replay run <file>
+///         run the game again offline and check that it takes the same path
+///     replay serve <file> [--player <rank>] [--port <port>]
+///         act as the server for a single phone and send it what the player received during the game
+
+const USAGE: &str =
+    "usage : replay run <file>\n        replay serve <file> [--player <rank>] [--port <port>]";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let res = match args.iter().map(|a| a.as_str()).collect::<Vec<_>>()[..] {
+        ["run", file] => run(Path::new(file)),
+        ["serve", file, ref options @ ..] => match parse_serve_options(options) {
+            Some((rank, port)) => serve(Path::new(file), rank, port),
+            None => {
+                eprintln!("{USAGE}");
+                return;
+            }
+        },
+        _ => {
+            eprintln!("{USAGE}");
+            return;
+        }
+    };
+    if let Err(e) = res {
+        eprintln!("replay failed : {e}");
+        std::process::exit(1);
+    }
+}
+
+fn parse_serve_options(options: &[&str]) -> Option<(u8, u16)> {
+    let (mut rank, mut port) = (0, 8888);
+    for option in options.chunks(2) {
+        match option {
+            ["--player", value] => rank = value.parse().ok()?,
+            ["--port", value] => port = value.parse().ok()?,
+            _ => return None,
+        }
+    }
+    Some((rank, port))
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Run
+///
+///
+//////////////////////////////////////////////
+
+/// Feed the recorded inputs and clock to the game and compare what it sends with the recording
+fn run(file: &Path) -> Result<(), Error> {
+    let replay = Replay::load(file)?;
+    let header = replay.header.clone();
+    let replay = Arc::new(Mutex::new(replay));
+
+    let mut players = replay::players(&replay);
+
+    let game_id: client::Game = header.game_id.into();
+    println!(
+        "replaying {game_id} with {} players, seed {} and settings {:?}",
+        players.len(),
+        header.seed,
+        header.settings.values()
+    );
+    let mut ctx = GameContext::new(header.seed, Box::new(ReplayClock::new(replay.clone())))
+        .with_lobby(Box::new(replay::ReplayLobby::new(replay.clone())));
+    // a game cut short ends with the error of the end of the recording, the outcome tells if it matched
+    let res = game::play(game_id, &header.settings, &mut players, &mut ctx);
+
+    let events = replay.lock().unwrap().finish()?;
+    println!("identical : the game went through the {events} recorded events");
+    match res {
+        Ok(placements) => {
+            for p in placements {
+                println!(
+                    "player {} placed {} with {} kills",
+                    p.rank, p.place, p.kills
+                );
+            }
+        }
+        Err(e) => println!("the game ended with : {e}"),
+    }
+    Ok(())
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Serve
+///
+///
+//////////////////////////////////////////////
+
+/// Wait for a phone and play it the messages recorded for player `rank`, as if it was live
+fn serve(file: &Path, rank: u8, port: u16) -> Result<(), Error> {
+    let replay = Replay::load(file)?;
+
+    let listener = TcpListener::bind(("0.0.0.0", port))?;
+    println!("waiting for a phone on port {port} ...");
+    let (mut stream, addr) = listener.accept()?;
+    println!("phone connected from {addr}, it plays the player {rank}");
+
+    lobby(&mut stream, rank, &replay.header)?;
+
+    // the inputs of the phone are read and dropped so that it is never blocked on sending
+    let mut input = stream.try_clone()?;
+    thread::spawn(move || while packet::Packet::recv_packet(&mut input).is_ok() {});
+
+    let start = time::Instant::now();
+    let mut sent = 0;
+    for event in replay.events() {
+        match event {
+            Event::Now(now) => {
+                if let Some(wait) = now.checked_sub(start.elapsed()) {
+                    thread::sleep(wait);
+                }
+            }
+            Event::Send(player, data) if *player == rank => {
+                transmit(&mut stream, data)?;
+                sent += 1;
+            }
+            _ => {}
+        }
+    }
+    println!("sent the {sent} messages of the player {rank}");
+    Ok(())
+}
+
+/// Answer the phone like the server would, until the game is launched
+fn lobby(stream: &mut TcpStream, rank: u8, header: &replay::Header) -> Result<(), Error> {
+    const SESSION: u16 = 1;
+    const ROOM: u16 = 1;
+
+    let init = packet::Packet::recv_packet(stream)?;
+    init.check_packet_flag(packet::Flag::Init)?;
+    let catalogue = packet::Listing::pack_catalogue(&game::listings());
+    packet::Packet::new(packet::Flag::Init, 0, SESSION, 0, &catalogue, 0)?.send_packet(stream)?;
+
+    let request = packet::Packet::recv_packet(stream)?;
+    let master = match request.get_flag() {
+        packet::Flag::Create => true,
+        packet::Flag::Join => false,
+        _ => {
+            return Err(Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "an unexpected packet was received",
+            ))
+        }
+    };
+    packet::Packet::control(packet::Flag::Create, SESSION, ROOM, 0).send_packet(stream)?;
+
+    // the master phone locks and launches the room, the others are told directly
+    if master {
+        packet::Packet::recv_packet(stream)?.check_packet_flag(packet::Flag::Lock)?;
+    }
+    packet::Packet::control(packet::Flag::Lock, SESSION, ROOM, rank as u16).send_packet(stream)?;
+    if master {
+        packet::Packet::recv_packet(stream)?.check_packet_flag(packet::Flag::Launch)?;
+    }
+    let settings = header.settings.pack();
+    packet::Packet::new(
+        packet::Flag::Launch,
+        0,
+        SESSION,
+        ROOM,
+        &settings,
+        header.game_id,
+    )?
+    .send_packet(stream)
+}
+
+fn transmit(stream: &mut TcpStream, data: &[u8]) -> Result<(), Error> {
+    packet::Packet::new(packet::Flag::Transmit, 0, 1, 1, data, 0)?.send_packet(stream)
+}
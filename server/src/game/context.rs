@@ -0,0 +1,254 @@
+use crate::network::player;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::thread;
+use std::time;
+
+//////////////////////////////////////////////
+///
+///
+/// Clock
+///
+///
+//////////////////////////////////////////////
+
+/// Source of time of the games, so that tests and replays can step time manually
+pub trait Clock: Send {
+    /// Time elapsed since the clock was created
+    fn now(&self) -> time::Duration;
+
+    /// Wait for `duration`
+    fn sleep(&mut self, duration: time::Duration);
+}
+
+/// Wall clock time
+pub struct SystemClock {
+    start: time::Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        SystemClock {
+            start: time::Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> time::Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep(&mut self, duration: time::Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// Time only moves when asked to : sleeping returns at once after advancing the clock
+#[allow(unused)]
+#[derive(Default)]
+pub struct ManualClock {
+    now: time::Duration,
+}
+
+#[allow(unused)]
+impl ManualClock {
+    pub fn new() -> Self {
+        ManualClock {
+            now: time::Duration::ZERO,
+        }
+    }
+
+    pub fn advance(&mut self, duration: time::Duration) {
+        self.now += duration;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> time::Duration {
+        self.now
+    }
+
+    fn sleep(&mut self, duration: time::Duration) {
+        self.advance(duration);
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Lobby
+///
+///
+//////////////////////////////////////////////
+
+/// Where the players joining a running game come from, for the games letting them in
+pub trait Lobby: Send {
+    /// Add the players waiting to join at the end of `players`, return their ranks
+    fn admit(&mut self, players: &mut Vec<player::Player>) -> Vec<u8>;
+
+    /// Told once the players changed and the phones were laid out again
+    fn reflowed(&mut self, players: &[player::Player]);
+}
+
+/// How the players of a running game changed, for the game to resize its world
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Reflow {
+    /// Players added at the end of the players
+    pub joined: Vec<u8>,
+    /// Players no longer among the players
+    pub left: Vec<u8>,
+    /// Players whose screen moved in the layout, the ones who joined included
+    pub moved: Vec<u8>,
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Game context
+///
+///
+//////////////////////////////////////////////
+
+/// What a room hands to its game : all the randomness comes from `rng` and all the time from
+/// `clock`, so that the same seed and inputs give the same game
+pub struct GameContext {
+    pub rng: StdRng,
+    pub clock: Box<dyn Clock>,
+    // players joining the game while it runs, None if nobody can
+    lobby: Option<Box<dyn Lobby>>,
+}
+
+impl GameContext {
+    pub fn new(seed: u64, clock: Box<dyn Clock>) -> Self {
+        GameContext {
+            rng: StdRng::seed_from_u64(seed),
+            clock,
+            lobby: None,
+        }
+    }
+
+    /// Let the players of `lobby` join the game while it runs
+    pub fn with_lobby(mut self, lobby: Box<dyn Lobby>) -> Self {
+        self.lobby = Some(lobby);
+        self
+    }
+
+    /// Remove the players `gone`, add the ones waiting in the lobby and lay the phones out again
+    /// The games letting players join or leave call it once per frame, it returns None while the
+    /// players stay the same
+    pub fn reflow(&mut self, players: &mut Vec<player::Player>, gone: &[u8]) -> Option<Reflow> {
+        let before: Vec<(u8, f32, f32)> = players
+            .iter()
+            .map(|p| (p.rank, p.top_left_x, p.top_left_y))
+            .collect();
+        players.retain(|p| !gone.contains(&p.rank));
+        let left: Vec<u8> = before
+            .iter()
+            .map(|(rank, _, _)| *rank)
+            .filter(|rank| gone.contains(rank))
+            .collect();
+        let joined = match &mut self.lobby {
+            Some(lobby) => lobby.admit(players),
+            None => Vec::new(),
+        };
+        if left.is_empty() && joined.is_empty() {
+            return None;
+        }
+
+        player::layout(players);
+        let moved = players
+            .iter()
+            .filter(|p| !before.contains(&(p.rank, p.top_left_x, p.top_left_y)))
+            .map(|p| p.rank)
+            .collect();
+        if let Some(lobby) = &mut self.lobby {
+            lobby.reflowed(players);
+        }
+        Some(Reflow {
+            joined,
+            left,
+            moved,
+        })
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Tests
+///
+///
+//////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::mock;
+    use rand::Rng;
+
+    /// Lobby letting in the players it was given, at the first frame
+    struct Queue(Vec<player::Player>);
+
+    impl Lobby for Queue {
+        fn admit(&mut self, players: &mut Vec<player::Player>) -> Vec<u8> {
+            let ranks = self.0.iter().map(|p| p.rank).collect();
+            players.append(&mut self.0);
+            ranks
+        }
+
+        fn reflowed(&mut self, _: &[player::Player]) {}
+    }
+
+    #[test]
+    fn test_same_seed_same_rng() {
+        let mut a = GameContext::new(7, Box::new(ManualClock::new()));
+        let mut b = GameContext::new(7, Box::new(ManualClock::new()));
+        for _ in 0..10 {
+            assert_eq!(a.rng.gen::<u64>(), b.rng.gen::<u64>());
+        }
+    }
+
+    #[test]
+    fn test_manual_clock() {
+        let mut clock = ManualClock::new();
+        clock.sleep(time::Duration::from_millis(20));
+        clock.advance(time::Duration::from_millis(5));
+        assert_eq!(clock.now(), time::Duration::from_millis(25));
+    }
+
+    #[test]
+    fn test_reflow() {
+        let (mut players, _phones) = mock::room(&[mock::Screen::new(100, 200); 3]);
+        let (mut late, _late_phone) = mock::room(&[mock::Screen::new(100, 300)]);
+        late[0].rank = 3;
+        let mut ctx =
+            GameContext::new(0, Box::new(ManualClock::new())).with_lobby(Box::new(Queue(late)));
+
+        // a taller phone joins at the right, the others are centered on it again
+        let reflow = ctx.reflow(&mut players, &[]).unwrap();
+        assert_eq!(reflow.joined, vec![3]);
+        assert_eq!(reflow.moved, vec![0, 1, 2, 3]);
+        assert_eq!((players[3].top_left_x, players[3].top_left_y), (300., 0.));
+        assert_eq!(players[0].top_left_y, 50.);
+
+        // only the phones at the right of the one leaving move
+        let reflow = ctx.reflow(&mut players, &[1]).unwrap();
+        assert_eq!(
+            reflow,
+            Reflow {
+                joined: vec![],
+                left: vec![1],
+                moved: vec![2, 3],
+            }
+        );
+        assert_eq!(players[2].top_left_x, 200.);
+        assert_eq!(ctx.reflow(&mut players, &[]), None);
+    }
+}
@@ -14,10 +14,20 @@ pub struct Bullet {
     pub pos: Vector2,
     pub dir: Vector2,
     pub id: usize,
+    // unique id of the bullet, used to identify it in the snapshots
+    pub uid: u16,
+    // number of ticks the shooter saw the other sprites late when firing
+    pub lag: u32,
 }
 
 impl Bullet {
-    pub fn new(pos: Vector2, dir: Vector2, id: usize) -> Self {
-        Bullet { pos, dir, id }
+    pub fn new(pos: Vector2, dir: Vector2, id: usize, uid: u16, lag: u32) -> Self {
+        Bullet {
+            pos,
+            dir,
+            id,
+            uid,
+            lag,
+        }
     }
 }
@@ -0,0 +1,102 @@
+use std::collections::VecDeque;
+use std::vec;
+
+//////////////////////////////////////////////
+///
+///
+/// Hitbox
+///
+///
+//////////////////////////////////////////////
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hitbox {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Hitbox {
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        x > self.x && x < self.x + self.width && y > self.y && y < self.y + self.height
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Position history
+///
+///
+//////////////////////////////////////////////
+
+/// Hitboxes of the sprites during the last simulation ticks, to resolve the hits as the shooter
+/// saw them on its phone
+pub struct PositionHistory {
+    // oldest tick first, the last one is the current tick
+    ticks: VecDeque<Vec<Option<Hitbox>>>,
+    capacity: usize,
+}
+
+impl PositionHistory {
+    /// Keep the current tick and `max_rewind` ticks before
+    pub fn new(max_rewind: u32) -> Self {
+        PositionHistory {
+            ticks: VecDeque::with_capacity(max_rewind as usize + 1),
+            capacity: max_rewind as usize + 1,
+        }
+    }
+
+    /// Record the hitboxes of the current tick, indexed like the sprites, None for a dead sprite
+    pub fn record(&mut self, hitboxes: vec::Vec<Option<Hitbox>>) {
+        if self.ticks.len() == self.capacity {
+            self.ticks.pop_front();
+        }
+        self.ticks.push_back(hitboxes);
+    }
+
+    /// Hitbox of the sprite `index`, `ticks` ago, clamped to the oldest tick recorded
+    pub fn rewind(&self, ticks: u32, index: usize) -> Option<Hitbox> {
+        let back = (ticks as usize).min(self.ticks.len().checked_sub(1)?);
+        *self.ticks[self.ticks.len() - 1 - back].get(index)?
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Tests
+///
+///
+//////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hitbox(x: f32) -> Option<Hitbox> {
+        Some(Hitbox {
+            x,
+            y: 0.,
+            width: 10.,
+            height: 10.,
+        })
+    }
+
+    #[test]
+    fn test_rewind() {
+        let mut history = PositionHistory::new(2);
+        assert_eq!(history.rewind(0, 0), None);
+
+        for x in 0..5 {
+            history.record(vec![hitbox(x as f32), None]);
+        }
+        assert_eq!(history.rewind(0, 0), hitbox(4.));
+        assert_eq!(history.rewind(1, 0), hitbox(3.));
+        // the window is capped
+        assert_eq!(history.rewind(10, 0), hitbox(2.));
+        assert_eq!(history.rewind(0, 1), None);
+        assert_eq!(history.rewind(0, 2), None);
+    }
+}
@@ -1,8 +1,9 @@
 use super::Vector2;
 use crate::network::player;
-use rand;
+use rand::Rng;
 use std::vec;
 
+/// Default size of the cells of the maze
 pub const WALL_LENGTH: usize = 200;
 
 //////////////////////////////////////////////
@@ -20,6 +21,12 @@ pub struct Wall {
 }
 
 impl Wall {
+    /// Walls are generated with the maze, this one is meant for tests
+    #[allow(unused)]
+    pub fn new(start: Vector2, end: Vector2) -> Self {
+        Wall { start, end }
+    }
+
     pub fn realign_sprite(&self, sprite: &mut Vector2, width: usize, height: usize) {
         // first check if this is pertinent to check for colisions
         let mut col = Vector2 {
@@ -137,7 +144,8 @@ impl Wall {
 ///
 //////////////////////////////////////////////
 
-pub fn gen_walls(players: &mut [player::Player]) -> Vec<Wall> {
+/// Maze of cells of `size` covering the screens of the players
+pub fn gen_walls<R: Rng>(players: &mut [player::Player], size: usize, rng: &mut R) -> Vec<Wall> {
     // for now build a map of the width the sum of width and heigh max(height)
     let mut width = 0.;
     let mut height = 0.;
@@ -146,14 +154,33 @@ pub fn gen_walls(players: &mut [player::Player]) -> Vec<Wall> {
         height = p.physical_height.max(height);
     }
 
-    let size = WALL_LENGTH;
     let mut res = vec::Vec::new();
+    resize_walls(&mut res, (0., 0.), (width, height), size, rng);
+    res
+}
 
-    for i in 0..(width as usize / size) {
-        for j in 0..(height as usize / size) {
-            let seed: f32 = rand::random();
+/// Fit the maze to the screens of the players once they changed from `old` to `new` (width,
+/// height) : the walls outside of them are dropped and the cells they gained are generated
+pub fn resize_walls<R: Rng>(
+    maze: &mut Vec<Wall>,
+    old: (f32, f32),
+    new: (f32, f32),
+    size: usize,
+    rng: &mut R,
+) {
+    let (old_columns, old_rows) = (old.0 as usize / size, old.1 as usize / size);
+    let (columns, rows) = (new.0 as usize / size, new.1 as usize / size);
+    let (right, bottom) = ((columns * size) as f32, (rows * size) as f32);
+    maze.retain(|w| w.start.x.max(w.end.x) <= right && w.start.y.max(w.end.y) <= bottom);
+
+    for i in 0..columns {
+        for j in 0..rows {
+            if i < old_columns && j < old_rows {
+                continue;
+            }
+            let seed: f32 = rng.gen();
             if seed < 0.35 {
-                res.push(Wall {
+                maze.push(Wall {
                     start: Vector2 {
                         x: (i * size) as f32,
                         y: (j * size) as f32,
@@ -165,7 +192,7 @@ pub fn gen_walls(players: &mut [player::Player]) -> Vec<Wall> {
                 });
             }
             if seed > 0.65 {
-                res.push(Wall {
+                maze.push(Wall {
                     start: Vector2 {
                         x: (i * size) as f32,
                         y: (j * size) as f32,
@@ -178,7 +205,6 @@ pub fn gen_walls(players: &mut [player::Player]) -> Vec<Wall> {
             }
         }
     }
-    res
 }
 
 //////////////////////////////////////////////
@@ -189,7 +215,7 @@ pub fn gen_walls(players: &mut [player::Player]) -> Vec<Wall> {
 ///
 //////////////////////////////////////////////
 
-pub fn pack_maze(p: &mut crate::network::player::Player, maze: &Vec<Wall>) -> Vec<u8> {
+pub fn pack_maze(p: &mut crate::network::player::Player, maze: &[Wall]) -> Vec<u8> {
     let mut res = vec::Vec::new();
     let tmp2 = (maze.len() as u16).to_be_bytes();
     res.append(&mut tmp2.to_vec());
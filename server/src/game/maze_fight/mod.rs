@@ -1,9 +1,13 @@
+use super::context::{GameContext, Reflow};
+use super::scheduler;
+use super::settings::{Setting, Settings};
+use super::snapshot;
+use super::{Info, Placement};
+use crate::network::client::Game;
 use crate::network::packet;
 use crate::network::{self, player};
-use rand;
-use std::io::Error;
-use std::thread;
-use std::time;
+use rand::Rng;
+use std::io::{Error, ErrorKind};
 use std::vec;
 
 //////////////////////////////////////////////
@@ -14,6 +18,7 @@ use std::vec;
 ///
 //////////////////////////////////////////////
 mod bullet;
+mod history;
 mod maze;
 mod powerup;
 mod sprite;
@@ -29,6 +34,33 @@ mod weapon;
 
 const BULLET_SIZE: usize = 20;
 
+/// Size of an input sent by a phone
+const INPUT_SIZE: usize = 16;
+
+/// Simulation rate and rate at which the state is sent to the phones, in Hz
+const TICK_RATE: u32 = 60;
+const NETWORK_RATE: u32 = 30;
+
+/// Time between two power-up generations, in seconds
+const MODIFIER_GEN_PERIOD: f32 = 5.;
+
+/// Phones render the other sprites this late to interpolate, in seconds
+const INTERPOLATION_DELAY: f32 = 0.1;
+
+/// Hits are resolved at most this far in the past, in seconds
+const MAX_REWIND: f32 = 0.2;
+const MAX_REWIND_TICKS: u32 = (MAX_REWIND * TICK_RATE as f32) as u32;
+
+/// Start of the updates of the map sent when players join or leave, the snapshots never start
+/// with the seq 0
+/// Update : 0 (u16) | kind (u8) | maze or sprites, as sent at the start of the game
+const UPDATE: [u8; 2] = [0, 0];
+const UPDATE_MAZE: u8 = 0;
+const UPDATE_SPRITES: u8 = 1;
+
+/// Killer of the sprites of the players who left
+const NOBODY: usize = usize::MAX;
+
 //////////////////////////////////////////////
 ///
 ///
@@ -46,95 +78,406 @@ pub struct Vector2 {
 //////////////////////////////////////////////
 ///
 ///
-/// Entry point
+/// Input
 ///
 ///
 //////////////////////////////////////////////
 
-pub fn maze_fight(players: &mut [network::player::Player]) -> Result<(), Error> {
-    let maze = maze::gen_walls(players);
+/// Inputs of the phone, one per frame, applied in order :
+/// seq (u16) | frame duration (f32) | speed x (f32) | speed y (f32) | last snapshot received (u16)
+#[derive(Debug, Clone, Copy)]
+pub struct Input {
+    pub seq: u16,
+    pub dt: f32,
+    pub speed: Vector2,
+    pub ack: u16,
+}
 
-    let mut width: f32 = 0.;
-    let mut height: f32 = 0.;
-    for p in players.iter() {
-        width += p.physical_width;
-        height = height.max(p.physical_height);
+impl Input {
+    /// Read an input, refusing the ones that would corrupt the simulation
+    pub fn unpack(data: &[u8]) -> Result<Self, Error> {
+        if data.len() < INPUT_SIZE {
+            return Err(Error::new(ErrorKind::InvalidData, "input too short"));
+        }
+        let f32_at =
+            |i: usize| f32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+        let input = Input {
+            seq: u16::from_be_bytes([data[0], data[1]]),
+            dt: f32_at(2),
+            speed: Vector2 {
+                x: f32_at(6),
+                y: f32_at(10),
+            },
+            ack: u16::from_be_bytes([data[14], data[15]]),
+        };
+        if !(input.dt.is_finite() && input.speed.x.is_finite() && input.speed.y.is_finite()) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("non finite input : {input:?}"),
+            ));
+        }
+        Ok(input)
     }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Unique ids
+///
+///
+//////////////////////////////////////////////
 
-    let mut bullets = vec::Vec::new();
+/// Generator of the unique ids of the bullets and power-ups
+pub struct Uids(u16);
 
-    let mut internal_timer = time::Instant::now();
+impl Uids {
+    pub fn generate(&mut self) -> u16 {
+        self.0 = self.0.wrapping_add(1);
+        self.0
+    }
+}
 
-    let mut powerups = vec::Vec::new();
-    let mut last_modifier_gen = time::Instant::now();
+//////////////////////////////////////////////
+///
+///
+/// Config
+///
+///
+//////////////////////////////////////////////
 
-    for p in players.iter() {
-        for _ in 0..3 {
-            let x: f32 = ((rand::random::<f32>() * p.physical_width) as usize
-                + maze::WALL_LENGTH / 2
-                - ((rand::random::<f32>() * p.physical_width) as usize % maze::WALL_LENGTH))
-                as f32;
-            let y: f32 = ((rand::random::<f32>() * p.physical_height) as usize
-                + maze::WALL_LENGTH / 2
-                - ((rand::random::<f32>() * p.physical_height) as usize % maze::WALL_LENGTH))
-                as f32;
-            let powerup = (rand::random::<f32>() * powerup::POWERUP_COUNT as f32) as usize;
-
-            powerups.push(powerup::PowerUp::new(
-                powerup.into(),
-                Vector2 {
-                    x: x + p.top_left_x,
-                    y: y + p.top_left_y,
-                },
-            ));
+pub const INFO: Info = Info {
+    game: Game::MazeFight,
+    name: "Maze Fight",
+    description: "Shoot your way through a maze spread over the phones, the last one standing wins",
+    players: 2..=9,
+    late_join: true,
+};
+
+/// Settings of the master, in the order of the fields of `Config`
+pub const SETTINGS: &[Setting] = &[
+    Setting::number("Lives", 10, 1, 99, 1),
+    Setting::number("Wall length", maze::WALL_LENGTH as u16, 100, 400, 50),
+    Setting::number(
+        "Power-up duration (s)",
+        powerup::TIME_TO_LIVE as u16,
+        1,
+        60,
+        1,
+    ),
+    Setting::number(
+        "Firing period (ms)",
+        weapon::FIRERING_SPEED as u16,
+        100,
+        2000,
+        50,
+    ),
+    Setting::toggle("Lag compensation", true),
+];
+
+pub struct Config {
+    pub lives: usize,
+    /// Size of the cells of the maze, entities this far outside of a screen are still sent to
+    /// the phone so that they don't pop in
+    pub wall_length: usize,
+    /// Duration of the effect of a power-up, in seconds
+    pub powerup_ttl: f32,
+    /// Time between two shots without power-up, in milliseconds
+    pub firing_period: f32,
+    /// Resolve the hits of the bullets where their shooter saw the other sprites on its phone,
+    /// instead of where they currently are
+    pub lag_compensation: bool,
+}
+
+impl From<&Settings> for Config {
+    fn from(settings: &Settings) -> Self {
+        Config {
+            lives: settings.get(0) as usize,
+            wall_length: settings.get(1) as usize,
+            powerup_ttl: settings.get(2) as f32,
+            firing_period: settings.get(3) as f32,
+            lag_compensation: settings.get(4) != 0,
         }
     }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        (&Settings::defaults(SETTINGS)).into()
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Entry point
+///
+///
+//////////////////////////////////////////////
+
+/// Run a fight until a single sprite is left, the last ones to die are placed first
+/// Players may join the fight with a new sprite and leave it, their sprite then dies
+pub fn maze_fight(
+    players: &mut Vec<network::player::Player>,
+    config: Config,
+    ctx: &mut GameContext,
+) -> Result<Vec<Placement>, Error> {
+    let mut world = World::new(players, config, &mut ctx.rng);
+    // ranks of the phones found disconnected, they leave at the next frame
+    let mut gone = vec::Vec::new();
 
-    let mut sprites = sprite::Sprite::create_sprites(players);
     for p in players.iter_mut() {
-        let packed_maze = maze::pack_maze(p, &maze);
-        p.send(&packed_maze)?;
-        let data = sprite::Sprite::pack_sprites(&sprites);
-        p.send(&data)?;
+        send_map(p, &world, &mut gone)?;
     }
 
+    let mut scheduler =
+        scheduler::Scheduler::new("Maze fight", TICK_RATE, NETWORK_RATE, ctx.clock.as_ref());
+    let mut histories: Vec<(u8, snapshot::SnapshotHistory)> = players
+        .iter()
+        .map(|p| (p.rank, snapshot::SnapshotHistory::new()))
+        .collect();
+
     loop {
-        for s in sprites.iter_mut() {
-            s.update_sprite_status(&maze, &mut bullets, internal_timer);
+        let frame = scheduler.next_frame(ctx.clock.as_mut());
+
+        let reflow = ctx.reflow(players, &gone);
+        gone.clear();
+        if let Some(reflow) = reflow {
+            world.reflow(players, &reflow, &mut ctx.rng);
+            histories.retain(|(rank, _)| !reflow.left.contains(rank));
+            for rank in reflow.joined.iter() {
+                histories.push((*rank, snapshot::SnapshotHistory::new()));
+            }
+            send_reflow(players, &world, &reflow, &mut gone)?;
         }
 
-        update_bullet_status(&mut bullets, &maze, internal_timer);
+        for (p, (_, history)) in players.iter_mut().zip(histories.iter_mut()) {
+            let res = recv_game_data(p, &mut world, history);
+            check_connected(res, p.rank, &mut gone)?;
+        }
 
-        for s in sprites.iter_mut() {
-            s.update_dead_status(&mut bullets);
-            s.update_powerup_status(&mut powerups);
+        for _ in 0..frame.steps {
+            world.step(scheduler.dt(), &mut ctx.rng);
         }
 
-        if last_modifier_gen.elapsed().as_secs() > 5 {
-            last_modifier_gen = time::Instant::now();
-            generate_new_modifiers(&mut powerups, width, height);
+        // the phones see the last death before the game ends
+        let placements = world.placements();
+        if frame.send || placements.is_some() {
+            for (p, (_, history)) in players.iter_mut().zip(histories.iter_mut()) {
+                let res = send_game_data(p, history, &world);
+                check_connected(res, p.rank, &mut gone)?;
+            }
+        }
+        if let Some(placements) = placements {
+            return Ok(placements);
         }
+    }
+}
+
+/// A phone found disconnected is added to `gone` and the fight goes on without it, the other
+/// errors end the fight
+fn check_connected(res: Result<(), Error>, rank: u8, gone: &mut Vec<u8>) -> Result<(), Error> {
+    match res {
+        Err(e) if e.kind() == ErrorKind::NotConnected => {
+            gone.push(rank);
+            Ok(())
+        }
+        res => res,
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// World
+///
+///
+//////////////////////////////////////////////
+
+struct World {
+    maze: Vec<maze::Wall>,
+    sprites: Vec<sprite::Sprite>,
+    bullets: Vec<bullet::Bullet>,
+    powerups: Vec<powerup::PowerUp>,
+
+    width: f32,
+    height: f32,
+
+    // simulation time since the last power-up generation, in seconds
+    last_modifier_gen: f32,
 
-        internal_timer = time::Instant::now();
+    uids: Uids,
+
+    tick: u32,
+    history: history::PositionHistory,
+    config: Config,
+
+    // sprites killed, in order, with the id of their killer
+    deaths: Vec<(usize, usize)>,
+}
+
+impl World {
+    fn new<R: Rng>(players: &mut [player::Player], config: Config, rng: &mut R) -> Self {
+        let size = config.wall_length;
+        let maze = maze::gen_walls(players, size, rng);
+        let (width, height) = extent(players);
+
+        let mut powerups = vec::Vec::new();
+        let mut uids = Uids(0);
+
+        for p in players.iter() {
+            for _ in 0..3 {
+                // at the center of a cell of the maze
+                let x = (rng.gen::<f32>() * p.physical_width) as usize / size * size + size / 2;
+                let y = (rng.gen::<f32>() * p.physical_height) as usize / size * size + size / 2;
+                let powerup = (rng.gen::<f32>() * powerup::POWERUP_COUNT as f32) as usize;
+
+                powerups.push(powerup::PowerUp::new(
+                    powerup.into(),
+                    Vector2 {
+                        x: x as f32 + p.top_left_x,
+                        y: y as f32 + p.top_left_y,
+                    },
+                    uids.generate(),
+                    config.powerup_ttl,
+                ));
+            }
+        }
 
-        for p in players.iter_mut() {
-            send_game_data(p, &bullets, &powerups, &sprites)?;
-            recv_game_data(p, &mut sprites);
+        World {
+            maze,
+            sprites: sprite::Sprite::create_sprites(players, config.lives),
+            bullets: vec::Vec::new(),
+            powerups,
+            width,
+            height,
+            last_modifier_gen: 0.,
+            uids,
+            tick: 0,
+            history: history::PositionHistory::new(MAX_REWIND_TICKS),
+            config,
+            deaths: vec::Vec::new(),
         }
-        thread::sleep(time::Duration::from_millis(10));
+    }
+
+    /// Advance the simulation of `dt` seconds
+    fn step<R: Rng>(&mut self, dt: f32, rng: &mut R) {
+        self.tick = self.tick.wrapping_add(1);
+        self.history
+            .record(self.sprites.iter().map(|s| s.hitbox()).collect());
+
+        for s in self.sprites.iter_mut() {
+            s.update_sprite_status(
+                &self.maze,
+                &mut self.bullets,
+                &mut self.uids,
+                self.config.firing_period,
+                dt,
+            );
+        }
+
+        update_bullet_status(&mut self.bullets, &self.maze, dt);
+
+        let history = if self.config.lag_compensation {
+            Some(&self.history)
+        } else {
+            None
+        };
+        for (i, s) in self.sprites.iter_mut().enumerate() {
+            if let Some(killer) = s.update_dead_status(i, &mut self.bullets, history) {
+                self.deaths.push((i, killer));
+            }
+            s.update_powerup_status(&mut self.powerups);
+        }
+
+        self.last_modifier_gen += dt;
+        if self.last_modifier_gen > MODIFIER_GEN_PERIOD {
+            self.last_modifier_gen = 0.;
+            generate_new_modifiers(
+                &mut self.powerups,
+                self.width,
+                self.height,
+                self.uids.generate(),
+                &self.config,
+                rng,
+            );
+        }
+    }
+
+    /// Follow the players who joined or left : the sprites of the ones leaving die, the ones
+    /// joining get a new sprite, and the maze is fit to the screens laid out again
+    fn reflow<R: Rng>(&mut self, players: &[player::Player], reflow: &Reflow, rng: &mut R) {
+        for (i, s) in self.sprites.iter_mut().enumerate() {
+            if reflow.left.contains(&(s.get_id() as u8)) && s.leave() {
+                self.deaths.push((i, NOBODY));
+            }
+        }
+        // the ranks of the players joining follow the ones of the sprites
+        for p in players.iter().filter(|p| reflow.joined.contains(&p.rank)) {
+            self.sprites.push(sprite::Sprite::new(p, self.config.lives));
+        }
+
+        let (width, height) = extent(players);
+        maze::resize_walls(
+            &mut self.maze,
+            (self.width, self.height),
+            (width, height),
+            self.config.wall_length,
+            rng,
+        );
+        self.powerups
+            .retain(|p| p.pos().x < width && p.pos().y < height);
+        for s in self.sprites.iter_mut() {
+            s.keep_within(width, height);
+        }
+        self.width = width;
+        self.height = height;
+    }
+
+    /// Placements of the players once a single sprite is left, None while the fight goes on
+    fn placements(&self) -> Option<Vec<Placement>> {
+        let alive: Vec<usize> = (0..self.sprites.len())
+            .filter(|i| !self.sprites[*i].is_dead())
+            .collect();
+        if self.sprites.len() < 2 || alive.len() > 1 {
+            return None;
+        }
+        let order = alive
+            .into_iter()
+            .chain(self.deaths.iter().rev().map(|(i, _)| *i));
+        let placements = order
+            .enumerate()
+            .map(|(place, i)| {
+                let id = self.sprites[i].get_id();
+                Placement {
+                    rank: id as u8,
+                    place: place as u8 + 1,
+                    kills: self.deaths.iter().filter(|(_, k)| *k == id).count() as u32,
+                    best_lap: None,
+                }
+            })
+            .collect();
+        Some(placements)
     }
 }
 
-fn update_bullet_status(
-    bullets: &mut Vec<bullet::Bullet>,
-    maze: &[maze::Wall],
-    internal_timer: time::Instant,
-) {
+/// Width and height of the map covering the screens of the players
+fn extent(players: &[player::Player]) -> (f32, f32) {
+    let mut width: f32 = 0.;
+    let mut height: f32 = 0.;
+    for p in players.iter() {
+        width += p.physical_width;
+        height = height.max(p.physical_height);
+    }
+    (width, height)
+}
+
+fn update_bullet_status(bullets: &mut Vec<bullet::Bullet>, maze: &[maze::Wall], dt: f32) {
     let mut i = 0;
     while i < bullets.len() {
         let b = &mut bullets[i];
-        b.pos.x += b.dir.x * internal_timer.elapsed().as_secs_f32() * 50.;
-        b.pos.y += b.dir.y * internal_timer.elapsed().as_secs_f32() * 50.;
+        b.pos.x += b.dir.x * dt * 50.;
+        b.pos.y += b.dir.y * dt * 50.;
         if b.pos.x < 0. || b.pos.y < 0. || b.pos.x > 5000. || b.pos.y > 5000. {
             bullets.swap_remove(i);
         } else {
@@ -150,110 +493,479 @@ fn update_bullet_status(
     }
 }
 
-fn generate_new_modifiers(powerups: &mut Vec<powerup::PowerUp>, width: f32, height: f32) {
-    let x: f32 = (rand::random::<f32>() * width).floor() + maze::WALL_LENGTH as f32 / 2.;
-    let y: f32 = (rand::random::<f32>() * height).floor() + maze::WALL_LENGTH as f32 / 2.;
-    let powerup = (rand::random::<f32>() * powerup::POWERUP_COUNT as f32) as usize;
+fn generate_new_modifiers<R: Rng>(
+    powerups: &mut Vec<powerup::PowerUp>,
+    width: f32,
+    height: f32,
+    uid: u16,
+    config: &Config,
+    rng: &mut R,
+) {
+    let x: f32 = (rng.gen::<f32>() * width).floor() + config.wall_length as f32 / 2.;
+    let y: f32 = (rng.gen::<f32>() * height).floor() + config.wall_length as f32 / 2.;
+    let powerup = (rng.gen::<f32>() * powerup::POWERUP_COUNT as f32) as usize;
+
+    powerups.push(powerup::PowerUp::new(
+        powerup.into(),
+        Vector2 { x, y },
+        uid,
+        config.powerup_ttl,
+    ));
+}
 
-    powerups.push(powerup::PowerUp::new(powerup.into(), Vector2 { x, y }));
+/// Send to the phone the maze and the sprites, once at the start of the game or when it joins
+fn send_map(p: &mut player::Player, world: &World, gone: &mut Vec<u8>) -> Result<(), Error> {
+    let packed_maze = maze::pack_maze(p, &world.maze);
+    check_connected(p.send(&packed_maze), p.rank, gone)?;
+    let data = sprite::Sprite::pack_sprites(&world.sprites, p);
+    check_connected(p.send(&data), p.rank, gone)
+}
+
+/// Tell the phones about the players who joined or left : the ones joining get the map, the
+/// others the maze if their screen moved and the sprites if some were added
+fn send_reflow(
+    players: &mut [player::Player],
+    world: &World,
+    reflow: &Reflow,
+    gone: &mut Vec<u8>,
+) -> Result<(), Error> {
+    for p in players.iter_mut() {
+        if reflow.joined.contains(&p.rank) {
+            send_map(p, world, gone)?;
+            continue;
+        }
+        if reflow.moved.contains(&p.rank) {
+            let update = [
+                &UPDATE[..],
+                &[UPDATE_MAZE],
+                &maze::pack_maze(p, &world.maze),
+            ]
+            .concat();
+            check_connected(p.send(&update), p.rank, gone)?;
+        }
+        if !reflow.joined.is_empty() {
+            let update = [
+                &UPDATE[..],
+                &[UPDATE_SPRITES],
+                &sprite::Sprite::pack_sprites(&world.sprites, p),
+            ]
+            .concat();
+            check_connected(p.send(&update), p.rank, gone)?;
+        }
+    }
+    Ok(())
 }
 
+/// Send to the phone the entities around its screen, delta encoded against the last snapshot it
+/// acknowledged
+///
+/// Snapshot header : life (u8) | last input applied (u16)
+/// Sections : sprites (pos, speed, size), bullets (pos, speed, size), power-ups (pos)
 fn send_game_data(
     p: &mut player::Player,
-    bullets: &[bullet::Bullet],
-    powerups: &[powerup::PowerUp],
-    sprites: &[sprite::Sprite],
+    history: &mut snapshot::SnapshotHistory,
+    world: &World,
 ) -> Result<(), Error> {
-    let mut data = vec::Vec::new();
+    let (bullets, powerups, sprites) = (&world.bullets, &world.powerups, &world.sprites);
+    let margin = world.config.wall_length as f32;
+
+    let mut header = vec::Vec::new();
     for s in sprites.iter() {
         if s.get_id() == p.rank as usize {
-            data.push((s.get_life() as u8).to_be());
+            header.push((s.get_life() as u8).to_be());
+            header.extend_from_slice(&s.get_last_input().to_be_bytes());
         }
     }
 
-    data.append(&mut sprite::Sprite::pack_game_sprites(sprites, p));
-
-    data.push((bullets.len() as u8).to_be());
-
+    let mut bullet_section = snapshot::Section::new(20);
     for bullet in bullets.iter() {
-        let (x, y) = p.to_local_coordinates(bullet.pos.x, bullet.pos.y);
-
-        let pos_x = x.to_be_bytes();
-        let pos_y = y.to_be_bytes();
+        let size = BULLET_SIZE as f32;
+        if !p.sees(bullet.pos.x, bullet.pos.y, size, size, margin) {
+            continue;
+        }
+        let mut data = vec::Vec::with_capacity(20);
 
-        data.append(&mut pos_x.to_vec());
-        data.append(&mut pos_y.to_vec());
+        let (x, y) = p.to_local_coordinates(bullet.pos.x, bullet.pos.y);
+        data.extend_from_slice(&x.to_be_bytes());
+        data.extend_from_slice(&y.to_be_bytes());
 
         let vx = p.to_local_proportion_horizontal(bullet.dir.x);
         let vy = p.to_local_proportion_vertical(bullet.dir.y);
+        data.extend_from_slice(&vx.to_be_bytes());
+        data.extend_from_slice(&vy.to_be_bytes());
 
-        let speed_x = vx.to_be_bytes();
-        let speed_y = vy.to_be_bytes();
-
-        data.append(&mut speed_x.to_vec());
-        data.append(&mut speed_y.to_vec());
-
-        let _size = p.to_local_proportion_vertical(BULLET_SIZE as f32);
-
-        let size = _size.to_be_bytes();
+        data.extend_from_slice(&p.to_local_proportion_vertical(size).to_be_bytes());
 
-        data.append(&mut size.to_vec());
+        bullet_section.push(bullet.uid, data);
     }
 
-    data.push((powerups.len() as u8).to_be());
-
+    let mut powerup_section = snapshot::Section::new(8);
     for powerup in powerups.iter() {
-        let (x, y) = p.to_local_coordinates(powerup.pos().x, powerup.pos().y);
+        if !p.sees(powerup.pos().x, powerup.pos().y, 0., 0., margin) {
+            continue;
+        }
+        let mut data = vec::Vec::with_capacity(8);
 
-        let pos_x = x.to_be_bytes();
-        let pos_y = y.to_be_bytes();
+        let (x, y) = p.to_local_coordinates(powerup.pos().x, powerup.pos().y);
+        data.extend_from_slice(&x.to_be_bytes());
+        data.extend_from_slice(&y.to_be_bytes());
 
-        data.append(&mut pos_x.to_vec());
-        data.append(&mut pos_y.to_vec());
+        powerup_section.push(powerup.uid(), data);
     }
 
-    p.send(&data)
+    let snapshot = snapshot::Snapshot {
+        header,
+        sections: vec![
+            sprite::Sprite::pack_game_sprites(sprites, p, margin),
+            bullet_section,
+            powerup_section,
+        ],
+    };
+
+    p.send(&history.encode(snapshot, world.tick, packet::MAX_DATA_SIZE))
 }
 
-fn recv_game_data(p: &mut player::Player, sprites: &mut [sprite::Sprite]) {
+/// Apply the inputs of the phone, the malformed ones are dropped
+///
+/// The phone sees the other sprites as they were when the snapshot it acknowledged was made, plus
+/// its interpolation delay
+fn recv_game_data(
+    p: &mut player::Player,
+    world: &mut World,
+    history: &mut snapshot::SnapshotHistory,
+) -> Result<(), Error> {
     let mut buffer = [0_u8; packet::MAX_DATA_SIZE];
-    let mut anex = [0_u8; packet::MAX_DATA_SIZE];
-    let n1 = p.recv(&mut anex).unwrap();
-    buffer.copy_from_slice(&anex);
-    let mut n = p.recv(&mut anex).unwrap();
-    while n > 0 {
-        buffer.copy_from_slice(&anex);
-        n = p.recv(&mut anex).unwrap();
-    }
-    if n1 > 0 {
-        for s in sprites.iter_mut() {
+    loop {
+        let n = p.recv(&mut buffer)?;
+        if n == 0 {
+            return Ok(());
+        }
+        let Ok(input) = Input::unpack(&buffer[..n]) else {
+            continue;
+        };
+
+        history.ack(input.ack);
+        let view_lag = history.acked_tick().map_or(MAX_REWIND_TICKS, |tick| {
+            (world.tick.wrapping_sub(tick) + (INTERPOLATION_DELAY * TICK_RATE as f32) as u32)
+                .min(MAX_REWIND_TICKS)
+        });
+
+        for s in world.sprites.iter_mut() {
             if s.get_id() == p.rank as usize {
-                let mut bb = [0_u8; 4];
-
-                bb.copy_from_slice(&buffer[..4]);
-                s.speed.x = f32::from_be_bytes(bb);
-                bb.copy_from_slice(&buffer[4..8]);
-                s.speed.y = f32::from_be_bytes(bb);
-
-                let mut norm = s.speed.x * s.speed.x + s.speed.y * s.speed.y;
-                norm = norm.sqrt();
-
-                let mut speed_modifiers = 0.;
-                for m in s.get_modifiers().iter() {
-                    if m.get_type() == powerup::Type::SpeedDown
-                        || m.get_type() == powerup::Type::SpeedUp
-                    {
-                        speed_modifiers += m.modifier();
-                    }
-                }
+                s.apply_input(input.seq, input.speed, input.dt, &world.maze);
+                s.set_view_lag(view_lag);
+            }
+        }
+    }
+}
 
-                let limit = (6. + speed_modifiers).max(1.);
+//////////////////////////////////////////////
+///
+///
+/// Tests
+///
+///
+//////////////////////////////////////////////
 
-                if norm > limit {
-                    s.speed.x *= limit / norm;
-                    s.speed.y *= limit / norm;
-                }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::mock;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    const DT: f32 = 1. / 60.;
+
+    /// Game driven tick by tick, the test plays the phones
+    struct Match {
+        world: World,
+        players: Vec<player::Player>,
+        phones: Vec<mock::MockPhone>,
+        histories: Vec<snapshot::SnapshotHistory>,
+        readers: Vec<mock::SnapshotReader>,
+        rng: StdRng,
+        seq: u16,
+    }
+
+    impl Match {
+        /// The maze is empty and there is no power-up, the tests add what they need
+        fn new(screens: &[mock::Screen]) -> Self {
+            let (mut players, mut phones) = mock::room(screens);
+            let mut rng = StdRng::seed_from_u64(0);
+            let mut world = World::new(&mut players, Config::default(), &mut rng);
+            world.maze.clear();
+            world.powerups.clear();
+            for phone in phones.iter_mut() {
+                phone.recv();
+            }
+            Match {
+                world,
+                players,
+                phones,
+                histories: screens
+                    .iter()
+                    .map(|_| snapshot::SnapshotHistory::new())
+                    .collect(),
+                readers: screens
+                    .iter()
+                    .map(|_| mock::SnapshotReader::default())
+                    .collect(),
+                rng,
+                seq: 0,
+            }
+        }
+
+        /// The phone of `rank` sends an input for the next frame
+        fn input(&mut self, rank: usize, x: f32, y: f32) {
+            self.input_lasting(rank, x, y, DT);
+        }
+
+        /// The phone of `rank` sends an input claiming its frame lasted `dt`
+        fn input_lasting(&mut self, rank: usize, x: f32, y: f32, dt: f32) {
+            self.seq += 1;
+            let mut data = vec::Vec::with_capacity(INPUT_SIZE);
+            data.extend_from_slice(&self.seq.to_be_bytes());
+            data.extend_from_slice(&dt.to_be_bytes());
+            data.extend_from_slice(&x.to_be_bytes());
+            data.extend_from_slice(&y.to_be_bytes());
+            data.extend_from_slice(&self.readers[rank].last_seq().to_be_bytes());
+            self.phones[rank].send(&data);
+        }
+
+        /// Run a tick and return the snapshot each phone received
+        fn tick(&mut self) -> Vec<snapshot::Snapshot> {
+            for (p, history) in self.players.iter_mut().zip(self.histories.iter_mut()) {
+                recv_game_data(p, &mut self.world, history).unwrap();
+            }
+            self.world.step(DT, &mut self.rng);
+            for (p, history) in self.players.iter_mut().zip(self.histories.iter_mut()) {
+                send_game_data(p, history, &self.world).unwrap();
+            }
+
+            let mut snapshots = vec::Vec::new();
+            for (phone, reader) in self.phones.iter_mut().zip(self.readers.iter_mut()) {
+                let messages = phone.recv();
+                assert_eq!(messages.len(), 1);
+                snapshots.push(reader.read(&messages[0]));
+            }
+            snapshots
+        }
+    }
+
+    /// Field `index` of an entry of a section, the entries are made of f32
+    fn field(snapshot: &snapshot::Snapshot, section: usize, id: u16, index: usize) -> Option<f32> {
+        let (_, entry) = snapshot.sections[section]
+            .entries
+            .iter()
+            .find(|(i, _)| *i == id)?;
+        let mut bb = [0_u8; 4];
+        bb.copy_from_slice(&entry[index * 4..index * 4 + 4]);
+        Some(f32::from_be_bytes(bb))
+    }
+
+    /// Run a game of 10 seconds with the same inputs and return what the first phone received
+    fn play(seed: u64) -> Vec<u8> {
+        let (mut players, mut phones) = mock::room(&[mock::Screen::new(1000, 1600); 2]);
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut world = World::new(&mut players, Config::default(), &mut rng);
+        for tick in 0..600 {
+            let speed = Vector2 {
+                x: (tick as f32 / 50.).cos() * 5.,
+                y: (tick as f32 / 50.).sin() * 5.,
+            };
+            for s in world.sprites.iter_mut() {
+                s.apply_input(tick as u16 + 1, speed, 1. / 60., &world.maze);
+            }
+            world.step(1. / 60., &mut rng);
+        }
+
+        let mut history = snapshot::SnapshotHistory::new();
+        send_game_data(&mut players[0], &mut history, &world).unwrap();
+        phones[0].recv().last().unwrap().clone()
+    }
+
+    #[test]
+    fn test_same_seed_same_game() {
+        assert_eq!(play(42), play(42));
+        assert_ne!(play(42), play(43));
+    }
+
+    #[test]
+    fn test_malformed_inputs_are_dropped() {
+        let mut game = Match::new(&[mock::Screen::new(1000, 1600)]);
+        let start = field(&game.tick()[0], 0, 0, 0).unwrap();
+
+        game.input(0, f32::NAN, 6.);
+        game.input(0, f32::INFINITY, 0.);
+        game.phones[0].send(&[0xff; INPUT_SIZE - 1]);
+        let x = field(&game.tick()[0], 0, 0, 0).unwrap();
+        assert_eq!(x, start);
+
+        game.input(0, 6., 0.);
+        assert!(field(&game.tick()[0], 0, 0, 0).unwrap() > start);
+    }
+
+    #[test]
+    fn test_inputs_move_no_further_than_a_tick() {
+        let mut game = Match::new(&[mock::Screen::new(1000, 1600)]);
+        let start = field(&game.tick()[0], 0, 0, 0).unwrap();
+
+        // a burst of inputs each claiming the longest frame, all received in the same tick
+        for _ in 0..100 {
+            game.input_lasting(0, 6., 0., 0.1);
+        }
+        let x = field(&game.tick()[0], 0, 0, 0).unwrap();
+        assert!(x > start);
+        assert!(x - start <= 6. * DT * 50. + 0.01);
+    }
+
+    #[test]
+    fn test_walls_stop_sprites() {
+        let mut game = Match::new(&[mock::Screen::new(1000, 1600)]);
+        let start = field(&game.tick()[0], 0, 0, 0).unwrap();
+        let wall = start + 300.;
+        game.world.maze.push(maze::Wall::new(
+            Vector2 { x: wall, y: 0. },
+            Vector2 { x: wall, y: 1600. },
+        ));
+
+        // run to the right for 2 seconds, far enough to cross the wall
+        let mut snapshot = game.tick();
+        for _ in 0..120 {
+            game.input(0, 6., 0.);
+            snapshot = game.tick();
+        }
+        let x = field(&snapshot[0], 0, 0, 0).unwrap();
+        let width = field(&snapshot[0], 0, 0, 4).unwrap();
+        assert!(x > start);
+        assert!(x + width <= wall + 1.);
+    }
+
+    #[test]
+    fn test_powerups_are_picked_up() {
+        let mut game = Match::new(&[mock::Screen::new(1000, 1600)]);
+        let snapshot = game.tick();
+        let x = field(&snapshot[0], 0, 0, 0).unwrap();
+        let y = field(&snapshot[0], 0, 0, 1).unwrap();
+        let width = field(&snapshot[0], 0, 0, 4).unwrap();
+        let under = Vector2 {
+            x: x + 10.,
+            y: y + 10.,
+        };
+        let away = Vector2 { x: 100., y: 100. };
+        game.world.powerups.push(powerup::PowerUp::new(
+            powerup::Type::SizeUp,
+            under,
+            1000,
+            10.,
+        ));
+        game.world.powerups.push(powerup::PowerUp::new(
+            powerup::Type::SizeUp,
+            away,
+            1001,
+            10.,
+        ));
+
+        let snapshot = game.tick();
+        let powerups = &snapshot[0].sections[2].entries;
+        assert_eq!(
+            powerups.iter().map(|(id, _)| *id).collect::<Vec<u16>>(),
+            vec![1001]
+        );
+
+        let bigger = field(&snapshot[0], 0, 0, 4).unwrap();
+        assert_eq!(bigger, width + powerup::Type::SizeUp.get_modifier());
+    }
+
+    #[test]
+    fn test_bullets_damage_sprites() {
+        let mut game = Match::new(&[mock::Screen::new(1000, 1600); 2]);
+        let life = game.tick()[1].header[0];
+
+        // the first sprite shoots to the right at the second one, which doesn't move
+        game.input(0, 6., 0.);
+        let mut snapshots = game.tick();
+        for _ in 0..120 {
+            snapshots = game.tick();
+        }
+        assert!(snapshots[1].header[0] < life);
+        assert_eq!(snapshots[0].header[0], life);
+    }
+
+    /// The second sprite runs away, then a bullet of the first one is put where it stood when the
+    /// shooter last saw it and another where it stands now
+    /// Return whether each of them hit
+    fn shoot_at_running_sprite(lag_compensation: bool) -> (bool, bool) {
+        let mut game = Match::new(&[mock::Screen::new(1000, 1600); 2]);
+        game.world.config.lag_compensation = lag_compensation;
+        game.tick();
+        let seen = game.world.sprites[1].hitbox().unwrap();
+        let lag = 10;
+        for _ in 0..lag {
+            game.input(1, 6., 0.);
+            game.tick();
+        }
+        let now = game.world.sprites[1].hitbox().unwrap();
+        assert!(now.x - seen.x > 10.);
+        let still = Vector2 { x: 0., y: 0. };
+        let y = seen.y + seen.height / 2.;
+        let behind = Vector2 {
+            x: (seen.x + now.x) / 2.,
+            y,
+        };
+        let ahead = Vector2 {
+            x: now.x + now.width - 5.,
+            y,
+        };
+        // the history gets the current tick before the hits are resolved
+        for (pos, uid) in [(behind, 1000), (ahead, 1001)] {
+            game.world
+                .bullets
+                .push(bullet::Bullet::new(pos, still, 0, uid, lag + 1));
+        }
+        game.tick();
+
+        let hit = |uid| !game.world.bullets.iter().any(|b| b.uid == uid);
+        (hit(1000), hit(1001))
+    }
+
+    #[test]
+    fn test_hits_are_judged_where_the_shooter_saw_the_sprite() {
+        assert_eq!(shoot_at_running_sprite(true), (true, false));
+    }
+
+    #[test]
+    fn test_hits_are_judged_at_current_positions_without_lag_compensation() {
+        assert_eq!(shoot_at_running_sprite(false), (false, true));
+    }
+
+    #[test]
+    fn test_last_sprite_standing_wins() {
+        let mut game = Match::new(&[mock::Screen::new(1000, 1600); 2]);
+        game.tick();
+        assert_eq!(game.world.placements(), None);
+
+        // the first sprite shoots the second one until it dies
+        game.input(0, 6., 0.);
+        for _ in 0..2000 {
+            if game.world.placements().is_some() {
+                break;
             }
+            game.tick();
         }
+        let winner = Placement {
+            rank: 0,
+            place: 1,
+            kills: 1,
+            best_lap: None,
+        };
+        let loser = Placement {
+            rank: 1,
+            place: 2,
+            kills: 0,
+            best_lap: None,
+        };
+        assert_eq!(game.world.placements(), Some(vec![winner, loser]));
     }
 }
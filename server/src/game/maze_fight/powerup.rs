@@ -1,5 +1,4 @@
 use super::Vector2;
-use std::time;
 
 pub const POWERUP_COUNT: usize = 6;
 
@@ -45,42 +44,54 @@ impl Type {
 #[derive(Debug, Clone, Copy)]
 pub struct PowerUp {
     t: Type,
+    uid: u16,
     is_activated: bool,
     position: Vector2,
-    timer: time::Instant,
-    time_to_live: u64,
+    // simulation time left before the end of the effect, in seconds
+    time_left: f32,
+    ttl: f32,
     modifier: f32,
 }
 
-const TIME_TO_LIVE: u64 = 10;
+/// Default duration of the effect of a power-up, in seconds
+pub const TIME_TO_LIVE: f32 = 10.;
 
 impl PowerUp {
-    pub fn new(t: Type, position: Vector2) -> Self {
+    /// Power-up whose effect lasts `ttl` seconds once picked up
+    pub fn new(t: Type, position: Vector2, uid: u16, ttl: f32) -> Self {
         PowerUp {
             t,
+            uid,
             is_activated: false,
             position,
-            timer: time::Instant::now(),
-            time_to_live: TIME_TO_LIVE,
+            time_left: ttl,
+            ttl,
             modifier: t.get_modifier(),
         }
     }
 
+    pub fn uid(&self) -> u16 {
+        self.uid
+    }
+
     pub fn pos(&self) -> Vector2 {
         self.position
     }
 
     pub fn is_activated(&self) -> bool {
-        if self.is_activated {
-            self.timer.elapsed().as_secs() < self.time_to_live
-        } else {
-            false
-        }
+        self.is_activated && self.time_left > 0.
     }
 
     pub fn activate(&mut self) {
         self.is_activated = true;
-        self.timer = time::Instant::now();
+        self.time_left = self.ttl;
+    }
+
+    /// Advance the effect of `dt` seconds
+    pub fn elapse(&mut self, dt: f32) {
+        if self.is_activated {
+            self.time_left -= dt;
+        }
     }
 
     pub fn get_type(&self) -> Type {
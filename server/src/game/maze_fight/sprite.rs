@@ -1,70 +1,178 @@
+use crate::game::snapshot;
 use crate::network::player;
 
 use super::bullet;
+use super::history;
 use super::maze;
 use super::powerup;
-use super::weapon;
+use super::Uids;
 use super::Vector2;
 
-use std::time;
 use std::vec;
 
 const SPRITE_WIDTH: f32 = 80.;
 const SPRITE_HEIGHT: f32 = SPRITE_WIDTH * 36. / 30.;
 
+/// Norm of the speed without power-up, the phones predict their movement with it
+const SPEED_LIMIT: f32 = 6.;
+
+/// Longest duration an input can last, so that a phone can't move faster by lying on its frame time
+/// It also caps the movement a sprite can save up while its phone sends no input
+const MAX_INPUT_DT: f32 = 0.1;
+
 pub struct Sprite {
     pos: Vector2,
     pub speed: Vector2,
     id: usize,
-    timer: time::Instant,
+    // time since the last shot, in milliseconds
+    reload_timer: f32,
     skin: usize,
     is_dead: bool,
     modifiers: Vec<powerup::PowerUp>,
     life: usize,
+    // sequence number of the last input applied
+    last_input: u16,
+    // number of ticks the phone sees the other sprites late
+    view_lag: u32,
+    // simulated time the inputs can still move the sprite for, in seconds
+    move_budget: f32,
 }
 
 impl Sprite {
-    pub fn create_sprites(players: &[player::Player]) -> Vec<Self> {
-        let mut sprites = vec::Vec::new();
-        for (i, p) in players.iter().enumerate() {
-            sprites.push(Self {
-                pos: Vector2 {
-                    x: p.physical_width / 2. + p.top_left_x,
-                    y: p.physical_height / 2. + p.top_left_y,
-                },
-                speed: Vector2 { x: 0., y: 0. },
-                id: p.rank as usize,
-                timer: time::Instant::now(),
-                skin: i,
-                is_dead: false,
-                modifiers: vec::Vec::new(),
-                life: 10,
-            });
+    pub fn create_sprites(players: &[player::Player], lives: usize) -> Vec<Self> {
+        players.iter().map(|p| Self::new(p, lives)).collect()
+    }
+
+    /// Sprite of the player, at the center of its screen
+    pub fn new(p: &player::Player, lives: usize) -> Self {
+        Self {
+            pos: Vector2 {
+                x: p.physical_width / 2. + p.top_left_x,
+                y: p.physical_height / 2. + p.top_left_y,
+            },
+            speed: Vector2 { x: 0., y: 0. },
+            id: p.rank as usize,
+            reload_timer: 0.,
+            skin: p.avatar as usize,
+            is_dead: false,
+            modifiers: vec::Vec::new(),
+            life: lives,
+            last_input: 0,
+            view_lag: 0,
+            move_budget: 0.,
         }
-        sprites
     }
 
-    pub fn get_id(&self) -> usize {
-        self.id
+    /// The player left the game, its sprite dies where it is
+    /// Return false if it was already dead
+    pub fn leave(&mut self) -> bool {
+        let was_alive = !self.is_dead;
+        self.life = 0;
+        self.is_dead = true;
+        was_alive
     }
 
-    pub fn get_modifiers(&self) -> &Vec<powerup::PowerUp> {
-        &self.modifiers
+    /// Bring the sprite back on the screens once they shrank to `width` x `height`
+    pub fn keep_within(&mut self, width: f32, height: f32) {
+        self.pos.x = self.pos.x.min(width - SPRITE_WIDTH).max(0.);
+        self.pos.y = self.pos.y.min(height - SPRITE_HEIGHT).max(0.);
+    }
+
+    pub fn get_id(&self) -> usize {
+        self.id
     }
 
     pub fn get_life(&self) -> usize {
         self.life
     }
 
+    pub fn is_dead(&self) -> bool {
+        self.is_dead
+    }
+
+    pub fn get_last_input(&self) -> u16 {
+        self.last_input
+    }
+
+    pub fn set_view_lag(&mut self, ticks: u32) {
+        self.view_lag = ticks;
+    }
+
+    pub fn hitbox(&self) -> Option<history::Hitbox> {
+        if self.is_dead {
+            None
+        } else {
+            Some(history::Hitbox {
+                x: self.pos.x,
+                y: self.pos.y,
+                width: SPRITE_WIDTH,
+                height: SPRITE_HEIGHT,
+            })
+        }
+    }
+
+    /// Move the sprite as the phone asked, inputs older than the last one applied are ignored
+    ///
+    /// The phone predicts its own sprite with the same code, so both have to stay in sync
+    /// The inputs can't move the sprite for longer than the simulated time that passed, however
+    /// many of them the phone sends
+    pub fn apply_input(&mut self, seq: u16, speed: Vector2, dt: f32, maze: &[maze::Wall]) {
+        if (seq.wrapping_sub(self.last_input) as i16) <= 0 {
+            return;
+        }
+        self.last_input = seq;
+
+        self.speed = speed;
+        let norm = (speed.x * speed.x + speed.y * speed.y).sqrt();
+
+        let mut speed_modifiers = 0.;
+        for m in self.modifiers.iter() {
+            if m.get_type() == powerup::Type::SpeedDown || m.get_type() == powerup::Type::SpeedUp {
+                speed_modifiers += m.modifier();
+            }
+        }
+
+        let limit = (SPEED_LIMIT + speed_modifiers).max(1.);
+
+        if norm > limit {
+            self.speed.x *= limit / norm;
+            self.speed.y *= limit / norm;
+        }
+
+        if self.is_dead {
+            return;
+        }
+
+        let dt = dt.clamp(0., MAX_INPUT_DT).min(self.move_budget);
+        self.move_budget -= dt;
+        self.pos.x += self.speed.x * dt * 50.;
+        self.pos.y += self.speed.y * dt * 50.;
+
+        let size_modifiers = self.size_modifiers();
+        for w in maze.iter() {
+            w.realign_sprite(
+                &mut self.pos,
+                (SPRITE_WIDTH + size_modifiers) as usize,
+                (SPRITE_HEIGHT + size_modifiers) as usize,
+            );
+        }
+    }
+
+    /// Shoot and let the power-ups elapse, `firing_period` is the time between two shots without
+    /// power-up, in milliseconds
     pub fn update_sprite_status(
         &mut self,
         maze: &[maze::Wall],
         bullets: &mut Vec<bullet::Bullet>,
-        internal_timer: time::Instant,
+        uids: &mut Uids,
+        firing_period: f32,
+        dt: f32,
     ) {
+        self.move_budget = (self.move_budget + dt).min(MAX_INPUT_DT);
         if !self.is_dead {
+            self.reload_timer += dt * 1000.;
             let mut size_modifiers = 0.;
-            let mut firing_speed_modifiers = weapon::FIRERING_SPEED as f32;
+            let mut firing_speed_modifiers = firing_period;
             for m in self.modifiers.iter() {
                 if m.get_type() == powerup::Type::SizeUp || m.get_type() == powerup::Type::SizeDown
                 {
@@ -78,8 +186,8 @@ impl Sprite {
             }
             let mut norm = self.speed.x * self.speed.x + self.speed.y * self.speed.y;
             norm = norm.sqrt();
-            if self.timer.elapsed().as_millis() > firing_speed_modifiers as u128 && norm > 0. {
-                self.timer = std::time::Instant::now();
+            if self.reload_timer > firing_speed_modifiers && norm > 0. {
+                self.reload_timer = 0.;
                 bullets.push(bullet::Bullet::new(
                     Vector2 {
                         x: self.pos.x + (size_modifiers + SPRITE_WIDTH) / 2.,
@@ -90,12 +198,13 @@ impl Sprite {
                         y: bullet::BULLET_SPEED * self.speed.y / norm,
                     },
                     self.id,
+                    uids.generate(),
+                    self.view_lag,
                 ));
             }
 
-            self.pos.x += self.speed.x * internal_timer.elapsed().as_secs_f32() * 50.;
-            self.pos.y += self.speed.y * internal_timer.elapsed().as_secs_f32() * 50.;
-
+            // the sprite is moved by the inputs of the phone, it only has to stay out of the walls
+            // when its size changes
             for w in maze.iter() {
                 w.realign_sprite(
                     &mut self.pos,
@@ -106,6 +215,7 @@ impl Sprite {
 
             let mut i = 0;
             while i < self.modifiers.len() {
+                self.modifiers[i].elapse(dt);
                 if !self.modifiers[i].is_activated() {
                     self.modifiers.swap_remove(i);
                 } else {
@@ -115,27 +225,35 @@ impl Sprite {
         }
     }
 
-    pub fn update_dead_status(&mut self, bullets: &mut Vec<bullet::Bullet>) {
-        if !self.is_dead {
-            let mut i = 0;
-            while i < bullets.len() {
-                let b = &bullets[i];
-                if b.id != self.id
-                    && b.pos.x > self.pos.x
-                    && b.pos.x < self.pos.x + SPRITE_WIDTH
-                    && b.pos.y > self.pos.y
-                    && b.pos.y < self.pos.y + SPRITE_HEIGHT
-                {
-                    self.life -= 1;
-                    if self.life == 0 {
-                        self.is_dead = true;
-                    }
-                    bullets.remove(i);
-                } else {
-                    i += 1;
+    /// Resolve the hits of the bullets, `index` is the one of the sprite in `history`
+    /// Return the id of the shooter when the sprite dies
+    ///
+    /// With a history, each bullet is tested against the sprite where its shooter saw it
+    pub fn update_dead_status(
+        &mut self,
+        index: usize,
+        bullets: &mut Vec<bullet::Bullet>,
+        history: Option<&history::PositionHistory>,
+    ) -> Option<usize> {
+        let current = self.hitbox()?;
+        let mut i = 0;
+        while i < bullets.len() {
+            let b = &bullets[i];
+            let hitbox = history
+                .and_then(|h| h.rewind(b.lag, index))
+                .unwrap_or(current);
+            if b.id != self.id && hitbox.contains(b.pos.x, b.pos.y) {
+                self.life -= 1;
+                let shooter = bullets.remove(i).id;
+                if self.life == 0 {
+                    self.is_dead = true;
+                    return Some(shooter);
                 }
+            } else {
+                i += 1;
             }
         }
+        None
     }
 
     pub fn update_powerup_status(&mut self, powerups: &mut Vec<powerup::PowerUp>) {
@@ -159,7 +277,7 @@ impl Sprite {
         }
     }
 
-    pub fn pack_sprites(sprites: &[Self]) -> Vec<u8> {
+    pub fn pack_sprites(sprites: &[Self], player: &player::Player) -> Vec<u8> {
         // We need : initial pos, skin, id
         let mut data = vec::Vec::new();
         data.push((sprites.len() as u8).to_be());
@@ -169,61 +287,60 @@ impl Sprite {
             data.push((s.id as u8).to_be());
         }
 
+        // then the sprite of the phone and its scale, to predict its movement
+        data.push(player.rank.to_be());
+        data.extend_from_slice(&player.to_local_proportion_horizontal(1.).to_be_bytes());
+        data.extend_from_slice(&player.to_local_proportion_vertical(1.).to_be_bytes());
+
         data
     }
 
-    pub fn pack_game_sprites(sprites: &[Self], player: &player::Player) -> Vec<u8> {
-        let mut len: u8 = 0;
-        for s in sprites.iter() {
-            if !s.is_dead {
-                len += 1;
-            }
-        }
-
-        let mut data = vec::Vec::new();
-        data.push(len.to_be());
+    /// Section of the snapshot of `player` : the sprites alive on its screen, extended by `margin`
+    pub fn pack_game_sprites(
+        sprites: &[Self],
+        player: &player::Player,
+        margin: f32,
+    ) -> snapshot::Section {
+        let mut section = snapshot::Section::new(24);
 
         for s in sprites.iter() {
-            // we need to send the id, pos, speed and size
-            if !s.is_dead {
-                data.push((s.id as u8).to_be());
+            let size_modifiers = s.size_modifiers();
+            let (width, height) = (
+                size_modifiers + SPRITE_WIDTH,
+                size_modifiers + SPRITE_HEIGHT,
+            );
 
-                let (x, y) = player.to_local_coordinates(s.pos.x, s.pos.y);
+            // we need to send the pos, speed and size, the id is the one of the entry
+            if !s.is_dead && player.sees(s.pos.x, s.pos.y, width, height, margin) {
+                let mut data = vec::Vec::with_capacity(24);
 
-                let pos_x = x.to_be_bytes();
-                let pos_y = y.to_be_bytes();
-
-                data.append(&mut pos_x.to_vec());
-                data.append(&mut pos_y.to_vec());
+                let (x, y) = player.to_local_coordinates(s.pos.x, s.pos.y);
+                data.extend_from_slice(&x.to_be_bytes());
+                data.extend_from_slice(&y.to_be_bytes());
 
                 let vx = player.to_local_proportion_horizontal(s.speed.x);
-                let vy = player.to_local_proportion_horizontal(s.speed.y);
-
-                let speed_x = vx.to_be_bytes();
-                let speed_y = vy.to_be_bytes();
+                let vy = player.to_local_proportion_vertical(s.speed.y);
+                data.extend_from_slice(&vx.to_be_bytes());
+                data.extend_from_slice(&vy.to_be_bytes());
 
-                data.append(&mut speed_x.to_vec());
-                data.append(&mut speed_y.to_vec());
+                let width = player.to_local_proportion_horizontal(width);
+                let height = player.to_local_proportion_vertical(height);
+                data.extend_from_slice(&width.to_be_bytes());
+                data.extend_from_slice(&height.to_be_bytes());
 
-                let mut size_modifiers = 0.;
-                for m in s.modifiers.iter() {
-                    if m.get_type() == powerup::Type::SizeUp
-                        || m.get_type() == powerup::Type::SizeDown
-                    {
-                        size_modifiers += m.modifier();
-                    }
-                }
-
-                let _width = player.to_local_proportion_horizontal(size_modifiers + SPRITE_WIDTH);
-                let _height = player.to_local_proportion_vertical(size_modifiers + SPRITE_HEIGHT);
-
-                let width = _width.to_be_bytes();
-                let height = _height.to_be_bytes();
+                section.push(s.id as u16, data);
+            }
+        }
+        section
+    }
 
-                data.append(&mut width.to_vec());
-                data.append(&mut height.to_vec());
+    fn size_modifiers(&self) -> f32 {
+        let mut size_modifiers = 0.;
+        for m in self.modifiers.iter() {
+            if m.get_type() == powerup::Type::SizeUp || m.get_type() == powerup::Type::SizeDown {
+                size_modifiers += m.modifier();
             }
         }
-        data
+        size_modifiers
     }
 }
@@ -1,2 +1,176 @@
+pub mod context;
 pub mod maze_fight;
 pub mod racer;
+pub mod scheduler;
+pub mod settings;
+pub mod snapshot;
+
+use crate::network::client::Game;
+use crate::network::{packet, player};
+use context::GameContext;
+use settings::{Setting, Settings};
+use std::io::Error;
+use std::ops::RangeInclusive;
+use std::time;
+
+//////////////////////////////////////////////
+///
+///
+/// Catalogue
+///
+///
+//////////////////////////////////////////////
+
+/// What the phones are told about a game the server runs
+pub struct Info {
+    pub game: Game,
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Number of players the room needs to be locked for the game
+    pub players: RangeInclusive<usize>,
+    /// Players may join and leave while the game runs, the game follows them with
+    /// `GameContext::reflow`
+    pub late_join: bool,
+}
+
+/// Games offered to the phones, in the order of their menu
+pub const CATALOGUE: &[&Info] = &[&racer::INFO, &maze_fight::INFO];
+
+/// Info of `game`, None if the server doesn't run it
+/// The test game isn't offered to the phones, but it can be locked like the others
+pub fn info(game: Game) -> Option<&'static Info> {
+    match game {
+        Game::Test => Some(&TEST_INFO),
+        _ => CATALOGUE.iter().find(|i| i.game == game).copied(),
+    }
+}
+
+/// The catalogue as sent to the phones, with the settings of each game
+pub fn listings() -> Vec<packet::Listing> {
+    CATALOGUE
+        .iter()
+        .map(|i| packet::Listing {
+            game: i.game.into(),
+            name: i.name.to_string(),
+            description: i.description.to_string(),
+            min_players: *i.players.start() as u8,
+            max_players: *i.players.end() as u8,
+            settings: settings(i.game)
+                .iter()
+                .map(|s| packet::ListedSetting {
+                    name: s.name.to_string(),
+                    kind: s.kind,
+                    default: s.default,
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Outcome
+///
+///
+//////////////////////////////////////////////
+
+/// How a player ended a game, kept in the statistics of the players
+#[derive(Debug, Clone, PartialEq)]
+pub struct Placement {
+    pub rank: u8,
+    // 1 for the winner
+    pub place: u8,
+    pub kills: u32,
+    // only for the games timing laps
+    pub best_lap: Option<time::Duration>,
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Dispatch
+///
+///
+//////////////////////////////////////////////
+
+/// Settings the master can change before playing `game`
+pub fn settings(game: Game) -> &'static [Setting] {
+    match game {
+        Game::Racer => racer::SETTINGS,
+        Game::MazeFight => maze_fight::SETTINGS,
+        _ => &[],
+    }
+}
+
+/// Run the game `game` until it ends, shared by the rooms and the replay tool
+/// Return the placements of the players, empty if the game doesn't rank them
+/// The games letting players join and leave keep `players` up to date as they go
+pub fn play(
+    game: Game,
+    settings: &Settings,
+    players: &mut Vec<player::Player>,
+    ctx: &mut GameContext,
+) -> Result<Vec<Placement>, Error> {
+    match game {
+        Game::Racer => racer::racer(players, ctx).map(|_| Vec::new()),
+        Game::Snake => Ok(Vec::new()),
+        Game::MazeFight => maze_fight::maze_fight(players, settings.into(), ctx),
+        Game::Test => test_function(players, ctx),
+        Game::Unknown => Ok(Vec::new()),
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Test server
+///
+///
+//////////////////////////////////////////////
+
+/// Data ending the test game, the player sending it wins and the others follow by rank
+pub const TEST_END: &[u8] = b"END";
+
+const TEST_INFO: Info = Info {
+    game: Game::Test,
+    name: "Test",
+    description: "Forwards the messages of each of the first two players to the other",
+    players: 1..=16,
+    late_join: false,
+};
+
+fn test_function(
+    players: &mut [player::Player],
+    ctx: &mut GameContext,
+) -> Result<Vec<Placement>, Error> {
+    let mut buffer = [0_u8; packet::MAX_DATA_SIZE];
+    loop {
+        for from in 0..players.len().min(2) {
+            let size = players[from].recv(&mut buffer)?;
+            if buffer[..size] == *TEST_END {
+                return Ok(test_placements(players, from));
+            }
+            if size > 0 && players.len() > 1 {
+                players[1 - from].send(&buffer)?;
+            }
+        }
+        ctx.clock.sleep(time::Duration::from_millis(10));
+    }
+}
+
+fn test_placements(players: &[player::Player], winner: usize) -> Vec<Placement> {
+    let mut order: Vec<&player::Player> = players.iter().collect();
+    let first = order.remove(winner);
+    order.insert(0, first);
+    order
+        .iter()
+        .enumerate()
+        .map(|(i, p)| Placement {
+            rank: p.rank,
+            place: i as u8 + 1,
+            kills: 0,
+            best_lap: None,
+        })
+        .collect()
+}
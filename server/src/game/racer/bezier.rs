@@ -1,5 +1,4 @@
 use rand::Rng;
-use rand::SeedableRng;
 
 #[derive(Debug, Clone, Copy, Default, PartialEq)]
 pub struct Point(pub f64, pub f64);
@@ -189,9 +188,12 @@ impl Bezier {
     /// Transform this list of pairs into a list of Bezier curves with starting and ending points corresponding to each pair.
     /// The pairs (ok, ik+1) correspond to temp curves that are used to generate smooth transitions between two curves by enforcing their control point to be the symmetry of the surronding curves.
     ///
-    pub fn random_map(dimensions: &Vec<(f64, f64)>, io_points: Data) -> Vec<Self> {
-        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
-
+    /// The curves are drawn from `rng`, the one of the room, so a seed always gives the same map
+    pub fn random_map<R: Rng>(
+        dimensions: &[(f64, f64)],
+        io_points: Data,
+        rng: &mut R,
+    ) -> Vec<Self> {
         let (_total_width, total_height) =
             dimensions
                 .iter()
@@ -2,14 +2,62 @@
 mod bezier;
 mod vehicle;
 
-use crate::network::player;
+use super::context::GameContext;
+use super::scheduler;
+use super::settings::Setting;
+use super::Info;
+use crate::network::client::Game;
+use crate::network::{packet, player};
+use std::io::Error;
 
-pub fn racer(players: &[player::Player]) {
-    // We first want to get the phisical as well as the rendering size (cause I fucked up in the protocol and at this point it's easier to do it like that)
+/// Rate at which the inputs are processed and rate at which the cars are sent to the phones, in Hz
+const TICK_RATE: u32 = 60;
+const NETWORK_RATE: u32 = 30;
 
-    // Then we want to send the map, unfortunatly we don't have anything working so I have to build a small map on the fly
+pub const INFO: Info = Info {
+    game: Game::Racer,
+    name: "Racer",
+    description: "Race around a track drawn across the phones, touch the screen to accelerate",
+    players: 2..=8,
+    late_join: false,
+};
 
-    // Finally we just have to run the game, simple state machine I hope ... need to use very intensivally to_bits
+/// The cars are simulated on the phones, they apply these settings themselves, in percent of
+/// their own defaults
+pub const SETTINGS: &[Setting] = &[
+    Setting::number("Acceleration (%)", 100, 25, 400, 25),
+    Setting::number("Top speed (%)", 100, 50, 200, 10),
+];
 
-    // We dont care of who win or loose or anything I DONT CARE I JUST WANT FUCKING CARS WORKING
+pub fn racer(players: &mut [player::Player], ctx: &mut GameContext) -> Result<(), Error> {
+    // For now the cars are simulated on the phones : the server only relays the last state
+    // of each car to the phone of the next rank, at a fixed rate
+
+    let mut states: Vec<Option<Vec<u8>>> = vec![None; players.len()];
+    let mut buffer = [0_u8; packet::MAX_DATA_SIZE];
+
+    let mut scheduler =
+        scheduler::Scheduler::new("Racer", TICK_RATE, NETWORK_RATE, ctx.clock.as_ref());
+
+    loop {
+        let frame = scheduler.next_frame(ctx.clock.as_mut());
+
+        for (i, p) in players.iter_mut().enumerate() {
+            let mut n = p.recv(&mut buffer)?;
+            while n > 0 {
+                states[i] = Some(buffer[..n].to_vec());
+                n = p.recv(&mut buffer)?;
+            }
+        }
+
+        if frame.send {
+            for i in 0..players.len() {
+                let next_rank = (players[i].rank as usize + 1) % players.len();
+                let next = players.iter().position(|p| p.rank as usize == next_rank);
+                if let Some(state) = next.and_then(|j| states[j].as_ref()) {
+                    players[i].send(state)?;
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,162 @@
+use super::context::Clock;
+use crate::metrics::metrics;
+use log::warn;
+use std::time;
+
+//////////////////////////////////////////////
+///
+///
+/// Constants
+///
+///
+//////////////////////////////////////////////
+
+/// Maximum number of simulation steps run in one frame to catch up, the remaining time is dropped
+const MAX_CATCH_UP_STEPS: u32 = 5;
+
+//////////////////////////////////////////////
+///
+///
+/// Frame
+///
+///
+//////////////////////////////////////////////
+
+/// What the game loop has to do for the current frame
+pub struct Frame {
+    /// Number of simulation steps of `Scheduler::dt` to run
+    pub steps: u32,
+    /// True if the state should be sent to the players
+    pub send: bool,
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Scheduler
+///
+///
+//////////////////////////////////////////////
+
+/// Fixed timestep scheduler : the simulation always advance of the same `dt`, whatever the load of
+/// the machine, and the state is sent at a separate (lower) network rate
+pub struct Scheduler {
+    target: String,
+
+    tick: time::Duration,
+    network_tick: time::Duration,
+
+    accumulator: time::Duration,
+    network_accumulator: time::Duration,
+    // time of the clock at the last frame
+    last_update: time::Duration,
+
+    // number of frames where the loop was late of more than one tick
+    overruns: u64,
+
+    // real time at the end of the last frame, to measure the work of the game in the metrics
+    frame_start: Option<time::Instant>,
+}
+
+impl Scheduler {
+    pub fn new(target: &str, tick_rate: u32, network_rate: u32, clock: &dyn Clock) -> Self {
+        assert!(tick_rate > 0 && network_rate > 0, "rates must be positive");
+        Scheduler {
+            target: target.to_string(),
+            tick: time::Duration::from_secs(1) / tick_rate,
+            network_tick: time::Duration::from_secs(1) / network_rate,
+            accumulator: time::Duration::ZERO,
+            // the first frame always sends the state
+            network_accumulator: time::Duration::from_secs(1) / network_rate,
+            last_update: clock.now(),
+            overruns: 0,
+            frame_start: None,
+        }
+    }
+
+    /// Duration of a simulation step in seconds
+    pub fn dt(&self) -> f32 {
+        self.tick.as_secs_f32()
+    }
+
+    /// Wait for the next tick and return what the game loop has to do
+    pub fn next_frame(&mut self, clock: &mut dyn Clock) -> Frame {
+        if let Some(start) = self.frame_start {
+            metrics().tick(&self.target, start.elapsed());
+        }
+        let elapsed = clock.now() - self.last_update;
+        if self.accumulator + elapsed < self.tick {
+            clock.sleep(self.tick - self.accumulator - elapsed);
+        }
+        let now = clock.now();
+        self.accumulator += now - self.last_update;
+        self.last_update = now;
+
+        let mut steps = (self.accumulator.as_nanos() / self.tick.as_nanos()) as u32;
+        if steps > 1 {
+            self.overruns += 1;
+        }
+        if steps > MAX_CATCH_UP_STEPS {
+            warn!(target: self.target.as_str(), "simulation overrun #{} : {:?} late, dropping {} steps", self.overruns, self.accumulator, steps - MAX_CATCH_UP_STEPS);
+            steps = MAX_CATCH_UP_STEPS;
+            self.accumulator = self.tick * steps;
+        }
+        self.accumulator -= self.tick * steps;
+
+        self.network_accumulator += self.tick * steps;
+        let send = self.network_accumulator >= self.network_tick;
+        if send {
+            // never send twice in a row to catch up, the state is the same anyway
+            self.network_accumulator = (self.network_accumulator - self.network_tick)
+                .min(self.network_tick - time::Duration::from_nanos(1));
+        }
+
+        self.frame_start = Some(time::Instant::now());
+        Frame { steps, send }
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Tests
+///
+///
+//////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::super::context::ManualClock;
+    use super::*;
+
+    #[test]
+    fn test_constant_dt() {
+        let scheduler = Scheduler::new("test", 50, 10, &ManualClock::new());
+        assert_eq!(scheduler.dt(), 0.02);
+    }
+
+    #[test]
+    fn test_manual_steps() {
+        let mut clock = ManualClock::new();
+        let mut scheduler = Scheduler::new("test", 100, 25, &clock);
+        let mut sends = 0;
+        for _ in 0..100 {
+            let frame = scheduler.next_frame(&mut clock);
+            assert_eq!(frame.steps, 1);
+            sends += frame.send as u32;
+        }
+        // one send every four ticks, plus the first frame
+        assert_eq!(sends, 26);
+        assert_eq!(clock.now(), time::Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_overrun_is_capped() {
+        let mut clock = ManualClock::new();
+        let mut scheduler = Scheduler::new("test", 1000, 10, &clock);
+        clock.advance(time::Duration::from_millis(50));
+        let frame = scheduler.next_frame(&mut clock);
+        assert_eq!(frame.steps, MAX_CATCH_UP_STEPS);
+        assert_eq!(scheduler.overruns, 1);
+    }
+}
@@ -0,0 +1,170 @@
+use std::io::{Error, ErrorKind};
+
+/// Each game publishes the settings the master of a room can change before locking it, with
+/// their defaults and bounds. The values chosen are sent with the Lock packet, checked by the
+/// server, and sent back to every phone with the Launch packet, in the order of the schema :
+///     count (u8) | value (u16) per setting
+/// A Lock packet without data keeps the defaults.
+
+//////////////////////////////////////////////
+///
+///
+/// Schema
+///
+///
+//////////////////////////////////////////////
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    /// 0 or 1
+    Toggle,
+    /// Between `min` and `max` included, the phones change it by `step`
+    Number { min: u16, max: u16, step: u16 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Setting {
+    pub name: &'static str,
+    pub kind: Kind,
+    pub default: u16,
+}
+
+impl Setting {
+    pub const fn toggle(name: &'static str, default: bool) -> Self {
+        Setting {
+            name,
+            kind: Kind::Toggle,
+            default: default as u16,
+        }
+    }
+
+    pub const fn number(name: &'static str, default: u16, min: u16, max: u16, step: u16) -> Self {
+        Setting {
+            name,
+            kind: Kind::Number { min, max, step },
+            default,
+        }
+    }
+
+    fn check(&self, value: u16) -> Result<(), Error> {
+        let (min, max) = match self.kind {
+            Kind::Toggle => (0, 1),
+            Kind::Number { min, max, .. } => (min, max),
+        };
+        if value < min || value > max {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{} must be between {min} and {max}, not {value}", self.name),
+            ));
+        }
+        Ok(())
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Settings
+///
+///
+//////////////////////////////////////////////
+
+/// Values of the settings of a game, in the order of its schema
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Settings {
+    values: Vec<u16>,
+}
+
+impl Settings {
+    pub fn defaults(schema: &[Setting]) -> Self {
+        Settings {
+            values: schema.iter().map(|s| s.default).collect(),
+        }
+    }
+
+    /// Settings of `schema`, refused if a value is missing or out of its bounds
+    pub fn from_values(schema: &[Setting], values: Vec<u16>) -> Result<Self, Error> {
+        if values.len() != schema.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{} settings expected, not {}", schema.len(), values.len()),
+            ));
+        }
+        for (setting, value) in schema.iter().zip(values.iter()) {
+            setting.check(*value)?;
+        }
+        Ok(Settings { values })
+    }
+
+    /// Settings packed by `pack`, the defaults of `schema` if there is no data
+    pub fn unpack(schema: &[Setting], data: &[u8]) -> Result<Self, Error> {
+        let Some((count, data)) = data.split_first() else {
+            return Ok(Settings::defaults(schema));
+        };
+        if data.len() < 2 * *count as usize {
+            return Err(Error::new(ErrorKind::InvalidData, "settings too short"));
+        }
+        let values = data
+            .chunks_exact(2)
+            .take(*count as usize)
+            .map(|v| u16::from_be_bytes([v[0], v[1]]))
+            .collect();
+        Settings::from_values(schema, values)
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut data = vec![self.values.len() as u8];
+        for value in self.values.iter() {
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+        data
+    }
+
+    /// Value of the setting at `index` in the schema
+    pub fn get(&self, index: usize) -> u16 {
+        self.values[index]
+    }
+
+    pub fn values(&self) -> &[u16] {
+        &self.values
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Tests
+///
+///
+//////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEMA: &[Setting] = &[
+        Setting::number("Lives", 10, 1, 50, 1),
+        Setting::toggle("Lag compensation", true),
+    ];
+
+    #[test]
+    fn test_settings_are_checked() {
+        assert_eq!(Settings::unpack(SCHEMA, &[]).unwrap().values(), &[10, 1]);
+
+        let settings = Settings::from_values(SCHEMA, vec![3, 0]).unwrap();
+        assert_eq!(
+            Settings::unpack(SCHEMA, &settings.pack()).unwrap(),
+            settings
+        );
+        assert_eq!(settings.get(0), 3);
+
+        // out of bounds, missing or truncated values
+        assert!(Settings::from_values(SCHEMA, vec![0, 1]).is_err());
+        assert!(Settings::from_values(SCHEMA, vec![51, 1]).is_err());
+        assert!(Settings::from_values(SCHEMA, vec![3, 2]).is_err());
+        assert!(Settings::unpack(SCHEMA, &[1, 0, 3]).is_err());
+        assert!(Settings::unpack(SCHEMA, &[2, 0, 3, 0]).is_err());
+        assert!(Settings::unpack(SCHEMA, &[3, 0, 3, 0, 1, 0, 1]).is_err());
+        assert!(Settings::unpack(&[], &[]).unwrap().values().is_empty());
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind};
+use std::vec;
+
+//////////////////////////////////////////////
+///
+///
+/// Constants
+///
+///
+//////////////////////////////////////////////
+
+/// Number of snapshots kept per phone to be used as baseline, the client keeps as many
+pub const HISTORY_SIZE: usize = 32;
+
+/// Sequence number meaning "no baseline", the snapshot is then complete
+pub const NO_BASELINE: u16 = 0;
+
+//////////////////////////////////////////////
+///
+///
+/// Snapshot
+///
+///
+//////////////////////////////////////////////
+
+/// Entities of one kind : every entity is identified by an id and serialized on `entry_size` bytes
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+    pub entry_size: u8,
+    pub entries: Vec<(u16, Vec<u8>)>,
+}
+
+impl Section {
+    pub fn new(entry_size: u8) -> Self {
+        Section {
+            entry_size,
+            entries: vec::Vec::new(),
+        }
+    }
+
+    /// Add an entity, `data` has to be `entry_size` long
+    pub fn push(&mut self, id: u16, data: Vec<u8>) {
+        debug_assert_eq!(data.len(), self.entry_size as usize);
+        self.entries.push((id, data));
+    }
+
+    fn get(&self, id: u16) -> Option<&Vec<u8>> {
+        self.entries.iter().find(|(i, _)| *i == id).map(|(_, d)| d)
+    }
+}
+
+/// State of the game seen by one phone : a small header always sent as is and sections of entities
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub header: Vec<u8>,
+    pub sections: Vec<Section>,
+}
+
+impl Snapshot {
+    /// Encode the snapshot as a delta against `baseline`, or completely if there is none
+    ///
+    /// Format : seq (u16) | baseline seq (u16) | header len (u8) | header | section count (u8) |
+    /// for each section : entry size (u8) | upserts (u16) | [id (u16) | entry]* | removed (u16) | [id (u16)]*
+    pub fn encode(&self, seq: u16, baseline: Option<(u16, &Snapshot)>) -> Vec<u8> {
+        let mut data = vec::Vec::new();
+        data.extend_from_slice(&seq.to_be_bytes());
+        data.extend_from_slice(&baseline.map_or(NO_BASELINE, |(s, _)| s).to_be_bytes());
+        data.push(self.header.len() as u8);
+        data.extend_from_slice(&self.header);
+        data.push(self.sections.len() as u8);
+
+        for (i, section) in self.sections.iter().enumerate() {
+            let base = baseline.and_then(|(_, b)| b.sections.get(i));
+
+            let upserts: Vec<&(u16, Vec<u8>)> = section
+                .entries
+                .iter()
+                .filter(|(id, entry)| base.and_then(|b| b.get(*id)) != Some(entry))
+                .collect();
+            let removed: Vec<u16> = match base {
+                Some(b) => b
+                    .entries
+                    .iter()
+                    .map(|(id, _)| *id)
+                    .filter(|id| section.get(*id).is_none())
+                    .collect(),
+                None => vec::Vec::new(),
+            };
+
+            data.push(section.entry_size);
+            data.extend_from_slice(&(upserts.len() as u16).to_be_bytes());
+            for (id, entry) in upserts {
+                data.extend_from_slice(&id.to_be_bytes());
+                data.extend_from_slice(entry);
+            }
+            data.extend_from_slice(&(removed.len() as u16).to_be_bytes());
+            for id in removed {
+                data.extend_from_slice(&id.to_be_bytes());
+            }
+        }
+        data
+    }
+
+    /// Decode a snapshot, `baseline` has to be the snapshot designated by `baseline_seq`
+    /// The phones have their own decoder, this one is meant for tests and tools
+    #[allow(unused)]
+    pub fn decode(data: &[u8], baseline: Option<&Snapshot>) -> Result<(u16, Snapshot), Error> {
+        let mut reader = Reader { data, index: 0 };
+        let seq = reader.u16()?;
+        let baseline_seq = reader.u16()?;
+        let baseline = match (baseline_seq, baseline) {
+            (NO_BASELINE, _) => None,
+            (_, Some(b)) => Some(b),
+            (_, None) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("missing baseline {baseline_seq}"),
+                ))
+            }
+        };
+
+        let header_len = reader.u8()? as usize;
+        let header = reader.bytes(header_len)?.to_vec();
+
+        let section_count = reader.u8()? as usize;
+        let mut sections = vec::Vec::with_capacity(section_count);
+        for i in 0..section_count {
+            let entry_size = reader.u8()?;
+            let mut section = match baseline.and_then(|b| b.sections.get(i)) {
+                Some(b) if b.entry_size == entry_size => b.clone(),
+                _ => Section::new(entry_size),
+            };
+
+            let upserts = reader.u16()?;
+            for _ in 0..upserts {
+                let id = reader.u16()?;
+                let entry = reader.bytes(entry_size as usize)?.to_vec();
+                match section.entries.iter_mut().find(|(i, _)| *i == id) {
+                    Some((_, e)) => *e = entry,
+                    None => section.entries.push((id, entry)),
+                }
+            }
+            let removed = reader.u16()?;
+            for _ in 0..removed {
+                let id = reader.u16()?;
+                section.entries.retain(|(i, _)| *i != id);
+            }
+            sections.push(section);
+        }
+
+        Ok((seq, Snapshot { header, sections }))
+    }
+
+    /// Remove the last entity of the biggest section, return false if there was nothing to remove
+    fn trim(&mut self) -> bool {
+        match self
+            .sections
+            .iter_mut()
+            .filter(|s| !s.entries.is_empty())
+            .max_by_key(|s| s.entries.len() * (s.entry_size as usize + 2))
+        {
+            Some(section) => {
+                section.entries.pop();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// History
+///
+///
+//////////////////////////////////////////////
+
+/// Snapshots sent to one phone, used to delta encode against the last one it acknowledged
+pub struct SnapshotHistory {
+    next_seq: u16,
+    // sequence number, simulation tick of the state and snapshot
+    sent: VecDeque<(u16, u32, Snapshot)>,
+    acked: Option<u16>,
+}
+
+impl Default for SnapshotHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SnapshotHistory {
+    pub fn new() -> Self {
+        SnapshotHistory {
+            next_seq: 1,
+            sent: VecDeque::with_capacity(HISTORY_SIZE),
+            acked: None,
+        }
+    }
+
+    /// Record that the phone received the snapshot `seq`
+    pub fn ack(&mut self, seq: u16) {
+        if seq != NO_BASELINE && self.sent.iter().any(|(s, _, _)| *s == seq) {
+            self.acked = Some(seq);
+        }
+    }
+
+    /// Simulation tick of the last snapshot acknowledged by the phone
+    pub fn acked_tick(&self) -> Option<u32> {
+        self.acked
+            .and_then(|a| self.sent.iter().find(|(s, _, _)| *s == a))
+            .map(|(_, tick, _)| *tick)
+    }
+
+    /// Encode the snapshot of the simulation tick `tick` against the last acknowledged one,
+    /// entities are dropped if it doesn't fit in `max_size` bytes
+    pub fn encode(&mut self, mut snapshot: Snapshot, tick: u32, max_size: usize) -> Vec<u8> {
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1).max(1);
+
+        let baseline = self
+            .acked
+            .and_then(|a| self.sent.iter().find(|(s, _, _)| *s == a))
+            .map(|(s, _, b)| (*s, b));
+
+        let mut data = snapshot.encode(seq, baseline);
+        while data.len() > max_size && snapshot.trim() {
+            data = snapshot.encode(seq, baseline);
+        }
+
+        if self.sent.len() == HISTORY_SIZE {
+            self.sent.pop_front();
+        }
+        self.sent.push_back((seq, tick, snapshot));
+        data
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Helpers
+///
+///
+//////////////////////////////////////////////
+
+#[allow(unused)]
+struct Reader<'a> {
+    data: &'a [u8],
+    index: usize,
+}
+
+#[allow(unused)]
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        if self.index + n > self.data.len() {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "truncated snapshot"));
+        }
+        let res = &self.data[self.index..self.index + n];
+        self.index += n;
+        Ok(res)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Tests
+///
+///
+//////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(entities: &[(u16, u8)]) -> Snapshot {
+        let mut section = Section::new(2);
+        for (id, value) in entities {
+            section.push(*id, vec![*value, *value]);
+        }
+        Snapshot {
+            header: vec![42],
+            sections: vec![section],
+        }
+    }
+
+    #[test]
+    fn test_full_snapshot() {
+        let mut history = SnapshotHistory::new();
+        let snap = snapshot(&[(1, 1), (2, 2)]);
+        let data = history.encode(snap.clone(), 0, 2000);
+        let (seq, decoded) = Snapshot::decode(&data, None).unwrap();
+        assert_eq!(seq, 1);
+        assert_eq!(decoded, snap);
+    }
+
+    #[test]
+    fn test_delta_snapshot() {
+        let mut history = SnapshotHistory::new();
+        let first = snapshot(&[(1, 1), (2, 2), (3, 3)]);
+        let full = history.encode(first.clone(), 0, 2000);
+        history.ack(1);
+        assert_eq!(history.acked_tick(), Some(0));
+
+        let second = snapshot(&[(1, 1), (3, 4), (5, 5)]);
+        let delta = history.encode(second.clone(), 1, 2000);
+        // only 3 and 5 are sent, 2 is removed
+        assert!(delta.len() < full.len());
+
+        let (_, baseline) = Snapshot::decode(&full, None).unwrap();
+        let (seq, mut decoded) = Snapshot::decode(&delta, Some(&baseline)).unwrap();
+        assert_eq!(seq, 2);
+        decoded.sections[0].entries.sort();
+        assert_eq!(decoded, second);
+
+        // the baseline is mandatory
+        assert!(Snapshot::decode(&delta, None).is_err());
+    }
+
+    #[test]
+    fn test_snapshot_too_large() {
+        let mut history = SnapshotHistory::new();
+        let entities: Vec<(u16, u8)> = (0..100).map(|i| (i, i as u8)).collect();
+        let data = history.encode(snapshot(&entities), 0, 100);
+        assert!(data.len() <= 100);
+        assert!(Snapshot::decode(&data, None).is_ok());
+    }
+
+    #[test]
+    fn test_truncated_snapshot() {
+        let mut history = SnapshotHistory::new();
+        let data = history.encode(snapshot(&[(1, 1)]), 0, 2000);
+        for i in 0..data.len() {
+            assert!(Snapshot::decode(&data[..i], None).is_err());
+        }
+    }
+}
@@ -0,0 +1,9 @@
+// The sections of the modules are delimited by doc banners followed by an empty line
+#![allow(clippy::empty_line_after_doc_comments)]
+
+pub mod game;
+pub mod logging;
+pub mod metrics;
+pub mod network;
+pub mod replay;
+pub mod stats;
@@ -0,0 +1,339 @@
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::{btree_map::Entry, BTreeMap};
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, ErrorKind, Write};
+use std::path::PathBuf;
+use std::sync::{Mutex, RwLock};
+use std::time::SystemTime;
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+/// Log system of the server
+///
+/// The level is chosen per target with a spec like `info,Packet=debug,Room 3=trace` : a target is
+/// matched by the longest key that is the target itself or its first words (`Client` matches
+/// `Client 4 (1.2.3.4:5678)`, `Room 1` doesn't match `Room 12`).
+/// Each thread carries the session, room and game it works for, they are printed with the records
+/// in JSON lines, and the records of a room go to its own file when a directory is given.
+
+//////////////////////////////////////////////
+///
+///
+/// Filter
+///
+///
+//////////////////////////////////////////////
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    default: LevelFilter,
+    // sorted from the longest key, so that the first match is the most specific
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter {
+            default: LevelFilter::Info,
+            targets: Vec::new(),
+        }
+    }
+}
+
+impl Filter {
+    /// Parse a spec of comma separated `level` or `target=level`
+    pub fn parse(spec: &str) -> Result<Filter, Error> {
+        let mut filter = Filter::default();
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((target, level)) => filter
+                    .targets
+                    .push((target.trim().to_string(), parse_level(level)?)),
+                None => filter.default = parse_level(directive)?,
+            }
+        }
+        filter
+            .targets
+            .sort_by_key(|(target, _)| Reverse(target.len()));
+        Ok(filter)
+    }
+
+    /// Level of the records shown for `target`
+    pub fn level(&self, target: &str) -> LevelFilter {
+        self.targets
+            .iter()
+            .find(|(key, _)| {
+                target == key
+                    || (target.starts_with(key.as_str()) && target[key.len()..].starts_with(' '))
+            })
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+
+    fn max(&self) -> LevelFilter {
+        self.targets
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, |a, b| a.max(b))
+    }
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, Error> {
+    level.trim().parse().map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("unknown log level : {}", level.trim()),
+        )
+    })
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Context
+///
+///
+//////////////////////////////////////////////
+
+/// What the current thread works for
+#[derive(Debug, Clone, Default)]
+pub struct Context {
+    pub session: Option<u16>,
+    pub room: Option<u16>,
+    pub game: Option<String>,
+}
+
+thread_local! {
+    static CONTEXT: RefCell<Context> = RefCell::new(Context::default());
+}
+
+/// Session the current thread handles
+pub fn set_session(session: u16) {
+    CONTEXT.with(|c| c.borrow_mut().session = Some(session));
+}
+
+/// Room the current thread works for, its records go to the file of the room
+pub fn set_room(room: u16) {
+    CONTEXT.with(|c| c.borrow_mut().room = Some(room));
+}
+
+/// Game played by the current thread, None once it is over
+pub fn set_game(game: Option<String>) {
+    CONTEXT.with(|c| c.borrow_mut().game = game);
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Logger
+///
+///
+//////////////////////////////////////////////
+
+struct Logger {
+    filter: RwLock<Filter>,
+    json: RwLock<bool>,
+    room_dir: RwLock<Option<PathBuf>>,
+    room_files: Mutex<BTreeMap<u16, File>>,
+}
+
+static LOGGER: Logger = Logger {
+    filter: RwLock::new(Filter {
+        default: LevelFilter::Info,
+        targets: Vec::new(),
+    }),
+    json: RwLock::new(false),
+    room_dir: RwLock::new(None),
+    room_files: Mutex::new(BTreeMap::new()),
+};
+
+/// Install the logger, does nothing if it is already installed
+pub fn install() {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(LOGGER.filter.read().unwrap().max());
+    }
+}
+
+/// Change the levels of the targets, at any time
+pub fn set_filter(filter: Filter) {
+    log::set_max_level(filter.max());
+    *LOGGER.filter.write().unwrap() = filter;
+}
+
+/// Print the records as JSON lines instead of columns
+pub fn set_json(json: bool) {
+    *LOGGER.json.write().unwrap() = json;
+}
+
+/// Also write the records of each room to `<dir>/room-<token>.log`
+pub fn set_room_dir(dir: PathBuf) -> Result<(), Error> {
+    fs::create_dir_all(&dir)?;
+    *LOGGER.room_dir.write().unwrap() = Some(dir);
+    Ok(())
+}
+
+/// Close the log file of a room that ended
+pub fn close_room(room: u16) {
+    LOGGER.room_files.lock().unwrap().remove(&room);
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.read().unwrap().level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let context = CONTEXT.with(|c| c.borrow().clone());
+        let line = if *self.json.read().unwrap() {
+            format_json(record, &context, SystemTime::now())
+        } else {
+            format_text(record)
+        };
+        println!("{line}");
+
+        if let (Some(room), Some(dir)) = (context.room, self.room_dir.read().unwrap().as_ref()) {
+            let mut files = self.room_files.lock().unwrap();
+            let file = match files.entry(room) {
+                Entry::Occupied(file) => Some(file.into_mut()),
+                Entry::Vacant(entry) => match OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(dir.join(format!("room-{room}.log")))
+                {
+                    Ok(file) => Some(entry.insert(file)),
+                    Err(e) => {
+                        eprintln!("couldn't open the log file of room {room} : {e}");
+                        None
+                    }
+                },
+            };
+            if let Some(file) = file {
+                let _ = writeln!(file, "{line}");
+            }
+        }
+    }
+
+    fn flush(&self) {
+        for file in self.room_files.lock().unwrap().values_mut() {
+            let _ = file.flush();
+        }
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Formats
+///
+///
+//////////////////////////////////////////////
+
+fn format_text(record: &Record) -> String {
+    format!(
+        "{:<10}{:>30} -- {}",
+        format!("[ {} ]", record.level()).as_str(),
+        record.target(),
+        record.args()
+    )
+}
+
+fn format_json(record: &Record, context: &Context, now: SystemTime) -> String {
+    let time = now
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64();
+    let mut line = format!(
+        "{{\"time\":{time:.6},\"level\":\"{}\",\"target\":{}",
+        record.level(),
+        json_string(record.target())
+    );
+    if let Some(session) = context.session {
+        let _ = write!(line, ",\"session\":{session}");
+    }
+    if let Some(room) = context.room {
+        let _ = write!(line, ",\"room\":{room}");
+    }
+    if let Some(game) = &context.game {
+        let _ = write!(line, ",\"game\":{}", json_string(game));
+    }
+    let _ = write!(
+        line,
+        ",\"message\":{}}}",
+        json_string(&record.args().to_string())
+    );
+    line
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Tests
+///
+///
+//////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_filter() {
+        let filter = Filter::parse("warn, Packet=debug,Room=info,Room 1=trace").unwrap();
+        assert_eq!(filter.level("Server"), LevelFilter::Warn);
+        assert_eq!(filter.level("Packet"), LevelFilter::Debug);
+        assert_eq!(filter.level("Room 1"), LevelFilter::Trace);
+        assert_eq!(filter.level("Room 12"), LevelFilter::Info);
+        assert_eq!(filter.level("Roomba"), LevelFilter::Warn);
+        assert_eq!(filter.max(), LevelFilter::Trace);
+
+        assert!(Filter::parse("Client=loud").is_err());
+    }
+
+    #[test]
+    fn test_json() {
+        let context = Context {
+            session: Some(4),
+            room: Some(2),
+            game: Some("Maze-Fight".to_string()),
+        };
+        let line = format_json(
+            &Record::builder()
+                .level(log::Level::Warn)
+                .target("Room 2")
+                .args(format_args!("say \"hi\"\n"))
+                .build(),
+            &context,
+            SystemTime::UNIX_EPOCH + Duration::from_millis(1500),
+        );
+        assert_eq!(
+            line,
+            "{\"time\":1.500000,\"level\":\"WARN\",\"target\":\"Room 2\",\"session\":4,\"room\":2,\"game\":\"Maze-Fight\",\"message\":\"say \\\"hi\\\"\\n\"}"
+        );
+    }
+}
@@ -1,7 +1,92 @@
-mod game;
-mod network;
+use server::network::admin;
+use server::{logging, metrics, network};
+use std::env;
+use std::path::PathBuf;
+use std::time;
+
+const USAGE: &str = "usage : server [--record <dir>] [--stats <file>] [--metrics <address>] [--metrics-file <file>] [--metrics-period <seconds>] [--log <spec>] [--log-format text|json] [--log-dir <dir>] [--admin stdin|<address>] [--admin-password <password>]";
 
 fn main() {
     let mut server = network::Server::new();
+
+    // --record <dir> : record every game in <dir>, to be replayed with the replay tool
+    // --stats <file> : keep the statistics of the players in <file>, for the leaderboards to last
+    // --metrics <address> : serve the metrics on http://<address>/metrics
+    // --metrics-file <file> : dump the metrics in <file> every --metrics-period seconds (10 by default)
+    // --log <spec> : levels of the logs, like `info,Packet=debug,Room 3=trace`
+    // --log-format text|json : print the logs in columns (default) or as JSON lines
+    // --log-dir <dir> : also write the logs of each room to <dir>/room-<token>.log
+    // --admin stdin|<address> : admin console on the terminal, or on a loopback address
+    // --admin-password <password> : password of the console on an address, PHONETILE_ADMIN_PASSWORD by default
+    let mut metrics_file = None;
+    let mut metrics_period = time::Duration::from_secs(10);
+    let mut admin_address = None;
+    let mut admin_password = env::var("PHONETILE_ADMIN_PASSWORD").ok();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let res = match (arg.as_str(), args.next()) {
+            ("--record", Some(dir)) => {
+                server.record_games(PathBuf::from(dir));
+                Ok(())
+            }
+            ("--stats", Some(file)) => server.keep_stats(&PathBuf::from(file)),
+            ("--metrics", Some(address)) => metrics::serve(address.as_str()).map(|_| ()),
+            ("--metrics-file", Some(file)) => {
+                metrics_file = Some(PathBuf::from(file));
+                Ok(())
+            }
+            ("--metrics-period", Some(seconds)) => match seconds.parse() {
+                Ok(seconds) if seconds > 0 => {
+                    metrics_period = time::Duration::from_secs(seconds);
+                    Ok(())
+                }
+                _ => {
+                    eprintln!("{USAGE}");
+                    return;
+                }
+            },
+            ("--log", Some(spec)) => logging::Filter::parse(&spec).map(logging::set_filter),
+            ("--log-format", Some(format)) if format == "text" || format == "json" => {
+                logging::set_json(format == "json");
+                Ok(())
+            }
+            ("--log-dir", Some(dir)) => logging::set_room_dir(PathBuf::from(dir)),
+            ("--admin", Some(address)) => {
+                admin_address = Some(address);
+                Ok(())
+            }
+            ("--admin-password", Some(password)) => {
+                admin_password = Some(password);
+                Ok(())
+            }
+            _ => {
+                eprintln!("{USAGE}");
+                return;
+            }
+        };
+        if let Err(e) = res {
+            eprintln!("{arg} : {e}");
+            return;
+        }
+    }
+    logging::install();
+    if let Some(file) = metrics_file {
+        metrics::dump_every(file, metrics_period);
+    }
+    match (admin_address, admin_password) {
+        (Some(address), _) if address == "stdin" => admin::serve_stdin(server.admin()),
+        (Some(address), Some(password)) if !password.is_empty() => {
+            if let Err(e) = admin::serve(server.admin(), address.as_str(), password) {
+                eprintln!("--admin : {e}");
+                return;
+            }
+        }
+        (Some(_), _) => {
+            eprintln!("--admin : the console on an address needs a password");
+            return;
+        }
+        (None, _) => {}
+    }
+
     let _ = server.launch_server();
 }
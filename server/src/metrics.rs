@@ -0,0 +1,511 @@
+use crate::network::packet::Flag;
+use log::{info, warn};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{BufRead, BufReader, Error, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time;
+
+/// Operational metrics of the server, shared by all its threads and exported in the Prometheus
+/// text format, on a local HTTP endpoint and/or periodically to a file
+
+//////////////////////////////////////////////
+///
+///
+/// Constants
+///
+///
+//////////////////////////////////////////////
+
+/// Upper bounds of the buckets of the tick durations, in seconds
+const TICK_BUCKETS: [f64; 8] = [0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1];
+
+//////////////////////////////////////////////
+///
+///
+/// Rooms
+///
+///
+//////////////////////////////////////////////
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RoomState {
+    Lobby,
+    Locked,
+    Playing,
+}
+
+impl RoomState {
+    fn label(&self) -> &'static str {
+        match self {
+            RoomState::Lobby => "lobby",
+            RoomState::Locked => "locked",
+            RoomState::Playing => "playing",
+        }
+    }
+}
+
+impl Display for RoomState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.label())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RoomInfo {
+    pub state: RoomState,
+    pub game: String,
+    pub players: usize,
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Histogram
+///
+///
+//////////////////////////////////////////////
+
+#[derive(Default)]
+struct Histogram {
+    // count of the observations under each bound of TICK_BUCKETS
+    buckets: [u64; TICK_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (bound, bucket) in TICK_BUCKETS.iter().zip(self.buckets.iter_mut()) {
+            if value <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Metrics
+///
+///
+//////////////////////////////////////////////
+
+// direction and flag of the packets
+type PacketLabels = (&'static str, &'static str);
+// number of packets and of bytes
+type PacketCount = (u64, u64);
+
+#[derive(Default)]
+pub struct Metrics {
+    sessions: AtomicI64,
+    rooms: Mutex<BTreeMap<u16, RoomInfo>>,
+    packets: Mutex<BTreeMap<PacketLabels, PacketCount>>,
+    malformed_packets: AtomicU64,
+    dropped_packets: AtomicU64,
+    send_errors: AtomicU64,
+    ticks: Mutex<BTreeMap<String, Histogram>>,
+}
+
+/// Metrics of the process
+pub fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::default)
+}
+
+impl Metrics {
+    pub fn session_opened(&self) {
+        self.sessions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn session_closed(&self) {
+        self.sessions.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn room_opened(&self, room: u16) {
+        self.rooms.lock().unwrap().insert(
+            room,
+            RoomInfo {
+                state: RoomState::Lobby,
+                game: "Unknown".to_string(),
+                players: 0,
+            },
+        );
+    }
+
+    pub fn room_state(&self, room: u16, state: RoomState, game: &str) {
+        if let Some(info) = self.rooms.lock().unwrap().get_mut(&room) {
+            info.state = state;
+            info.game = game.to_string();
+        }
+    }
+
+    pub fn room_players(&self, room: u16, players: usize) {
+        if let Some(info) = self.rooms.lock().unwrap().get_mut(&room) {
+            info.players = players;
+        }
+    }
+
+    /// State of an open room
+    pub fn room(&self, room: u16) -> Option<RoomInfo> {
+        self.rooms.lock().unwrap().get(&room).cloned()
+    }
+
+    pub fn room_closed(&self, room: u16) {
+        self.rooms.lock().unwrap().remove(&room);
+    }
+
+    pub fn packet_in(&self, flag: Flag, bytes: usize) {
+        self.count_packet("in", flag, bytes);
+    }
+
+    pub fn packet_out(&self, flag: Flag, bytes: usize) {
+        self.count_packet("out", flag, bytes);
+    }
+
+    pub fn malformed_packet(&self) {
+        self.malformed_packets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Packet of a client dropped by the rate limiter
+    pub fn packet_dropped(&self) {
+        self.dropped_packets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn send_error(&self) {
+        self.send_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Time the game `game` took to run one frame
+    pub fn tick(&self, game: &str, duration: time::Duration) {
+        let mut ticks = self.ticks.lock().unwrap();
+        match ticks.get_mut(game) {
+            Some(histogram) => histogram.observe(duration.as_secs_f64()),
+            None => {
+                let mut histogram = Histogram::default();
+                histogram.observe(duration.as_secs_f64());
+                ticks.insert(game.to_string(), histogram);
+            }
+        }
+    }
+
+    fn count_packet(&self, direction: &'static str, flag: Flag, bytes: usize) {
+        let mut packets = self.packets.lock().unwrap();
+        let entry = packets.entry((direction, flag_label(flag))).or_default();
+        entry.0 += 1;
+        entry.1 += bytes as u64;
+    }
+
+    /// Metrics in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "phonetile_sessions",
+            "gauge",
+            "Connected sessions",
+        );
+        let _ = writeln!(
+            out,
+            "phonetile_sessions {}",
+            self.sessions.load(Ordering::Relaxed)
+        );
+
+        let rooms = self.rooms.lock().unwrap();
+        let mut by_state: BTreeMap<(RoomState, &str), usize> = BTreeMap::new();
+        for info in rooms.values() {
+            *by_state
+                .entry((info.state, info.game.as_str()))
+                .or_default() += 1;
+        }
+        header(
+            &mut out,
+            "phonetile_rooms",
+            "gauge",
+            "Open rooms by state and game",
+        );
+        for ((state, game), count) in by_state.iter() {
+            let _ = writeln!(
+                out,
+                "phonetile_rooms{{state=\"{}\",game=\"{}\"}} {count}",
+                state.label(),
+                escape(game)
+            );
+        }
+        header(
+            &mut out,
+            "phonetile_room_players",
+            "gauge",
+            "Players in each room",
+        );
+        for (room, info) in rooms.iter() {
+            let _ = writeln!(
+                out,
+                "phonetile_room_players{{room=\"{room}\"}} {}",
+                info.players
+            );
+        }
+        drop(rooms);
+
+        let packets = self.packets.lock().unwrap();
+        header(
+            &mut out,
+            "phonetile_packets_total",
+            "counter",
+            "Packets by direction and flag",
+        );
+        for ((direction, flag), (count, _)) in packets.iter() {
+            let _ = writeln!(
+                out,
+                "phonetile_packets_total{{direction=\"{direction}\",flag=\"{flag}\"}} {count}"
+            );
+        }
+        header(
+            &mut out,
+            "phonetile_packet_bytes_total",
+            "counter",
+            "Bytes on the wire by direction and flag",
+        );
+        for ((direction, flag), (_, bytes)) in packets.iter() {
+            let _ = writeln!(
+                out,
+                "phonetile_packet_bytes_total{{direction=\"{direction}\",flag=\"{flag}\"}} {bytes}"
+            );
+        }
+        drop(packets);
+
+        header(
+            &mut out,
+            "phonetile_malformed_packets_total",
+            "counter",
+            "Packets that could not be decoded",
+        );
+        let _ = writeln!(
+            out,
+            "phonetile_malformed_packets_total {}",
+            self.malformed_packets.load(Ordering::Relaxed)
+        );
+        header(
+            &mut out,
+            "phonetile_rate_limited_packets_total",
+            "counter",
+            "Packets of flooding clients dropped by the rate limiter",
+        );
+        let _ = writeln!(
+            out,
+            "phonetile_rate_limited_packets_total {}",
+            self.dropped_packets.load(Ordering::Relaxed)
+        );
+        header(
+            &mut out,
+            "phonetile_send_errors_total",
+            "counter",
+            "Messages that could not be sent to a client or a game",
+        );
+        let _ = writeln!(
+            out,
+            "phonetile_send_errors_total {}",
+            self.send_errors.load(Ordering::Relaxed)
+        );
+
+        header(
+            &mut out,
+            "phonetile_tick_duration_seconds",
+            "histogram",
+            "Time the games take to run a frame",
+        );
+        for (game, histogram) in self.ticks.lock().unwrap().iter() {
+            let game = escape(game);
+            for (bound, count) in TICK_BUCKETS.iter().zip(histogram.buckets.iter()) {
+                let _ = writeln!(
+                    out,
+                    "phonetile_tick_duration_seconds_bucket{{game=\"{game}\",le=\"{bound}\"}} {count}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "phonetile_tick_duration_seconds_bucket{{game=\"{game}\",le=\"+Inf\"}} {}",
+                histogram.count
+            );
+            let _ = writeln!(
+                out,
+                "phonetile_tick_duration_seconds_sum{{game=\"{game}\"}} {}",
+                histogram.sum
+            );
+            let _ = writeln!(
+                out,
+                "phonetile_tick_duration_seconds_count{{game=\"{game}\"}} {}",
+                histogram.count
+            );
+        }
+
+        out
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Export
+///
+///
+//////////////////////////////////////////////
+
+/// Serve the metrics on `http://<address>/metrics`, return the address actually bound
+pub fn serve<A: ToSocketAddrs>(address: A) -> Result<SocketAddr, Error> {
+    let listener = TcpListener::bind(address)?;
+    let address = listener.local_addr()?;
+    info!(target: "Metrics", "serving the metrics on http://{address}/metrics");
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = answer(stream) {
+                warn!(target: "Metrics", "couldn't answer a request : {e}");
+            }
+        }
+    });
+    Ok(address)
+}
+
+fn answer(mut stream: TcpStream) -> Result<(), Error> {
+    stream.set_read_timeout(Some(time::Duration::from_secs(1)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // the headers are not needed, but have to be read before answering
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+
+    let (status, body) = match request.split_whitespace().nth(1) {
+        Some("/metrics") => ("200 OK", metrics().render()),
+        _ => ("404 Not Found", "not found\n".to_string()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+/// Write the metrics to `path` every `period`
+pub fn dump_every(path: PathBuf, period: time::Duration) {
+    info!(target: "Metrics", "dumping the metrics to {} every {:?}", path.display(), period);
+    thread::spawn(move || {
+        // written aside then renamed, so that readers never see a partial file
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        loop {
+            thread::sleep(period);
+            if let Err(e) =
+                fs::write(&tmp, metrics().render()).and_then(|_| fs::rename(&tmp, &path))
+            {
+                warn!(target: "Metrics", "couldn't dump the metrics : {e}");
+            }
+        }
+    });
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Helpers
+///
+///
+//////////////////////////////////////////////
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn flag_label(flag: Flag) -> &'static str {
+    match flag {
+        Flag::Init => "init",
+        Flag::Create => "create",
+        Flag::Join => "join",
+        Flag::Lock => "lock",
+        Flag::Launch => "launch",
+        Flag::Transmit => "transmit",
+        Flag::Message => "message",
+        Flag::Roster => "roster",
+        Flag::Ready => "ready",
+        Flag::Countdown => "countdown",
+        Flag::Chat => "chat",
+        Flag::End => "end",
+        Flag::Leaderboard => "leaderboard",
+        Flag::Pause => "pause",
+        Flag::Error(_) => "error",
+        Flag::Unknown => "unknown",
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Tests
+///
+///
+//////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_render() {
+        let m = Metrics::default();
+        m.session_opened();
+        m.room_opened(3);
+        m.room_players(3, 2);
+        m.room_state(3, RoomState::Playing, "Maze-Fight");
+        m.packet_out(Flag::Transmit, 2048);
+        m.packet_out(Flag::Transmit, 2048);
+        m.tick("Racer", time::Duration::from_millis(2));
+
+        let text = m.render();
+        assert!(text.contains("phonetile_sessions 1\n"));
+        assert!(text.contains("phonetile_rooms{state=\"playing\",game=\"Maze-Fight\"} 1\n"));
+        assert!(text.contains("phonetile_room_players{room=\"3\"} 2\n"));
+        assert!(text
+            .contains("phonetile_packet_bytes_total{direction=\"out\",flag=\"transmit\"} 4096\n"));
+        assert!(text
+            .contains("phonetile_tick_duration_seconds_bucket{game=\"Racer\",le=\"0.001\"} 0\n"));
+        assert!(text
+            .contains("phonetile_tick_duration_seconds_bucket{game=\"Racer\",le=\"0.0025\"} 1\n"));
+
+        m.room_closed(3);
+        assert!(!m.render().contains("room=\"3\""));
+    }
+
+    #[test]
+    fn test_serve() {
+        let address = serve("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("# TYPE phonetile_sessions gauge"));
+    }
+}
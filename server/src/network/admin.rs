@@ -0,0 +1,296 @@
+use crate::logging;
+use log::{info, warn};
+use std::io::{self, BufRead, BufReader, Error, ErrorKind, Write};
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::sync::mpsc;
+use std::thread;
+use std::time;
+
+/// Admin console of the running server
+///
+/// The commands are typed on stdin or on a loopback TCP port protected by a password, the
+/// consoles forward them to the main thread of the server, which answers between two connections
+
+//////////////////////////////////////////////
+///
+///
+/// Constants
+///
+///
+//////////////////////////////////////////////
+
+const HELP: &str = "commands :
+    sessions              list the connected clients
+    rooms                 list the rooms with their state, game and players
+    kick <session>        disconnect a client
+    close <room>          close a room and disconnect its players
+    broadcast <text>      send a message to every client
+    log <spec>            change the log levels, like `info,Packet=debug,Room 3=trace`
+    shutdown              warn the clients, close everything and stop the server
+    help                  show this help
+    quit                  leave the console";
+
+/// Longest time the console waits for the server to answer
+const ANSWER_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+/// Wrong passwords a TCP console accepts before closing the connection
+const PASSWORD_ATTEMPTS: usize = 3;
+
+//////////////////////////////////////////////
+///
+///
+/// Commands
+///
+///
+//////////////////////////////////////////////
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Sessions,
+    Rooms,
+    Kick(u16),
+    Close(u16),
+    Broadcast(String),
+    Log(String),
+    Shutdown,
+    Help,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, Error> {
+        let line = line.trim();
+        let (name, argument) = match line.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (line, ""),
+        };
+        let token = |what: &str| {
+            argument.parse::<u16>().map_err(|_| {
+                Error::new(ErrorKind::InvalidInput, format!("usage : {name} <{what}>"))
+            })
+        };
+        match name {
+            "sessions" => Ok(Command::Sessions),
+            "rooms" => Ok(Command::Rooms),
+            "kick" => Ok(Command::Kick(token("session")?)),
+            "close" => Ok(Command::Close(token("room")?)),
+            "broadcast" if !argument.is_empty() => Ok(Command::Broadcast(argument.to_string())),
+            "log" if !argument.is_empty() => Ok(Command::Log(argument.to_string())),
+            "shutdown" => Ok(Command::Shutdown),
+            "help" => Ok(Command::Help),
+            "broadcast" | "log" => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "usage : {name} <{}>",
+                    if name == "log" { "spec" } else { "text" }
+                ),
+            )),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("unknown command `{name}`, try `help`"),
+            )),
+        }
+    }
+}
+
+/// Command for the server, with where to send the answer
+pub struct Request {
+    pub command: Command,
+    pub answer: mpsc::Sender<String>,
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Admin
+///
+///
+//////////////////////////////////////////////
+
+/// Handle to run commands on the server, it can be cloned for each console
+#[derive(Clone)]
+pub struct Admin {
+    sender: mpsc::Sender<Request>,
+}
+
+impl Admin {
+    pub fn new(sender: mpsc::Sender<Request>) -> Self {
+        Admin { sender }
+    }
+
+    /// Run a command line and return the answer to print
+    pub fn execute(&self, line: &str) -> String {
+        let command = match Command::parse(line) {
+            Ok(command) => command,
+            Err(e) => return e.to_string(),
+        };
+        match command {
+            Command::Help => HELP.to_string(),
+            Command::Log(spec) => match logging::Filter::parse(&spec) {
+                Ok(filter) => {
+                    logging::set_filter(filter);
+                    format!("log levels set to `{spec}`")
+                }
+                Err(e) => e.to_string(),
+            },
+            command => {
+                let (answer, receiver) = mpsc::channel();
+                if self.sender.send(Request { command, answer }).is_err() {
+                    return "the server is stopped".to_string();
+                }
+                receiver
+                    .recv_timeout(ANSWER_TIMEOUT)
+                    .unwrap_or_else(|_| "the server didn't answer".to_string())
+            }
+        }
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Consoles
+///
+///
+//////////////////////////////////////////////
+
+/// Read commands from `input` and write the answers to `output` until `quit` or the end of input
+pub fn console<R: BufRead, W: Write>(admin: &Admin, input: R, mut output: W) -> Result<(), Error> {
+    write!(output, "> ")?;
+    output.flush()?;
+    for line in input.lines() {
+        let line = line?;
+        match line.trim() {
+            "" => {}
+            "quit" | "exit" => break,
+            line => writeln!(output, "{}", admin.execute(line))?,
+        }
+        write!(output, "> ")?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+/// Console on the terminal of the server
+pub fn serve_stdin(admin: Admin) {
+    thread::spawn(move || {
+        if let Err(e) = console(&admin, io::stdin().lock(), io::stdout()) {
+            warn!(target: "Admin", "console stopped : {e}");
+        }
+    });
+}
+
+/// Consoles on a loopback TCP port, each one asks for `password` first
+/// Return the address actually bound
+pub fn serve<A: ToSocketAddrs>(
+    admin: Admin,
+    address: A,
+    password: String,
+) -> Result<SocketAddr, Error> {
+    // checked before binding, a public address must never listen even for a moment
+    let addresses: Vec<SocketAddr> = address.to_socket_addrs()?.collect();
+    if addresses.is_empty() || addresses.iter().any(|a| !a.ip().is_loopback()) {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "the admin console only listens on a loopback address",
+        ));
+    }
+    let listener = TcpListener::bind(&addresses[..])?;
+    let address = listener.local_addr()?;
+    info!(target: "Admin", "admin console on {address}");
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let admin = admin.clone();
+            let password = password.clone();
+            thread::spawn(move || {
+                let peer = stream
+                    .peer_addr()
+                    .map(|a| a.to_string())
+                    .unwrap_or_default();
+                let res = stream.try_clone().and_then(|mut output| {
+                    let mut input = BufReader::new(stream);
+                    login(&mut input, &mut output, &password, &peer)?;
+                    info!(target: "Admin", "operator connected from {peer}");
+                    console(&admin, input, output)
+                });
+                if let Err(e) = res {
+                    warn!(target: "Admin", "console of {peer} stopped : {e}");
+                }
+            });
+        }
+    });
+    Ok(address)
+}
+
+/// Ask for the password, the connection is closed after `PASSWORD_ATTEMPTS` wrong ones
+fn login<R: BufRead, W: Write>(
+    input: &mut R,
+    output: &mut W,
+    password: &str,
+    peer: &str,
+) -> Result<(), Error> {
+    for attempt in 1..=PASSWORD_ATTEMPTS {
+        write!(output, "password : ")?;
+        output.flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "no password"));
+        }
+        if line.trim_end_matches(['\r', '\n']) == password {
+            return Ok(());
+        }
+        warn!(target: "Admin", "wrong password from {peer} ({attempt}/{PASSWORD_ATTEMPTS})");
+        writeln!(output, "wrong password")?;
+    }
+    Err(Error::new(
+        ErrorKind::PermissionDenied,
+        "too many wrong passwords",
+    ))
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Tests
+///
+///
+//////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Command::parse(" kick 4 ").unwrap(), Command::Kick(4));
+        assert_eq!(
+            Command::parse("broadcast back in  5 minutes").unwrap(),
+            Command::Broadcast("back in  5 minutes".to_string())
+        );
+        assert!(Command::parse("close").is_err());
+        assert!(Command::parse("kick everyone").is_err());
+        assert!(Command::parse("reboot").is_err());
+    }
+
+    #[test]
+    fn test_tcp_console_is_guarded() {
+        let (sender, _) = mpsc::channel();
+        let refused = serve(Admin::new(sender), "0.0.0.0:0", "secret".to_string());
+        assert_eq!(refused.unwrap_err().kind(), ErrorKind::PermissionDenied);
+
+        let mut output = Vec::new();
+        let res = login(
+            &mut "nope\nsecret\n".as_bytes(),
+            &mut output,
+            "secret",
+            "test",
+        );
+        assert!(res.is_ok());
+        let res = login(
+            &mut "a\nb\nc\nsecret\n".as_bytes(),
+            &mut output,
+            "secret",
+            "test",
+        );
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::PermissionDenied);
+    }
+}
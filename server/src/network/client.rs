@@ -1,7 +1,8 @@
 use super::packet;
 use std::fmt::{write, Display};
 use std::io::{Error, ErrorKind, Read, Write};
-use std::net::TcpStream;
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
 use std::{thread, time};
 
 /// All of those functions are completely non-blocking
@@ -14,7 +15,7 @@ use std::{thread, time};
 ///
 //////////////////////////////////////////////
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum Game {
     Racer,
     Snake,
@@ -73,7 +74,7 @@ pub enum Status {
     Disconnected,
     InRoom,
     InLockRoom(u8),
-    InGame,
+    InGame(u16),
 }
 
 //////////////////////////////////////////////
@@ -87,8 +88,29 @@ pub enum Status {
 pub struct Network {
     stream: TcpStream,
     session_token: u16,
+    // secret given by the server, every packet is signed with it
+    secret: u64,
     room_token: u16,
+    game_id: u16,
     status: Status,
+    // text messages of the server not read yet
+    messages: Vec<String>,
+    // players of the room, as last sent by the server
+    roster: Vec<packet::Member>,
+    // seconds left before the launch, or before the game goes on after a pause
+    countdown: Option<u16>,
+    // the game is paused, as last sent by the server
+    paused: bool,
+    // chat of the room not read yet
+    chat: Vec<packet::Chat>,
+    // place of the phone in the last game, if it was ranked
+    place: Option<u8>,
+    // leaderboard asked for, not read yet
+    leaderboard: Option<Vec<packet::Standing>>,
+    // settings of the game launched, in the order of its schema
+    settings: Vec<u16>,
+    // games run by the server, sent with the handshake
+    catalogue: Vec<packet::Listing>,
 }
 
 impl Network {
@@ -101,27 +123,64 @@ impl Network {
     //////////////////////////////////////////////
 
     /// Connect to the server, you must do this action BEFORE ANYTHING ELSE
+    /// The room names the phone and picks its avatar
     pub fn connect(
+        address: &SocketAddr,
         physical_height: f32,
         physical_width: f32,
         window_height: u32,
         window_width: u32,
     ) -> Result<Self, Error> {
-        match TcpStream::connect("127.0.0.1:8888") {
+        Network::connect_as(
+            address,
+            "",
+            None,
+            physical_height,
+            physical_width,
+            window_height,
+            window_width,
+        )
+    }
+
+    /// Connect to the server with a nickname and a preferred avatar, which the room may change
+    /// when another player already has them
+    pub fn connect_as(
+        address: &SocketAddr,
+        nickname: &str,
+        avatar: Option<u8>,
+        physical_height: f32,
+        physical_width: f32,
+        window_height: u32,
+        window_width: u32,
+    ) -> Result<Self, Error> {
+        match TcpStream::connect_timeout(address, Duration::from_secs(1)) {
             Ok(stream) => {
                 stream.set_nonblocking(true)?;
                 let mut network = Network {
                     stream,
                     session_token: 0,
+                    secret: 0,
                     room_token: 0,
+                    game_id: 0,
                     status: Status::Connected,
+                    messages: Vec::new(),
+                    roster: Vec::new(),
+                    countdown: None,
+                    paused: false,
+                    chat: Vec::new(),
+                    place: None,
+                    settings: Vec::new(),
+                    catalogue: Vec::new(),
+                    leaderboard: None,
                 };
-                network.init_handshake(
+                network.init_handshake(packet::Handshake {
                     physical_height,
                     physical_width,
                     window_height,
                     window_width,
-                )?;
+                    nickname: nickname.to_string(),
+                    avatar,
+                })?;
                 Ok(network)
             }
             Err(_) => Err(Error::new(
@@ -131,35 +190,28 @@ impl Network {
         }
     }
 
-    /// Create a room and send back the ID of the room in order for the other
-    /// to connect themselves to it
-    pub fn create_room(&mut self) -> Result<u16, Error> {
-        let packet_room_creation =
-            packet::Packet::new(packet::Flag::Create, 0, self.session_token, 0, &[], 0);
-        packet_room_creation.send_packet(&mut self.stream)?;
-
-        let packet = packet::Packet::recv_packet(&mut self.stream)?;
-        self.room_token = packet.room;
-        self.status = Status::InRoom;
-        Ok(packet.room)
+    /// Create a room and send back the code the others have to type to join it
+    pub fn create_room(&mut self) -> Result<u32, Error> {
+        self.enter_room(packet::Flag::Create, &[])
     }
 
-    /// Join a room with the given room ID
-    pub fn join_room(&mut self, room_token: u16) -> Result<(), Error> {
-        packet::Packet::new(
-            packet::Flag::Join,
-            0,
-            self.session_token,
-            room_token,
-            &[],
-            0,
-        )
-        .send_packet(&mut self.stream)?;
+    /// Create a room that can only be joined with `pin` on top of its code
+    pub fn create_private_room(&mut self, pin: u16) -> Result<u32, Error> {
+        self.enter_room(packet::Flag::Create, &pin.to_be_bytes())
+    }
 
-        let packet = packet::Packet::recv_packet(&mut self.stream)?;
-        self.room_token = packet.room;
-        self.status = Status::InRoom;
+    /// Join the room with the given code
+    pub fn join_room(&mut self, code: u32) -> Result<(), Error> {
+        self.enter_room(packet::Flag::Join, &code.to_be_bytes())?;
+        Ok(())
+    }
 
+    /// Join a private room with its code and PIN
+    pub fn join_private_room(&mut self, code: u32, pin: u16) -> Result<(), Error> {
+        let mut data = [0_u8; 6];
+        data[..4].copy_from_slice(&code.to_be_bytes());
+        data[4..].copy_from_slice(&pin.to_be_bytes());
+        self.enter_room(packet::Flag::Join, &data)?;
         Ok(())
     }
 
@@ -167,36 +219,104 @@ impl Network {
     /// The position of each user is given from this point when the get_status is triggered
     /// THIS FUNCTION WILL WORK ONLY IF create_room HAS BEEN CALLED BEFORE THAT
     pub fn lock_room(&mut self, game_id: Game) -> Result<(), Error> {
-        packet::Packet::new(
+        self.game_id = game_id.into();
+        self.send_packet(packet::Packet::control(
+            packet::Flag::Lock,
+            self.session_token,
+            self.room_token,
+            game_id.into(),
+        ))
+    }
+
+    /// Lock the room with the settings chosen for the game, in the order of its schema
+    /// The server tells in a message why it refuses them, the room stays open in that case
+    /// THIS FUNCTION WILL WORK ONLY IF create_room HAS BEEN CALLED BEFORE THAT
+    pub fn lock_room_with(&mut self, game_id: Game, settings: &[u16]) -> Result<(), Error> {
+        self.game_id = game_id.into();
+        let mut data = vec![settings.len() as u8];
+        for value in settings.iter() {
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+        let packet = packet::Packet::new(
             packet::Flag::Lock,
             0,
             self.session_token,
             self.room_token,
-            &[],
+            &data,
             game_id.into(),
-        )
-        .send_packet(&mut self.stream)
+        )?;
+        self.send_packet(packet)
+    }
+
+    /// Tell the room whether the phone is ready to play, once the room is locked
+    pub fn set_ready(&mut self, ready: bool) -> Result<(), Error> {
+        self.send_packet(packet::Packet::control(
+            packet::Flag::Ready,
+            self.session_token,
+            self.room_token,
+            ready as u16,
+        ))
     }
 
-    /// Launch the actual game
+    /// Launch the actual game, once every other phone is ready
+    /// The room counts down then the status turns to InGame, otherwise a message tells who isn't
+    /// ready yet
     /// THIS FUNCTION WILL WORK ONLY IF create_room HAS BEEN CALLED BEFORE THAT
     pub fn launch_game(&mut self) -> Result<(), Error> {
-        match packet::Packet::new(
-            packet::Flag::Launch,
-            0,
+        self.send_launch(false)
+    }
+
+    /// Launch the actual game even if some phones aren't ready
+    /// THIS FUNCTION WILL WORK ONLY IF create_room HAS BEEN CALLED BEFORE THAT
+    pub fn force_launch_game(&mut self) -> Result<(), Error> {
+        self.send_launch(true)
+    }
+
+    //////////////////////////////////////////////
+    ///
+    ///
+    /// Chat
+    ///
+    ///
+    //////////////////////////////////////////////
+
+    /// Say something to the players of the room, whether the game is on or not
+    pub fn send_chat(&mut self, text: &str) -> Result<(), Error> {
+        self.send_chat_line(None, text)
+    }
+
+    /// Show a quick emote to the players of the room, below `packet::EMOTES`
+    pub fn send_emote(&mut self, emote: u8) -> Result<(), Error> {
+        self.send_chat_line(Some(emote), "")
+    }
+
+    /// Chat lines and emotes of the room since the last call, its own included
+    pub fn chat(&mut self) -> Vec<packet::Chat> {
+        std::mem::take(&mut self.chat)
+    }
+
+    //////////////////////////////////////////////
+    ///
+    ///
+    /// Leaderboards
+    ///
+    ///
+    //////////////////////////////////////////////
+
+    /// Ask the server for the all-time leaderboard of `game`, anywhere but in game
+    /// Call `get_status` until `leaderboard` gives it
+    pub fn query_leaderboard(&mut self, game: Game) -> Result<(), Error> {
+        self.send_packet(packet::Packet::control(
+            packet::Flag::Leaderboard,
             self.session_token,
             self.room_token,
-            &[],
-            0,
-        )
-        .send_packet(&mut self.stream)
-        {
-            Ok(_) => {
-                self.status = Status::InGame;
-                Ok(())
-            }
-            Err(e) => Err(e),
-        }
+            game.into(),
+        ))
+    }
+
+    /// Leaderboard asked with `query_leaderboard`, once the server sent it, best players first
+    pub fn leaderboard(&mut self) -> Option<Vec<packet::Standing>> {
+        self.leaderboard.take()
     }
 
     //////////////////////////////////////////////
@@ -210,22 +330,40 @@ impl Network {
     /// Send data to the server ; this action can only be done in game
     /// If you use this function outisde of a game, this will simply discard the message
     pub fn send(&mut self, data: &[u8]) -> Result<(), Error> {
-        packet::Packet::new(packet::Flag::Transmit, 0, self.session_token, 0, data, 0)
-            .send_packet(&mut self.stream)
+        let packet =
+            packet::Packet::new(packet::Flag::Transmit, 0, self.session_token, 0, data, 0)?;
+        self.send_packet(packet)
     }
 
     /// Receive data from the server ; this action can only be done in game
     /// It return the amount of data read
-    pub fn recv(&mut self, buffer: &mut [u8; packet::MAX_DATA_SIZE]) -> bool {
-        match packet::Packet::try_recv_packet(&mut self.stream) {
+    pub fn recv(&mut self, buffer: &mut [u8; packet::MAX_DATA_SIZE]) -> usize {
+        match self.next_packet() {
             Some(packet) => {
                 buffer.copy_from_slice(&packet.data);
-                true
+                packet.size
             }
-            None => false,
+            None => 0,
         }
     }
 
+    /// Hold the game of the room paused, like a phone going to the background, or let it go on
+    /// The game goes on after a countdown once nobody holds it, the master lets it go on for everyone
+    pub fn pause_game(&mut self, paused: bool) -> Result<(), Error> {
+        self.send_packet(packet::Packet::control(
+            packet::Flag::Pause,
+            self.session_token,
+            self.room_token,
+            paused as u16,
+        ))
+    }
+
+    /// The game is paused, `countdown` gives the seconds left once it is about to go on
+    /// Call `recv` to keep it up to date
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
     //////////////////////////////////////////////
     ///
     ///
@@ -235,18 +373,36 @@ impl Network {
     //////////////////////////////////////////////
 
     /// Get the current status of the network
+    /// The status goes back to InRoom once the game ends
     pub fn get_status(&mut self) -> Status {
         match self.status {
-            Status::InRoom => match packet::Packet::try_recv_packet(&mut self.stream) {
+            Status::Connected => {
+                // nothing but the leaderboards comes before a room
+                let _ = self.next_packet();
+                self.status.clone()
+            }
+            Status::InRoom => match self.next_packet() {
                 Some(packet) => {
+                    self.countdown = None;
                     self.status = Status::InLockRoom(packet.option as u8);
                     self.status.clone()
                 }
                 None => self.status.clone(),
             },
-            Status::InLockRoom(_) => match packet::Packet::try_recv_packet(&mut self.stream) {
-                Some(_) => {
-                    self.status = Status::InGame;
+            Status::InLockRoom(_) => match self.next_packet() {
+                // ranked again, a player left before the launch
+                Some(packet) if packet.get_flag() == packet::Flag::Lock => {
+                    self.status = Status::InLockRoom(packet.option as u8);
+                    self.status.clone()
+                }
+                Some(packet) => {
+                    self.settings = packet.data[..packet.size]
+                        .get(1..)
+                        .unwrap_or_default()
+                        .chunks_exact(2)
+                        .map(|v| u16::from_be_bytes([v[0], v[1]]))
+                        .collect();
+                    self.status = Status::InGame(packet.option);
                     self.status.clone()
                 }
                 None => self.status.clone(),
@@ -255,6 +411,64 @@ impl Network {
         }
     }
 
+    /// Poll the status until `reached` accepts it, for at most `timeout`
+    pub fn wait_for_status(
+        &mut self,
+        timeout: Duration,
+        reached: fn(&Status) -> bool,
+    ) -> Result<Status, Error> {
+        let start = time::Instant::now();
+        loop {
+            let status = self.get_status();
+            if reached(&status) {
+                return Ok(status);
+            }
+            if start.elapsed() > timeout {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    "the server didn't answer in time",
+                ));
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    /// Token of the room the phone is in, 0 while it is in none
+    pub fn room_token(&self) -> u16 {
+        self.room_token
+    }
+
+    /// Players of the room, in the order they joined it until the room is locked
+    /// Call `get_status` or `recv` to keep it up to date
+    pub fn roster(&self) -> &[packet::Member] {
+        &self.roster
+    }
+
+    /// Seconds left before the game is launched, once the launch is counted down
+    pub fn countdown(&self) -> Option<u16> {
+        self.countdown
+    }
+
+    /// Place of the phone in the last game, 1 for the winner, None if the game didn't rank it
+    pub fn last_place(&self) -> Option<u8> {
+        self.place
+    }
+
+    /// Settings of the game launched, in the order of its schema
+    pub fn settings(&self) -> &[u16] {
+        &self.settings
+    }
+
+    /// Games the server can lock a room for, with their player limits and settings
+    pub fn catalogue(&self) -> &[packet::Listing] {
+        &self.catalogue
+    }
+
+    /// Text messages sent by the server since the last call, like the announces of the operators
+    pub fn messages(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.messages)
+    }
+
     //////////////////////////////////////////////
     ///
     ///
@@ -263,23 +477,120 @@ impl Network {
     ///
     //////////////////////////////////////////////
 
-    fn init_handshake(
-        &mut self,
-        physical_height: f32,
-        physical_width: f32,
-        window_height: u32,
-        window_width: u32,
-    ) -> Result<(), Error> {
-        let mut data = [0_u8; 16];
-        data[..4].copy_from_slice(&physical_height.to_be_bytes());
-        data[4..8].copy_from_slice(&physical_width.to_be_bytes());
-        data[8..12].copy_from_slice(&window_height.to_be_bytes());
-        data[12..16].copy_from_slice(&window_width.to_be_bytes());
+    /// Next packet of the game or the room, the news of the room are put aside
+    fn next_packet(&mut self) -> Option<packet::Packet> {
+        loop {
+            let packet = packet::Packet::try_recv_packet(&mut self.stream)?;
+            match packet.get_flag() {
+                packet::Flag::Message => self
+                    .messages
+                    .push(String::from_utf8_lossy(&packet.data[..packet.size]).into_owned()),
+                packet::Flag::Roster => {
+                    if let Ok(roster) = packet::Member::unpack_roster(&packet.data[..packet.size]) {
+                        self.roster = roster;
+                    }
+                }
+                packet::Flag::Countdown => self.countdown = Some(packet.option),
+                packet::Flag::Pause => {
+                    self.paused = packet.option != 0;
+                    if self.paused {
+                        self.countdown = None;
+                    }
+                }
+                packet::Flag::Chat => {
+                    if let Ok(chat) = packet::Chat::unpack(&packet.data[..packet.size]) {
+                        self.chat.push(chat);
+                    }
+                }
+                packet::Flag::End => {
+                    self.status = Status::InRoom;
+                    self.countdown = None;
+                    self.paused = false;
+                    self.place = (packet.option != 0).then_some(packet.option as u8);
+                    // the packets after it belong to the room, not to the game
+                    return None;
+                }
+                packet::Flag::Leaderboard => {
+                    self.leaderboard =
+                        packet::Standing::unpack_leaderboard(&packet.data[..packet.size]).ok();
+                }
+                _ => return Some(packet),
+            }
+        }
+    }
+
+    /// Wait for the answer of the server to a request, the stream doesn't block otherwise
+    fn recv_answer(&mut self) -> Result<packet::Packet, Error> {
+        self.stream.set_nonblocking(false)?;
+        let packet = packet::Packet::recv_packet(&mut self.stream);
+        self.stream.set_nonblocking(true)?;
+        packet
+    }
 
-        packet::Packet::new(packet::Flag::Init, 0, 0, 0, &data, 0).send_packet(&mut self.stream)?;
+    fn init_handshake(&mut self, handshake: packet::Handshake) -> Result<(), Error> {
+        packet::Packet::new(packet::Flag::Init, 0, 0, 0, &handshake.pack(), 0)?
+            .send_packet(&mut self.stream)?;
 
-        let packet = packet::Packet::recv_packet(&mut self.stream)?;
+        let packet = self.recv_answer()?;
         self.session_token = packet.session;
+        self.secret = packet.secret;
+        self.catalogue = packet::Listing::unpack_catalogue(&packet.data[..packet.size])?;
         Ok(())
     }
+
+    /// Create or join a room, return its code
+    fn enter_room(&mut self, flag: packet::Flag, data: &[u8]) -> Result<u32, Error> {
+        let packet = packet::Packet::new(flag, 0, self.session_token, 0, data, 0)?;
+        self.send_packet(packet)?;
+
+        let packet = self.recv_answer()?;
+        if let packet::Flag::Error(e) = packet.get_flag() {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("the server refused : {e}"),
+            ));
+        }
+        self.room_token = packet.room;
+        self.status = Status::InRoom;
+        let code = &packet.data[..4];
+        Ok(u32::from_be_bytes([code[0], code[1], code[2], code[3]]))
+    }
+
+    fn send_chat_line(&mut self, emote: Option<u8>, text: &str) -> Result<(), Error> {
+        if text.len() > packet::CHAT_SIZE {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("chat lines are cut at {} bytes", packet::CHAT_SIZE),
+            ));
+        }
+        let chat = packet::Chat {
+            session: self.session_token,
+            emote,
+            text: text.to_string(),
+        };
+        let packet = packet::Packet::new(
+            packet::Flag::Chat,
+            0,
+            self.session_token,
+            0,
+            &chat.pack(),
+            0,
+        )?;
+        self.send_packet(packet)
+    }
+
+    fn send_launch(&mut self, force: bool) -> Result<(), Error> {
+        self.send_packet(packet::Packet::control(
+            packet::Flag::Launch,
+            self.session_token,
+            self.room_token,
+            force as u16,
+        ))
+    }
+
+    fn send_packet(&mut self, packet: packet::Packet) -> Result<(), Error> {
+        packet
+            .with_secret(self.secret)
+            .send_packet(&mut self.stream)
+    }
 }
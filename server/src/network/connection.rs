@@ -1,18 +1,37 @@
 use super::client;
+use super::limits::{Limits, RateLimiter, Verdict};
 use super::packet::{HEADER_SIZE, MAX_DATA_SIZE};
 use super::pipe::{self, GameMessage};
+use crate::game::{self, settings::Settings};
+use crate::logging;
+use crate::metrics::metrics;
 use crate::network::packet;
+use crate::stats;
 
 use log::{error, info, warn};
+use rand::Rng;
 use std::io::{Error, ErrorKind, Read, Write};
-use std::net::TcpStream;
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicU16, Ordering};
 use std::sync::mpsc::{self, TryRecvError};
+use std::sync::{Arc, Mutex};
 use std::time;
 use std::{error, thread};
 
 /// TODO: I ave to implement the forwading of the game chosen to the room manager !!!!!!!!!!!!!!!!!!!
 /// And choose when to do that .........
 
+//////////////////////////////////////////////
+///
+///
+/// Constants
+///
+///
+//////////////////////////////////////////////
+
+/// How long a client waited for in a room goes without the news of the room
+const ROSTER_POLL: time::Duration = time::Duration::from_millis(100);
+
 //////////////////////////////////////////////
 ///
 ///
@@ -32,6 +51,65 @@ enum Status {
     Initialized,
 }
 
+/// What a client waiting in a room receives first
+enum Event {
+    Client(packet::Packet),
+    Room(GameMessage),
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Session
+///
+///
+//////////////////////////////////////////////
+
+/// Part of a connection shared with the server, so that the operators can see and reach the client
+pub struct Session {
+    pub token: u16,
+    pub address: String,
+    // secret the client signs its packets with, so that no one else can talk for it
+    secret: u64,
+    // room of the client, 0 while it is in none
+    room: AtomicU16,
+    // every packet to the client is written through it, so that the server can send some too
+    writer: Mutex<TcpStream>,
+}
+
+impl Session {
+    pub fn new(stream: &TcpStream, token: u16) -> Result<Self, Error> {
+        Ok(Session {
+            token,
+            address: stream.peer_addr()?.to_string(),
+            secret: rand::thread_rng().gen_range(1..=u64::MAX),
+            room: AtomicU16::new(0),
+            writer: Mutex::new(stream.try_clone()?),
+        })
+    }
+
+    pub fn room(&self) -> Option<u16> {
+        match self.room.load(Ordering::Relaxed) {
+            0 => None,
+            room => Some(room),
+        }
+    }
+
+    fn set_room(&self, room: Option<u16>) {
+        self.room.store(room.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    /// Send a packet to the client, between the packets of the connection
+    pub fn send(&self, packet: &packet::Packet) -> Result<(), Error> {
+        packet.send_packet(&mut self.writer.lock().unwrap())
+    }
+
+    /// Disconnect the client, its connection ends on its next read or write
+    pub fn close(&self) {
+        let _ = self.writer.lock().unwrap().shutdown(Shutdown::Both);
+    }
+}
+
 //////////////////////////////////////////////
 ///
 ///
@@ -48,6 +126,7 @@ pub struct Connection {
     room_token: u16,
     game_id: client::Game,
     stream: TcpStream,
+    session: Arc<Session>,
 
     physical_height: f32,
     physical_width: f32,
@@ -62,6 +141,28 @@ pub struct Connection {
 
     // Receiver for the game thread to send us data
     my_recv: Option<mpsc::Receiver<GameMessage>>,
+
+    // Message of the room received while forwarding the roster, read next
+    pending: Option<GameMessage>,
+
+    // Pipes to the players of the room, to relay the chat
+    members: Option<pipe::Members>,
+
+    // Players holding the game of the room paused
+    pause: Option<pipe::Pause>,
+
+    // Profile asked in the handshake
+    nickname: String,
+    avatar: Option<u8>,
+
+    // Statistics of the players, for the leaderboards
+    stats: Arc<Mutex<stats::Stats>>,
+
+    limits: Limits,
+    // every packet of the client counts, in the lobby as in game
+    limiter: RateLimiter,
+    chat_limiter: RateLimiter,
+    failed_joins: u32,
 }
 
 impl Connection {
@@ -75,24 +176,21 @@ impl Connection {
 
     pub fn new(
         stream: TcpStream,
-        token: u16,
+        session: Arc<Session>,
         main_sender: mpsc::Sender<pipe::ServerMessage>,
+        limits: Limits,
+        stats: Arc<Mutex<stats::Stats>>,
     ) -> Self {
-        let mut target: String = "".to_string();
-        match stream.peer_addr() {
-            Ok(addr) => target = format!("Client {token} ({})", addr),
-            Err(e) => {
-                error!(target: format!("Client {token} ()").as_str(), "client disconnected : {e}");
-            }
-        }
+        let target = format!("Client {} ({})", session.token, session.address);
 
         Connection {
             status: Status::Created,
             target,
-            session_token: token,
+            session_token: session.token,
             room_token: 0,
             game_id: client::Game::Unknown,
             stream,
+            session,
             physical_height: 0.,
             physical_width: 0.,
             window_height: 0,
@@ -100,10 +198,21 @@ impl Connection {
             main_sender,
             game_sender: None,
             my_recv: None,
+            pending: None,
+            members: None,
+            pause: None,
+            nickname: String::new(),
+            avatar: None,
+            stats,
+            limits,
+            limiter: RateLimiter::new(limits, time::Instant::now()),
+            chat_limiter: RateLimiter::chat(limits, time::Instant::now()),
+            failed_joins: 0,
         }
     }
 
     pub fn manager(&mut self) -> Result<(), Error> {
+        logging::set_session(self.session_token);
         match self.handshake() {
             Ok(_) => {}
             Err(e) => {
@@ -114,29 +223,44 @@ impl Connection {
         info!(target: self.target.as_str(), "Handshake done");
 
         'room: loop {
+            self.session.set_room(None);
+            self.my_recv = None;
+            self.pending = None;
+            self.members = None;
+            self.pause = None;
             let lock = match self.join_room() {
                 Ok(l) => l,
                 Err(e)
                     if e.kind() == ErrorKind::InvalidInput
-                        || e.kind() == ErrorKind::NotConnected =>
+                        || e.kind() == ErrorKind::NotConnected
+                        || e.kind() == ErrorKind::ConnectionAborted =>
                 {
                     warn!(target: self.target.as_str(), "{e}");
                     return Ok(());
                 }
+                Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+                    warn!(target: self.target.as_str(), "{e}");
+                    continue 'room;
+                }
                 Err(e) => {
                     error!(target: self.target.as_str(), "{e}");
                     self.send_packet(packet::Packet::error_message(self.session_token))?;
                     continue 'room;
                 }
             };
+            logging::set_room(self.room_token);
+            self.session.set_room(Some(self.room_token));
             info!(target: self.target.as_str(), "Join room {}", self.room_token);
             'game: loop {
                 match self.lock_room(&lock) {
                     Ok(rank) => {
                         self.send_ranks(rank);
                         match self.launch_game(&lock) {
-                            Ok(_) => {}
-                            Err(e) if e.kind() == ErrorKind::NotConnected => {
+                            Ok(_) => logging::set_game(Some(self.game_id.to_string())),
+                            Err(e)
+                                if e.kind() == ErrorKind::NotConnected
+                                    || e.kind() == ErrorKind::ConnectionAborted =>
+                            {
                                 warn!(target: self.target.as_str(), "{e}");
                                 return Err(Error::new(
                                     ErrorKind::NotConnected,
@@ -151,8 +275,16 @@ impl Connection {
                                 continue 'room;
                             }
                         }
-                        match self.game_loop() {
+                        let res = self.game_loop(&lock);
+                        // a client leaving the game doesn't keep it paused
+                        self.hold_pause(false, &Lock::Disabled);
+                        logging::set_game(None);
+                        match res {
                             Ok(_) => {}
+                            Err(e) if e.kind() == ErrorKind::ConnectionAborted => {
+                                warn!(target: self.target.as_str(), "{e}");
+                                return Ok(());
+                            }
                             Err(e) => {
                                 error!(target: self.target.as_str(), "{e}");
                                 self.send_packet(packet::Packet::error_message(
@@ -162,7 +294,10 @@ impl Connection {
                             }
                         }
                     }
-                    Err(e) if e.kind() == ErrorKind::NotConnected => {
+                    Err(e)
+                        if e.kind() == ErrorKind::NotConnected
+                            || e.kind() == ErrorKind::ConnectionAborted =>
+                    {
                         warn!(target: self.target.as_str(), "{e}");
                         return Err(Error::new(
                             ErrorKind::NotConnected,
@@ -188,20 +323,31 @@ impl Connection {
 
     /// Initial handshake
     fn handshake(&mut self) -> Result<(), Error> {
-        match packet::Packet::recv_packet(&mut self.stream) {
+        match self.recv_within(self.limits.handshake_timeout) {
             Ok(packet) => {
                 packet.check_packet_flag(packet::Flag::Init)?;
-                let mut tmp = [0_u8; 4];
-                tmp.copy_from_slice(&packet.data[..4]);
-                self.physical_height = f32::from_be_bytes(tmp);
-                tmp.copy_from_slice(&packet.data[4..8]);
-                self.physical_width = f32::from_be_bytes(tmp);
-                tmp.copy_from_slice(&packet.data[8..12]);
-                self.window_height = u32::from_be_bytes(tmp);
-                tmp.copy_from_slice(&packet.data[12..16]);
-                self.window_width = u32::from_be_bytes(tmp);
-
-                self.send_packet(packet);
+                let screen = packet::Handshake::unpack(&packet.data[..packet.size])?;
+                self.physical_height = screen.physical_height;
+                self.physical_width = screen.physical_width;
+                self.window_height = screen.window_height;
+                self.window_width = screen.window_width;
+                self.nickname = screen.nickname;
+                self.avatar = screen.avatar;
+
+                // the client signs its next packets with the secret of its session
+                // along with the games it can lock a room for
+                let catalogue = packet::Listing::pack_catalogue(&game::listings());
+                self.send_packet(
+                    packet::Packet::new(
+                        packet::Flag::Init,
+                        0,
+                        self.session_token,
+                        0,
+                        &catalogue,
+                        0,
+                    )?
+                    .with_secret(self.session.secret),
+                )?;
                 self.status = Status::Initialized;
                 Ok(())
             }
@@ -211,136 +357,254 @@ impl Connection {
 
     fn join_room(&mut self) -> Result<Lock, Error> {
         // two possible things : either we create a game, either we connect to one !
-        match packet::Packet::recv_packet(&mut self.stream) {
-            Ok(packet) => {
-                let flag = packet.get_flag();
-                let room_token = packet.room;
-                match flag {
-                    packet::Flag::Create => self.join_room_with_create(),
-                    packet::Flag::Join => self.join_room_with_join(room_token),
-                    _ => Err(Error::new(
-                        ErrorKind::InvalidInput,
-                        "an unexpected packet was received",
-                    )),
+        // the client may look at the leaderboards meanwhile
+        loop {
+            match self.recv_within(self.limits.lobby_timeout) {
+                Ok(_) if !self.within_limits()? => {}
+                Ok(packet) => {
+                    // Create : [PIN (u16)], Join : code (u32) [PIN (u16)]
+                    let data = &packet.data[..packet.size];
+                    let word =
+                        |i: usize| data.get(i..i + 2).map(|w| u16::from_be_bytes([w[0], w[1]]));
+                    return match packet.get_flag() {
+                        packet::Flag::Create => self.join_room_with_create(word(0)),
+                        packet::Flag::Join => {
+                            let code = match (word(0), word(2)) {
+                                (Some(high), Some(low)) => (high as u32) << 16 | low as u32,
+                                _ => 0,
+                            };
+                            self.join_room_with_join(code, word(4))
+                        }
+                        packet::Flag::Leaderboard => {
+                            self.send_leaderboard(&packet)?;
+                            continue;
+                        }
+                        _ => Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            "an unexpected packet was received",
+                        )),
+                    };
                 }
+                Err(e) if e.kind() == ErrorKind::ConnectionAborted => return Err(e),
+                Err(e) => return Err(Error::new(ErrorKind::NotConnected, "client disconnected")),
             }
-            Err(e) => Err(Error::new(ErrorKind::NotConnected, "client disconnected")),
         }
     }
 
+    /// Wait in the lobby until the room is locked, relaying the chat of the client meanwhile
+    /// The game and the settings of the master are checked before the room is locked, the master
+    /// is told why they are refused and may lock it again
     fn lock_room(&mut self, lock: &Lock) -> Result<u8, Error> {
-        match lock {
-            Lock::Enabled => {
-                // listen to stream
-                match packet::Packet::recv_packet(&mut self.stream) {
-                    Ok(packet) => {
-                        packet.check_packet_flag(packet::Flag::Lock)?;
-                        self.game_id = packet.option.into();
-                    }
-                    Err(_) => {
-                        return Err(Error::new(ErrorKind::NotConnected, "client disconnected"));
-                    }
+        // only the master has to act, the others may wait for it as long as it takes
+        let timeout = match lock {
+            Lock::Enabled => Some(self.limits.lobby_timeout),
+            Lock::Disabled => None,
+        };
+        let settings = loop {
+            let event = match self.next_event(timeout) {
+                Ok(event) => event,
+                Err(e)
+                    if e.kind() == ErrorKind::ConnectionAborted
+                        || e.kind() == ErrorKind::BrokenPipe =>
+                {
+                    return Err(e)
                 }
-                let sender = self.game_sender.as_ref().unwrap();
-                match sender.send(pipe::GameMessage::lock_message(self.game_id.into())) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        return Err(Error::new(ErrorKind::BrokenPipe, "pipe with game broken"))
-                    }
+                Err(_) => return Err(Error::new(ErrorKind::NotConnected, "client disconnected")),
+            };
+            if matches!(event, Event::Client(_)) && !self.within_limits()? {
+                continue;
+            }
+            match (event, lock) {
+                (Event::Client(packet), _) if packet.get_flag() == packet::Flag::Chat => {
+                    self.relay_chat(&packet)?
                 }
-                match self.my_recv.as_ref().unwrap().recv() {
-                    Ok(m) => Ok(m.rank.unwrap() as u8), // should never be None
-                    Err(e) => Err(Error::new(ErrorKind::BrokenPipe, "pipe with game broken")),
+                (Event::Client(packet), _) if packet.get_flag() == packet::Flag::Leaderboard => {
+                    self.send_leaderboard(&packet)?
                 }
-            }
-            Lock::Disabled => {
-                // listen to game_receiver for lock message
-                match self.my_recv.as_ref().unwrap().recv() {
-                    Ok(m) => Ok(m.rank.unwrap() as u8), // should never be None
-                    Err(e) => Err(Error::new(ErrorKind::BrokenPipe, "pipe with game broken")),
+                // inputs sent before the client heard that the last game ended
+                (Event::Client(packet), _) if packet.get_flag() == packet::Flag::Transmit => {}
+                (Event::Client(packet), Lock::Enabled) => {
+                    packet.check_packet_flag(packet::Flag::Lock)?;
+                    let game_id: client::Game = packet.option.into();
+                    match self.check_lock(game_id, &packet.data[..packet.size]) {
+                        Ok(settings) => {
+                            self.game_id = game_id;
+                            break settings;
+                        }
+                        Err(e) => {
+                            warn!(target: self.target.as_str(), "lock for {game_id} refused : {e}");
+                            let text = e.to_string();
+                            self.send_packet(packet::Packet::text_message(
+                                self.session_token,
+                                &text,
+                            ))?;
+                        }
+                    }
+                }
+                (Event::Client(packet), Lock::Disabled) => {
+                    warn!(target: self.target.as_str(), "unexpected {} packet in the lobby", packet.get_flag());
                 }
+                (Event::Room(message), _) => match message.flag {
+                    pipe::GameMessageFlag::Lock => return Ok(message.rank.unwrap() as u8), // should never be None
+                    _ => {
+                        return Err(Error::new(
+                            ErrorKind::Interrupted,
+                            "the room stopped before being locked",
+                        ))
+                    }
+                },
             }
+        };
+
+        let sender = self.game_sender.as_ref().unwrap();
+        let message = pipe::GameMessage::lock_request(self.game_id.into(), &settings.pack());
+        match sender.send(message) {
+            Ok(_) => {}
+            Err(e) => return Err(Error::new(ErrorKind::BrokenPipe, "pipe with game broken")),
+        }
+        match self.recv_from_room() {
+            Ok(m) => Ok(m.rank.unwrap() as u8), // should never be None
+            Err(e) => Err(Error::new(ErrorKind::BrokenPipe, "pipe with game broken")),
+        }
+    }
+
+    /// Settings of the lock asked by the master, if the server runs the game for as many players
+    /// as the room has
+    fn check_lock(&self, game_id: client::Game, data: &[u8]) -> Result<Settings, Error> {
+        let Some(info) = game::info(game_id) else {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("{game_id} isn't played on this server"),
+            ));
+        };
+        let players = self.members.as_ref().map_or(1, |m| m.lock().unwrap().len());
+        if !info.players.contains(&players) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "{} is played by {} to {} players, not {players}",
+                    info.name,
+                    info.players.start(),
+                    info.players.end()
+                ),
+            ));
         }
+        Settings::unpack(game::settings(game_id), data)
+            .map_err(|e| Error::new(e.kind(), format!("invalid settings : {e}")))
     }
 
     fn send_ranks(&mut self, rank: u8) {
-        let packet = packet::Packet::new(
+        let packet = packet::Packet::control(
             packet::Flag::Lock,
-            0,
             self.session_token,
             self.room_token,
-            &[],
             rank as u16,
         );
         self.send_packet(packet);
     }
 
+    /// Relay the ready state of the client, and the launch of the master, until the room
+    /// launches the game
     fn launch_game(&mut self, lock: &Lock) -> Result<(), Error> {
-        match lock {
-            Lock::Enabled => {
-                // listen to stream
-                match packet::Packet::recv_packet(&mut self.stream) {
-                    Ok(packet) => {
-                        packet.check_packet_flag(packet::Flag::Launch)?;
-                        self.game_id = packet.option.into();
-                    }
-                    Err(_) => {
-                        return Err(Error::new(ErrorKind::NotConnected, "client disconnected"));
-                    }
+        // only the master has to act, the others may wait for it as long as it takes
+        let timeout = match lock {
+            Lock::Enabled => Some(self.limits.lobby_timeout),
+            Lock::Disabled => None,
+        };
+        loop {
+            let event = self.next_event(timeout)?;
+            if matches!(event, Event::Client(_)) && !self.within_limits()? {
+                continue;
+            }
+            match event {
+                Event::Client(packet) if packet.get_flag() == packet::Flag::Chat => {
+                    self.relay_chat(&packet)?
                 }
-                let sender = self.game_sender.as_mut().expect("No sender !");
-                match sender.send(pipe::GameMessage::launch_message(self.game_id.into())) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        return Err(Error::new(ErrorKind::BrokenPipe, "pipe with game broken"))
-                    }
+                Event::Client(packet) if packet.get_flag() == packet::Flag::Leaderboard => {
+                    self.send_leaderboard(&packet)?
                 }
-                match self.my_recv.as_ref().unwrap().recv() {
-                    Ok(_) => {
-                        let packet = packet::Packet::new(
-                            packet::Flag::Launch,
-                            0,
-                            self.session_token,
-                            self.room_token,
-                            &[],
-                            self.game_id.into(),
-                        );
-                        self.send_packet(packet)?;
-                    }
-                    Err(e) => {
-                        return Err(Error::new(ErrorKind::BrokenPipe, "pipe with game broken"))
+                Event::Client(packet) if packet.get_flag() == packet::Flag::Transmit => {}
+                Event::Client(packet) => {
+                    let message = match (packet.get_flag(), lock) {
+                        (packet::Flag::Ready, _) => GameMessage::ready_message(packet.option != 0),
+                        (packet::Flag::Launch, Lock::Enabled) => {
+                            GameMessage::start_message(packet.option != 0)
+                        }
+                        (packet::Flag::Launch, Lock::Disabled) => {
+                            warn!(target: self.target.as_str(), "only the master can launch the game");
+                            continue;
+                        }
+                        _ => {
+                            return Err(Error::new(
+                                ErrorKind::InvalidInput,
+                                "an unexpected packet was received",
+                            ))
+                        }
+                    };
+                    if self.game_sender.as_ref().unwrap().send(message).is_err() {
+                        return Err(Error::new(ErrorKind::BrokenPipe, "pipe with game broken"));
                     }
                 }
-            }
-            Lock::Disabled => {
-                // listen to game_receiver for lock message
-                match self.my_recv.as_ref().unwrap().recv() {
-                    Ok(message) => {
+                Event::Room(message) => match message.flag {
+                    pipe::GameMessageFlag::Launch => {
+                        self.game_id = message.rank.unwrap().into(); // should never be None
+
+                        // the settings of the game, for the phones simulating a part of it
+                        let data = message.data.unwrap_or([0; MAX_DATA_SIZE]);
                         let packet = packet::Packet::new(
                             packet::Flag::Launch,
                             0,
                             self.session_token,
                             self.room_token,
-                            &[],
-                            message.rank.unwrap(),
-                        );
-                        self.send_packet(packet)?;
+                            &data[..message.size],
+                            self.game_id.into(),
+                        )?;
+                        return self.send_packet(packet);
                     }
-                    Err(e) => {
-                        return Err(Error::new(ErrorKind::BrokenPipe, "pipe with game broken"))
+                    // a player left before the launch, the others are ranked again
+                    pipe::GameMessageFlag::Lock => self.send_ranks(message.rank.unwrap() as u8), // should never be None
+                    pipe::GameMessageFlag::Error => {
+                        return Err(Error::new(
+                            ErrorKind::Interrupted,
+                            "the other players left the room",
+                        ))
                     }
-                }
+                    _ => {}
+                },
             }
         }
-        Ok(())
     }
 
-    fn game_loop(&mut self) -> Result<(), Error> {
+    fn game_loop(&mut self, lock: &Lock) -> Result<(), Error> {
+        // the game sends whether the client talks or not
+        self.stream.set_nonblocking(true)?;
+        // the game is stopped, from the pause until the end of the countdown
+        let mut paused = false;
         loop {
+            let mut idle = true;
+
             // try receive from the client
             if let Some(packet) = packet::Packet::try_recv_packet(&mut self.stream) {
+                idle = false;
+                let packet = self.authenticate(packet)?;
+                let forward = self.within_limits()?;
+                if packet.get_flag() == packet::Flag::Chat {
+                    if forward {
+                        self.relay_chat(&packet)?;
+                    }
+                    continue;
+                }
                 //packet.check_packet_flag(packet::Flag::Transmit)?;
+                if packet.get_flag() == packet::Flag::Pause {
+                    if forward {
+                        self.hold_pause(packet.option != 0, lock);
+                    }
+                    continue;
+                }
+                // the inputs sent while the game is stopped would move the player all the same
+                let stopped = paused || self.pause_held();
                 match &self.game_sender {
+                    Some(_) if !forward || stopped => {}
                     Some(sender) => match sender
                         .send(pipe::GameMessage::data_message(packet.data, packet.size))
                     {
@@ -357,7 +621,26 @@ impl Connection {
             // try receive from the game
             let error = &self.my_recv.as_ref().unwrap().try_recv(); // is will
             match error {
+                Ok(message) if is_notice(message) => {
+                    idle = false;
+                    if let pipe::GameMessageFlag::Pause = message.flag {
+                        paused = message.rank == Some(1);
+                    }
+                    self.send_notice(message)?;
+                }
+                Ok(message) if matches!(message.flag, pipe::GameMessageFlag::End) => {
+                    // back to the lobby of the room, where the client is waited for
+                    self.stream.set_nonblocking(false)?;
+                    let packet = packet::Packet::control(
+                        packet::Flag::End,
+                        self.session_token,
+                        self.room_token,
+                        message.rank.unwrap(), // should never be None
+                    );
+                    return self.send_packet(packet);
+                }
                 Ok(message) => {
+                    idle = false;
                     let packet = packet::Packet::new(
                         packet::Flag::Transmit,
                         0,
@@ -365,7 +648,7 @@ impl Connection {
                         self.room_token,
                         &message.data.unwrap(), // should never be None
                         0,
-                    );
+                    )?;
                     self.send_packet(packet)?;
                 }
                 Err(TryRecvError::Empty) => {}
@@ -373,6 +656,10 @@ impl Connection {
                     return Err(Error::new(ErrorKind::BrokenPipe, "pipe with game broken"))
                 }
             };
+
+            if idle {
+                thread::sleep(time::Duration::from_millis(1));
+            }
         }
         Ok(())
     }
@@ -385,20 +672,273 @@ impl Connection {
     ///
     //////////////////////////////////////////////
 
+    /// Account for a packet of the client, whatever its data, a whole buffer is read for each one
+    /// Tell whether it is within the rate limits of the session, see `enforce`
+    fn within_limits(&mut self) -> Result<bool, Error> {
+        let verdict = self
+            .limiter
+            .check(packet::BUFFER_SIZE, time::Instant::now());
+        self.enforce(verdict)
+    }
+
+    /// Tell whether the packet of the client is within its rate limits, warn or disconnect it if
+    /// it keeps going over
+    fn enforce(&mut self, verdict: Verdict) -> Result<bool, Error> {
+        match verdict {
+            Verdict::Accept => Ok(true),
+            Verdict::Drop => {
+                metrics().packet_dropped();
+                Ok(false)
+            }
+            Verdict::Warn => {
+                metrics().packet_dropped();
+                warn!(target: self.target.as_str(), "client is flooding, its packets are dropped");
+                self.send_packet(packet::Packet::text_message(
+                    self.session_token,
+                    "You are sending too much data, slow down or you will be disconnected",
+                ))?;
+                Ok(false)
+            }
+            Verdict::Disconnect => {
+                metrics().packet_dropped();
+                Err(self.disconnect(packet::ProtocolError::Flooding, "disconnected for flooding"))
+            }
+        }
+    }
+
+    /// Send the chat line of the client to every player of its room, itself included
+    fn relay_chat(&mut self, packet: &packet::Packet) -> Result<(), Error> {
+        let verdict = self
+            .chat_limiter
+            .check(packet::BUFFER_SIZE, time::Instant::now());
+        if !self.enforce(verdict)? {
+            return Ok(());
+        }
+        let mut chat = match packet::Chat::unpack(&packet.data[..packet.size]) {
+            Ok(chat) => chat,
+            Err(e) => {
+                warn!(target: self.target.as_str(), "{e}");
+                return Ok(());
+            }
+        };
+        chat.session = self.session_token;
+        let data = chat.pack();
+        if let Some(members) = &self.members {
+            for member in members.lock().unwrap().iter() {
+                let _ = member.send(GameMessage::chat_message(&data));
+            }
+        }
+        Ok(())
+    }
+
+    /// Hold the game of the room paused for the client, or let it go on
+    /// The master lets it go on for everyone, whoever paused it
+    fn hold_pause(&mut self, paused: bool, lock: &Lock) {
+        let Some(pause) = &self.pause else {
+            return;
+        };
+        let mut holders = pause.lock().unwrap();
+        match (paused, lock) {
+            (true, _) if !holders.contains(&self.session_token) => {
+                info!(target: self.target.as_str(), "Game paused");
+                holders.push(self.session_token);
+            }
+            (true, _) => {}
+            (false, Lock::Enabled) => holders.clear(),
+            (false, Lock::Disabled) => holders.retain(|s| *s != self.session_token),
+        }
+    }
+
+    /// Whether a player holds the game of the room paused
+    fn pause_held(&self) -> bool {
+        self.pause
+            .as_ref()
+            .is_some_and(|pause| !pause.lock().unwrap().is_empty())
+    }
+
+    /// Answer a query of the client for the leaderboard of a game, given in the option
+    fn send_leaderboard(&mut self, query: &packet::Packet) -> Result<(), Error> {
+        let verdict = self
+            .chat_limiter
+            .check(packet::BUFFER_SIZE, time::Instant::now());
+        if !self.enforce(verdict)? {
+            return Ok(());
+        }
+        let game = client::Game::from(query.option);
+        let standings = self.stats.lock().unwrap().leaderboard(game);
+        let packet = packet::Packet::new(
+            packet::Flag::Leaderboard,
+            0,
+            self.session_token,
+            self.room_token,
+            &packet::Standing::pack_leaderboard(&standings),
+            query.option,
+        )?;
+        self.send_packet(packet)
+    }
+
+    /// Next packet of the client, which is disconnected if it doesn't send one within `timeout`
+    /// Meanwhile the news of the room are forwarded to it
+    fn recv_within(&mut self, timeout: time::Duration) -> Result<packet::Packet, Error> {
+        let deadline = time::Instant::now() + timeout;
+        loop {
+            self.forward_notices()?;
+            let slice = deadline.min(time::Instant::now() + ROSTER_POLL);
+            match packet::Packet::recv_packet_before(&mut self.stream, slice) {
+                Err(e) if e.kind() == ErrorKind::TimedOut && time::Instant::now() < deadline => {}
+                Err(e) if e.kind() == ErrorKind::TimedOut => {
+                    return Err(self.disconnect(
+                        packet::ProtocolError::Timeout,
+                        "disconnected after being idle for too long",
+                    ))
+                }
+                Ok(packet) => return self.authenticate(packet),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Next packet of the client or message of the room, whichever comes first
+    /// The client is disconnected if it doesn't send anything within `timeout`, if any
+    fn next_event(&mut self, timeout: Option<time::Duration>) -> Result<Event, Error> {
+        let deadline = timeout.map(|t| time::Instant::now() + t);
+        loop {
+            if !self.forward_notices()? {
+                return Err(Error::new(ErrorKind::BrokenPipe, "pipe with game broken"));
+            }
+            if let Some(message) = self.pending.take() {
+                return Ok(Event::Room(message));
+            }
+            let now = time::Instant::now();
+            let slice = deadline.map_or(now + ROSTER_POLL, |d| d.min(now + ROSTER_POLL));
+            match packet::Packet::recv_packet_before(&mut self.stream, slice) {
+                Err(e)
+                    if e.kind() == ErrorKind::TimedOut
+                        && deadline.is_none_or(|d| time::Instant::now() < d) => {}
+                Err(e) if e.kind() == ErrorKind::TimedOut => {
+                    return Err(self.disconnect(
+                        packet::ProtocolError::Timeout,
+                        "disconnected after being idle for too long",
+                    ))
+                }
+                Ok(packet) => return Ok(Event::Client(self.authenticate(packet)?)),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Next message of the room, the news are forwarded to the client on the way
+    fn recv_from_room(&mut self) -> Result<GameMessage, Error> {
+        if let Some(message) = self.pending.take() {
+            return Ok(message);
+        }
+        loop {
+            let message = match self.my_recv.as_ref().map(|r| r.recv()) {
+                Some(Ok(message)) => message,
+                _ => return Err(Error::new(ErrorKind::BrokenPipe, "pipe with game broken")),
+            };
+            match is_notice(&message) {
+                true => self.send_notice(&message)?,
+                false => return Ok(message),
+            }
+        }
+    }
+
+    /// Forward the news already sent by the room, keep its other message for `recv_from_room`
+    /// Return false once the room is gone
+    fn forward_notices(&mut self) -> Result<bool, Error> {
+        while self.pending.is_none() {
+            let message = match self.my_recv.as_ref().map(|r| r.try_recv()) {
+                Some(Ok(message)) => message,
+                Some(Err(TryRecvError::Disconnected)) => return Ok(false),
+                _ => return Ok(true),
+            };
+            match is_notice(&message) {
+                true => self.send_notice(&message)?,
+                false => self.pending = Some(message),
+            }
+        }
+        Ok(true)
+    }
+
+    /// Tell the client about the room : its roster, the countdown to the launch, some text or the
+    /// chat of the players
+    fn send_notice(&mut self, message: &GameMessage) -> Result<(), Error> {
+        let data = message.data.unwrap_or([0; MAX_DATA_SIZE]);
+        let flag = match message.flag {
+            pipe::GameMessageFlag::Chat => packet::Flag::Chat,
+            _ => packet::Flag::Roster,
+        };
+        let packet = match message.flag {
+            pipe::GameMessageFlag::Countdown => packet::Packet::control(
+                packet::Flag::Countdown,
+                self.session_token,
+                self.room_token,
+                message.rank.unwrap(), // should never be None
+            ),
+            pipe::GameMessageFlag::Pause => packet::Packet::control(
+                packet::Flag::Pause,
+                self.session_token,
+                self.room_token,
+                message.rank.unwrap(), // should never be None
+            ),
+            pipe::GameMessageFlag::Text => {
+                let text = String::from_utf8_lossy(&data[..message.size]);
+                packet::Packet::text_message(self.session_token, &text)
+            }
+            _ => packet::Packet::new(
+                flag,
+                0,
+                self.session_token,
+                self.room_token,
+                &data[..message.size],
+                0,
+            )?,
+        };
+        self.send_packet(packet)
+    }
+
+    /// Disconnect the client if the packet isn't signed with the credentials of its session
+    fn authenticate(&mut self, packet: packet::Packet) -> Result<packet::Packet, Error> {
+        match self.status {
+            Status::Created => Ok(packet),
+            Status::Initialized
+                if packet.session == self.session_token && packet.secret == self.session.secret =>
+            {
+                Ok(packet)
+            }
+            Status::Initialized => Err(self.disconnect(
+                packet::ProtocolError::Unauthorized,
+                "disconnected for a packet with wrong credentials",
+            )),
+        }
+    }
+
+    /// Tell the client why it is disconnected and close the connection
+    fn disconnect(&mut self, error: packet::ProtocolError, reason: &str) -> Error {
+        let _ = self.send_packet(packet::Packet::protocol_error(error, self.session_token));
+        self.session.close();
+        Error::new(ErrorKind::ConnectionAborted, reason)
+    }
+
     fn send_packet(&mut self, packet: packet::Packet) -> Result<(), Error> {
-        if let Err(e) = packet.send_packet(&mut self.stream) {
+        if let Err(e) = self.session.send(&packet) {
             return Err(Error::new(ErrorKind::NotConnected, "client disconnected"));
         }
         Ok(())
     }
 
-    fn join_room_with_create(&mut self) -> Result<Lock, Error> {
+    fn join_room_with_create(&mut self, pin: Option<u16>) -> Result<Lock, Error> {
         let (sender, receiver) = mpsc::channel();
         self.main_sender
             .send(pipe::ServerMessage {
                 session_token: self.session_token,
                 flag: pipe::ServerMessageFlag::Create,
                 room_token: 0,
+                code: 0,
+                pin,
+                nickname: self.nickname.clone(),
+                avatar: self.avatar,
                 sender,
                 physical_height: self.physical_height,
                 physical_width: self.physical_width,
@@ -411,28 +951,26 @@ impl Connection {
             Ok(message) => {
                 self.room_token = message.room_token;
                 self.game_sender = message.sender;
-                let packet = packet::Packet::new(
-                    packet::Flag::Create,
-                    0,
-                    self.session_token,
-                    self.room_token,
-                    &[],
-                    0,
-                );
-                self.send_packet(packet);
+                self.members = message.members;
+                self.pause = message.pause;
+                self.send_room_code(message.code)?;
                 Ok(Lock::Enabled)
             }
             Err(_) => Err(Error::new(ErrorKind::BrokenPipe, "pipe with game broken")),
         }
     }
 
-    fn join_room_with_join(&mut self, room_token: u16) -> Result<Lock, Error> {
+    fn join_room_with_join(&mut self, code: u32, pin: Option<u16>) -> Result<Lock, Error> {
         let (sender, receiver) = mpsc::channel();
         self.main_sender
             .send(pipe::ServerMessage {
                 session_token: self.session_token,
                 flag: pipe::ServerMessageFlag::Join,
-                room_token,
+                room_token: 0,
+                code,
+                pin,
+                nickname: self.nickname.clone(),
+                avatar: self.avatar,
                 sender,
                 physical_height: self.physical_height,
                 physical_width: self.physical_width,
@@ -442,21 +980,59 @@ impl Connection {
             .unwrap();
         self.my_recv = Some(receiver);
         match self.my_recv.as_ref().unwrap().recv() {
-            Ok(message) => {
-                self.room_token = message.room_token;
-                self.game_sender = message.sender;
-                let packet = packet::Packet::new(
-                    packet::Flag::Create,
-                    0,
-                    self.session_token,
-                    self.room_token,
-                    &[],
-                    0,
-                );
-                self.send_packet(packet);
-                Ok(Lock::Disabled)
-            }
+            Ok(message) => match message.flag {
+                pipe::GameMessageFlag::Init => {
+                    self.room_token = message.room_token;
+                    self.game_sender = message.sender;
+                    self.members = message.members;
+                    self.pause = message.pause;
+                    self.send_room_code(message.code)?;
+                    Ok(Lock::Disabled)
+                }
+                _ => {
+                    self.failed_joins += 1;
+                    if self.failed_joins >= self.limits.max_failed_joins {
+                        return Err(self.disconnect(
+                            packet::ProtocolError::Unauthorized,
+                            "disconnected after too many wrong room codes",
+                        ));
+                    }
+                    self.send_packet(packet::Packet::protocol_error(
+                        packet::ProtocolError::Unauthorized,
+                        self.session_token,
+                    ))?;
+                    Err(Error::new(
+                        ErrorKind::PermissionDenied,
+                        format!("wrong code or PIN for the room {code}"),
+                    ))
+                }
+            },
             Err(_) => Err(Error::new(ErrorKind::BrokenPipe, "pipe with game broken")),
         }
     }
+
+    /// Tell the client the room it is in, with the code to give to the other players
+    fn send_room_code(&mut self, code: u32) -> Result<(), Error> {
+        let packet = packet::Packet::new(
+            packet::Flag::Create,
+            0,
+            self.session_token,
+            self.room_token,
+            &code.to_be_bytes(),
+            0,
+        )?;
+        self.send_packet(packet)
+    }
+}
+
+/// Messages of the room the client is only told about
+fn is_notice(message: &GameMessage) -> bool {
+    matches!(
+        message.flag,
+        pipe::GameMessageFlag::Roster
+            | pipe::GameMessageFlag::Countdown
+            | pipe::GameMessageFlag::Pause
+            | pipe::GameMessageFlag::Text
+            | pipe::GameMessageFlag::Chat
+    )
 }
@@ -0,0 +1,252 @@
+use std::time;
+
+/// Limits protecting the server from slow or flooding clients
+///
+/// Each session has two token buckets, one for the packets and one for the bytes read, refilled
+/// continuously. Every packet is read as a whole buffer, whatever the size of its data. A packet over the limit is dropped ; when a client keeps going over, it is warned
+/// then disconnected with a `ProtocolError`.
+
+//////////////////////////////////////////////
+///
+///
+/// Limits
+///
+///
+//////////////////////////////////////////////
+
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// Sustained rate and burst of the packets of a session
+    pub packets_per_second: f64,
+    pub packet_burst: f64,
+    /// Sustained rate and burst of the bytes read from a session, in bytes
+    pub bytes_per_second: f64,
+    pub byte_burst: f64,
+    /// Dropped packets after which the client is warned, then disconnected
+    pub warn_after: u32,
+    pub disconnect_after: u32,
+    /// The dropped packets are forgotten after this long without any
+    pub violation_window: time::Duration,
+    /// Longest time a client may take to send its handshake
+    pub handshake_timeout: time::Duration,
+    /// Longest time a client may wait in the lobby without creating, joining or launching a room
+    pub lobby_timeout: time::Duration,
+    /// Wrong room codes or PINs after which the client is disconnected
+    pub max_failed_joins: u32,
+    /// Sustained rate and burst of the chat lines, emotes and leaderboard queries of a session
+    pub chat_per_second: f64,
+    pub chat_burst: f64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        // the phones send an input per frame, 60 a second at most, each one read as a 2 KiB buffer
+        Limits {
+            packets_per_second: 120.,
+            packet_burst: 240.,
+            bytes_per_second: 256. * 1024.,
+            byte_burst: 512. * 1024.,
+            warn_after: 60,
+            disconnect_after: 600,
+            violation_window: time::Duration::from_secs(10),
+            handshake_timeout: time::Duration::from_secs(10),
+            lobby_timeout: time::Duration::from_secs(600),
+            max_failed_joins: 5,
+            chat_per_second: 1.,
+            chat_burst: 5.,
+        }
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Token bucket
+///
+///
+//////////////////////////////////////////////
+
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last: time::Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, capacity: f64, now: time::Instant) -> Self {
+        TokenBucket {
+            rate,
+            capacity,
+            tokens: capacity,
+            last: now,
+        }
+    }
+
+    fn refill(&mut self, now: time::Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last = now;
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Rate limiter
+///
+///
+//////////////////////////////////////////////
+
+/// What to do with a packet received from the client
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+    Accept,
+    Drop,
+    /// Drop the packet and tell the client to slow down, returned once
+    Warn,
+    Disconnect,
+}
+
+pub struct RateLimiter {
+    limits: Limits,
+    packets: TokenBucket,
+    bytes: TokenBucket,
+    violations: u32,
+    last_violation: Option<time::Instant>,
+    warned: bool,
+}
+
+impl RateLimiter {
+    pub fn new(limits: Limits, now: time::Instant) -> Self {
+        RateLimiter {
+            limits,
+            packets: TokenBucket::new(limits.packets_per_second, limits.packet_burst, now),
+            bytes: TokenBucket::new(limits.bytes_per_second, limits.byte_burst, now),
+            violations: 0,
+            last_violation: None,
+            warned: false,
+        }
+    }
+
+    /// Limiter of the chat of a session, counting its lines instead of its packets
+    pub fn chat(limits: Limits, now: time::Instant) -> Self {
+        RateLimiter {
+            packets: TokenBucket::new(limits.chat_per_second, limits.chat_burst, now),
+            ..RateLimiter::new(limits, now)
+        }
+    }
+
+    /// Account for a packet of `size` bytes read at `now`
+    pub fn check(&mut self, size: usize, now: time::Instant) -> Verdict {
+        self.packets.refill(now);
+        self.bytes.refill(now);
+        if self.packets.tokens >= 1. && self.bytes.tokens >= size as f64 {
+            self.packets.tokens -= 1.;
+            self.bytes.tokens -= size as f64;
+            return Verdict::Accept;
+        }
+
+        if self
+            .last_violation
+            .is_some_and(|last| now.saturating_duration_since(last) > self.limits.violation_window)
+        {
+            self.violations = 0;
+            self.warned = false;
+        }
+        self.violations += 1;
+        self.last_violation = Some(now);
+
+        if self.violations >= self.limits.disconnect_after {
+            Verdict::Disconnect
+        } else if self.violations >= self.limits.warn_after && !self.warned {
+            self.warned = true;
+            Verdict::Warn
+        } else {
+            Verdict::Drop
+        }
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Tests
+///
+///
+//////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escalation() {
+        let limits = Limits {
+            packets_per_second: 10.,
+            packet_burst: 5.,
+            warn_after: 2,
+            disconnect_after: 4,
+            ..Limits::default()
+        };
+        let start = time::Instant::now();
+        let mut limiter = RateLimiter::new(limits, start);
+
+        let verdicts: Vec<Verdict> = (0..9).map(|_| limiter.check(16, start)).collect();
+        assert_eq!(
+            verdicts,
+            [
+                [Verdict::Accept; 5].as_slice(),
+                &[
+                    Verdict::Drop,
+                    Verdict::Warn,
+                    Verdict::Drop,
+                    Verdict::Disconnect
+                ]
+            ]
+            .concat()
+        );
+
+        // the bucket refills with time, and the violations are forgotten
+        let later = start + time::Duration::from_secs(11);
+        let mut limiter = RateLimiter::new(limits, start);
+        for _ in 0..6 {
+            limiter.check(16, start);
+        }
+        assert_eq!(limiter.check(16, later), Verdict::Accept);
+        for _ in 0..5 {
+            limiter.check(16, later);
+        }
+        assert_eq!(limiter.check(16, later), Verdict::Warn);
+    }
+
+    #[test]
+    fn test_byte_limit() {
+        let limits = Limits {
+            byte_burst: 100.,
+            ..Limits::default()
+        };
+        let now = time::Instant::now();
+        let mut limiter = RateLimiter::new(limits, now);
+        assert_eq!(limiter.check(80, now), Verdict::Accept);
+        assert_eq!(limiter.check(80, now), Verdict::Drop);
+        assert_eq!(limiter.check(20, now), Verdict::Accept);
+    }
+
+    #[test]
+    fn test_chat_limit() {
+        let limits = Limits {
+            chat_per_second: 1.,
+            chat_burst: 2.,
+            ..Limits::default()
+        };
+        let now = time::Instant::now();
+        let mut limiter = RateLimiter::chat(limits, now);
+        assert_eq!(limiter.check(10, now), Verdict::Accept);
+        assert_eq!(limiter.check(10, now), Verdict::Accept);
+        assert_eq!(limiter.check(10, now), Verdict::Drop);
+        let later = now + time::Duration::from_secs(1);
+        assert_eq!(limiter.check(10, later), Verdict::Accept);
+    }
+}
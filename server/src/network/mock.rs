@@ -0,0 +1,173 @@
+use super::{packet, pipe, player};
+use crate::game::snapshot::Snapshot;
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::vec;
+
+/// Players backed by in-memory channels, so that the games can be tested without connections :
+/// the test plays the phones, scripting what they send and reading everything they were sent
+
+//////////////////////////////////////////////
+///
+///
+/// Screen
+///
+///
+//////////////////////////////////////////////
+
+#[derive(Debug, Clone, Copy)]
+pub struct Screen {
+    pub physical_width: f32,
+    pub physical_height: f32,
+    pub window_width: u32,
+    pub window_height: u32,
+}
+
+impl Screen {
+    /// Screen of one pixel per physical unit
+    pub fn new(width: u32, height: u32) -> Self {
+        Screen {
+            physical_width: width as f32,
+            physical_height: height as f32,
+            window_width: width,
+            window_height: height,
+        }
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Room
+///
+///
+//////////////////////////////////////////////
+
+/// Players ranked in the order of `screens` and laid out like in a room, with their phones
+pub fn room(screens: &[Screen]) -> (Vec<player::Player>, Vec<MockPhone>) {
+    let mut players = vec::Vec::with_capacity(screens.len());
+    let mut phones = vec::Vec::with_capacity(screens.len());
+    for (rank, screen) in screens.iter().enumerate() {
+        let (sender, output) = mpsc::channel();
+        let (input, receiver) = mpsc::channel();
+        players.push(player::Player {
+            sender,
+            receiver,
+            rank: rank as u8,
+            session: rank as u16 + 1,
+            nickname: format!("Player {}", rank + 1),
+            identity: String::new(),
+            avatar: rank as u8 % player::AVATARS,
+            ready: true,
+            top_left_x: 0.,
+            top_left_y: 0.,
+            physical_height: screen.physical_height,
+            physical_width: screen.physical_width,
+            window_height: screen.window_height,
+            window_width: screen.window_width,
+            tape: None,
+        });
+        phones.push(MockPhone {
+            input: Some(input),
+            output: Some(output),
+            messages: vec::Vec::new(),
+            read: 0,
+        });
+    }
+    player::layout(&mut players);
+    (players, phones)
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Phone
+///
+///
+//////////////////////////////////////////////
+
+/// Other end of the channels of a player
+pub struct MockPhone {
+    input: Option<mpsc::Sender<pipe::GameMessage>>,
+    output: Option<mpsc::Receiver<pipe::GameMessage>>,
+    // every message the game sent to the phone, in order
+    messages: Vec<Vec<u8>>,
+    // number of messages already returned by `recv`
+    read: usize,
+}
+
+impl MockPhone {
+    /// Queue a message for the game, as if the phone sent it
+    pub fn send(&self, data: &[u8]) {
+        let mut buffer = [0_u8; packet::MAX_DATA_SIZE];
+        buffer[..data.len()].copy_from_slice(data);
+        if let Some(input) = &self.input {
+            let _ = input.send(pipe::GameMessage::data_message(buffer, data.len()));
+        }
+    }
+
+    /// The messages the game sent since the last call
+    pub fn recv(&mut self) -> &[Vec<u8>] {
+        self.collect();
+        let new = &self.messages[self.read..];
+        self.read = self.messages.len();
+        new
+    }
+
+    /// Every message the game sent to the phone since the beginning
+    pub fn messages(&mut self) -> &[Vec<u8>] {
+        self.collect();
+        &self.messages
+    }
+
+    /// Stop sending : the game finds the player disconnected once the queued messages are read
+    pub fn disconnect(&mut self) {
+        self.input = None;
+    }
+
+    /// Stop receiving : the next messages of the game can't be delivered
+    pub fn leave(&mut self) {
+        self.collect();
+        self.output = None;
+    }
+
+    fn collect(&mut self) {
+        if let Some(output) = &self.output {
+            for m in output.try_iter() {
+                self.messages.push(m.data.unwrap()[..m.size].to_vec());
+            }
+        }
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Snapshots
+///
+///
+//////////////////////////////////////////////
+
+/// Decoder of the delta encoded snapshots of a phone
+#[derive(Default)]
+pub struct SnapshotReader {
+    received: HashMap<u16, Snapshot>,
+    last: Option<u16>,
+}
+
+impl SnapshotReader {
+    /// Decode a snapshot against the one it was encoded from
+    pub fn read(&mut self, data: &[u8]) -> Snapshot {
+        let baseline = u16::from_be_bytes([data[2], data[3]]);
+        let (seq, snapshot) = Snapshot::decode(data, self.received.get(&baseline))
+            .expect("the snapshot should be decodable");
+        self.received.insert(seq, snapshot.clone());
+        self.last = Some(seq);
+        snapshot
+    }
+
+    /// Sequence number of the last snapshot read, to acknowledge it
+    pub fn last_seq(&self) -> u16 {
+        self.last.unwrap_or(0)
+    }
+}
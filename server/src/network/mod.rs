@@ -1,20 +1,34 @@
 #![allow(unused)]
+use crate::logging;
+use crate::metrics::metrics;
+use crate::stats;
 use log::{error, info, warn};
+use rand::Rng;
 use std::io::{self, Error, Write};
-use std::net::{TcpListener, TcpStream};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{self, TryRecvError};
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time;
 use std::vec::Vec;
 
+pub mod admin;
+pub mod client;
 mod connection;
+pub mod limits;
+#[cfg(test)]
+pub mod mock;
 pub mod packet;
+mod pause;
 mod pipe;
 pub mod player;
 mod room;
 
 /// The general pipe system will be the following :
 ///
+/// ```text
 ///     .------------ User1 <-----------.
 ///     |                               |
 ///     |                               |
@@ -24,6 +38,7 @@ mod room;
 ///     |                               |
 ///     |                               |
 ///     `------------- User2 <----------'
+/// ```
 ///
 ///
 
@@ -39,13 +54,18 @@ mod room;
 struct LocalGame {
     handle: thread::JoinHandle<Result<(), Error>>,
     token: u16,
+    // code typed by the players to join, and the PIN of a private room
+    code: u32,
+    pin: Option<u16>,
     sender: mpsc::Sender<pipe::ServerMessage>,
+    closed: Arc<AtomicBool>,
 }
 
 /// This structure save the handler for user threads
 struct LocalConnection {
     handle: thread::JoinHandle<()>,
     token: u16,
+    session: Arc<connection::Session>,
 }
 
 //////////////////////////////////////////////
@@ -64,6 +84,27 @@ pub struct Server {
     room_token: u16,
     sender: mpsc::Sender<pipe::ServerMessage>,
     receiver: mpsc::Receiver<pipe::ServerMessage>,
+    // Directory where the games are recorded, if any
+    record_dir: Option<PathBuf>,
+    listener: Option<TcpListener>,
+    // Set to stop accepting connections
+    shutdown: Arc<AtomicBool>,
+    // Commands of the admin consoles, if any
+    admin: Option<mpsc::Receiver<admin::Request>>,
+    // Set once the admin asked to stop, the server then waits for the clients and rooms to end
+    draining: bool,
+    // Rate limits and timeouts of the sessions
+    limits: limits::Limits,
+    // Time counted down on the phones before a game is launched
+    countdown: time::Duration,
+    // Statistics of the players, updated by the rooms and read by the clients
+    stats: Arc<Mutex<stats::Stats>>,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Server {
@@ -79,6 +120,18 @@ impl Server {
     const MAX_USERS: usize = 50;
     const MAX_GAMES: usize = 5;
 
+    /// Codes given to the rooms, long enough not to be guessed in the few tries a client has
+    const JOIN_CODES: std::ops::RangeInclusive<u32> = 10_000_000..=99_999_999;
+
+    /// Address the server listens on when it is not bound before being launched
+    const DEFAULT_ADDRESS: &'static str = "0.0.0.0:8888";
+
+    /// Longest time a shutdown asked by the admin waits for the clients and rooms to end
+    const DRAIN_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+    /// Time counted down on the phones before a game is launched, unless set otherwise
+    const COUNTDOWN: time::Duration = time::Duration::from_secs(3);
+
     //////////////////////////////////////////////
     ///
     ///
@@ -98,22 +151,84 @@ impl Server {
             room_token: 1,
             sender: send,
             receiver: recv,
+            record_dir: None,
+            listener: None,
+            shutdown: Arc::new(AtomicBool::new(false)),
+            admin: None,
+            draining: false,
+            limits: limits::Limits::default(),
+            countdown: Server::COUNTDOWN,
+            stats: Arc::new(Mutex::new(stats::Stats::new())),
         }
     }
 
+    /// Listen on `address`, port 0 lets the system pick a free port
+    /// Return the address actually bound
+    pub fn bind<A: ToSocketAddrs>(&mut self, address: A) -> std::io::Result<SocketAddr> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        let address = listener.local_addr()?;
+        self.listener = Some(listener);
+        Ok(address)
+    }
+
+    /// Address the server listens on, None until bound
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.listener.as_ref()?.local_addr().ok()
+    }
+
+    /// Flag stopping the server once set, the rooms already running are left to end on their own
+    pub fn shutdown_flag(&self) -> Arc<AtomicBool> {
+        self.shutdown.clone()
+    }
+
+    /// Handle for the admin consoles, the commands are run by the thread of `launch_server`
+    pub fn admin(&mut self) -> admin::Admin {
+        let (sender, receiver) = mpsc::channel();
+        self.admin = Some(receiver);
+        admin::Admin::new(sender)
+    }
+
+    /// Rate limits and timeouts applied to the sessions accepted from now on
+    pub fn set_limits(&mut self, limits: limits::Limits) {
+        self.limits = limits;
+    }
+
+    /// Time counted down on the phones between the launch and the start of the games
+    pub fn set_countdown(&mut self, countdown: time::Duration) {
+        self.countdown = countdown;
+    }
+
+    /// Record every game played from now on in `dir`, one replay file per game
+    pub fn record_games(&mut self, dir: PathBuf) {
+        self.record_dir = Some(dir);
+    }
+
+    /// Keep the statistics of the players in the file at `path`, so that the leaderboards last
+    /// across the restarts of the server
+    pub fn keep_stats(&mut self, path: &Path) -> std::io::Result<()> {
+        *self.stats.lock().unwrap() = stats::Stats::open(path)?;
+        Ok(())
+    }
+
     /// Launch the server
     pub fn launch_server(&mut self) -> std::io::Result<()> {
-        let _ = log::set_logger(&LOGGER).map(|()| log::set_max_level(log::LevelFilter::Info));
+        logging::install();
         info!(target: self.target.as_str(), "starting ...");
 
-        let listener = TcpListener::bind("0.0.0.0:8888")?;
-        listener
-            .set_nonblocking(true)
-            .expect("Cannot set non-blocking");
+        if self.listener.is_none() {
+            self.bind(Server::DEFAULT_ADDRESS)?;
+        }
+        let listener = self.listener.as_ref().unwrap().try_clone()?;
 
-        info!(target: self.target.as_str(), "started successfully");
+        info!(target: self.target.as_str(), "started successfully on {}", listener.local_addr()?);
 
         for stream in listener.incoming() {
+            self.handle_admin_requests();
+            if self.shutdown.load(Ordering::Relaxed) {
+                info!(target: self.target.as_str(), "shutting down");
+                break;
+            }
             self.update_connections_status();
             self.handle_connection_pipe();
             match stream {
@@ -129,15 +244,24 @@ impl Server {
                         }
                     };
                     if self.connections.len() < Server::MAX_USERS {
+                        let connections = &self.connections;
+                        let token = Server::next_token(&mut self.connection_token, |t| {
+                            connections.iter().any(|c| c.token == t)
+                        });
+                        let session = match connection::Session::new(&stream, token) {
+                            Ok(session) => Arc::new(session),
+                            Err(e) => {
+                                warn!(target: self.target.as_str(), "client unreachable : {e}");
+                                continue;
+                            }
+                        };
                         self.connections.push(LocalConnection {
-                            handle: self.first_handler(stream, &self.connection_token),
-                            token: self.connection_token,
+                            handle: self.first_handler(stream, session.clone()),
+                            token,
+                            session,
                         });
-                        self.connection_token += 1;
                     } else {
-                        match packet::Packet::error_message(self.connection_token)
-                            .send_packet(&mut stream)
-                        {
+                        match packet::Packet::error_message(0).send_packet(&mut stream) {
                             Ok(_) => warn!(target: self.target.as_str(), "no thread available"),
                             Err(e) => {
                                 warn!(target: self.target.as_str(), "couldn't disconnect client : {e}")
@@ -156,6 +280,9 @@ impl Server {
                 }
             }
         }
+        if self.draining {
+            self.wait_for_threads(Server::DRAIN_TIMEOUT);
+        }
         Ok(())
     }
 
@@ -168,52 +295,79 @@ impl Server {
     //////////////////////////////////////////////
 
     /// First handler of incomming connexions, is responsible to lauch the thread and build the local user structure
-    fn first_handler(&self, stream: TcpStream, &token: &u16) -> thread::JoinHandle<()> {
+    fn first_handler(
+        &self,
+        stream: TcpStream,
+        session: Arc<connection::Session>,
+    ) -> thread::JoinHandle<()> {
         let sender = self.sender.clone();
+        let limits = self.limits;
+        let stats = self.stats.clone();
         thread::spawn(move || {
-            let mut c = connection::Connection::new(stream, token, sender);
+            metrics().session_opened();
+            let mut c = connection::Connection::new(stream, session, sender, limits, stats);
             c.manager();
+            metrics().session_closed();
         })
     }
 
-    fn handle_connection_pipe_message(&mut self, message: pipe::ServerMessage) {
+    fn handle_connection_pipe_message(&mut self, mut message: pipe::ServerMessage) {
         match message.flag {
             pipe::ServerMessageFlag::Create => {
                 let (sender, receiver) = mpsc::channel();
-
-                let mut game = room::Room::new(receiver, self.room_token);
+                self.games.retain(|g| !g.handle.is_finished());
+                let games = &self.games;
+                let token = Server::next_token(&mut self.room_token, |t| {
+                    games.iter().any(|g| g.token == t)
+                });
+                let code = self.new_join_code();
+                let pin = message.pin;
+                message.code = code;
+
+                let closed = Arc::new(AtomicBool::new(false));
+                let mut game = room::Room::new(
+                    receiver,
+                    token,
+                    self.record_dir.clone(),
+                    closed.clone(),
+                    self.countdown,
+                    self.stats.clone(),
+                );
                 // game.add_player(message.sender);
 
                 self.games.push(LocalGame {
                     handle: thread::spawn(move || game.manager()),
-                    token: self.room_token,
+                    token,
+                    code,
+                    pin,
                     sender: sender.clone(),
+                    closed,
                 });
 
                 match sender.send(message) {
                     Ok(_) => {}
                     Err(e) => {
-                        error!(target: self.target.as_str(), "room {} pipe disconnected after creation",self.room_token)
+                        error!(target: self.target.as_str(), "room {} pipe disconnected after creation", token)
                     }
                 }
-
-                self.room_token += 1;
             }
             pipe::ServerMessageFlag::Join => {
-                for g in self.games.iter() {
-                    if g.token == message.room_token {
-                        match g.sender.send(message) {
-                            Ok(_) => {}
-                            Err(e) => {
-                                error!(target: self.target.as_str(), "room {} pipe disconnected",self.room_token)
-                            }
+                let room = self
+                    .games
+                    .iter()
+                    .find(|g| g.code == message.code && !g.handle.is_finished());
+                match room {
+                    Some(g) if g.pin.is_none() || g.pin == message.pin => {
+                        let token = g.token;
+                        if g.sender.send(message).is_err() {
+                            error!(target: self.target.as_str(), "room {} pipe disconnected", token)
                         }
-                        return;
+                    }
+                    _ => {
+                        warn!(target: self.target.as_str(), "client {} gave a wrong room code or PIN", message.session_token);
+                        let _ = message.sender.send(pipe::GameMessage::error_message());
                     }
                 }
-                // TODO: Add error message in the communication protocol for this case !
-                // Or at least send an general error message back to the client !
-                warn!(target: self.target.as_str(), "Unable to locate the game {}", message.room_token);
             }
         }
     }
@@ -238,114 +392,187 @@ impl Server {
             }
         }
     }
-}
-
-//////////////////////////////////////////////
-///
-///
-/// Log system, might go in main for other modules to use it too
-///
-///
-//////////////////////////////////////////////
-
-/// Log interface
-struct SimpleLogger;
 
-impl log::Log for SimpleLogger {
-    fn enabled(&self, metadata: &log::Metadata) -> bool {
-        metadata.level() <= log::Level::Info
-    }
+    //////////////////////////////////////////////
+    ///
+    ///
+    /// Admin
+    ///
+    ///
+    //////////////////////////////////////////////
 
-    fn log(&self, record: &log::Record) {
-        if self.enabled(record.metadata()) {
-            println!(
-                "{:<10}{:>30} -- {}",
-                format!("[ {} ]", record.level()).as_str(),
-                record.target(),
-                record.args()
-            );
+    fn handle_admin_requests(&mut self) {
+        let requests: Vec<admin::Request> = match &self.admin {
+            Some(receiver) => receiver.try_iter().collect(),
+            None => return,
+        };
+        for request in requests {
+            let answer = self.run_admin_command(request.command);
+            let _ = request.answer.send(answer);
         }
     }
 
-    fn flush(&self) {}
-}
-
-static LOGGER: SimpleLogger = SimpleLogger;
-
-//////////////////////////////////////////////
-///
-///
-/// Tests
-///
-///
-//////////////////////////////////////////////
-mod client;
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::*;
-    #[test]
-    fn test_server_client_comm() {
-        let _ = thread::spawn(|| {
-            let mut server = Server::new();
-            server.launch_server().unwrap();
-        });
-
-        thread::sleep(time::Duration::from_millis(100));
-
-        let client1 = thread::spawn(|| {
-            let mut client = client::Network::connect(10., 10.12, 1020, 1000).unwrap();
-            assert_eq!(client.create_room().unwrap(), 1_u16);
-            thread::sleep(time::Duration::from_millis(1000));
-            client.lock_room(client::Game::Test);
-            loop {
-                match client.get_status() {
-                    client::Status::InLockRoom(r) => break,
-                    _ => continue,
+    fn run_admin_command(&mut self, command: admin::Command) -> String {
+        self.update_connections_status();
+        match command {
+            admin::Command::Sessions => {
+                let mut lines = vec![format!("{} session(s)", self.connections.len())];
+                for c in self.connections.iter() {
+                    let room = match c.session.room() {
+                        Some(room) => format!("room {room}"),
+                        None => "no room".to_string(),
+                    };
+                    lines.push(format!(
+                        "  session {} from {}, {room}",
+                        c.token, c.session.address
+                    ));
+                }
+                lines.join("\n")
+            }
+            admin::Command::Rooms => {
+                let rooms: Vec<&LocalGame> = self
+                    .games
+                    .iter()
+                    .filter(|g| !g.handle.is_finished())
+                    .collect();
+                let mut lines = vec![format!("{} room(s)", rooms.len())];
+                for g in rooms {
+                    let sessions: Vec<String> = self
+                        .connections
+                        .iter()
+                        .filter(|c| c.session.room() == Some(g.token))
+                        .map(|c| c.token.to_string())
+                        .collect();
+                    match metrics().room(g.token) {
+                        Some(info) => lines.push(format!(
+                            "  room {} {}, game {}, {} player(s), sessions [{}]",
+                            g.token,
+                            info.state,
+                            info.game,
+                            info.players,
+                            sessions.join(", ")
+                        )),
+                        None => lines.push(format!("  room {} closing", g.token)),
+                    }
+                }
+                lines.join("\n")
+            }
+            admin::Command::Kick(token) => {
+                match self.connections.iter().find(|c| c.token == token) {
+                    Some(c) => {
+                        let _ = c.session.send(&packet::Packet::text_message(
+                            token,
+                            "You were disconnected by the server",
+                        ));
+                        c.session.close();
+                        warn!(target: self.target.as_str(), "session {token} kicked by the admin");
+                        format!("session {token} kicked")
+                    }
+                    None => format!("no session {token}"),
                 }
             }
-            client.launch_game().unwrap();
-            loop {
-                match client.get_status() {
-                    client::Status::InGame => break,
-                    _ => continue,
+            admin::Command::Close(token) => {
+                match self
+                    .games
+                    .iter()
+                    .find(|g| g.token == token && !g.handle.is_finished())
+                {
+                    Some(g) => {
+                        g.closed.store(true, Ordering::Relaxed);
+                        let mut kicked = 0;
+                        for c in self
+                            .connections
+                            .iter()
+                            .filter(|c| c.session.room() == Some(token))
+                        {
+                            let _ = c.session.send(&packet::Packet::protocol_error(
+                                packet::ProtocolError::RoomClosed,
+                                c.token,
+                            ));
+                            c.session.close();
+                            kicked += 1;
+                        }
+                        warn!(target: self.target.as_str(), "room {token} closed by the admin");
+                        format!("room {token} closed, {kicked} player(s) disconnected")
+                    }
+                    None => format!("no room {token}"),
+                }
+            }
+            admin::Command::Broadcast(text) => {
+                let mut sent = 0;
+                for c in self.connections.iter() {
+                    if c.session
+                        .send(&packet::Packet::text_message(c.token, &text))
+                        .is_ok()
+                    {
+                        sent += 1;
+                    }
                 }
+                info!(target: self.target.as_str(), "broadcast from the admin : {text}");
+                format!("sent to {sent} client(s)")
             }
-            let mut buffer = [1_u8; packet::MAX_DATA_SIZE];
-            // assert!(client.recv(&mut buffer));
-            // thread::sleep(time::Duration::from_millis(10000));
-        });
-
-        thread::sleep(time::Duration::from_millis(20));
-
-        let client2 = thread::spawn(|| {
-            let mut client = client::Network::connect(10., 10., 1000, 1000).unwrap();
-            assert_eq!(client.create_room().unwrap(), 2_u16);
-            thread::sleep(time::Duration::from_millis(200));
-        });
-
-        thread::sleep(time::Duration::from_millis(10));
-
-        let client3 = thread::spawn(|| {
-            let mut client = client::Network::connect(10., 10., 1000, 1000).unwrap();
-            client.join_room(1).unwrap();
-            thread::sleep(time::Duration::from_millis(1000));
-            loop {
-                match client.get_status() {
-                    client::Status::InLockRoom(_) => {}
-                    client::Status::InGame => break,
-                    _ => continue,
+            admin::Command::Shutdown => {
+                warn!(target: self.target.as_str(), "shutdown asked by the admin");
+                for g in self.games.iter() {
+                    g.closed.store(true, Ordering::Relaxed);
+                }
+                for c in self.connections.iter() {
+                    let _ = c.session.send(&packet::Packet::text_message(
+                        c.token,
+                        "The server is shutting down",
+                    ));
+                    let _ = c.session.send(&packet::Packet::protocol_error(
+                        packet::ProtocolError::ServerDown,
+                        c.token,
+                    ));
+                    c.session.close();
                 }
+                self.shutdown.store(true, Ordering::Relaxed);
+                self.draining = true;
+                format!(
+                    "shutting down, {} client(s) disconnected",
+                    self.connections.len()
+                )
+            }
+            // run by the console itself
+            admin::Command::Help | admin::Command::Log(_) => String::new(),
+        }
+    }
+
+    /// Next token of `counter` that is neither 0 nor `live`, the counter wraps around
+    fn next_token(counter: &mut u16, live: impl Fn(u16) -> bool) -> u16 {
+        loop {
+            let token = *counter;
+            *counter = counter.wrapping_add(1);
+            if token != 0 && !live(token) {
+                return token;
+            }
+        }
+    }
+
+    /// Random code, unused by the running rooms
+    fn new_join_code(&self) -> u32 {
+        let mut rng = rand::thread_rng();
+        loop {
+            let code = rng.gen_range(Server::JOIN_CODES);
+            if !self.games.iter().any(|g| g.code == code) {
+                return code;
             }
-            let mut buffer = [1_u8; packet::MAX_DATA_SIZE];
-            client.send(&buffer);
-            thread::sleep(time::Duration::from_millis(100));
-            // assert!(client.recv(&mut buffer));
-            thread::sleep(time::Duration::from_millis(1000));
-        });
-
-        client1.join().unwrap();
-        client2.join().unwrap();
-        client3.join().unwrap();
+        }
+    }
+
+    /// Wait for the connections and rooms to end, for at most `timeout`
+    fn wait_for_threads(&mut self, timeout: time::Duration) {
+        let start = time::Instant::now();
+        while start.elapsed() < timeout {
+            self.update_connections_status();
+            self.games.retain(|g| !g.handle.is_finished());
+            if self.connections.is_empty() && self.games.is_empty() {
+                info!(target: self.target.as_str(), "every client and room ended");
+                return;
+            }
+            thread::sleep(time::Duration::from_millis(10));
+        }
+        warn!(target: self.target.as_str(), "{} client(s) and {} room(s) still running, stopping anyway", self.connections.len(), self.games.len());
     }
 }
@@ -7,7 +7,9 @@ use std::net::TcpStream;
 use std::thread;
 use std::time::{self, SystemTime};
 
-use log::info;
+use crate::game::settings;
+use crate::metrics::metrics;
+use log::debug;
 
 //////////////////////////////////////////////
 ///
@@ -17,9 +19,11 @@ use log::info;
 ///
 //////////////////////////////////////////////
 
-pub const HEADER_SIZE: usize = 12;
-pub const MAX_DATA_SIZE: usize = 2036;
+pub const HEADER_SIZE: usize = 20;
+pub const MAX_DATA_SIZE: usize = 2028;
 pub const BUFFER_SIZE: usize = HEADER_SIZE + MAX_DATA_SIZE;
+/// Longest wait for the rest of a packet once it started to arrive
+const PACKET_TIMEOUT: time::Duration = time::Duration::from_secs(1);
 
 //////////////////////////////////////////////
 ///
@@ -36,18 +40,23 @@ pub enum ProtocolError {
     RoomClosed,
     InvalidPacket,
     InvalidRequest,
+    Timeout,
+    Flooding,
+    Unauthorized,
     Unknown,
 }
 
 impl From<u8> for ProtocolError {
-    fn from(mut orig: u8) -> Self {
-        orig -= 0x80_u8;
-        match orig {
+    fn from(orig: u8) -> Self {
+        match orig & !0x80_u8 {
             1 => Self::ServerDown,
             2 => Self::GameCrashed,
             3 => Self::RoomClosed,
             4 => Self::InvalidPacket,
             5 => Self::InvalidRequest,
+            6 => Self::Timeout,
+            7 => Self::Flooding,
+            8 => Self::Unauthorized,
             _ => Self::Unknown,
         }
     }
@@ -61,6 +70,9 @@ impl From<ProtocolError> for u8 {
             ProtocolError::RoomClosed => 3 | 0x80_u8,
             ProtocolError::InvalidPacket => 4 | 0x80_u8,
             ProtocolError::InvalidRequest => 5 | 0x80_u8,
+            ProtocolError::Timeout => 6 | 0x80_u8,
+            ProtocolError::Flooding => 7 | 0x80_u8,
+            ProtocolError::Unauthorized => 8 | 0x80_u8,
             ProtocolError::Unknown => 0xff_u8,
         }
     }
@@ -74,6 +86,9 @@ impl Display for ProtocolError {
             ProtocolError::RoomClosed => write!(f, "RoomClosed"),
             ProtocolError::InvalidPacket => write!(f, "InvalidPacket"),
             ProtocolError::InvalidRequest => write!(f, "InvalidRequest"),
+            ProtocolError::Timeout => write!(f, "Timeout"),
+            ProtocolError::Flooding => write!(f, "Flooding"),
+            ProtocolError::Unauthorized => write!(f, "Unauthorized"),
             ProtocolError::Unknown => write!(f, "Unknown"),
         }
     }
@@ -96,6 +111,14 @@ pub enum Flag {
     Lock,
     Launch,
     Transmit,
+    Message,
+    Roster,
+    Ready,
+    Countdown,
+    Chat,
+    End,
+    Leaderboard,
+    Pause,
     Unknown,
 }
 
@@ -111,6 +134,14 @@ impl From<u8> for Flag {
                 4 => Flag::Lock,
                 5 => Flag::Launch,
                 6 => Flag::Transmit,
+                7 => Flag::Message,
+                8 => Flag::Roster,
+                9 => Flag::Ready,
+                10 => Flag::Countdown,
+                11 => Flag::Chat,
+                12 => Flag::End,
+                13 => Flag::Leaderboard,
+                14 => Flag::Pause,
                 _ => Flag::Unknown,
             }
         }
@@ -127,6 +158,14 @@ impl From<Flag> for u8 {
             Flag::Lock => 4,
             Flag::Launch => 5,
             Flag::Transmit => 6,
+            Flag::Message => 7,
+            Flag::Roster => 8,
+            Flag::Ready => 9,
+            Flag::Countdown => 10,
+            Flag::Chat => 11,
+            Flag::End => 12,
+            Flag::Leaderboard => 13,
+            Flag::Pause => 14,
             Flag::Unknown => 0xff_u8,
         }
     }
@@ -141,6 +180,14 @@ impl Display for Flag {
             Flag::Lock => write!(f, "Lock"),
             Flag::Launch => write!(f, "Launch"),
             Flag::Transmit => write!(f, "Transmit"),
+            Flag::Message => write!(f, "Message"),
+            Flag::Roster => write!(f, "Roster"),
+            Flag::Ready => write!(f, "Ready"),
+            Flag::Countdown => write!(f, "Countdown"),
+            Flag::Chat => write!(f, "Chat"),
+            Flag::End => write!(f, "End"),
+            Flag::Leaderboard => write!(f, "Leaderboard"),
+            Flag::Pause => write!(f, "Pause"),
             Flag::Error(e) => write!(f, "Error : {}", e),
             Flag::Unknown => write!(f, "Unknown"),
         }
@@ -159,6 +206,7 @@ impl Display for Flag {
 #[derive(Clone, Copy, Debug)]
 pub enum Version {
     V0 = 0x0,
+    V1 = 0x1,
     Unknown = 0xf,
 }
 
@@ -166,6 +214,7 @@ impl From<u8> for Version {
     fn from(orig: u8) -> Self {
         match orig {
             0x00 => Version::V0,
+            0x01 => Version::V1,
             _ => Version::Unknown,
         }
     }
@@ -175,11 +224,436 @@ impl From<Version> for u8 {
     fn from(val: Version) -> Self {
         match val {
             Version::V0 => 0x00,
+            Version::V1 => 0x01,
             Version::Unknown => 0xff,
         }
     }
 }
 
+//////////////////////////////////////////////
+///
+///
+/// Handshake
+///
+///
+//////////////////////////////////////////////
+
+pub const HANDSHAKE_SIZE: usize = 16;
+/// Longest nickname, in bytes
+pub const NICKNAME_SIZE: usize = 16;
+/// Avatar byte of a phone without preference
+const NO_AVATAR: u8 = 0xff;
+
+/// Screen and profile of the phone, sent in the data of the Init packet :
+/// physical height (f32) | physical width (f32) | window height (u32) | window width (u32)
+/// then optionally : avatar (u8, 0xff for none) | nickname size (u8) | nickname (UTF-8)
+/// The server answers with an Init packet listing the games it runs, see `Listing`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Handshake {
+    pub physical_height: f32,
+    pub physical_width: f32,
+    pub window_height: u32,
+    pub window_width: u32,
+    /// Empty for a phone that didn't choose one, the room names it then
+    pub nickname: String,
+    pub avatar: Option<u8>,
+}
+
+impl Handshake {
+    pub fn pack(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(HANDSHAKE_SIZE + 2 + self.nickname.len());
+        data.extend_from_slice(&self.physical_height.to_be_bytes());
+        data.extend_from_slice(&self.physical_width.to_be_bytes());
+        data.extend_from_slice(&self.window_height.to_be_bytes());
+        data.extend_from_slice(&self.window_width.to_be_bytes());
+        data.push(self.avatar.unwrap_or(NO_AVATAR));
+        pack_text(&mut data, &self.nickname);
+        data
+    }
+
+    /// Read the screen of the phone, refusing the sizes the games can't lay out
+    pub fn unpack(data: &[u8]) -> Result<Self, Error> {
+        if data.len() < HANDSHAKE_SIZE {
+            return Err(Error::new(ErrorKind::InvalidData, "handshake too short"));
+        }
+        let word = |i: usize| [data[i], data[i + 1], data[i + 2], data[i + 3]];
+        let mut handshake = Handshake {
+            physical_height: f32::from_be_bytes(word(0)),
+            physical_width: f32::from_be_bytes(word(4)),
+            window_height: u32::from_be_bytes(word(8)),
+            window_width: u32::from_be_bytes(word(12)),
+            nickname: String::new(),
+            avatar: None,
+        };
+        let physical = [handshake.physical_height, handshake.physical_width];
+        if physical.iter().any(|s| !s.is_finite() || *s <= 0.)
+            || handshake.window_height == 0
+            || handshake.window_width == 0
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid screen in the handshake : {handshake:?}"),
+            ));
+        }
+
+        // the profile is optional, the older phones only send their screen
+        let mut profile = &data[HANDSHAKE_SIZE..];
+        if let Some((&avatar, rest)) = profile.split_first() {
+            handshake.avatar = (avatar != NO_AVATAR).then_some(avatar);
+            profile = rest;
+            handshake.nickname = unpack_text(&mut profile)?;
+        }
+        Ok(handshake)
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Roster
+///
+///
+//////////////////////////////////////////////
+
+/// Player of a room, as the phones see it in the data of the Roster packet :
+/// count (u8) then for each player
+/// session (u16) | rank (u8, 0xff before the room is locked) | avatar (u8) | ready (u8) | nickname size (u8) | nickname
+#[derive(Debug, Clone, PartialEq)]
+pub struct Member {
+    pub session: u16,
+    pub rank: Option<u8>,
+    pub avatar: u8,
+    // whether the player is ready to launch, once the room is locked
+    pub ready: bool,
+    pub nickname: String,
+}
+
+impl Member {
+    pub fn pack_roster(members: &[Member]) -> Vec<u8> {
+        let mut data = vec![members.len() as u8];
+        for m in members.iter() {
+            data.extend_from_slice(&m.session.to_be_bytes());
+            data.push(m.rank.unwrap_or(0xff));
+            data.push(m.avatar);
+            data.push(m.ready as u8);
+            pack_text(&mut data, &m.nickname);
+        }
+        data
+    }
+
+    pub fn unpack_roster(data: &[u8]) -> Result<Vec<Member>, Error> {
+        let too_short = || Error::new(ErrorKind::InvalidData, "roster too short");
+        let (&count, mut data) = data.split_first().ok_or_else(too_short)?;
+        let mut members = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            if data.len() < 5 {
+                return Err(too_short());
+            }
+            let (head, rest) = data.split_at(5);
+            data = rest;
+            members.push(Member {
+                session: u16::from_be_bytes([head[0], head[1]]),
+                rank: (head[2] != 0xff).then_some(head[2]),
+                avatar: head[3],
+                ready: head[4] != 0,
+                nickname: unpack_text(&mut data)?,
+            });
+        }
+        Ok(members)
+    }
+}
+
+/// Append a nickname with its size, cut to `NICKNAME_SIZE`
+fn pack_text(data: &mut Vec<u8>, text: &str) {
+    let mut end = text.len().min(NICKNAME_SIZE);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    data.push(end as u8);
+    data.extend_from_slice(&text.as_bytes()[..end]);
+}
+
+/// Read a nickname written by `pack_text` and move `data` past it
+fn unpack_text(data: &mut &[u8]) -> Result<String, Error> {
+    let invalid =
+        |what: &str| Error::new(ErrorKind::InvalidData, format!("invalid nickname : {what}"));
+    let (&size, rest) = data.split_first().ok_or_else(|| invalid("missing"))?;
+    let size = size as usize;
+    if size > NICKNAME_SIZE || size > rest.len() {
+        return Err(invalid("too long"));
+    }
+    let text = std::str::from_utf8(&rest[..size]).map_err(|_| invalid("not UTF-8"))?;
+    if text.chars().any(char::is_control) {
+        return Err(invalid("control character"));
+    }
+    *data = &rest[size..];
+    Ok(text.trim().to_string())
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Chat
+///
+///
+//////////////////////////////////////////////
+
+/// Longest chat line, in bytes
+pub const CHAT_SIZE: usize = 140;
+
+/// Number of quick emotes the phones know
+pub const EMOTES: u8 = 16;
+/// Emote byte of a chat line without any
+const NO_EMOTE: u8 = 0xff;
+
+/// Chat line or emote of a player, as sent in the data of the Chat packet :
+/// session (u16, set by the server) | emote (u8, 0xff for none) | text (UTF-8, up to CHAT_SIZE bytes)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chat {
+    pub session: u16,
+    pub emote: Option<u8>,
+    pub text: String,
+}
+
+impl Chat {
+    pub fn pack(&self) -> Vec<u8> {
+        let mut data = self.session.to_be_bytes().to_vec();
+        data.push(self.emote.unwrap_or(NO_EMOTE));
+        data.extend_from_slice(self.text.as_bytes());
+        data
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, Error> {
+        let invalid =
+            |what: &str| Error::new(ErrorKind::InvalidData, format!("invalid chat : {what}"));
+        if data.len() < 3 {
+            return Err(invalid("too short"));
+        }
+        if data.len() > 3 + CHAT_SIZE {
+            return Err(invalid("too long"));
+        }
+        let emote = match data[2] {
+            NO_EMOTE => None,
+            emote if emote < EMOTES => Some(emote),
+            _ => return Err(invalid("unknown emote")),
+        };
+        let text = std::str::from_utf8(&data[3..]).map_err(|_| invalid("not UTF-8"))?;
+        if text.chars().any(char::is_control) {
+            return Err(invalid("control character"));
+        }
+        let text = text.trim().to_string();
+        if text.is_empty() && emote.is_none() {
+            return Err(invalid("empty"));
+        }
+        Ok(Chat {
+            session: u16::from_be_bytes([data[0], data[1]]),
+            emote,
+            text,
+        })
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Leaderboard
+///
+///
+//////////////////////////////////////////////
+
+/// Most players sent in a Leaderboard packet
+pub const LEADERBOARD_SIZE: usize = 10;
+/// Lap time of a player who never finished a lap
+const NO_LAP: u32 = u32::MAX;
+
+/// All-time statistics of a player at a game, as sent in the data of the Leaderboard packet :
+/// count (u8) then for each player, best first
+/// played (u32) | wins (u32) | kills (u32) | best lap (u32, in ms, 0xffffffff for none) | nickname size (u8) | nickname
+#[derive(Debug, Clone, PartialEq)]
+pub struct Standing {
+    pub nickname: String,
+    pub played: u32,
+    pub wins: u32,
+    pub kills: u32,
+    pub best_lap: Option<time::Duration>,
+}
+
+impl Standing {
+    pub fn pack_leaderboard(standings: &[Standing]) -> Vec<u8> {
+        let standings = &standings[..standings.len().min(LEADERBOARD_SIZE)];
+        let mut data = vec![standings.len() as u8];
+        for s in standings.iter() {
+            let lap = s
+                .best_lap
+                .map_or(NO_LAP, |l| l.as_millis().min(NO_LAP as u128 - 1) as u32);
+            for field in [s.played, s.wins, s.kills, lap] {
+                data.extend_from_slice(&field.to_be_bytes());
+            }
+            pack_text(&mut data, &s.nickname);
+        }
+        data
+    }
+
+    pub fn unpack_leaderboard(data: &[u8]) -> Result<Vec<Standing>, Error> {
+        let too_short = || Error::new(ErrorKind::InvalidData, "leaderboard too short");
+        let (&count, mut data) = data.split_first().ok_or_else(too_short)?;
+        let mut standings = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            if data.len() < 16 {
+                return Err(too_short());
+            }
+            let (head, rest) = data.split_at(16);
+            data = rest;
+            let field =
+                |i: usize| u32::from_be_bytes([head[i], head[i + 1], head[i + 2], head[i + 3]]);
+            standings.push(Standing {
+                played: field(0),
+                wins: field(4),
+                kills: field(8),
+                best_lap: (field(12) != NO_LAP)
+                    .then(|| time::Duration::from_millis(field(12) as u64)),
+                nickname: unpack_text(&mut data)?,
+            });
+        }
+        Ok(standings)
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Catalogue
+///
+///
+//////////////////////////////////////////////
+
+/// A game run by the server, as listed in the data of the Init packet answering the handshake :
+/// count (u8) then for each game
+/// id (u16) | min players (u8) | max players (u8) | name | description | settings count (u8) | settings
+/// setting : kind (u8, 0 for a toggle) | default (u16) | min (u16) | max (u16) | step (u16) | name
+/// The texts are sent after their size (u8)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Listing {
+    pub game: u16,
+    pub name: String,
+    pub description: String,
+    pub min_players: u8,
+    pub max_players: u8,
+    pub settings: Vec<ListedSetting>,
+}
+
+/// A setting of a game of the catalogue, see `settings::Setting`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListedSetting {
+    pub name: String,
+    pub kind: settings::Kind,
+    pub default: u16,
+}
+
+impl Listing {
+    /// The games that don't fit in a packet are left out
+    pub fn pack_catalogue(listings: &[Listing]) -> Vec<u8> {
+        let mut data = vec![0];
+        for l in listings.iter() {
+            let mut entry = Vec::new();
+            entry.extend_from_slice(&l.game.to_be_bytes());
+            entry.push(l.min_players);
+            entry.push(l.max_players);
+            pack_label(&mut entry, &l.name);
+            pack_label(&mut entry, &l.description);
+            entry.push(l.settings.len() as u8);
+            for s in l.settings.iter() {
+                let (kind, min, max, step) = match s.kind {
+                    settings::Kind::Toggle => (0, 0, 1, 1),
+                    settings::Kind::Number { min, max, step } => (1, min, max, step),
+                };
+                entry.push(kind);
+                for field in [s.default, min, max, step] {
+                    entry.extend_from_slice(&field.to_be_bytes());
+                }
+                pack_label(&mut entry, &s.name);
+            }
+            if data.len() + entry.len() > MAX_DATA_SIZE {
+                break;
+            }
+            data[0] += 1;
+            data.extend_from_slice(&entry);
+        }
+        data
+    }
+
+    pub fn unpack_catalogue(data: &[u8]) -> Result<Vec<Listing>, Error> {
+        let too_short = || Error::new(ErrorKind::InvalidData, "catalogue too short");
+        let (&count, mut data) = data.split_first().ok_or_else(too_short)?;
+        let mut listings = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            if data.len() < 4 {
+                return Err(too_short());
+            }
+            let (head, rest) = data.split_at(4);
+            data = rest;
+            let name = unpack_label(&mut data)?;
+            let description = unpack_label(&mut data)?;
+            let (&setting_count, rest) = data.split_first().ok_or_else(too_short)?;
+            data = rest;
+            let mut settings = Vec::with_capacity(setting_count as usize);
+            for _ in 0..setting_count {
+                if data.len() < 9 {
+                    return Err(too_short());
+                }
+                let (fields, rest) = data.split_at(9);
+                data = rest;
+                let field = |i: usize| u16::from_be_bytes([fields[i], fields[i + 1]]);
+                let kind = match fields[0] {
+                    0 => settings::Kind::Toggle,
+                    _ => settings::Kind::Number {
+                        min: field(3),
+                        max: field(5),
+                        step: field(7),
+                    },
+                };
+                settings.push(ListedSetting {
+                    name: unpack_label(&mut data)?,
+                    kind,
+                    default: field(1),
+                });
+            }
+            listings.push(Listing {
+                game: u16::from_be_bytes([head[0], head[1]]),
+                name,
+                description,
+                min_players: head[2],
+                max_players: head[3],
+                settings,
+            });
+        }
+        Ok(listings)
+    }
+}
+
+/// Append a text with its size, cut to 255 bytes
+fn pack_label(data: &mut Vec<u8>, text: &str) {
+    let mut end = text.len().min(u8::MAX as usize);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    data.push(end as u8);
+    data.extend_from_slice(&text.as_bytes()[..end]);
+}
+
+/// Read a text written by `pack_label` and move `data` past it
+fn unpack_label(data: &mut &[u8]) -> Result<String, Error> {
+    let invalid = |what: &str| Error::new(ErrorKind::InvalidData, format!("invalid text : {what}"));
+    let (&size, rest) = data.split_first().ok_or_else(|| invalid("missing"))?;
+    let size = size as usize;
+    if size > rest.len() {
+        return Err(invalid("too long"));
+    }
+    let text = std::str::from_utf8(&rest[..size]).map_err(|_| invalid("not UTF-8"))?;
+    *data = &rest[size..];
+    Ok(text.to_string())
+}
+
 //////////////////////////////////////////////
 ///
 ///
@@ -197,6 +671,8 @@ pub struct Packet {
     pub option: u16,
     pub session: u16,
     pub room: u16,
+    // secret of the session, given by the server in the handshake and checked on every packet
+    pub secret: u64,
     pub data: [u8; MAX_DATA_SIZE],
 
     processed_time: SystemTime,
@@ -211,6 +687,7 @@ impl Packet {
     ///
     //////////////////////////////////////////////
 
+    /// Packet carrying `raw_data`, which has to fit in `MAX_DATA_SIZE`
     pub fn new(
         flag: Flag,
         sync: u8,
@@ -218,45 +695,62 @@ impl Packet {
         room: u16,
         raw_data: &[u8],
         option: u16,
-    ) -> Packet {
-        let mut data = [0_u8; MAX_DATA_SIZE];
+    ) -> Result<Packet, Error> {
         let size = raw_data.len();
-        if (size > MAX_DATA_SIZE) {
-            panic!("Trying to build a packet too large !");
-        }
-        if (size > 0) {
-            data[..size].copy_from_slice(raw_data);
-        }
-        Packet {
-            version: Version::V0,
-            flag,
-            sync,
-            size,
-            option,
-            session,
-            room,
-            data,
-            processed_time: SystemTime::now(),
+        if size > MAX_DATA_SIZE {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{size} bytes don't fit in a packet"),
+            ));
         }
+        let mut data = [0_u8; MAX_DATA_SIZE];
+        data[..size].copy_from_slice(raw_data);
+        Ok(Packet::with_data(
+            flag, sync, session, room, data, size, option,
+        ))
+    }
+
+    /// Packet without data, only its header matters
+    pub fn control(flag: Flag, session: u16, room: u16, option: u16) -> Packet {
+        Packet::with_data(flag, 0, session, room, [0_u8; MAX_DATA_SIZE], 0, option)
+    }
+
+    /// The same packet, signed with the secret of the session
+    pub fn with_secret(mut self, secret: u64) -> Packet {
+        self.secret = secret;
+        self
     }
 
     /// Receive and unpack a packet, it will be blocking until it receives a packet or the pipe is procken
     pub fn recv_packet(stream: &mut TcpStream) -> Result<Self, Error> {
         let mut buffer = [0_u8; BUFFER_SIZE];
 
-        Packet::block_read_exact(stream, &mut buffer)?;
-        Packet::unpack(&buffer)
+        Packet::block_read_exact(stream, &mut buffer, None)?;
+        Packet::received(&buffer)
+    }
+
+    /// Receive and unpack a packet, fail with `TimedOut` if it isn't there at `deadline`
+    pub fn recv_packet_before(
+        stream: &mut TcpStream,
+        deadline: time::Instant,
+    ) -> Result<Self, Error> {
+        let mut buffer = [0_u8; BUFFER_SIZE];
+
+        Packet::block_read_exact(stream, &mut buffer, Some(deadline))?;
+        Packet::received(&buffer)
     }
 
     /// Receive and unpack a packet without blocking
     pub fn try_recv_packet(stream: &mut TcpStream) -> Option<Self> {
         let mut buffer = [0_u8; BUFFER_SIZE];
 
+        // a partial read would lose the beginning of the packet, so wait for all of it
+        match stream.peek(&mut buffer) {
+            Ok(n) if n == BUFFER_SIZE => {}
+            _ => return None,
+        }
         match stream.read_exact(&mut buffer) {
-            Ok(_) => match Packet::unpack(&buffer) {
-                Ok(packet) => Some(packet),
-                Err(_) => None,
-            },
+            Ok(_) => Packet::received(&buffer).ok(),
             Err(_) => None,
         }
     }
@@ -265,7 +759,16 @@ impl Packet {
     pub fn send_packet(&self, stream: &mut TcpStream) -> Result<(), Error> {
         let mut buffer = [0_u8; BUFFER_SIZE];
         self.pack(&mut buffer);
-        stream.write_all(&buffer)
+        match stream.write_all(&buffer) {
+            Ok(_) => {
+                metrics().packet_out(self.flag, BUFFER_SIZE);
+                Ok(())
+            }
+            Err(e) => {
+                metrics().send_error();
+                Err(e)
+            }
+        }
     }
 
     //////////////////////////////////////////////
@@ -299,16 +802,32 @@ impl Packet {
     //////////////////////////////////////////////
 
     pub fn error_message(session_token: u16) -> Packet {
-        Self::new(
+        Self::with_data(
             Flag::Error(ProtocolError::Unknown),
             0,
             session_token,
             0,
-            &[0_u8; MAX_DATA_SIZE],
+            [0_u8; MAX_DATA_SIZE],
+            MAX_DATA_SIZE,
             0,
         )
     }
 
+    pub fn protocol_error(error: ProtocolError, session_token: u16) -> Packet {
+        Self::control(Flag::Error(error), session_token, 0, 0)
+    }
+
+    /// Text for the user of the phone, cut to fit in a packet
+    pub fn text_message(session_token: u16, text: &str) -> Packet {
+        let mut end = text.len().min(MAX_DATA_SIZE);
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        let mut data = [0_u8; MAX_DATA_SIZE];
+        data[..end].copy_from_slice(&text.as_bytes()[..end]);
+        Self::with_data(Flag::Message, 0, session_token, 0, data, end, 0)
+    }
+
     //////////////////////////////////////////////
     ///
     ///
@@ -322,13 +841,9 @@ impl Packet {
         self.flag
     }
 
-    /// Produce a log in stdout
+    /// Log the header of the packet, shown with the `Packet=debug` level
     pub fn log_packet(&self) {
-        info!(target: "Packet", "Procesed at {:?} :", self.processed_time);
-        info!(target: "Packet", "\t Version : {}", self.version as u8);
-        info!(target: "Packet", "\t Size : {:?}", self.size + HEADER_SIZE);
-        info!(target: "Packet", "\t Session : {:?}", self.session);
-        info!(target: "Packet", "\t Room : {:?}", self.room);
+        debug!(target: "Packet", "{} processed at {:?} : version {}, size {}, session {}, room {}", self.flag, self.processed_time, self.version as u8, self.size + HEADER_SIZE, self.session, self.room);
     }
 
     //////////////////////////////////////////////
@@ -339,10 +854,24 @@ impl Packet {
     ///
     //////////////////////////////////////////////
 
+    /// Unpack a packet read from the network, and count it
+    fn received(packet: &[u8; BUFFER_SIZE]) -> Result<Self, Error> {
+        let res = Packet::unpack(packet);
+        match &res {
+            Ok(p) => metrics().packet_in(p.flag, BUFFER_SIZE),
+            Err(_) => metrics().malformed_packet(),
+        }
+        res
+    }
+
     /// Create a packet from raw data
-    fn unpack(packet: &[u8; BUFFER_SIZE]) -> Result<Self, Error> {
+    pub fn unpack(packet: &[u8; BUFFER_SIZE]) -> Result<Self, Error> {
         match packet[0].into() {
-            Version::V0 => Self::process_v0_packet(packet),
+            Version::V1 => Self::process_v1_packet(packet),
+            Version::V0 => Err(Error::new(
+                ErrorKind::InvalidData,
+                "outdated packet : version 0 is not supported anymore",
+            )),
             _ => Err(Error::new(
                 ErrorKind::InvalidData,
                 "non-standard packet : not recognized version",
@@ -359,6 +888,7 @@ impl Packet {
         Self::pack_u16(self.option, &mut packet[6..8]);
         Self::pack_u16(self.session, &mut packet[8..10]);
         Self::pack_u16(self.room, &mut packet[10..12]);
+        packet[12..20].copy_from_slice(&self.secret.to_be_bytes());
         packet[HEADER_SIZE..].clone_from_slice(self.data.as_slice());
     }
 
@@ -370,30 +900,53 @@ impl Packet {
     ///
     //////////////////////////////////////////////
 
-    fn process_v0_packet(packet: &[u8; BUFFER_SIZE]) -> Result<Self, Error> {
+    fn process_v1_packet(packet: &[u8; BUFFER_SIZE]) -> Result<Self, Error> {
         let mut data = [0_u8; MAX_DATA_SIZE];
         let size = Packet::unpack_u16(&packet[4..6]) as usize;
 
         if size > MAX_DATA_SIZE {
-            panic!("Not well formed packet")
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("non-standard packet : declared size {size} is too large"),
+            ));
         }
+        data[..size].copy_from_slice(&packet[HEADER_SIZE..HEADER_SIZE + size]);
 
-        if size > 0 {
-            data[..size].clone_from_slice(&packet[HEADER_SIZE..HEADER_SIZE + size]);
-        }
+        Ok(Packet::with_data(
+            packet[1].into(),
+            packet[2],
+            Packet::unpack_u16(&packet[8..10]),
+            Packet::unpack_u16(&packet[10..12]),
+            data,
+            size,
+            Packet::unpack_u16(&packet[6..8]),
+        )
+        .with_secret(u64::from_be_bytes(
+            <[u8; 8]>::try_from(&packet[12..20]).unwrap(),
+        )))
+    }
 
-        Ok(Packet {
-            version: Version::V0,
-            flag: packet[1].into(),
-            sync: packet[2],
+    fn with_data(
+        flag: Flag,
+        sync: u8,
+        session: u16,
+        room: u16,
+        data: [u8; MAX_DATA_SIZE],
+        size: usize,
+        option: u16,
+    ) -> Packet {
+        Packet {
+            version: Version::V1,
+            flag,
+            sync,
             size,
-            option: Packet::unpack_u16(&packet[6..8]),
-            session: Packet::unpack_u16(&packet[8..10]),
-            room: Packet::unpack_u16(&packet[10..12]),
+            option,
+            session,
+            room,
+            secret: 0,
             data,
-
             processed_time: SystemTime::now(),
-        })
+        }
     }
 
     fn unpack_u16(data: &[u8]) -> u16 {
@@ -405,14 +958,236 @@ impl Packet {
         slice[1] = u8::try_from(int & (0x00ff_u16)).unwrap();
     }
 
-    fn block_read_exact(stream: &mut TcpStream, buf: &mut [u8]) -> Result<(), Error> {
-        loop {
-            match stream.read_exact(buf) {
-                Ok(_) => return Ok(()),
-                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
-                Err(e) => return Err(e),
+    fn block_read_exact(
+        stream: &mut TcpStream,
+        buf: &mut [u8],
+        deadline: Option<time::Instant>,
+    ) -> Result<(), Error> {
+        let res = Packet::read_exact_before(stream, buf, deadline);
+        if deadline.is_some() {
+            stream.set_read_timeout(None)?;
+        }
+        res
+    }
+
+    /// Read a whole packet, fail with `TimedOut` if it didn't start to arrive at `deadline`
+    /// The socket only gives the hand back at the deadline, nothing is consumed when it times out
+    fn read_exact_before(
+        stream: &mut TcpStream,
+        buf: &mut [u8],
+        deadline: Option<time::Instant>,
+    ) -> Result<(), Error> {
+        let Some(deadline) = deadline else {
+            return stream.read_exact(buf);
+        };
+        let timed_out =
+            |e: &Error| e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut;
+        let remaining = deadline.saturating_duration_since(time::Instant::now());
+        if remaining.is_zero() {
+            return Err(Error::new(
+                ErrorKind::TimedOut,
+                "no packet received in time",
+            ));
+        }
+        stream.set_read_timeout(Some(remaining))?;
+        let n = match stream.read(buf) {
+            Ok(0) => return Err(Error::new(ErrorKind::UnexpectedEof, "connection closed")),
+            Ok(n) => n,
+            Err(e) if timed_out(&e) => {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    "no packet received in time",
+                ))
+            }
+            Err(e) => return Err(e),
+        };
+        // the rest of a packet already started comes right after, even past the deadline, a client
+        // stopping in the middle of one is broken
+        stream.set_read_timeout(Some(PACKET_TIMEOUT))?;
+        stream.read_exact(&mut buf[n..]).map_err(|e| {
+            if timed_out(&e) {
+                Error::new(ErrorKind::InvalidData, "packet cut short")
+            } else {
+                e
             }
-            thread::sleep(time::Duration::from_millis(30));
+        })
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Tests
+///
+///
+//////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_malformed_packets() {
+        let mut buffer = [0_u8; BUFFER_SIZE];
+        buffer[0] = Version::V1.into();
+        buffer[1] = 6;
+        buffer[4..6].copy_from_slice(&(MAX_DATA_SIZE as u16 + 1).to_be_bytes());
+        assert!(Packet::unpack(&buffer).is_err());
+
+        assert!(Packet::new(Flag::Transmit, 0, 1, 1, &[0; MAX_DATA_SIZE + 1], 0).is_err());
+        assert_eq!(ProtocolError::from(0x03), ProtocolError::RoomClosed);
+
+        let screen = Handshake {
+            physical_height: 150.,
+            physical_width: 70.,
+            window_height: 2400,
+            window_width: 1080,
+            nickname: String::new(),
+            avatar: None,
+        };
+        assert_eq!(Handshake::unpack(&screen.pack()).unwrap(), screen);
+        let mut nan = screen.pack();
+        nan[..4].copy_from_slice(&f32::NAN.to_be_bytes());
+        assert!(Handshake::unpack(&nan).is_err());
+        assert!(Handshake::unpack(&screen.pack()[..15]).is_err());
+        // the profile is optional, and checked when it is there
+        assert_eq!(Handshake::unpack(&screen.pack()[..16]).unwrap(), screen);
+        let alice = Handshake {
+            nickname: "Alice".to_string(),
+            avatar: Some(3),
+            ..screen.clone()
+        };
+        assert_eq!(Handshake::unpack(&alice.pack()).unwrap(), alice);
+        let mut control = alice.pack();
+        control[18] = b'\n';
+        assert!(Handshake::unpack(&control).is_err());
+        assert!(Handshake::unpack(&alice.pack()[..20]).is_err());
+
+        let roster = [
+            Member {
+                session: 4,
+                rank: None,
+                avatar: 1,
+                ready: false,
+                nickname: "Zoé".to_string(),
+            },
+            Member {
+                session: 9,
+                rank: Some(0),
+                avatar: 0,
+                ready: true,
+                nickname: "Player 9".to_string(),
+            },
+        ];
+        assert_eq!(
+            Member::unpack_roster(&Member::pack_roster(&roster)).unwrap(),
+            roster
+        );
+        assert!(Member::unpack_roster(&Member::pack_roster(&roster)[..8]).is_err());
+
+        let hello = Chat {
+            session: 2,
+            emote: None,
+            text: "Hello !".to_string(),
+        };
+        assert_eq!(Chat::unpack(&hello.pack()).unwrap(), hello);
+        let wave = Chat {
+            session: 2,
+            emote: Some(3),
+            text: String::new(),
+        };
+        assert_eq!(Chat::unpack(&wave.pack()).unwrap(), wave);
+        let long = Chat {
+            text: "a".repeat(CHAT_SIZE + 1),
+            ..hello.clone()
+        };
+        assert!(Chat::unpack(&long.pack()).is_err());
+        assert!(Chat::unpack(&[0, 2, NO_EMOTE, b' ']).is_err());
+        assert!(Chat::unpack(&[0, 2, EMOTES]).is_err());
+
+        let leaderboard = [
+            Standing {
+                nickname: "Alice".to_string(),
+                played: 7,
+                wins: 4,
+                kills: 19,
+                best_lap: None,
+            },
+            Standing {
+                nickname: "Bob".to_string(),
+                played: 3,
+                wins: 0,
+                kills: 2,
+                best_lap: Some(time::Duration::from_millis(41_250)),
+            },
+        ];
+        let packed = Standing::pack_leaderboard(&leaderboard);
+        assert_eq!(Standing::unpack_leaderboard(&packed).unwrap(), leaderboard);
+        assert!(Standing::unpack_leaderboard(&packed[..20]).is_err());
+        let crowd = vec![leaderboard[0].clone(); LEADERBOARD_SIZE + 5];
+        assert_eq!(
+            Standing::unpack_leaderboard(&Standing::pack_leaderboard(&crowd))
+                .unwrap()
+                .len(),
+            LEADERBOARD_SIZE
+        );
+
+        let catalogue = [Listing {
+            game: 3,
+            name: "Maze Fight".to_string(),
+            description: "x".repeat(300),
+            min_players: 2,
+            max_players: 9,
+            settings: vec![
+                ListedSetting {
+                    name: "Lives".to_string(),
+                    kind: settings::Kind::Number {
+                        min: 1,
+                        max: 99,
+                        step: 1,
+                    },
+                    default: 10,
+                },
+                ListedSetting {
+                    name: "Lag compensation".to_string(),
+                    kind: settings::Kind::Toggle,
+                    default: 1,
+                },
+            ],
+        }];
+        let packed = Listing::pack_catalogue(&catalogue);
+        let unpacked = Listing::unpack_catalogue(&packed).unwrap();
+        assert_eq!(unpacked[0].description.len(), 255);
+        assert_eq!(unpacked[0].settings, catalogue[0].settings);
+        assert!(Listing::unpack_catalogue(&packed[..packed.len() - 1]).is_err());
+        let crowd = vec![catalogue[0].clone(); 20];
+        let packed = Listing::pack_catalogue(&crowd);
+        assert!(packed.len() <= MAX_DATA_SIZE);
+        assert!(Listing::unpack_catalogue(&packed).unwrap().len() < 20);
+
+        // the secret of the session goes through
+        let signed = Packet::control(Flag::Join, 3, 0, 0).with_secret(0xdead_beef_0123_4567);
+        signed.pack(&mut buffer);
+        assert_eq!(
+            Packet::unpack(&buffer).unwrap().secret,
+            0xdead_beef_0123_4567
+        );
+        buffer[0] = Version::V0.into();
+        assert!(Packet::unpack(&buffer).is_err());
+
+        // garbage never panics
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            rng.fill(&mut buffer[..]);
+            buffer[0] = Version::V1.into();
+            let _ = Packet::unpack(&buffer);
+            let _ = Handshake::unpack(&buffer[HEADER_SIZE..HEADER_SIZE + HANDSHAKE_SIZE + 20]);
+            let _ = Member::unpack_roster(&buffer[HEADER_SIZE..]);
+            let _ = Chat::unpack(&buffer[HEADER_SIZE..HEADER_SIZE + 3 + CHAT_SIZE]);
+            let _ = Standing::unpack_leaderboard(&buffer[HEADER_SIZE..]);
+            let _ = Listing::unpack_catalogue(&buffer[HEADER_SIZE..]);
         }
     }
 }
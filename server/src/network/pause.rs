@@ -0,0 +1,184 @@
+use super::pipe;
+use crate::game::context::Clock;
+use std::sync::Mutex;
+use std::time;
+
+//////////////////////////////////////////////
+///
+///
+/// Pause clock
+///
+///
+//////////////////////////////////////////////
+
+/// Clock of the game of a room, stopped while a player holds the game paused
+///
+/// Once nobody holds it anymore the game goes on after a countdown, the phones are told as it goes.
+/// The game sees no time passing meanwhile, so its timers don't expire.
+pub struct PauseClock {
+    inner: Box<dyn Clock>,
+    pause: pipe::Pause,
+    members: pipe::Members,
+    countdown: time::Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    // time spent in the previous pauses, hidden from the game
+    paused_for: time::Duration,
+    // last reading of the inner clock
+    last: time::Duration,
+    // time of the inner clock when the game was stopped, its last reading before the pause so
+    // that the game never runs past the moment the phones were told
+    stopped_at: Option<time::Duration>,
+    // time of the inner clock when the game goes on, once nobody holds the pause
+    resume_at: Option<time::Duration>,
+    // seconds of the countdown last told to the phones
+    announced: Option<u64>,
+}
+
+impl PauseClock {
+    pub fn new(
+        inner: Box<dyn Clock>,
+        pause: pipe::Pause,
+        members: pipe::Members,
+        countdown: time::Duration,
+    ) -> Self {
+        PauseClock {
+            inner,
+            pause,
+            members,
+            countdown,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn broadcast(&self, message: impl Fn() -> pipe::GameMessage) {
+        for member in self.members.lock().unwrap().iter() {
+            let _ = member.send(message());
+        }
+    }
+}
+
+impl Clock for PauseClock {
+    fn now(&self) -> time::Duration {
+        let now = self.inner.now();
+        let held = !self.pause.lock().unwrap().is_empty();
+        let mut state = self.state.lock().unwrap();
+        match (held, state.stopped_at) {
+            (true, None) => {
+                state.stopped_at = Some(state.last);
+                self.broadcast(|| pipe::GameMessage::pause_message(true));
+            }
+            // paused again during the countdown
+            (true, Some(_)) if state.resume_at.is_some() => {
+                state.resume_at = None;
+                state.announced = None;
+                self.broadcast(|| pipe::GameMessage::pause_message(true));
+            }
+            (false, Some(stopped)) => {
+                let resume_at = *state.resume_at.get_or_insert(now + self.countdown);
+                if now >= resume_at {
+                    state.paused_for += now - stopped;
+                    state.stopped_at = None;
+                    state.resume_at = None;
+                    state.announced = None;
+                    self.broadcast(|| pipe::GameMessage::pause_message(false));
+                } else {
+                    let left = resume_at - now;
+                    let seconds = left.as_secs() + u64::from(left.subsec_nanos() > 0);
+                    if state.announced != Some(seconds) {
+                        state.announced = Some(seconds);
+                        self.broadcast(|| pipe::GameMessage::countdown_message(seconds as u16));
+                    }
+                }
+            }
+            _ => {}
+        }
+        state.last = now;
+        state.stopped_at.unwrap_or(now) - state.paused_for
+    }
+
+    fn sleep(&mut self, duration: time::Duration) {
+        self.inner.sleep(duration);
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Tests
+///
+///
+//////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::context::ManualClock;
+    use std::sync::{mpsc, Arc};
+
+    /// Clock of the test, shared with the pause clock which owns its box
+    struct Shared(Arc<Mutex<ManualClock>>);
+
+    impl Clock for Shared {
+        fn now(&self) -> time::Duration {
+            self.0.lock().unwrap().now()
+        }
+
+        fn sleep(&mut self, duration: time::Duration) {
+            self.0.lock().unwrap().sleep(duration);
+        }
+    }
+
+    #[test]
+    fn test_pause_stops_the_game() {
+        let inner = Arc::new(Mutex::new(ManualClock::new()));
+        let pause: pipe::Pause = Arc::new(Mutex::new(Vec::new()));
+        let (sender, phone) = mpsc::channel();
+        let clock = PauseClock::new(
+            Box::new(Shared(inner.clone())),
+            pause.clone(),
+            Arc::new(Mutex::new(vec![sender])),
+            time::Duration::from_secs(3),
+        );
+        let advance = |secs| {
+            inner
+                .lock()
+                .unwrap()
+                .advance(time::Duration::from_secs(secs))
+        };
+        let told = || -> Vec<(bool, u16)> {
+            phone
+                .try_iter()
+                .map(|m| {
+                    let is_pause = matches!(m.flag, pipe::GameMessageFlag::Pause);
+                    (is_pause, m.rank.unwrap())
+                })
+                .collect()
+        };
+
+        advance(10);
+        assert_eq!(clock.now(), time::Duration::from_secs(10));
+
+        // the game stands still while the pause is held, and during the countdown
+        pause.lock().unwrap().push(1);
+        advance(1);
+        assert_eq!(clock.now(), time::Duration::from_secs(10));
+        advance(60);
+        assert_eq!(clock.now(), time::Duration::from_secs(10));
+        pause.lock().unwrap().clear();
+        assert_eq!(clock.now(), time::Duration::from_secs(10));
+        advance(2);
+        assert_eq!(clock.now(), time::Duration::from_secs(10));
+        assert_eq!(told(), [(true, 1), (false, 3), (false, 1)]);
+
+        // then it goes on from where it stopped
+        advance(1);
+        assert_eq!(clock.now(), time::Duration::from_secs(10));
+        assert_eq!(told(), [(true, 0)]);
+        advance(1);
+        assert_eq!(clock.now(), time::Duration::from_secs(11));
+    }
+}
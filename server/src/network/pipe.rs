@@ -1,4 +1,4 @@
-use std::sync::mpsc;
+use std::sync::{mpsc, Arc, Mutex};
 
 use super::packet::{self, BUFFER_SIZE};
 
@@ -18,6 +18,12 @@ pub enum ServerMessageFlag {
 pub struct ServerMessage {
     pub session_token: u16,
     pub room_token: u16,
+    // code typed to join the room, and its PIN if it is private
+    pub code: u32,
+    pub pin: Option<u16>,
+    // profile asked by the player, the room makes it unique
+    pub nickname: String,
+    pub avatar: Option<u8>,
     pub flag: ServerMessageFlag,
     pub sender: mpsc::Sender<GameMessage>,
     pub physical_height: f32,
@@ -34,11 +40,26 @@ pub struct ServerMessage {
 ///
 //////////////////////////////////////////////
 
+/// Pipes to every player of a room, shared by the room with the clients so that they relay
+/// their chat to each other whatever the room is doing
+pub type Members = Arc<Mutex<Vec<mpsc::Sender<GameMessage>>>>;
+
+/// Sessions of the players holding the running game of a room paused, shared by the room with
+/// the clients like the members : the game goes on once nobody holds it anymore
+pub type Pause = Arc<Mutex<Vec<u16>>>;
+
 pub enum GameMessageFlag {
     Init,
     Lock,
     Launch,
     Data,
+    Roster,
+    Ready,
+    Countdown,
+    Pause,
+    Text,
+    Chat,
+    End,
 
     Disconnected,
     Error,
@@ -48,18 +69,30 @@ pub struct GameMessage {
     pub flag: GameMessageFlag,
 
     pub room_token: u16,
+    pub code: u32,
     pub sender: Option<mpsc::Sender<GameMessage>>,
+    pub members: Option<Members>,
+    pub pause: Option<Pause>,
     pub rank: Option<u16>,
     pub size: usize,
     pub data: Option<[u8; packet::MAX_DATA_SIZE]>,
 }
 
 impl GameMessage {
-    pub fn init_message(sender: mpsc::Sender<GameMessage>, room_token: u16) -> Self {
+    pub fn init_message(
+        sender: mpsc::Sender<GameMessage>,
+        members: Members,
+        pause: Pause,
+        room_token: u16,
+        code: u32,
+    ) -> Self {
         GameMessage {
             flag: GameMessageFlag::Init,
             room_token,
+            code,
             sender: Some(sender),
+            members: Some(members),
+            pause: Some(pause),
             rank: None,
             size: 0,
             data: None,
@@ -70,19 +103,102 @@ impl GameMessage {
         GameMessage {
             flag: GameMessageFlag::Lock,
             room_token: 0,
+            code: 0,
             sender: None,
+            members: None,
+            pause: None,
             rank: Some(rank),
             size: 0,
             data: None,
         }
     }
 
-    pub fn launch_message(game_id: u16) -> Self {
+    /// Ask the room to lock for `game_id`, with the settings of the master packed by
+    /// `Settings::pack`
+    pub fn lock_request(game_id: u16, settings: &[u8]) -> Self {
+        GameMessage {
+            rank: Some(game_id),
+            ..GameMessage::bytes_message(GameMessageFlag::Lock, settings)
+        }
+    }
+
+    /// The game `game_id` is launched with `settings`, packed by `Settings::pack`
+    pub fn launch_message(game_id: u16, settings: &[u8]) -> Self {
+        GameMessage {
+            rank: Some(game_id),
+            ..GameMessage::bytes_message(GameMessageFlag::Launch, settings)
+        }
+    }
+
+    /// Ask the room to launch the game, `force` launches it even if some players aren't ready
+    pub fn start_message(force: bool) -> Self {
         GameMessage {
             flag: GameMessageFlag::Launch,
             room_token: 0,
+            code: 0,
             sender: None,
-            rank: Some(game_id),
+            members: None,
+            pause: None,
+            rank: Some(force as u16),
+            size: 0,
+            data: None,
+        }
+    }
+
+    pub fn ready_message(ready: bool) -> Self {
+        GameMessage {
+            flag: GameMessageFlag::Ready,
+            room_token: 0,
+            code: 0,
+            sender: None,
+            members: None,
+            pause: None,
+            rank: Some(ready as u16),
+            size: 0,
+            data: None,
+        }
+    }
+
+    /// Seconds left before the game is launched
+    pub fn countdown_message(seconds: u16) -> Self {
+        GameMessage {
+            flag: GameMessageFlag::Countdown,
+            room_token: 0,
+            code: 0,
+            sender: None,
+            members: None,
+            pause: None,
+            rank: Some(seconds),
+            size: 0,
+            data: None,
+        }
+    }
+
+    /// The game of the room was paused, or goes on again after the countdown of its resume
+    pub fn pause_message(paused: bool) -> Self {
+        GameMessage {
+            flag: GameMessageFlag::Pause,
+            room_token: 0,
+            code: 0,
+            sender: None,
+            members: None,
+            pause: None,
+            rank: Some(paused as u16),
+            size: 0,
+            data: None,
+        }
+    }
+
+    /// The game ended, `place` is the one of the player, 0 if the game doesn't rank the players
+    pub fn end_message(place: u8) -> Self {
+        GameMessage {
+            flag: GameMessageFlag::End,
+            room_token: 0,
+            code: 0,
+            sender: None,
+            members: None,
+            pause: None,
+            rank: Some(place as u16),
             size: 0,
             data: None,
         }
@@ -92,7 +208,42 @@ impl GameMessage {
         GameMessage {
             flag: GameMessageFlag::Data,
             room_token: 0,
+            code: 0,
+            sender: None,
+            members: None,
+            pause: None,
+            rank: None,
+            size,
+            data: Some(data),
+        }
+    }
+
+    /// Players of the room, packed by `packet::Member::pack_roster`
+    pub fn roster_message(roster: &[u8]) -> Self {
+        GameMessage::bytes_message(GameMessageFlag::Roster, roster)
+    }
+
+    /// Chat line of a player, packed by `packet::Chat::pack`
+    pub fn chat_message(chat: &[u8]) -> Self {
+        GameMessage::bytes_message(GameMessageFlag::Chat, chat)
+    }
+
+    /// Text for the player, forwarded as a Message packet
+    pub fn text_message(text: &str) -> Self {
+        GameMessage::bytes_message(GameMessageFlag::Text, text.as_bytes())
+    }
+
+    fn bytes_message(flag: GameMessageFlag, bytes: &[u8]) -> Self {
+        let mut data = [0_u8; packet::MAX_DATA_SIZE];
+        let size = bytes.len().min(packet::MAX_DATA_SIZE);
+        data[..size].copy_from_slice(&bytes[..size]);
+        GameMessage {
+            flag,
+            room_token: 0,
+            code: 0,
             sender: None,
+            members: None,
+            pause: None,
             rank: None,
             size,
             data: Some(data),
@@ -103,7 +254,10 @@ impl GameMessage {
         GameMessage {
             flag: GameMessageFlag::Error,
             room_token: 0,
+            code: 0,
             sender: None,
+            members: None,
+            pause: None,
             rank: None,
             size: 0,
             data: None,
@@ -1,5 +1,7 @@
 use super::packet;
 use super::pipe;
+use crate::metrics::metrics;
+use crate::replay;
 use std::io::Error;
 use std::io::ErrorKind;
 use std::sync::mpsc;
@@ -18,12 +20,24 @@ pub struct Player {
     pub receiver: mpsc::Receiver<pipe::GameMessage>,
 
     pub rank: u8,
+    // who the player is, unique in the room
+    pub session: u16,
+    pub nickname: String,
+    // nickname asked in the handshake, the statistics are kept under it, empty for anonymous players
+    // nothing proves it belongs to the player, anyone can play under it
+    pub identity: String,
+    pub avatar: u8,
+    // ready to launch, asked again each time the room is locked
+    pub ready: bool,
     pub top_left_x: f32,
     pub top_left_y: f32,
     pub physical_height: f32,
     pub physical_width: f32,
     pub window_height: u32,
     pub window_width: u32,
+
+    // When set, the messages are recorded to or replayed from the tape
+    pub tape: Option<replay::Tape>,
 }
 
 impl Player {
@@ -31,26 +45,45 @@ impl Player {
     pub fn send(&mut self, raw_data: &[u8]) -> Result<(), Error> {
         let mut data = [0_u8; packet::MAX_DATA_SIZE];
         let size = raw_data.len();
-        if (size > packet::MAX_DATA_SIZE) {
-            panic!("Trying to send too much data");
+        if size > packet::MAX_DATA_SIZE {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{size} bytes don't fit in a packet"),
+            ));
         }
         if (size > 0) {
             data[..size].copy_from_slice(raw_data);
         }
-        match self
+        if let Some(res) = self.tape.as_ref().and_then(|t| t.send(self.rank, raw_data)) {
+            return res;
+        }
+        let res = match self
             .sender
             .send(pipe::GameMessage::data_message(data, size))
         {
             Ok(_) => Ok(()),
-            Err(_) => Err(Error::new(ErrorKind::NotConnected, "client not connected")),
+            Err(_) => {
+                metrics().send_error();
+                Err(Error::new(ErrorKind::NotConnected, "client not connected"))
+            }
+        };
+        if let Some(tape) = &self.tape {
+            tape.sent(self.rank, raw_data, res.as_ref().map(|_| ()));
         }
+        res
     }
 
     /// Receive data from the associated client
     /// Return how much data was actually received
     /// If no data was received, the function return 0
     pub fn recv(&mut self, buffer: &mut [u8; packet::MAX_DATA_SIZE]) -> Result<usize, Error> {
-        match self.receiver.try_recv() {
+        if let Some(res) = self.tape.as_ref().and_then(|t| t.recv(self.rank)) {
+            let data = res?;
+            buffer[..data.len()].copy_from_slice(&data);
+            return Ok(data.len());
+        }
+
+        let res = match self.receiver.try_recv() {
             Ok(m) => {
                 buffer.copy_from_slice(&m.data.unwrap());
                 Ok(m.size)
@@ -59,7 +92,11 @@ impl Player {
             Err(TryRecvError::Disconnected) => {
                 Err(Error::new(ErrorKind::NotConnected, "client not connected"))
             }
+        };
+        if let Some(tape) = &self.tape {
+            tape.received(self.rank, res.as_ref().map(|size| &buffer[..*size]));
         }
+        res
     }
 
     /// Convert physical coordinates to screen coordinates
@@ -74,6 +111,14 @@ impl Player {
         (res_x, res_y)
     }
 
+    /// True if the physical rectangle intersects the screen of the player extended by `margin`
+    pub fn sees(&self, x: f32, y: f32, width: f32, height: f32, margin: f32) -> bool {
+        x + width >= self.top_left_x - margin
+            && x <= self.top_left_x + self.physical_width + margin
+            && y + height >= self.top_left_y - margin
+            && y <= self.top_left_y + self.physical_height + margin
+    }
+
     pub fn to_local_proportion_vertical(&self, x: f32) -> f32 {
         x * self.window_height as f32 / self.physical_height
     }
@@ -87,3 +132,114 @@ impl Player {
         todo!()
     }
 }
+
+//////////////////////////////////////////////
+///
+///
+/// Layout
+///
+///
+//////////////////////////////////////////////
+
+/// Place the phones side by side by upward rank, centered vertically on the highest one
+/// The ranks may have gaps, left by the players who left a running game
+pub fn layout(players: &mut [Player]) {
+    let mut max_height: f32 = 0.;
+    for p in players.iter() {
+        max_height = max_height.max(p.physical_height);
+    }
+
+    let mut order: Vec<usize> = (0..players.len()).collect();
+    order.sort_by_key(|i| players[*i].rank);
+    let mut current_x: f32 = 0.;
+    for i in order {
+        let p = &mut players[i];
+        p.top_left_x = current_x;
+        current_x += p.physical_width;
+        p.top_left_y = (max_height - p.physical_height) / 2.;
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Profile
+///
+///
+//////////////////////////////////////////////
+
+/// Number of avatars the phones can draw
+pub const AVATARS: u8 = 8;
+
+/// Nickname and avatar of a player joining `players`, as close as possible to the ones it asked for
+/// The nickname gets a number when it is taken, the avatar is the first free one when it is taken
+pub fn profile(
+    players: &[Player],
+    session: u16,
+    nickname: &str,
+    avatar: Option<u8>,
+) -> (String, u8) {
+    let base = match nickname.trim() {
+        "" => format!("Player {session}"),
+        nickname => nickname.to_string(),
+    };
+    let mut nickname = base.clone();
+    let mut n = 2;
+    while players.iter().any(|p| p.nickname == nickname) {
+        let suffix = format!(" {n}");
+        let mut end = base.len().min(packet::NICKNAME_SIZE - suffix.len());
+        while !base.is_char_boundary(end) {
+            end -= 1;
+        }
+        nickname = format!("{}{suffix}", &base[..end]);
+        n += 1;
+    }
+
+    let taken = |a: u8| players.iter().any(|p| p.avatar == a);
+    let avatar = match avatar {
+        Some(a) if a < AVATARS && !taken(a) => a,
+        _ => (0..AVATARS)
+            .find(|a| !taken(*a))
+            .unwrap_or(players.len() as u8 % AVATARS),
+    };
+    (nickname, avatar)
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Tests
+///
+///
+//////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::mock;
+
+    #[test]
+    fn test_profile() {
+        // the mock players are "Player 1" and "Player 2", with the avatars 0 and 1
+        let (players, _) = mock::room(&[mock::Screen::new(100, 200); 2]);
+        assert_eq!(
+            profile(&players, 7, " Bob ", Some(5)),
+            ("Bob".to_string(), 5)
+        );
+        assert_eq!(
+            profile(&players, 7, "", Some(0)),
+            ("Player 7".to_string(), 2)
+        );
+        assert_eq!(
+            profile(&players, 7, "Player 2", Some(42)),
+            ("Player 2 2".to_string(), 2)
+        );
+        // the number always fits in a nickname
+        let mut players = players;
+        players[0].nickname = "Sixteen bytes!!!".to_string();
+        assert_eq!(
+            profile(&players, 7, "Sixteen bytes!!!", None).0,
+            "Sixteen bytes! 2"
+        );
+    }
+}
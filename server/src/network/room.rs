@@ -1,9 +1,20 @@
 use log::{info, warn};
 
+use super::pause::PauseClock;
 use super::{client, player};
 use super::{packet, pipe};
+use crate::game::context::{Clock, GameContext, Lobby, SystemClock};
+use crate::game::settings::Settings;
+use crate::game::{self, Placement};
+use crate::logging;
+use crate::metrics::{metrics, RoomState};
+use crate::replay;
+use crate::stats;
 use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{self, TryRecvError};
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time;
 
@@ -21,10 +32,34 @@ pub struct Room {
 
     game_id: client::Game,
 
-    // Receiver for the main thread (join request)
-    main_receiver: mpsc::Receiver<pipe::ServerMessage>,
+    // Settings of the game chosen by the master
+    settings: Settings,
+
+    // Seed of the random generator of the games, logged to reproduce a game
+    seed: u64,
+
+    // Directory where the game is recorded, if any
+    record_dir: Option<PathBuf>,
+
+    // Set by the server to close the room
+    closed: Arc<AtomicBool>,
+
+    // Time counted down on the phones between the launch and the game
+    countdown: time::Duration,
+
+    // Receiver for the main thread (join request), shared with the lobby of the running game
+    main_receiver: Arc<Mutex<mpsc::Receiver<pipe::ServerMessage>>>,
 
     players: Vec<player::Player>,
+
+    // Pipes to the players, for the clients to relay their chat
+    members: pipe::Members,
+
+    // Players holding the running game paused, set by their clients
+    pause: pipe::Pause,
+
+    // Statistics of the players, shared by the rooms
+    stats: Arc<Mutex<stats::Stats>>,
 }
 
 impl Room {
@@ -36,28 +71,52 @@ impl Room {
     ///
     //////////////////////////////////////////////
 
-    pub fn new(receiver: mpsc::Receiver<pipe::ServerMessage>, token: u16) -> Room {
+    pub fn new(
+        receiver: mpsc::Receiver<pipe::ServerMessage>,
+        token: u16,
+        record_dir: Option<PathBuf>,
+        closed: Arc<AtomicBool>,
+        countdown: time::Duration,
+        stats: Arc<Mutex<stats::Stats>>,
+    ) -> Room {
         let target: String = format!("Room {token}");
+        metrics().room_opened(token);
         Room {
             token,
             target,
             game_id: client::Game::Unknown,
-            main_receiver: receiver,
+            settings: Settings::default(),
+            seed: rand::random(),
+            record_dir,
+            closed,
+            countdown,
+            main_receiver: Arc::new(Mutex::new(receiver)),
             players: Vec::new(),
+            members: Arc::new(Mutex::new(Vec::new())),
+            pause: Arc::new(Mutex::new(Vec::new())),
+            stats,
         }
     }
 
     pub fn manager(&mut self) -> Result<(), Error> {
         let mut is_game_on: bool = false;
+        logging::set_room(self.token);
         info!(target: self.target.as_str(), "Room created successfully");
 
         while !is_game_on {
+            if self.closed.load(Ordering::Relaxed) {
+                info!(target: self.target.as_str(), "Room closed");
+                return Ok(());
+            }
             self.check_for_new_players();
             if !self.players.is_empty() && self.should_game_launch()? {
                 // We go first through a phase of locked game where we send a message to all the players of where they are positioned
                 info!(target: self.target.as_str(), "Room locked");
+                metrics().room_state(self.token, RoomState::Locked, &self.game_id.to_string());
                 self.assign_rank();
-                self.launch_game()?;
+                let res = self.launch_game();
+                metrics().room_state(self.token, RoomState::Lobby, &self.game_id.to_string());
+                res?;
 
                 is_game_on = false;
             }
@@ -76,7 +135,8 @@ impl Room {
     //////////////////////////////////////////////
 
     fn check_for_new_players(&mut self) {
-        match self.main_receiver.try_recv() {
+        let message = self.main_receiver.lock().unwrap().try_recv();
+        match message {
             Ok(message) => {
                 info!(target: self.target.as_str(), "Client {} joined the room", message.session_token);
                 self.add_player(message);
@@ -89,18 +149,28 @@ impl Room {
     // TODO: might be usefull to warn the other threads before dropping the thread
     fn should_game_launch(&mut self) -> Result<bool, Error> {
         // NOTE : we can always ensure that the first player we receive is the master device
-        match self.players[0].receiver.try_recv() {
-            Ok(message) => {
-                self.game_id = message.rank.unwrap().into(); // should never be None
-                Ok(true)
-            }
-            Err(TryRecvError::Empty) => Ok(false),
-            Err(e) => {
-                self.remove_player(0);
-                Err(Error::new(
-                    ErrorKind::Interrupted,
-                    "master client disconnected",
-                ))
+        loop {
+            match self.players[0].receiver.try_recv() {
+                Ok(message) if matches!(message.flag, pipe::GameMessageFlag::Lock) => {
+                    self.game_id = message.rank.unwrap().into(); // should never be None
+
+                    // already checked by the client of the master
+                    let schema = game::settings(self.game_id);
+                    let data = message.data.unwrap_or([0; packet::MAX_DATA_SIZE]);
+                    self.settings = Settings::unpack(schema, &data[..message.size])
+                        .unwrap_or_else(|_| Settings::defaults(schema));
+                    return Ok(true);
+                }
+                // inputs sent before the master heard that the last game ended
+                Ok(_) => {}
+                Err(TryRecvError::Empty) => return Ok(false),
+                Err(e) => {
+                    self.remove_player(0);
+                    return Err(Error::new(
+                        ErrorKind::Interrupted,
+                        "master client disconnected",
+                    ));
+                }
             }
         }
     }
@@ -110,6 +180,7 @@ impl Room {
         let mut index = 0;
         while index < self.players.len() {
             self.players[index].rank = index as u8;
+            self.players[index].ready = false;
             match self.players[index]
                 .sender
                 .send(pipe::GameMessage::lock_message(index as u16))
@@ -121,7 +192,8 @@ impl Room {
                 }
             }
         }
-        self.set_player_phone_location();
+        player::layout(&mut self.players);
+        self.send_roster(true);
     }
 
     fn unlock_game(&mut self) -> Result<(), Error> {
@@ -129,8 +201,10 @@ impl Room {
         while index < self.players.len() {
             match self.players[index]
                 .sender
-                .send(pipe::GameMessage::launch_message(self.game_id.into()))
-            {
+                .send(pipe::GameMessage::launch_message(
+                    self.game_id.into(),
+                    &self.settings.pack(),
+                )) {
                 Ok(_) => index += 1,
                 Err(e) => {
                     self.remove_player(index);
@@ -139,60 +213,237 @@ impl Room {
             }
         }
 
-        info!(target: self.target.as_str(), "Game {} launched", self.game_id);
+        info!(target: self.target.as_str(), "Game {} launched with seed {} and settings {:?}", self.game_id, self.seed, self.settings.values());
+        metrics().room_state(self.token, RoomState::Playing, &self.game_id.to_string());
+        logging::set_game(Some(self.game_id.to_string()));
+        // the recording sees the time of the game, which stands still while it is paused
+        self.pause.lock().unwrap().clear();
+        let mut clock: Box<dyn Clock> = Box::new(PauseClock::new(
+            Box::new(SystemClock::new()),
+            self.pause.clone(),
+            self.members.clone(),
+            self.countdown,
+        ));
+        let recorder = self.start_recording();
+        if let Some(recorder) = &recorder {
+            clock = Box::new(replay::RecordingClock::new(clock, recorder.clone()));
+        }
+        let mut ctx = GameContext::new(self.seed, clock);
+        if game::info(self.game_id).is_some_and(|i| i.late_join) {
+            ctx = ctx.with_lobby(Box::new(RoomLobby {
+                token: self.token,
+                target: self.target.clone(),
+                main_receiver: self.main_receiver.clone(),
+                members: self.members.clone(),
+                pause: self.pause.clone(),
+                game_id: self.game_id,
+                settings: self.settings.pack(),
+                max_players: game::info(self.game_id).map_or(0, |i| *i.players.end()),
+                next_rank: self.players.len() as u8,
+                recorder: recorder.clone(),
+            }));
+        }
 
-        // Here we will put the interface code with the client
-        match self.game_id {
-            client::Game::Racer => crate::game::racer::racer(&self.players),
-            client::Game::Snake => {}
-            client::Game::MazeFight => crate::game::maze_fight::maze_fight(&mut self.players)?,
-            client::Game::Test => test_function(&mut self.players),
-            client::Game::Unknown => {}
+        let master = self.players[0].session;
+        let res = game::play(self.game_id, &self.settings, &mut self.players, &mut ctx);
+        logging::set_game(None);
+        self.pause.lock().unwrap().clear();
+        // the next game of the room is another one
+        self.seed = rand::random();
+
+        if let Some(recorder) = recorder {
+            for p in self.players.iter_mut() {
+                p.tape = None;
+            }
+            match recorder.lock().unwrap().flush() {
+                Ok(_) => info!(target: self.target.as_str(), "Game recorded"),
+                Err(e) => warn!(target: self.target.as_str(), "couldn't save the recording : {e}"),
+            }
+        }
+        // the master may have left a game going on without it, the room can't go on
+        if self.players.first().map(|p| p.session) != Some(master) {
+            return Err(Error::new(
+                ErrorKind::Interrupted,
+                "master client disconnected",
+            ));
         }
+        let placements = res?;
+        info!(target: self.target.as_str(), "Game {} ended", self.game_id);
+        self.record_stats(&placements);
+        self.end_game(&placements);
         Ok(())
     }
 
-    // TODO: might be usefull to warn the other threads before dropping the thread
+    /// Count the placements in the statistics of the players
+    fn record_stats(&mut self, placements: &[Placement]) {
+        let placements: Vec<(String, Placement)> = placements
+            .iter()
+            .filter_map(|placement| {
+                let p = self.players.iter().find(|p| p.rank == placement.rank)?;
+                Some((p.identity.clone(), placement.clone()))
+            })
+            .collect();
+        if let Err(e) = self.stats.lock().unwrap().record(self.game_id, &placements) {
+            warn!(target: self.target.as_str(), "couldn't save the statistics : {e}");
+        }
+    }
+
+    /// Tell the players their place and send them back to the lobby
+    fn end_game(&mut self, placements: &[Placement]) {
+        for p in self.players.iter_mut() {
+            let place = placements
+                .iter()
+                .find(|placement| placement.rank == p.rank)
+                .map_or(0, |placement| placement.place);
+            let _ = p.sender.send(pipe::GameMessage::end_message(place));
+            p.ready = false;
+        }
+        self.send_roster(false);
+    }
+
+    /// Create the replay file of the game and plug it to the players, if the games are recorded
+    fn start_recording(&mut self) -> Option<Arc<Mutex<replay::Recorder>>> {
+        let dir = self.record_dir.as_ref()?;
+        let path = dir.join(format!("room-{}-{}.replay", self.token, self.seed));
+        let header = replay::Header {
+            game_id: self.game_id.into(),
+            seed: self.seed,
+            settings: self.settings.clone(),
+            layouts: self.players.iter().map(replay::Layout::of).collect(),
+        };
+        match replay::Recorder::create(&path, &header) {
+            Ok(recorder) => {
+                info!(target: self.target.as_str(), "Recording the game to {}", path.display());
+                let recorder = Arc::new(Mutex::new(recorder));
+                for p in self.players.iter_mut() {
+                    p.tape = Some(replay::Tape::Record(recorder.clone()));
+                }
+                Some(recorder)
+            }
+            Err(e) => {
+                warn!(target: self.target.as_str(), "couldn't record the game : {e}");
+                None
+            }
+        }
+    }
+
+    /// Gather the ready state of the players until the master launches the game, which only
+    /// happens once everyone is ready unless the master forces it
     fn launch_game(&mut self) -> Result<(), Error> {
-        match self.players[0].receiver.recv() {
-            Ok(_) => self.unlock_game(),
-            Err(_) => {
-                self.remove_player(0)?;
-                Err(Error::new(
-                    ErrorKind::Interrupted,
-                    "master client disconnected",
-                ))
+        loop {
+            let mut index = 0;
+            while index < self.players.len() {
+                match self.players[index].receiver.try_recv() {
+                    Ok(message) => match message.flag {
+                        pipe::GameMessageFlag::Ready => {
+                            self.players[index].ready = message.rank == Some(1);
+                            self.send_roster(true);
+                            index += 1;
+                        }
+                        pipe::GameMessageFlag::Launch if index == 0 => {
+                            let waiting = self.players[1..].iter().filter(|p| !p.ready).count();
+                            if waiting == 0 || message.rank == Some(1) {
+                                self.count_down();
+                                return self.unlock_game();
+                            }
+                            let _ = self.players[0].sender.send(pipe::GameMessage::text_message(
+                                &format!("{waiting} player(s) not ready yet"),
+                            ));
+                            index += 1;
+                        }
+                        _ => index += 1,
+                    },
+                    Err(TryRecvError::Empty) => index += 1,
+                    Err(TryRecvError::Disconnected) if index == 0 => {
+                        self.remove_player(0)?;
+                        return Err(Error::new(
+                            ErrorKind::Interrupted,
+                            "master client disconnected",
+                        ));
+                    }
+                    Err(TryRecvError::Disconnected) => {
+                        info!(target: self.target.as_str(), "Client {} left the room", self.players[index].session);
+                        self.remove_player(index)?;
+                        // the ranks and the layout follow the players left, who get ready again
+                        self.assign_rank();
+                        index = 0;
+                    }
+                }
             }
+            thread::sleep(time::Duration::from_millis(10));
+        }
+    }
+
+    /// Tell the players how many seconds are left until the launch, once a second
+    fn count_down(&mut self) {
+        let launch = time::Instant::now() + self.countdown;
+        loop {
+            let left = launch.saturating_duration_since(time::Instant::now());
+            if left.is_zero() {
+                return;
+            }
+            let seconds = left.as_secs() + u64::from(left.subsec_nanos() > 0);
+            for p in self.players.iter() {
+                let _ = p
+                    .sender
+                    .send(pipe::GameMessage::countdown_message(seconds as u16));
+            }
+            thread::sleep(left - time::Duration::from_secs(seconds - 1));
         }
     }
 
     fn add_player(&mut self, message: pipe::ServerMessage) {
-        let (sender, receiver) = mpsc::channel();
-        match message
-            .sender
-            .send(pipe::GameMessage::init_message(sender, self.token))
-        {
-            Ok(_) => {
-                self.players.push(player::Player {
-                    sender: message.sender,
-                    receiver,
-                    rank: 0,
-                    top_left_x: 0.,
-                    top_left_y: 0.,
-                    physical_height: message.physical_height,
-                    physical_width: message.physical_width,
-                    window_height: message.window_height,
-                    window_width: message.window_width,
-                });
+        if let Some(p) = new_player(
+            message,
+            &self.players,
+            &self.members,
+            &self.pause,
+            self.token,
+            &self.target,
+        ) {
+            self.players.push(p);
+            metrics().room_players(self.token, self.players.len());
+            self.share_members();
+            self.send_roster(false);
+        }
+    }
+
+    /// Tell every player who is in the room, with the ranks once they are given
+    fn send_roster(&mut self, ranked: bool) {
+        loop {
+            let roster = pack_roster(&self.players, ranked);
+
+            // the players leaving the lobby are only noticed here, the master is left to
+            // `should_game_launch` which closes the room without it
+            let before = self.players.len();
+            let mut index = self.players.len();
+            while index > 0 {
+                index -= 1;
+                let sent = self.players[index]
+                    .sender
+                    .send(pipe::GameMessage::roster_message(&roster));
+                if sent.is_err() && index > 0 {
+                    let left = self.players.remove(index);
+                    info!(target: self.target.as_str(), "Client {} left the room", left.session);
+                }
             }
-            Err(_) => {
-                warn!(target: self.target.as_str(), "client disconnected");
+            if self.players.len() == before {
+                break;
             }
+            metrics().room_players(self.token, self.players.len());
+            self.share_members();
         }
     }
 
+    /// Give the clients the pipes of the players now in the room
+    fn share_members(&self) {
+        *self.members.lock().unwrap() = self.players.iter().map(|p| p.sender.clone()).collect();
+    }
+
     fn remove_player(&mut self, index: usize) -> Result<(), Error> {
         let _ = self.players.swap_remove(index);
+        metrics().room_players(self.token, self.players.len());
+        self.share_members();
         if self.players.len() == 1 {
             match self.players[0]
                 .sender
@@ -210,60 +461,159 @@ impl Room {
         }
         Ok(())
     }
+}
 
-    fn set_player_phone_location(&mut self) {
-        // find max
-        let mut max_height: f32 = 0.;
-        for p in &self.players {
-            if p.physical_height > max_height {
-                max_height = p.physical_height
-            }
-        }
+//////////////////////////////////////////////
+///
+///
+/// Players
+///
+///
+//////////////////////////////////////////////
 
-        let mut current_x: f32 = 0.;
-        // compute pos by upward rank
-        for i in 0..self.players.len() {
-            let j = self.find_player_of_rank(i as u8);
-            self.players[j].top_left_x = current_x;
-            current_x += self.players[j].physical_width;
-            self.players[j].top_left_y = (max_height - self.players[j].physical_height) / 2.;
-        }
+/// Give the client of the join request its pipe to the room and make it a player, None if it
+/// left meanwhile
+fn new_player(
+    message: pipe::ServerMessage,
+    players: &[player::Player],
+    members: &pipe::Members,
+    pause: &pipe::Pause,
+    token: u16,
+    target: &str,
+) -> Option<player::Player> {
+    let (sender, receiver) = mpsc::channel();
+    let init = pipe::GameMessage::init_message(
+        sender,
+        members.clone(),
+        pause.clone(),
+        token,
+        message.code,
+    );
+    // the client counts the members as soon as it has the answer, like when the room is locked
+    members.lock().unwrap().push(message.sender.clone());
+    if message.sender.send(init).is_err() {
+        members.lock().unwrap().pop();
+        warn!(target: target, "client disconnected");
+        return None;
     }
+    let (nickname, avatar) = player::profile(
+        players,
+        message.session_token,
+        &message.nickname,
+        message.avatar,
+    );
+    info!(target: target, "Client {} plays as {nickname} with the avatar {avatar}", message.session_token);
+    Some(player::Player {
+        sender: message.sender,
+        receiver,
+        rank: 0,
+        session: message.session_token,
+        nickname,
+        identity: message.nickname.trim().to_string(),
+        avatar,
+        ready: false,
+        top_left_x: 0.,
+        top_left_y: 0.,
+        physical_height: message.physical_height,
+        physical_width: message.physical_width,
+        window_height: message.window_height,
+        window_width: message.window_width,
+        tape: None,
+    })
+}
 
-    fn find_player_of_rank(&self, rank: u8) -> usize {
-        for i in 0..self.players.len() {
-            if self.players[i].rank == rank {
-                return i;
-            }
-        }
-        panic!("should never happened")
-    }
+/// Who is in the room, as sent to the phones
+fn pack_roster(players: &[player::Player], ranked: bool) -> Vec<u8> {
+    let members: Vec<packet::Member> = players
+        .iter()
+        .map(|p| packet::Member {
+            session: p.session,
+            rank: ranked.then_some(p.rank),
+            avatar: p.avatar,
+            ready: p.ready,
+            nickname: p.nickname.clone(),
+        })
+        .collect();
+    packet::Member::pack_roster(&members)
 }
 
 //////////////////////////////////////////////
 ///
 ///
-/// Test server
+/// Lobby of the running game
 ///
 ///
 //////////////////////////////////////////////
 
-fn test_function(players: &mut Vec<player::Player>) {
-    let mut buffer = [0_u8; packet::MAX_DATA_SIZE];
-    loop {
-        // let mut p1 = &mut players[0];
-        // let mut p2 = &mut players[1];
-        if players.len() > 1 {
-            if players[0].recv(&mut buffer).unwrap() > 0 {
-                // println!("{buffer:?}");
-                players[1].send(&buffer);
+/// Let the clients join the running game of the room, for the games following the players
+struct RoomLobby {
+    token: u16,
+    target: String,
+    main_receiver: Arc<Mutex<mpsc::Receiver<pipe::ServerMessage>>>,
+    members: pipe::Members,
+    pause: pipe::Pause,
+    game_id: client::Game,
+    // packed settings of the game, sent with the launch
+    settings: Vec<u8>,
+    max_players: usize,
+    // ranks aren't given twice in a game, the phones index the sprites with them
+    next_rank: u8,
+    recorder: Option<Arc<Mutex<replay::Recorder>>>,
+}
+
+impl Lobby for RoomLobby {
+    fn admit(&mut self, players: &mut Vec<player::Player>) -> Vec<u8> {
+        let mut joined = Vec::new();
+        while players.len() < self.max_players && self.next_rank < u8::MAX {
+            let Ok(message) = self.main_receiver.lock().unwrap().try_recv() else {
+                break;
+            };
+            info!(target: self.target.as_str(), "Client {} joined the running game", message.session_token);
+            let Some(mut p) = new_player(
+                message,
+                players,
+                &self.members,
+                &self.pause,
+                self.token,
+                &self.target,
+            ) else {
+                continue;
+            };
+            p.rank = self.next_rank;
+            // the client goes through the lock and the launch like the ones already playing
+            let lock = pipe::GameMessage::lock_message(p.rank as u16);
+            let launch = pipe::GameMessage::launch_message(self.game_id.into(), &self.settings);
+            if p.sender.send(lock).is_err() || p.sender.send(launch).is_err() {
+                warn!(target: self.target.as_str(), "client disconnected");
+                continue;
             }
-            if players[1].recv(&mut buffer).unwrap() > 0 {
-                players[0].send(&buffer);
+            self.next_rank += 1;
+            if let Some(recorder) = &self.recorder {
+                recorder
+                    .lock()
+                    .unwrap()
+                    .record(&replay::Event::Joined(replay::Layout::of(&p)));
+                p.tape = Some(replay::Tape::Record(recorder.clone()));
             }
-        } else {
-            let _ = players[0].recv(&mut buffer);
+            joined.push(p.rank);
+            players.push(p);
+        }
+        joined
+    }
+
+    fn reflowed(&mut self, players: &[player::Player]) {
+        metrics().room_players(self.token, players.len());
+        *self.members.lock().unwrap() = players.iter().map(|p| p.sender.clone()).collect();
+        let roster = pack_roster(players, true);
+        for p in players.iter() {
+            let _ = p.sender.send(pipe::GameMessage::roster_message(&roster));
         }
-        thread::sleep(time::Duration::from_millis(10));
+    }
+}
+
+impl Drop for Room {
+    fn drop(&mut self) {
+        metrics().room_closed(self.token);
+        logging::close_room(self.token);
     }
 }
@@ -0,0 +1,845 @@
+use crate::game::context::{Clock, Lobby};
+use crate::game::{self, settings::Settings};
+use crate::network::player;
+use log::warn;
+use std::fs;
+use std::io::{BufWriter, Error, ErrorKind, Write};
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time;
+use std::vec;
+
+/// A replay is everything a game took from the outside world, in the order it took it : the clock
+/// readings and the messages received from each player, along with the messages sent to them.
+/// With the seed, the settings and the layout of the players, a game fed with the same events
+/// behaves the same.
+///
+/// File format :
+///     header : "PTRP" | version (u8) | game id (u16) | seed (u64) | settings | player count (u8)
+///              | layouts
+///     settings : count (u8) | value (u16) per setting
+///     layout : rank (u8) | top left x, y (f32) | physical height, width (f32) | window height, width (u32)
+///              | avatar (u8) | nickname size (u8) | nickname
+///     events : tag (u8) followed by
+///         Now          : nanoseconds since the previous reading (varint)
+///         Recv, Send   : player (u8) | size (varint) | data
+///         Disconnected : player (u8)
+///         Lost         : player (u8), the previous message could not be sent to the player
+///         Joined       : layout of a player who joined the running game
+
+//////////////////////////////////////////////
+///
+///
+/// Constants
+///
+///
+//////////////////////////////////////////////
+
+const MAGIC: &[u8; 4] = b"PTRP";
+const VERSION: u8 = 4;
+
+const TAG_NOW: u8 = 1;
+const TAG_RECV: u8 = 2;
+const TAG_SEND: u8 = 3;
+const TAG_DISCONNECTED: u8 = 4;
+const TAG_LOST: u8 = 5;
+const TAG_JOINED: u8 = 6;
+
+//////////////////////////////////////////////
+///
+///
+/// Header
+///
+///
+//////////////////////////////////////////////
+
+/// Position and profile of a player in the room, enough to rebuild it for the game
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    pub rank: u8,
+    pub top_left_x: f32,
+    pub top_left_y: f32,
+    pub physical_height: f32,
+    pub physical_width: f32,
+    pub window_height: u32,
+    pub window_width: u32,
+    pub avatar: u8,
+    pub nickname: String,
+}
+
+impl Layout {
+    pub fn of(p: &player::Player) -> Self {
+        Layout {
+            rank: p.rank,
+            top_left_x: p.top_left_x,
+            top_left_y: p.top_left_y,
+            physical_height: p.physical_height,
+            physical_width: p.physical_width,
+            window_height: p.window_height,
+            window_width: p.window_width,
+            avatar: p.avatar,
+            nickname: p.nickname.clone(),
+        }
+    }
+
+    fn pack(&self, data: &mut Vec<u8>) {
+        data.push(self.rank);
+        data.extend_from_slice(&self.top_left_x.to_be_bytes());
+        data.extend_from_slice(&self.top_left_y.to_be_bytes());
+        data.extend_from_slice(&self.physical_height.to_be_bytes());
+        data.extend_from_slice(&self.physical_width.to_be_bytes());
+        data.extend_from_slice(&self.window_height.to_be_bytes());
+        data.extend_from_slice(&self.window_width.to_be_bytes());
+        data.push(self.avatar);
+        data.push(self.nickname.len() as u8);
+        data.extend_from_slice(self.nickname.as_bytes());
+    }
+
+    fn unpack(reader: &mut Reader) -> Result<Self, Error> {
+        Ok(Layout {
+            rank: reader.u8()?,
+            top_left_x: f32::from_be_bytes(reader.array()?),
+            top_left_y: f32::from_be_bytes(reader.array()?),
+            physical_height: f32::from_be_bytes(reader.array()?),
+            physical_width: f32::from_be_bytes(reader.array()?),
+            window_height: u32::from_be_bytes(reader.array()?),
+            window_width: u32::from_be_bytes(reader.array()?),
+            avatar: reader.u8()?,
+            nickname: {
+                let size = reader.u8()? as usize;
+                String::from_utf8_lossy(reader.bytes(size)?).into_owned()
+            },
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub game_id: u16,
+    pub seed: u64,
+    pub settings: Settings,
+    pub layouts: Vec<Layout>,
+}
+
+impl Header {
+    fn pack(&self) -> Vec<u8> {
+        let mut data = vec::Vec::new();
+        data.extend_from_slice(MAGIC);
+        data.push(VERSION);
+        data.extend_from_slice(&self.game_id.to_be_bytes());
+        data.extend_from_slice(&self.seed.to_be_bytes());
+        data.extend_from_slice(&self.settings.pack());
+        data.push(self.layouts.len() as u8);
+        for l in self.layouts.iter() {
+            l.pack(&mut data);
+        }
+        data
+    }
+
+    fn unpack(reader: &mut Reader) -> Result<Self, Error> {
+        if reader.bytes(4)? != MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not a replay file"));
+        }
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported replay version {version}"),
+            ));
+        }
+        let game_id = u16::from_be_bytes(reader.array()?);
+        let seed = u64::from_be_bytes(reader.array()?);
+        let mut values = vec::Vec::new();
+        for _ in 0..reader.u8()? {
+            values.push(u16::from_be_bytes(reader.array()?));
+        }
+        let settings = Settings::from_values(game::settings(game_id.into()), values)?;
+        let count = reader.u8()?;
+        let mut layouts = vec::Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            layouts.push(Layout::unpack(reader)?);
+        }
+        Ok(Header {
+            game_id,
+            seed,
+            settings,
+            layouts,
+        })
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Events
+///
+///
+//////////////////////////////////////////////
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// Reading of the clock of the game
+    Now(time::Duration),
+    /// Data received from a player, empty if there was nothing to receive
+    Recv(u8, Vec<u8>),
+    /// Data sent to a player
+    Send(u8, Vec<u8>),
+    /// A player was found disconnected while receiving
+    Disconnected(u8),
+    /// The message sent just before to the player could not be delivered
+    Lost(u8),
+    /// A player joined the running game
+    Joined(Layout),
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Recorder
+///
+///
+//////////////////////////////////////////////
+
+/// Write the events of a game as they happen
+pub struct Recorder {
+    out: Box<dyn Write + Send>,
+    last_now: time::Duration,
+    failed: bool,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, header: &Header) -> Result<Self, Error> {
+        Recorder::new(Box::new(BufWriter::new(fs::File::create(path)?)), header)
+    }
+
+    pub fn new(mut out: Box<dyn Write + Send>, header: &Header) -> Result<Self, Error> {
+        out.write_all(&header.pack())?;
+        Ok(Recorder {
+            out,
+            last_now: time::Duration::ZERO,
+            failed: false,
+        })
+    }
+
+    /// Record an event, the game goes on even if the recording fails
+    pub fn record(&mut self, event: &Event) {
+        let mut data = vec::Vec::new();
+        match event {
+            Event::Now(now) => {
+                data.push(TAG_NOW);
+                let delta = now.saturating_sub(self.last_now);
+                push_varint(&mut data, delta.as_nanos() as u64);
+                self.last_now = *now;
+            }
+            Event::Recv(player, payload) | Event::Send(player, payload) => {
+                data.push(match event {
+                    Event::Recv(..) => TAG_RECV,
+                    _ => TAG_SEND,
+                });
+                data.push(*player);
+                push_varint(&mut data, payload.len() as u64);
+                data.extend_from_slice(payload);
+            }
+            Event::Disconnected(player) => {
+                data.push(TAG_DISCONNECTED);
+                data.push(*player);
+            }
+            Event::Lost(player) => {
+                data.push(TAG_LOST);
+                data.push(*player);
+            }
+            Event::Joined(layout) => {
+                data.push(TAG_JOINED);
+                layout.pack(&mut data);
+            }
+        }
+        if let Err(e) = self.out.write_all(&data) {
+            if !self.failed {
+                warn!(target: "Replay", "recording stopped : {e}");
+            }
+            self.failed = true;
+        }
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.out.flush()
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Replay
+///
+///
+//////////////////////////////////////////////
+
+/// Recorded game, fed back event by event to a game running offline
+pub struct Replay {
+    pub header: Header,
+    events: Vec<Event>,
+    cursor: usize,
+    // reason why the game took a different path than the recorded one
+    diverged: Option<String>,
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        Replay::parse(&fs::read(path)?)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let mut reader = Reader { data, index: 0 };
+        let header = Header::unpack(&mut reader)?;
+
+        let mut events = vec::Vec::new();
+        let mut now = time::Duration::ZERO;
+        while !reader.is_empty() {
+            let event = match reader.u8()? {
+                TAG_NOW => {
+                    now += time::Duration::from_nanos(reader.varint()?);
+                    Event::Now(now)
+                }
+                tag @ (TAG_RECV | TAG_SEND) => {
+                    let player = reader.u8()?;
+                    let size = reader.varint()? as usize;
+                    let payload = reader.bytes(size)?.to_vec();
+                    if tag == TAG_RECV {
+                        Event::Recv(player, payload)
+                    } else {
+                        Event::Send(player, payload)
+                    }
+                }
+                TAG_DISCONNECTED => Event::Disconnected(reader.u8()?),
+                TAG_LOST => Event::Lost(reader.u8()?),
+                TAG_JOINED => Event::Joined(Layout::unpack(&mut reader)?),
+                tag => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("unknown event {tag}"),
+                    ))
+                }
+            };
+            events.push(event);
+        }
+
+        Ok(Replay {
+            header,
+            events,
+            cursor: 0,
+            diverged: None,
+        })
+    }
+
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// Check that the game went through all the recorded events, return how many there were
+    pub fn finish(&self) -> Result<usize, Error> {
+        match &self.diverged {
+            Some(reason) => Err(Error::new(ErrorKind::InvalidData, reason.clone())),
+            None if self.cursor < self.events.len() => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "the game stopped after {} of the {} events",
+                    self.cursor,
+                    self.events.len()
+                ),
+            )),
+            None => Ok(self.events.len()),
+        }
+    }
+
+    /// Next event, which has to be the one the game is asking for
+    fn next(&mut self, expected: &str) -> Result<&Event, Error> {
+        if let Some(reason) = &self.diverged {
+            return Err(Error::new(ErrorKind::InvalidData, reason.clone()));
+        }
+        match self.events.get(self.cursor) {
+            Some(_) => {
+                self.cursor += 1;
+                Ok(&self.events[self.cursor - 1])
+            }
+            None => Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("end of the replay, the game asked for {expected}"),
+            )),
+        }
+    }
+
+    fn diverge(&mut self, expected: &str) -> Error {
+        let reason = format!(
+            "the game diverged at event {} : it asked for {expected} but {:?} was recorded",
+            self.cursor - 1,
+            self.events[self.cursor - 1]
+        );
+        self.diverged = Some(reason.clone());
+        Error::new(ErrorKind::InvalidData, reason)
+    }
+
+    fn recv(&mut self, index: u8) -> Result<Vec<u8>, Error> {
+        let expected = format!("a message of player {index}");
+        match self.next(&expected)? {
+            Event::Recv(player, data) if *player == index => Ok(data.clone()),
+            Event::Disconnected(player) if *player == index => {
+                Err(Error::new(ErrorKind::NotConnected, "client not connected"))
+            }
+            _ => Err(self.diverge(&expected)),
+        }
+    }
+
+    fn send(&mut self, index: u8, data: &[u8]) -> Result<(), Error> {
+        let expected = format!("to send {} bytes to player {index}", data.len());
+        match self.next(&expected)? {
+            Event::Send(player, recorded) if *player == index && recorded == data => {}
+            _ => return Err(self.diverge(&expected)),
+        }
+        if self.events.get(self.cursor) == Some(&Event::Lost(index)) {
+            self.cursor += 1;
+            return Err(Error::new(ErrorKind::NotConnected, "client not connected"));
+        }
+        Ok(())
+    }
+
+    fn now(&mut self) -> Result<time::Duration, Error> {
+        match self.next("the time")? {
+            Event::Now(now) => Ok(*now),
+            _ => Err(self.diverge("the time")),
+        }
+    }
+}
+
+/// Players of the recorded game, reading from and writing to the replay instead of a client
+pub fn players(replay: &Arc<Mutex<Replay>>) -> Vec<player::Player> {
+    let layouts = replay.lock().unwrap().header.layouts.clone();
+    layouts.iter().map(|l| replay_player(replay, l)).collect()
+}
+
+fn replay_player(replay: &Arc<Mutex<Replay>>, l: &Layout) -> player::Player {
+    // the channels are never used, the tape takes their place
+    let (sender, _) = mpsc::channel();
+    let (_, receiver) = mpsc::channel();
+    player::Player {
+        sender,
+        receiver,
+        rank: l.rank,
+        session: 0,
+        nickname: l.nickname.clone(),
+        identity: String::new(),
+        avatar: l.avatar,
+        ready: true,
+        top_left_x: l.top_left_x,
+        top_left_y: l.top_left_y,
+        physical_height: l.physical_height,
+        physical_width: l.physical_width,
+        window_height: l.window_height,
+        window_width: l.window_width,
+        tape: Some(Tape::Replay(replay.clone())),
+    }
+}
+
+/// Lobby of the recorded game, the players join when the replay reaches them
+pub struct ReplayLobby(Arc<Mutex<Replay>>);
+
+impl ReplayLobby {
+    pub fn new(replay: Arc<Mutex<Replay>>) -> Self {
+        ReplayLobby(replay)
+    }
+}
+
+impl Lobby for ReplayLobby {
+    fn admit(&mut self, players: &mut Vec<player::Player>) -> Vec<u8> {
+        let mut joined = vec::Vec::new();
+        loop {
+            let layout = {
+                let mut guard = self.0.lock().unwrap();
+                let replay = &mut *guard;
+                match replay.events.get(replay.cursor) {
+                    Some(Event::Joined(layout)) if replay.diverged.is_none() => {
+                        replay.cursor += 1;
+                        layout.clone()
+                    }
+                    _ => return joined,
+                }
+            };
+            joined.push(layout.rank);
+            players.push(replay_player(&self.0, &layout));
+        }
+    }
+
+    fn reflowed(&mut self, _players: &[player::Player]) {}
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Tape
+///
+///
+//////////////////////////////////////////////
+
+/// Attached to a player, the messages it sends and receives go through the tape
+#[derive(Clone)]
+pub enum Tape {
+    Record(Arc<Mutex<Recorder>>),
+    Replay(Arc<Mutex<Replay>>),
+}
+
+impl Tape {
+    /// Return Some if the message has to be taken from the replay instead of the client
+    pub fn recv(&self, index: u8) -> Option<Result<Vec<u8>, Error>> {
+        match self {
+            Tape::Record(_) => None,
+            Tape::Replay(replay) => Some(replay.lock().unwrap().recv(index)),
+        }
+    }
+
+    /// Record what was received from the client
+    pub fn received(&self, index: u8, result: Result<&[u8], &Error>) {
+        if let Tape::Record(recorder) = self {
+            recorder.lock().unwrap().record(&match result {
+                Ok(data) => Event::Recv(index, data.to_vec()),
+                Err(_) => Event::Disconnected(index),
+            });
+        }
+    }
+
+    /// Return Some if the message must be checked against the replay instead of sent to the client
+    pub fn send(&self, index: u8, data: &[u8]) -> Option<Result<(), Error>> {
+        match self {
+            Tape::Record(_) => None,
+            Tape::Replay(replay) => Some(replay.lock().unwrap().send(index, data)),
+        }
+    }
+
+    /// Record what was sent to the client
+    pub fn sent(&self, index: u8, data: &[u8], result: Result<(), &Error>) {
+        if let Tape::Record(recorder) = self {
+            let mut recorder = recorder.lock().unwrap();
+            recorder.record(&Event::Send(index, data.to_vec()));
+            if result.is_err() {
+                recorder.record(&Event::Lost(index));
+            }
+        }
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Clocks
+///
+///
+//////////////////////////////////////////////
+
+/// Clock recording each of its readings
+pub struct RecordingClock {
+    inner: Box<dyn Clock>,
+    recorder: Arc<Mutex<Recorder>>,
+}
+
+impl RecordingClock {
+    pub fn new(inner: Box<dyn Clock>, recorder: Arc<Mutex<Recorder>>) -> Self {
+        RecordingClock { inner, recorder }
+    }
+}
+
+impl Clock for RecordingClock {
+    fn now(&self) -> time::Duration {
+        let now = self.inner.now();
+        self.recorder.lock().unwrap().record(&Event::Now(now));
+        now
+    }
+
+    fn sleep(&mut self, duration: time::Duration) {
+        self.inner.sleep(duration);
+    }
+}
+
+/// Clock giving back the recorded readings, sleeping returns at once
+pub struct ReplayClock {
+    replay: Arc<Mutex<Replay>>,
+    last: Mutex<time::Duration>,
+}
+
+impl ReplayClock {
+    pub fn new(replay: Arc<Mutex<Replay>>) -> Self {
+        ReplayClock {
+            replay,
+            last: Mutex::new(time::Duration::ZERO),
+        }
+    }
+}
+
+impl Clock for ReplayClock {
+    fn now(&self) -> time::Duration {
+        // once the replay is over or diverged, time stops and the players report the error
+        let mut last = self.last.lock().unwrap();
+        if let Ok(now) = self.replay.lock().unwrap().now() {
+            *last = now;
+        }
+        *last
+    }
+
+    fn sleep(&mut self, _duration: time::Duration) {}
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Helpers
+///
+///
+//////////////////////////////////////////////
+
+fn push_varint(data: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        data.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    index: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.index >= self.data.len()
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        if self.index + n > self.data.len() {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "truncated replay"));
+        }
+        let res = &self.data[self.index..self.index + n];
+        self.index += n;
+        Ok(res)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let mut res = [0_u8; N];
+        res.copy_from_slice(self.bytes(N)?);
+        Ok(res)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn varint(&mut self) -> Result<u64, Error> {
+        let mut value = 0_u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::new(ErrorKind::InvalidData, "varint too long"))
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Tests
+///
+///
+//////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{self, context};
+    use crate::network::client;
+    use std::thread;
+
+    /// Writer shared with the test, the recorder owns the other end
+    #[derive(Clone)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_record_and_parse() {
+        let header = Header {
+            game_id: 3,
+            seed: 42,
+            settings: Settings::from_values(game::maze_fight::SETTINGS, vec![3, 250, 5, 300, 0])
+                .unwrap(),
+            layouts: vec![Layout {
+                rank: 0,
+                top_left_x: 0.,
+                top_left_y: 10.,
+                physical_height: 100.,
+                physical_width: 50.,
+                window_height: 1000,
+                window_width: 500,
+                avatar: 3,
+                nickname: "Zoé".to_string(),
+            }],
+        };
+        let events = [
+            Event::Now(time::Duration::from_millis(16)),
+            Event::Recv(0, vec![]),
+            Event::Recv(0, vec![1, 2, 3]),
+            Event::Now(time::Duration::from_secs(1000)),
+            Event::Send(0, vec![4; 300]),
+            Event::Disconnected(0),
+            Event::Send(0, vec![5]),
+            Event::Lost(0),
+            Event::Joined(Layout {
+                rank: 1,
+                top_left_x: 0.,
+                top_left_y: 0.,
+                physical_height: 120.,
+                physical_width: 60.,
+                window_height: 1200,
+                window_width: 600,
+                avatar: 0,
+                nickname: "Late".to_string(),
+            }),
+        ];
+
+        let out = Shared(Arc::new(Mutex::new(vec::Vec::new())));
+        let mut recorder = Recorder::new(Box::new(out.clone()), &header).unwrap();
+        for e in events.iter() {
+            recorder.record(e);
+        }
+
+        let data = out.0.lock().unwrap().clone();
+        let replay = Replay::parse(&data).unwrap();
+        assert_eq!(replay.header, header);
+        assert_eq!(replay.events(), &events[..]);
+
+        assert!(Replay::parse(&data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_divergence() {
+        let mut replay = Replay {
+            header: Header {
+                game_id: 0,
+                seed: 0,
+                settings: Settings::default(),
+                layouts: vec![],
+            },
+            events: vec![Event::Recv(0, vec![1]), Event::Send(1, vec![2])],
+            cursor: 0,
+            diverged: None,
+        };
+        assert_eq!(replay.recv(0).unwrap(), vec![1]);
+        assert!(replay.send(1, &[3]).is_err());
+        assert!(replay.finish().is_err());
+    }
+
+    #[test]
+    fn test_replay_game() {
+        let header = Header {
+            game_id: client::Game::MazeFight.into(),
+            seed: 7,
+            settings: Settings::defaults(game::maze_fight::SETTINGS),
+            layouts: (0..2)
+                .map(|i| Layout {
+                    rank: i,
+                    top_left_x: i as f32 * 1000.,
+                    top_left_y: 0.,
+                    physical_height: 1600.,
+                    physical_width: 1000.,
+                    window_height: 1600,
+                    window_width: 1000,
+                    avatar: i,
+                    nickname: format!("Player {i}"),
+                })
+                .collect(),
+        };
+        let out = Shared(Arc::new(Mutex::new(vec::Vec::new())));
+        let recorder = Arc::new(Mutex::new(
+            Recorder::new(Box::new(out.clone()), &header).unwrap(),
+        ));
+
+        // the phones never answer and the first one leaves after 100 messages, the second one
+        // then wins
+        let mut inputs = vec::Vec::new();
+        let mut players: Vec<player::Player> = header
+            .layouts
+            .iter()
+            .map(|l| {
+                let (sender, output) = mpsc::channel();
+                let (input, receiver) = mpsc::channel();
+                inputs.push(input);
+                let count = if l.rank == 0 { 100 } else { usize::MAX };
+                thread::spawn(move || output.iter().take(count).count());
+                player::Player {
+                    sender,
+                    receiver,
+                    rank: l.rank,
+                    session: 0,
+                    nickname: l.nickname.clone(),
+                    identity: String::new(),
+                    avatar: l.avatar,
+                    ready: true,
+                    top_left_x: l.top_left_x,
+                    top_left_y: l.top_left_y,
+                    physical_height: l.physical_height,
+                    physical_width: l.physical_width,
+                    window_height: l.window_height,
+                    window_width: l.window_width,
+                    tape: Some(Tape::Record(recorder.clone())),
+                }
+            })
+            .collect();
+        let clock = RecordingClock::new(Box::new(context::ManualClock::new()), recorder.clone());
+        let mut ctx = context::GameContext::new(header.seed, Box::new(clock));
+        let placements = game::play(
+            client::Game::MazeFight,
+            &header.settings,
+            &mut players,
+            &mut ctx,
+        )
+        .unwrap();
+        assert_eq!(placements[0].rank, 1);
+        assert_eq!(players.len(), 1);
+
+        let replay = Arc::new(Mutex::new(Replay::parse(&out.0.lock().unwrap()).unwrap()));
+        let mut players = super::players(&replay);
+        let mut ctx =
+            context::GameContext::new(header.seed, Box::new(ReplayClock::new(replay.clone())))
+                .with_lobby(Box::new(ReplayLobby::new(replay.clone())));
+        let replayed = game::play(
+            client::Game::MazeFight,
+            &header.settings,
+            &mut players,
+            &mut ctx,
+        )
+        .unwrap();
+        assert_eq!(replayed, placements);
+        assert!(replay.lock().unwrap().finish().is_ok());
+
+        // another seed takes another path
+        replay.lock().unwrap().cursor = 0;
+        let mut players = super::players(&replay);
+        let mut ctx = context::GameContext::new(8, Box::new(ReplayClock::new(replay.clone())));
+        assert!(game::play(
+            client::Game::MazeFight,
+            &header.settings,
+            &mut players,
+            &mut ctx
+        )
+        .is_err());
+        assert!(replay.lock().unwrap().finish().is_err());
+    }
+}
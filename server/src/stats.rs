@@ -0,0 +1,287 @@
+use crate::game::Placement;
+use crate::network::client::Game;
+use crate::network::packet;
+use log::warn;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::path::Path;
+use std::time;
+
+/// The statistics of the players are every placement they got, summed up per game and per
+/// nickname. They are kept in memory and appended to a file when the server has one, so that the
+/// leaderboards outlive the server. The players without a nickname are not counted.
+///
+/// The nickname is the only identity the handshake gives : the phones don't prove who they are, so
+/// anyone playing under the nickname of someone else adds to their statistics. The leaderboards
+/// are meant for friends sharing a server, not for a public ranking.
+///
+/// File format, one line per placement, the fields separated by tabs :
+///     game id | place | kills | best lap (in ms, - for none) | nickname
+/// The nickname comes last and has no control character, so it can't hide a tab or a new line.
+/// Each write ends with a new line, a last line without one was cut by a crash and is dropped.
+
+//////////////////////////////////////////////
+///
+///
+/// Record
+///
+///
+//////////////////////////////////////////////
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Record {
+    played: u32,
+    wins: u32,
+    kills: u32,
+    best_lap: Option<time::Duration>,
+}
+
+impl Record {
+    fn add(&mut self, placement: &Placement) {
+        self.played += 1;
+        self.wins += (placement.place == 1) as u32;
+        self.kills += placement.kills;
+        self.best_lap = match (self.best_lap, placement.best_lap) {
+            (Some(best), Some(lap)) => Some(best.min(lap)),
+            (best, lap) => best.or(lap),
+        };
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Stats
+///
+///
+//////////////////////////////////////////////
+
+#[derive(Default)]
+pub struct Stats {
+    // records per game id and nickname
+    records: HashMap<(u16, String), Record>,
+    file: Option<fs::File>,
+}
+
+impl Stats {
+    /// Statistics kept in memory only, lost with the server
+    pub fn new() -> Self {
+        Stats::default()
+    }
+
+    /// Statistics of the file at `path`, created if needed, the next ones are appended to it
+    /// A malformed line fails, but for a last line cut while it was written which is removed
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let mut stats = Stats::new();
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+
+        let complete = content
+            .iter()
+            .rposition(|b| *b == b'\n')
+            .map_or(0, |i| i + 1);
+        if complete < content.len() {
+            warn!(target: "Stats", "{} : dropping the last line, cut while it was written", path.display());
+            file.set_len(complete as u64)?;
+        }
+        let content = std::str::from_utf8(&content[..complete])
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{} : {e}", path.display())))?;
+        for (i, line) in content.lines().enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let (game, nickname, placement) = parse_line(line).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{} : malformed line {}", path.display(), i + 1),
+                )
+            })?;
+            stats
+                .records
+                .entry((game, nickname))
+                .or_default()
+                .add(&placement);
+        }
+        stats.file = Some(file);
+        Ok(stats)
+    }
+
+    /// Count the placements of a game, with the nickname of each player
+    /// The placements are counted even if they can't be saved
+    pub fn record(&mut self, game: Game, placements: &[(String, Placement)]) -> Result<(), Error> {
+        let mut lines = String::new();
+        for (nickname, placement) in placements.iter().filter(|(n, _)| !n.is_empty()) {
+            self.records
+                .entry((game.into(), nickname.clone()))
+                .or_default()
+                .add(placement);
+            let lap = placement
+                .best_lap
+                .map_or("-".to_string(), |l| l.as_millis().to_string());
+            lines.push_str(&format!(
+                "{}\t{}\t{}\t{lap}\t{nickname}\n",
+                u16::from(game),
+                placement.place,
+                placement.kills
+            ));
+        }
+        match &mut self.file {
+            Some(file) if !lines.is_empty() => file.write_all(lines.as_bytes()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Best players of `game`, by wins, then best lap, then kills
+    pub fn leaderboard(&self, game: Game) -> Vec<packet::Standing> {
+        let game: u16 = game.into();
+        let mut standings: Vec<packet::Standing> = self
+            .records
+            .iter()
+            .filter(|((g, _), _)| *g == game)
+            .map(|((_, nickname), r)| packet::Standing {
+                nickname: nickname.clone(),
+                played: r.played,
+                wins: r.wins,
+                kills: r.kills,
+                best_lap: r.best_lap,
+            })
+            .collect();
+        standings.sort_by(|a, b| {
+            b.wins
+                .cmp(&a.wins)
+                .then_with(|| match (a.best_lap, b.best_lap) {
+                    (Some(a), Some(b)) => a.cmp(&b),
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (None, None) => Ordering::Equal,
+                })
+                .then_with(|| b.kills.cmp(&a.kills))
+                .then_with(|| a.nickname.cmp(&b.nickname))
+        });
+        standings.truncate(packet::LEADERBOARD_SIZE);
+        standings
+    }
+}
+
+/// Game, nickname and placement of a line of the file, the rank of the player isn't kept
+fn parse_line(line: &str) -> Option<(u16, String, Placement)> {
+    let mut fields = line.splitn(5, '\t');
+    let game = fields.next()?.parse().ok()?;
+    let place = fields.next()?.parse().ok()?;
+    let kills = fields.next()?.parse().ok()?;
+    let best_lap = match fields.next()? {
+        "-" => None,
+        ms => Some(time::Duration::from_millis(ms.parse().ok()?)),
+    };
+    let nickname = fields.next().filter(|n| !n.is_empty())?.to_string();
+    let placement = Placement {
+        rank: 0,
+        place,
+        kills,
+        best_lap,
+    };
+    Some((game, nickname, placement))
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Tests
+///
+///
+//////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn placement(place: u8, kills: u32, best_lap: Option<u64>) -> Placement {
+        Placement {
+            rank: 0,
+            place,
+            kills,
+            best_lap: best_lap.map(time::Duration::from_millis),
+        }
+    }
+
+    #[test]
+    fn test_stats_outlive_the_server() {
+        let path = std::env::temp_dir().join(format!("phonetile-stats-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut stats = Stats::open(&path).unwrap();
+        let fight = [
+            ("Alice".to_string(), placement(1, 3, None)),
+            ("Bob".to_string(), placement(2, 1, None)),
+            (String::new(), placement(3, 0, None)),
+        ];
+        stats.record(Game::MazeFight, &fight).unwrap();
+        let rematch = [
+            ("Bob".to_string(), placement(1, 2, None)),
+            ("Alice".to_string(), placement(2, 2, None)),
+        ];
+        stats.record(Game::MazeFight, &rematch).unwrap();
+        stats
+            .record(
+                Game::Racer,
+                &[("Bob".to_string(), placement(1, 0, Some(41_250)))],
+            )
+            .unwrap();
+
+        let expected = stats.leaderboard(Game::MazeFight);
+        assert_eq!(
+            expected,
+            vec![
+                packet::Standing {
+                    nickname: "Alice".to_string(),
+                    played: 2,
+                    wins: 1,
+                    kills: 5,
+                    best_lap: None,
+                },
+                packet::Standing {
+                    nickname: "Bob".to_string(),
+                    played: 2,
+                    wins: 1,
+                    kills: 3,
+                    best_lap: None,
+                },
+            ]
+        );
+        drop(stats);
+
+        let stats = Stats::open(&path).unwrap();
+        assert_eq!(stats.leaderboard(Game::MazeFight), expected);
+        let racer = stats.leaderboard(Game::Racer);
+        assert_eq!(racer.len(), 1);
+        assert_eq!(racer[0].best_lap, Some(time::Duration::from_millis(41_250)));
+        assert!(stats.leaderboard(Game::Snake).is_empty());
+
+        // a crash while writing the last line only loses that line
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"3\t1\t2\t").unwrap();
+        drop(file);
+        let mut stats = Stats::open(&path).unwrap();
+        assert_eq!(stats.leaderboard(Game::MazeFight), expected);
+        stats
+            .record(
+                Game::MazeFight,
+                &[("Bob".to_string(), placement(1, 0, None))],
+            )
+            .unwrap();
+        drop(stats);
+        let stats = Stats::open(&path).unwrap();
+        assert_eq!(stats.leaderboard(Game::MazeFight)[0].nickname, "Bob");
+
+        fs::write(&path, "3\t1\tthree\t-\tAlice\n").unwrap();
+        assert!(Stats::open(&path).is_err());
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,237 @@
+// The sections of the modules are delimited by doc banners followed by an empty line
+#![allow(clippy::empty_line_after_doc_comments)]
+#![allow(unused)]
+
+use server::network::{admin, client, limits, packet, Server};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Harness of the integration tests : each test runs its own server on a port picked by the
+/// system, and every wait is bounded so that a broken server fails the test instead of hanging
+
+//////////////////////////////////////////////
+///
+///
+/// Constants
+///
+///
+//////////////////////////////////////////////
+
+/// Longest time any step of a test may wait for the server
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Countdown of the test servers, short so that the launches don't slow the tests down
+const COUNTDOWN: Duration = Duration::from_millis(100);
+
+/// Screen given to the test phones
+const PHYSICAL_HEIGHT: f32 = 1547.;
+const PHYSICAL_WIDTH: f32 = 757.;
+const WINDOW_HEIGHT: u32 = 2400;
+const WINDOW_WIDTH: u32 = 1080;
+
+//////////////////////////////////////////////
+///
+///
+/// Test server
+///
+///
+//////////////////////////////////////////////
+
+/// Server running in a thread of the test, stopped when dropped
+pub struct TestServer {
+    pub address: SocketAddr,
+    pub admin: admin::Admin,
+    shutdown: Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<std::io::Result<()>>>,
+}
+
+impl TestServer {
+    pub fn start() -> Self {
+        TestServer::start_with(limits::Limits::default())
+    }
+
+    /// Server applying `limits` to its sessions
+    pub fn start_with(limits: limits::Limits) -> Self {
+        let mut server = Server::new();
+        server.set_limits(limits);
+        TestServer::serve(server)
+    }
+
+    /// Server keeping the statistics of the players in the file at `path`
+    pub fn start_keeping_stats(path: &Path) -> Self {
+        let mut server = Server::new();
+        server
+            .keep_stats(path)
+            .expect("couldn't open the statistics");
+        TestServer::serve(server)
+    }
+
+    fn serve(mut server: Server) -> Self {
+        server.set_countdown(COUNTDOWN);
+        let address = server
+            .bind("127.0.0.1:0")
+            .expect("couldn't bind the test server");
+        let shutdown = server.shutdown_flag();
+        let admin = server.admin();
+        let handle = thread::spawn(move || server.launch_server());
+        TestServer {
+            address,
+            admin,
+            shutdown,
+            handle: Some(handle),
+        }
+    }
+
+    /// Connect a new phone
+    pub fn connect(&self) -> client::Network {
+        client::Network::connect(
+            &self.address,
+            PHYSICAL_HEIGHT,
+            PHYSICAL_WIDTH,
+            WINDOW_HEIGHT,
+            WINDOW_WIDTH,
+        )
+        .expect("couldn't connect to the test server")
+    }
+
+    /// Connect a new phone with a nickname and a preferred avatar
+    pub fn connect_as(&self, nickname: &str, avatar: Option<u8>) -> client::Network {
+        client::Network::connect_as(
+            &self.address,
+            nickname,
+            avatar,
+            PHYSICAL_HEIGHT,
+            PHYSICAL_WIDTH,
+            WINDOW_HEIGHT,
+            WINDOW_WIDTH,
+        )
+        .expect("couldn't connect to the test server")
+    }
+
+    /// Create a room with `clients` phones, the first one being the master
+    pub fn room(&self, clients: usize) -> (u16, Vec<client::Network>) {
+        self.room_as(&vec![""; clients])
+    }
+
+    /// Create a room with a phone per nickname, the first one being the master
+    pub fn room_as(&self, nicknames: &[&str]) -> (u16, Vec<client::Network>) {
+        let mut master = self.connect_as(nicknames[0], None);
+        let code = master.create_room().expect("couldn't create a room");
+        let room = master.room_token();
+        let mut phones = vec![master];
+        for nickname in nicknames[1..].iter() {
+            let mut phone = self.connect_as(nickname, None);
+            phone.join_room(code).expect("couldn't join the room");
+            phones.push(phone);
+        }
+        (room, phones)
+    }
+
+    /// Create a room with `clients` phones and lock it for `game`, return the ranks of the phones
+    pub fn lock(&self, clients: usize, game: client::Game) -> (Vec<u8>, Vec<client::Network>) {
+        let (_, mut phones) = self.room(clients);
+        let ranks = lock(&mut phones, game);
+        (ranks, phones)
+    }
+
+    /// Create a room with `clients` phones and launch `game`, the phones are sorted by rank
+    pub fn launch(&self, clients: usize, game: client::Game) -> Vec<client::Network> {
+        let (_, phones) = self.room(clients);
+        launch(phones, game)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+//////////////////////////////////////////////
+///
+///
+/// Helpers
+///
+///
+//////////////////////////////////////////////
+
+/// Poll `f` until it returns Some, panic with `what` after `TIMEOUT`
+pub fn wait_for<T>(what: &str, mut f: impl FnMut() -> Option<T>) -> T {
+    let start = Instant::now();
+    loop {
+        if let Some(res) = f() {
+            return res;
+        }
+        if start.elapsed() > TIMEOUT {
+            panic!("timed out waiting for {what}");
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+/// Lock the room of `phones` for `game`, the first one being the master, return their ranks
+pub fn lock(phones: &mut [client::Network], game: client::Game) -> Vec<u8> {
+    phones[0].lock_room(game).expect("couldn't lock the room");
+    let mut ranks = Vec::new();
+    for phone in phones.iter_mut() {
+        match phone.wait_for_status(TIMEOUT, |s| matches!(s, client::Status::InLockRoom(_))) {
+            Ok(client::Status::InLockRoom(rank)) => ranks.push(rank),
+            _ => panic!("the room was not locked"),
+        }
+    }
+    ranks
+}
+
+/// Launch `game` in the room of `phones`, the first one being the master, and sort them by rank
+pub fn launch(mut phones: Vec<client::Network>, game: client::Game) -> Vec<client::Network> {
+    let clients = phones.len();
+    let ranks = lock(&mut phones, game);
+
+    for phone in phones.iter_mut().skip(1) {
+        phone.set_ready(true).expect("couldn't get ready");
+    }
+    wait_for("every phone to be ready", || {
+        phones[0].get_status();
+        let roster = phones[0].roster();
+        (roster.len() == clients && roster[1..].iter().all(|m| m.ready)).then_some(())
+    });
+    phones[0].launch_game().expect("couldn't launch the game");
+    for phone in phones.iter_mut() {
+        phone
+            .wait_for_status(TIMEOUT, |s| matches!(s, client::Status::InGame(_)))
+            .expect("the game was not launched");
+    }
+
+    let mut ranked: Vec<(u8, client::Network)> = ranks.into_iter().zip(phones).collect();
+    ranked.sort_by_key(|(rank, _)| *rank);
+    ranked.into_iter().map(|(_, phone)| phone).collect()
+}
+
+/// Wait for the game of `phone` to end, return its place
+pub fn wait_for_end(phone: &mut client::Network) -> Option<u8> {
+    let mut buffer = [0_u8; packet::MAX_DATA_SIZE];
+    wait_for("the end of the game", || {
+        phone.recv(&mut buffer);
+        matches!(phone.get_status(), client::Status::InRoom).then_some(())
+    });
+    phone.last_place()
+}
+
+/// Wait for the next message of the game on `phone`
+pub fn recv(phone: &mut client::Network) -> Vec<u8> {
+    let mut buffer = [0_u8; packet::MAX_DATA_SIZE];
+    let size = wait_for("a message from the game", || {
+        match phone.recv(&mut buffer) {
+            0 => None,
+            n => Some(n),
+        }
+    });
+    buffer[..size].to_vec()
+}
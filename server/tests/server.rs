@@ -0,0 +1,616 @@
+mod common;
+
+use common::TestServer;
+use server::game;
+use server::network::{client, limits, packet};
+use std::net::TcpStream;
+use std::time::Duration;
+
+#[test]
+fn test_rooms_get_their_own_token() {
+    let server = TestServer::start();
+    let (first, _phones) = server.room(2);
+    let (second, _) = server.room(1);
+    assert_eq!(first, 1);
+    assert_eq!(second, 2);
+}
+
+#[test]
+fn test_launch_and_transmit() {
+    let server = TestServer::start();
+    let mut phones = server.launch(2, client::Game::Test);
+
+    // the test game forwards the messages of the master to the other phone
+    phones[0].send(&[1, 2, 3]).unwrap();
+    let received = common::recv(&mut phones[1]);
+    assert_eq!(&received[..3], &[1, 2, 3]);
+}
+
+#[test]
+fn test_launch_maze_fight() {
+    let server = TestServer::start();
+    let mut phones = server.launch(3, client::Game::MazeFight);
+
+    // every phone gets its part of the maze first, the game has its default settings
+    let defaults = game::settings::Settings::defaults(game::settings(client::Game::MazeFight));
+    for phone in phones.iter_mut() {
+        assert!(!common::recv(phone).is_empty());
+        assert_eq!(phone.settings(), defaults.values());
+    }
+}
+
+#[test]
+fn test_late_join() {
+    let server = TestServer::start();
+    let mut master = server.connect();
+    let code = master.create_room().unwrap();
+    let mut phone = server.connect();
+    phone.join_room(code).unwrap();
+    let mut phones = common::launch(vec![master, phone], client::Game::MazeFight);
+    for phone in phones.iter_mut() {
+        common::recv(phone);
+        common::recv(phone);
+    }
+
+    // the late phone gets its rank, the maze and the three sprites, the others are told about
+    // its sprite and keep their part of the maze
+    let mut late = server.connect();
+    late.join_room(code).unwrap();
+    let status = late.wait_for_status(common::TIMEOUT, |s| {
+        matches!(s, client::Status::InLockRoom(_))
+    });
+    assert!(matches!(status, Ok(client::Status::InLockRoom(2))));
+    late.wait_for_status(common::TIMEOUT, |s| matches!(s, client::Status::InGame(_)))
+        .unwrap();
+    assert!(!common::recv(&mut late).is_empty());
+    assert_eq!(common::recv(&mut late)[0], 3);
+    for phone in phones.iter_mut() {
+        assert_eq!(next_update(phone)[..3], [0, 0, 1]);
+    }
+
+    // the screen of the late phone takes the place of the one leaving
+    drop(phones.remove(1));
+    assert_eq!(next_update(&mut late)[..3], [0, 0, 0]);
+    for _ in 0..10 {
+        assert_ne!(common::recv(&mut phones[0])[..2], [0, 0]);
+    }
+}
+
+/// Next update of the map sent to the phone, skipping the snapshots
+fn next_update(phone: &mut client::Network) -> Vec<u8> {
+    common::wait_for("an update of the map", || {
+        let message = common::recv(phone);
+        (message[..2] == [0, 0]).then_some(message)
+    })
+}
+
+#[test]
+fn test_pause() {
+    let server = TestServer::start();
+    let mut phones = server.launch(2, client::Game::MazeFight);
+    let mut buffer = [0_u8; packet::MAX_DATA_SIZE];
+
+    // a phone going to the background pauses the game for everyone, nothing is sent meanwhile
+    phones[1].pause_game(true).unwrap();
+    for phone in phones.iter_mut() {
+        common::wait_for("the pause", || {
+            phone.recv(&mut buffer);
+            phone.is_paused().then_some(())
+        });
+    }
+    std::thread::sleep(Duration::from_millis(200));
+    for phone in phones.iter_mut() {
+        assert_eq!(phone.recv(&mut buffer), 0);
+    }
+
+    // the master lets the game go on for everyone, after a countdown
+    phones[0].pause_game(false).unwrap();
+    for phone in phones.iter_mut() {
+        let seconds = common::wait_for("the countdown", || {
+            phone.recv(&mut buffer);
+            phone.countdown()
+        });
+        assert_eq!(seconds, 1);
+        common::wait_for("the game to go on", || {
+            phone.recv(&mut buffer);
+            (!phone.is_paused()).then_some(())
+        });
+        assert!(!common::recv(phone).is_empty());
+    }
+}
+
+#[test]
+fn test_pause_drops_inputs() {
+    let server = TestServer::start();
+    let mut phones = server.launch(2, client::Game::MazeFight);
+    let mut buffer = [0_u8; packet::MAX_DATA_SIZE];
+    for phone in phones.iter_mut() {
+        common::recv(phone);
+    }
+    let start = own_x(&mut phones[0]);
+
+    // the inputs sent while the game is paused don't move the sprite, even once it goes on
+    phones[1].pause_game(true).unwrap();
+    common::wait_for("the pause", || {
+        phones[0].recv(&mut buffer);
+        phones[0].is_paused().then_some(())
+    });
+    for seq in 1..=30_u16 {
+        let mut input = seq.to_be_bytes().to_vec();
+        input.extend_from_slice(&0.1_f32.to_be_bytes());
+        input.extend_from_slice(&6_f32.to_be_bytes());
+        input.extend_from_slice(&0_f32.to_be_bytes());
+        input.extend_from_slice(&0_u16.to_be_bytes());
+        phones[0].send(&input).unwrap();
+    }
+    phones[0].pause_game(false).unwrap();
+    common::wait_for("the game to go on", || {
+        phones[0].recv(&mut buffer);
+        (!phones[0].is_paused()).then_some(())
+    });
+    assert_eq!(own_x(&mut phones[0]), start);
+}
+
+/// Abscissa of the sprite of the master in the next snapshot it receives
+fn own_x(phone: &mut client::Network) -> f32 {
+    common::wait_for("a snapshot", || {
+        let message = common::recv(phone);
+        let (_, snapshot) = game::snapshot::Snapshot::decode(&message, None).ok()?;
+        let (_, entry) = snapshot.sections[0]
+            .entries
+            .iter()
+            .find(|(id, _)| *id == 0)?;
+        Some(f32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]))
+    })
+}
+
+#[test]
+fn test_game_catalogue() {
+    let server = TestServer::start();
+    let (_, mut phones) = server.room(1);
+
+    // the phones learn the games of the server with the handshake, the test game isn't offered
+    let catalogue = phones[0].catalogue().to_vec();
+    let names: Vec<&str> = catalogue.iter().map(|l| l.name.as_str()).collect();
+    assert_eq!(names, vec!["Racer", "Maze Fight"]);
+    let maze = &catalogue[1];
+    assert_eq!((maze.min_players, maze.max_players), (2, 9));
+    assert_eq!(
+        maze.settings.len(),
+        game::settings(client::Game::MazeFight).len()
+    );
+
+    // a room can't be locked for a game the server doesn't run, or with too few players
+    let refusal = |phone: &mut client::Network, game| {
+        phone.lock_room(game).unwrap();
+        common::wait_for("the refusal", || {
+            phone.get_status();
+            phone.messages().pop()
+        })
+    };
+    let text = refusal(&mut phones[0], client::Game::Snake);
+    assert!(text.contains("isn't played"), "{text}");
+    let text = refusal(&mut phones[0], client::Game::MazeFight);
+    assert!(text.contains("2 to 9 players, not 1"), "{text}");
+    assert!(matches!(phones[0].get_status(), client::Status::InRoom));
+}
+
+#[test]
+fn test_game_settings() {
+    let server = TestServer::start();
+    let (_, mut phones) = server.room(2);
+
+    // the master is told why its settings are refused, and the room stays open
+    phones[0]
+        .lock_room_with(client::Game::MazeFight, &[0, 200, 10, 400, 1])
+        .unwrap();
+    let messages = common::wait_for("the refusal", || {
+        phones[0].get_status();
+        let messages = phones[0].messages();
+        (!messages.is_empty()).then_some(messages)
+    });
+    assert!(messages[0].contains("Lives"), "{messages:?}");
+    assert!(matches!(phones[1].get_status(), client::Status::InRoom));
+
+    // the settings accepted are sent to every phone with the launch
+    let settings = [3, 250, 5, 300, 0];
+    phones[0]
+        .lock_room_with(client::Game::MazeFight, &settings)
+        .unwrap();
+    for phone in phones.iter_mut() {
+        phone
+            .wait_for_status(common::TIMEOUT, |s| {
+                matches!(s, client::Status::InLockRoom(_))
+            })
+            .unwrap();
+    }
+    phones[0].force_launch_game().unwrap();
+    for phone in phones.iter_mut() {
+        phone
+            .wait_for_status(common::TIMEOUT, |s| matches!(s, client::Status::InGame(_)))
+            .unwrap();
+        assert_eq!(phone.settings(), &settings);
+    }
+}
+
+#[test]
+fn test_admin_console() {
+    let server = TestServer::start();
+    let (room, mut phones) = server.room(2);
+
+    let sessions = server.admin.execute("sessions");
+    assert!(sessions.starts_with("2 session(s)"), "{sessions}");
+    let rooms = server.admin.execute("rooms");
+    assert!(rooms.contains(&format!("room {room} lobby")), "{rooms}");
+
+    assert_eq!(
+        server.admin.execute("broadcast hello"),
+        "sent to 2 client(s)"
+    );
+    let messages = common::wait_for("the broadcast", || {
+        phones[1].get_status();
+        let messages = phones[1].messages();
+        (!messages.is_empty()).then_some(messages)
+    });
+    assert_eq!(messages, vec!["hello".to_string()]);
+
+    assert!(server
+        .admin
+        .execute(&format!("close {room}"))
+        .contains("2 player(s) disconnected"));
+    common::wait_for("the room to close", || {
+        server
+            .admin
+            .execute("rooms")
+            .starts_with("0 room(s)")
+            .then_some(())
+    });
+    assert!(server.admin.execute("kick 99").starts_with("no session"));
+}
+
+#[test]
+fn test_handshake_timeout() {
+    let server = TestServer::start_with(limits::Limits {
+        handshake_timeout: Duration::from_millis(200),
+        ..limits::Limits::default()
+    });
+
+    // a client that never sends its handshake is dropped
+    let _silent = TcpStream::connect(server.address).unwrap();
+    common::wait_for("the silent client to be accepted", || {
+        server
+            .admin
+            .execute("sessions")
+            .starts_with("1 session(s)")
+            .then_some(())
+    });
+    common::wait_for("the silent client to be dropped", || {
+        server
+            .admin
+            .execute("sessions")
+            .starts_with("0 session(s)")
+            .then_some(())
+    });
+}
+
+#[test]
+fn test_flooding_client_is_disconnected() {
+    let server = TestServer::start_with(limits::Limits {
+        packets_per_second: 1.,
+        packet_burst: 5.,
+        warn_after: 2,
+        disconnect_after: 10,
+        ..limits::Limits::default()
+    });
+    let mut phones = server.launch(1, client::Game::Test);
+
+    for _ in 0..30 {
+        let _ = phones[0].send(&[0; 16]);
+    }
+    let messages = common::wait_for("the warning", || {
+        let mut buffer = [0_u8; packet::MAX_DATA_SIZE];
+        phones[0].recv(&mut buffer);
+        let messages = phones[0].messages();
+        (!messages.is_empty()).then_some(messages)
+    });
+    assert!(messages[0].contains("slow down"), "{messages:?}");
+    common::wait_for("the flooding client to be dropped", || {
+        server
+            .admin
+            .execute("sessions")
+            .starts_with("0 session(s)")
+            .then_some(())
+    });
+}
+
+#[test]
+fn test_flooding_lobby_is_disconnected() {
+    let server = TestServer::start_with(limits::Limits {
+        packets_per_second: 1.,
+        packet_burst: 5.,
+        warn_after: 2,
+        disconnect_after: 10,
+        ..limits::Limits::default()
+    });
+    let (_, mut phones) = server.room(2);
+
+    // the packets without data count as much as the others, before the room is locked too
+    for i in 0..30 {
+        let _ = phones[1].set_ready(i % 2 == 0);
+    }
+    common::wait_for("the flooding client to be dropped", || {
+        server
+            .admin
+            .execute("sessions")
+            .starts_with("1 session(s)")
+            .then_some(())
+    });
+}
+
+#[test]
+fn test_private_room() {
+    let server = TestServer::start();
+    let mut master = server.connect();
+    let code = master.create_private_room(1234).unwrap();
+    assert!((10_000_000..=99_999_999).contains(&code));
+
+    let mut phone = server.connect();
+    assert!(phone.join_room(code).is_err());
+    assert!(phone.join_private_room(code, 4321).is_err());
+    assert!(phone.join_private_room(code + 1, 1234).is_err());
+    phone.join_private_room(code, 1234).unwrap();
+    assert_eq!(phone.room_token(), master.room_token());
+}
+
+#[test]
+fn test_wrong_credentials() {
+    let server = TestServer::start_with(limits::Limits {
+        max_failed_joins: 2,
+        ..limits::Limits::default()
+    });
+
+    // a packet with the session of someone else is refused
+    let mut stream = TcpStream::connect(server.address).unwrap();
+    let screen = packet::Handshake {
+        physical_height: 150.,
+        physical_width: 70.,
+        window_height: 2400,
+        window_width: 1080,
+        nickname: String::new(),
+        avatar: None,
+    };
+    packet::Packet::new(packet::Flag::Init, 0, 0, 0, &screen.pack(), 0)
+        .unwrap()
+        .send_packet(&mut stream)
+        .unwrap();
+    let init = packet::Packet::recv_packet(&mut stream).unwrap();
+    assert_ne!(init.secret, 0);
+    packet::Packet::control(packet::Flag::Create, init.session, 0, 0)
+        .with_secret(init.secret.wrapping_add(1))
+        .send_packet(&mut stream)
+        .unwrap();
+    let refused = packet::Packet::recv_packet(&mut stream).unwrap();
+    assert_eq!(
+        refused.get_flag(),
+        packet::Flag::Error(packet::ProtocolError::Unauthorized)
+    );
+
+    // so is a client guessing room codes
+    let mut phone = server.connect();
+    assert!(phone.join_room(1).is_err());
+    assert!(phone.join_room(2).is_err());
+    common::wait_for("the guessing client to be dropped", || {
+        server
+            .admin
+            .execute("sessions")
+            .starts_with("0 session(s)")
+            .then_some(())
+    });
+}
+
+#[test]
+fn test_roster() {
+    let server = TestServer::start();
+    let mut master = server.connect_as("Alice", Some(2));
+    let code = master.create_room().unwrap();
+    let mut phone = server.connect_as("Alice", Some(2));
+    phone.join_room(code).unwrap();
+    let mut anonymous = server.connect();
+    anonymous.join_room(code).unwrap();
+
+    // everyone in the lobby sees the three players, with their names and avatars made unique
+    for network in [&mut master, &mut phone, &mut anonymous] {
+        let roster = common::wait_for("the roster", || {
+            network.get_status();
+            (network.roster().len() == 3).then(|| network.roster().to_vec())
+        });
+        let profiles: Vec<(&str, u8)> = roster
+            .iter()
+            .map(|m| (m.nickname.as_str(), m.avatar))
+            .collect();
+        assert_eq!(profiles[..2], [("Alice", 2), ("Alice 2", 0)]);
+        assert!(profiles[2].0.starts_with("Player "));
+        assert_eq!(profiles[2].1, 1);
+        assert!(roster.iter().all(|m| m.rank.is_none()));
+    }
+}
+
+#[test]
+fn test_ready_check() {
+    let server = TestServer::start();
+    let (_, mut phones) = server.lock(3, client::Game::Test);
+
+    // everyone sees who is ready
+    phones[1].set_ready(true).unwrap();
+    for phone in phones.iter_mut() {
+        common::wait_for("the ready states", || {
+            phone.get_status();
+            let ready: Vec<bool> = phone.roster().iter().map(|m| m.ready).collect();
+            (ready == [false, true, false]).then_some(())
+        });
+    }
+
+    // the launch waits for every phone to be ready
+    phones[0].launch_game().unwrap();
+    let messages = common::wait_for("the refusal", || {
+        phones[0].get_status();
+        let messages = phones[0].messages();
+        (!messages.is_empty()).then_some(messages)
+    });
+    assert_eq!(messages, vec!["1 player(s) not ready yet".to_string()]);
+    assert!(matches!(
+        phones[0].get_status(),
+        client::Status::InLockRoom(_)
+    ));
+
+    phones[2].set_ready(true).unwrap();
+    common::wait_for("the last phone to be ready", || {
+        phones[0].get_status();
+        let ready: Vec<bool> = phones[0].roster().iter().map(|m| m.ready).collect();
+        (ready == [false, true, true]).then_some(())
+    });
+
+    // then the launch is counted down on every phone
+    phones[0].launch_game().unwrap();
+    for phone in phones.iter_mut() {
+        phone
+            .wait_for_status(common::TIMEOUT, |s| matches!(s, client::Status::InGame(_)))
+            .unwrap();
+        assert_eq!(phone.countdown(), Some(1));
+    }
+}
+
+#[test]
+fn test_leaving_before_the_launch() {
+    let server = TestServer::start();
+    let (ranks, mut phones) = server.lock(4, client::Game::Test);
+    assert_eq!(ranks, [0, 1, 2, 3]);
+    phones[2].set_ready(true).unwrap();
+    common::wait_for("the phone to be ready", || {
+        phones[0].get_status();
+        phones[0].roster()[2].ready.then_some(())
+    });
+
+    // the players left are ranked again, without a gap, and get ready again
+    drop(phones.remove(1));
+    for (phone, rank) in phones.iter_mut().zip([0, 2, 1]) {
+        common::wait_for("the new ranks", || {
+            phone.get_status();
+            let roster: Vec<(Option<u8>, bool)> =
+                phone.roster().iter().map(|m| (m.rank, m.ready)).collect();
+            let ranked = roster == [(Some(0), false), (Some(1), false), (Some(2), false)];
+            (ranked && matches!(phone.get_status(), client::Status::InLockRoom(r) if r == rank))
+                .then_some(())
+        });
+    }
+
+    for phone in phones.iter_mut().skip(1) {
+        phone.set_ready(true).unwrap();
+    }
+    common::wait_for("every phone to be ready", || {
+        phones[0].get_status();
+        phones[0].roster()[1..]
+            .iter()
+            .all(|m| m.ready)
+            .then_some(())
+    });
+    phones[0].launch_game().unwrap();
+    for phone in phones.iter_mut() {
+        phone
+            .wait_for_status(common::TIMEOUT, |s| matches!(s, client::Status::InGame(_)))
+            .unwrap();
+    }
+}
+
+#[test]
+fn test_force_launch() {
+    let server = TestServer::start();
+    let (_, mut phones) = server.lock(2, client::Game::Test);
+
+    phones[0].force_launch_game().unwrap();
+    for phone in phones.iter_mut() {
+        phone
+            .wait_for_status(common::TIMEOUT, |s| matches!(s, client::Status::InGame(_)))
+            .unwrap();
+    }
+}
+
+#[test]
+fn test_chat() {
+    let server = TestServer::start();
+    let (_, mut phones) = server.room(2);
+    let wait_chat = |phone: &mut client::Network| {
+        common::wait_for("the chat", || {
+            phone.get_status();
+            let mut buffer = [0_u8; packet::MAX_DATA_SIZE];
+            phone.recv(&mut buffer);
+            let chat = phone.chat();
+            (!chat.is_empty()).then_some(chat)
+        })
+    };
+
+    // the lobby hears everyone, with the session of who speaks
+    phones[1].send_chat("  hello  ").unwrap();
+    for phone in phones.iter_mut() {
+        let chat = wait_chat(phone);
+        assert_eq!(chat[0].text, "hello");
+        assert_eq!(chat[0].emote, None);
+        assert_eq!(chat[0].session, phone.roster()[1].session);
+    }
+    assert!(phones[1]
+        .send_chat(&"a".repeat(packet::CHAT_SIZE + 1))
+        .is_err());
+
+    // and so does the game
+    let mut phones = server.launch(2, client::Game::Test);
+    phones[0].send_emote(3).unwrap();
+    let chat = wait_chat(&mut phones[1]);
+    assert_eq!(chat[0].emote, Some(3));
+}
+
+#[test]
+fn test_leaderboards() {
+    let path = std::env::temp_dir().join(format!("phonetile-leaderboard-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let leaderboard = |phone: &mut client::Network| {
+        phone.query_leaderboard(client::Game::Test).unwrap();
+        common::wait_for("the leaderboard", || {
+            phone.get_status();
+            phone.leaderboard()
+        })
+    };
+
+    let server = TestServer::start_keeping_stats(&path);
+    let mut alice = server.connect_as("Alice", None);
+    assert!(leaderboard(&mut alice).is_empty());
+
+    // Bob wins the first game, the phones are back in the room after it
+    let (_, phones) = server.room_as(&["Alice", "Bob"]);
+    let mut phones = common::launch(phones, client::Game::Test);
+    phones[1].send(game::TEST_END).unwrap();
+    assert_eq!(common::wait_for_end(&mut phones[0]), Some(2));
+    assert_eq!(common::wait_for_end(&mut phones[1]), Some(1));
+
+    // and Alice the rematch
+    let mut phones = common::launch(phones, client::Game::Test);
+    phones[0].send(game::TEST_END).unwrap();
+    assert_eq!(common::wait_for_end(&mut phones[0]), Some(1));
+    assert_eq!(common::wait_for_end(&mut phones[1]), Some(2));
+
+    let expected: Vec<(String, u32, u32)> = vec![("Alice".into(), 2, 1), ("Bob".into(), 2, 1)];
+    let summary = |board: Vec<packet::Standing>| -> Vec<(String, u32, u32)> {
+        board
+            .into_iter()
+            .map(|s| (s.nickname, s.played, s.wins))
+            .collect()
+    };
+    assert_eq!(summary(leaderboard(&mut phones[1])), expected);
+    drop(phones);
+    drop(server);
+
+    // the statistics outlive the server
+    let server = TestServer::start_keeping_stats(&path);
+    let mut phone = server.connect();
+    assert_eq!(summary(leaderboard(&mut phone)), expected);
+    let _ = std::fs::remove_file(&path);
+}